@@ -0,0 +1,101 @@
+/*
+ * C interface to the CHIP-8 emulator core, implemented in src/ffi.rs.
+ *
+ * Link against libchip8_emulator.a (with -lpthread -ldl -lm) or
+ * libchip8_emulator.so. Every function takes the opaque handle made by
+ * chip8_new and returns CHIP8_OK or one of the negative error codes below,
+ * no function panics or aborts. A panic in the core is caught and returned
+ * as CHIP8_ERROR_PANIC. A handle is not thread safe.
+ */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CHIP8_OK 0
+#define CHIP8_ERROR_NULL -1
+#define CHIP8_ERROR_INVALID_ROM -2
+#define CHIP8_ERROR_INVALID_KEY -3
+#define CHIP8_ERROR_BUFFER_TOO_SMALL -4
+#define CHIP8_ERROR_INVALID_STATE -5
+/* the core hit something it could not run, load a rom or state to recover */
+#define CHIP8_ERROR_CRASHED -6
+/* a bug in the core, the handle may be left half updated and only chip8_free is safe */
+#define CHIP8_ERROR_PANIC -7
+#define CHIP8_ERROR_INVALID_PLATFORM -8
+
+/* the machines chip8_load_rom_for_platform can boot */
+#define CHIP8_PLATFORM_CHIP8 0
+/* the two page 64x64 VIP interpreter */
+#define CHIP8_PLATFORM_HIRES 1
+#define CHIP8_PLATFORM_ETI660 2
+#define CHIP8_PLATFORM_CHIP8X 3
+#define CHIP8_PLATFORM_MEGACHIP 4
+
+typedef struct Chip8Handle Chip8Handle;
+
+Chip8Handle *chip8_new(void);
+void chip8_free(Chip8Handle *handle);
+
+/*
+ * Resets the machine to a CHIP-8 and boots the rom, at 0x200 unless the
+ * rom database has it on a platform that loads programs elsewhere.
+ */
+int32_t chip8_load_rom(Chip8Handle *handle, const uint8_t *data, size_t length);
+
+/*
+ * Resets the machine to the platform and boots the rom at the platform's
+ * load address, 0x600 on the ETI-660 and 0x300 on the CHIP-8X. The rom
+ * database can still move a rom it knows to another platform.
+ * CHIP8_ERROR_INVALID_ROM when the rom doesn't fit the platform's memory.
+ */
+int32_t chip8_load_rom_for_platform(Chip8Handle *handle, const uint8_t *data, size_t length, int32_t platform);
+
+/* runs one 60Hz frame */
+int32_t chip8_run_frame(Chip8Handle *handle);
+
+/* key is 0x0 to 0xF, pressed is 0 for released */
+int32_t chip8_set_key(Chip8Handle *handle, uint8_t key, int32_t pressed);
+
+/*
+ * The display as one byte per pixel, 1 when lit, row by row. The pointer
+ * stays valid until the next call on the handle. NULL on a NULL handle.
+ */
+const uint8_t *chip8_framebuffer(Chip8Handle *handle, size_t *width, size_t *height);
+
+/*
+ * The colours the rom database picked for the rom as 0xRRGGBB, black and
+ * white by default. Either pointer can be NULL.
+ */
+int32_t chip8_palette(const Chip8Handle *handle, uint32_t *background, uint32_t *foreground);
+
+/*
+ * The chip-8 key a character typed on the host keyboard presses, using the
+ * rom database's keymap for the rom or 1234/qwer/asdf/zxcv.
+ * CHIP8_ERROR_INVALID_KEY when the character isn't mapped.
+ */
+int32_t chip8_key_for_char(const Chip8Handle *handle, uint32_t c);
+
+/* 1 while the buzzer sounds, 0 when it is quiet */
+int32_t chip8_sound_active(const Chip8Handle *handle);
+
+/*
+ * Copies a save state into buffer. size is always set to the size of the
+ * state, so calling with a NULL buffer finds out how much room it needs.
+ */
+int32_t chip8_save_state(const Chip8Handle *handle, uint8_t *buffer, size_t capacity, size_t *size);
+
+/* the machine is left alone when the state is refused */
+int32_t chip8_load_state(Chip8Handle *handle, const uint8_t *data, size_t length);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,554 @@
+// libretro core
+//
+// Cargo builds this as libchip8_libretro.so, which goes in the frontend's
+// cores directory as chip8_libretro.so. Roms are opened from their path,
+// so every format the emulator reads works: raw images and Intel HEX.
+// The rom database picks the platform, quirks, speed, colours and keymap,
+// and the core options override the quirks and speed.
+//
+// Controls: unless the database has a keymap for the rom the keyboard
+// uses the usual layout
+//
+//     1 2 3 4        1 2 3 C
+//     q w e r   ->   4 5 6 D
+//     a s d f        7 8 9 E
+//     z x c v        A 0 B F
+//
+// and the joypad has the d-pad on 2, 8, 4 and 6 (the arrows on the hex
+// keypad), A on 5 and the remaining buttons on the other keys. The second
+// joypad drives the second keypad of the CHIP-8X.
+//
+// The pointers the frontend passes in have to be valid as libretro.h
+// describes, and the core is only used from one thread at a time.
+
+#![allow(clippy::missing_safety_doc)]
+
+extern crate chip8_emulator;
+
+mod libretro;
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+use chip8_emulator::chip8::Chip8;
+use chip8_emulator::chip8::megachip::{MEGACHIP_SCREEN_HEIGHT, MEGACHIP_SCREEN_WIDTH};
+use chip8_emulator::chip8::platform::Platform;
+use chip8_emulator::chip8::quirks::Quirks;
+use chip8_emulator::chip8::rom::{Rom, RomError};
+use chip8_emulator::chip8::timing::Timing;
+use chip8_emulator::chip8::video::VideoFilter;
+
+use libretro::*;
+
+const SAMPLE_RATE: u32 = 44100;
+const FRAMES_PER_SECOND: u32 = 60;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAMES_PER_SECOND) as usize;
+// the buzzer is a square wave
+const TONE_FREQUENCY: u32 = 440;
+const TONE_VOLUME: i16 = 0x1800;
+
+// chip-8 key for each joypad button
+const JOYPAD_KEYS: [(c_uint, u8); 16] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0x7),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x9),
+    (RETRO_DEVICE_ID_JOYPAD_L, 0x1),
+    (RETRO_DEVICE_ID_JOYPAD_R, 0x3),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, 0xA),
+    (RETRO_DEVICE_ID_JOYPAD_START, 0xB),
+    (RETRO_DEVICE_ID_JOYPAD_L2, 0xC),
+    (RETRO_DEVICE_ID_JOYPAD_R2, 0xD),
+    (RETRO_DEVICE_ID_JOYPAD_L3, 0xE),
+    (RETRO_DEVICE_ID_JOYPAD_R3, 0xF),
+];
+
+// Core options, "database" leaves the setting to the rom database
+const SPEED_OPTION: &[u8] = b"chip8_speed\0";
+const SPEED_VALUES: &[u8] = b"CPU speed (instructions per frame); database|vip|7|10|15|20|30|50|100|200|500|1000\0";
+const QUIRK_OPTIONS: [(&[u8], &[u8]); 6] = [
+    (b"chip8_quirk_vf_reset\0", b"Logic ops reset VF; database|enabled|disabled\0"),
+    (b"chip8_quirk_memory\0", b"FX55/FX65 increment I; database|enabled|disabled\0"),
+    (b"chip8_quirk_shifting\0", b"Shifts ignore VY; database|enabled|disabled\0"),
+    (b"chip8_quirk_jumping\0", b"BNNN jumps with VX; database|enabled|disabled\0"),
+    (b"chip8_quirk_clipping\0", b"Sprites clip at the edges; database|enabled|disabled\0"),
+    (b"chip8_quirk_display_wait\0", b"Wait for vblank to draw; database|enabled|disabled\0"),
+];
+
+// the quirk set by QUIRK_OPTIONS[index]
+fn quirk_mut(quirks: &mut Quirks, index: usize) -> &mut bool {
+    match index {
+        0 => &mut quirks.vf_reset,
+        1 => &mut quirks.memory_increments_i,
+        2 => &mut quirks.shift_ignores_vy,
+        3 => &mut quirks.jump_uses_vx,
+        4 => &mut quirks.clipping,
+        _ => &mut quirks.display_wait,
+    }
+}
+
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+struct Game {
+    chip8: Chip8,
+    rom: Rom,
+    // booting starts from it, the database can pick another
+    platform: Platform,
+    // what the database set, for options left on "database"
+    quirks: Quirks,
+    instructions_per_frame: u32,
+    timing: Timing,
+    filter: VideoFilter,
+    pixels: Vec<u8>,
+    framebuffer: Vec<u32>,
+    // of the last frame sent, to tell the frontend when it changes
+    size: (usize, usize),
+    // position in the buzzer's square wave, in samples
+    tone_phase: u32,
+    // set when the program faulted, it stays stopped until reset
+    crashed: bool,
+}
+
+struct Core {
+    callbacks: Callbacks,
+    game: Option<Game>,
+}
+
+static CORE: Mutex<Core> = Mutex::new(Core {
+    callbacks: Callbacks {
+        environment: None,
+        video_refresh: None,
+        audio_sample_batch: None,
+        input_poll: None,
+        input_state: None,
+    },
+    game: None,
+});
+
+// a panic in an earlier call has already been reported
+fn core() -> MutexGuard<'static, Core> {
+    CORE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+unsafe fn environment(callbacks: &Callbacks, cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks.environment {
+        Some(environment) => environment(cmd, data),
+        None => false,
+    }
+}
+
+unsafe fn variable(callbacks: &Callbacks, key: &'static [u8]) -> Option<String> {
+    let mut variable = RetroVariable {
+        key: key.as_ptr() as *const c_char,
+        value: ptr::null(),
+    };
+    if !environment(callbacks, RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut RetroVariable as *mut c_void)
+        || variable.value.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(variable.value).to_string_lossy().into_owned())
+}
+
+fn geometry(width: usize, height: usize) -> RetroGameGeometry {
+    RetroGameGeometry {
+        base_width: width as c_uint,
+        base_height: height as c_uint,
+        max_width: MEGACHIP_SCREEN_WIDTH as c_uint,
+        max_height: MEGACHIP_SCREEN_HEIGHT as c_uint,
+        aspect_ratio: width as f32 / height as f32,
+    }
+}
+
+impl Game {
+    // Roms are read with room for a MEGA-CHIP program, whether one fits is
+    // only known once booting has picked the platform. .mc8 files the
+    // database doesn't know boot as MEGA-CHIP programs.
+    fn load(path: &Path) -> Result<Game, RomError> {
+        let rom = Rom::from_file_with_memory_size(path, Platform::default().load_address(), Platform::MegaChip.memory_size())?;
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+        let platform = match extension.as_deref() {
+            Some("mc8") => Platform::MegaChip,
+            _ => Platform::default(),
+        };
+        Game::boot(rom, platform)
+    }
+
+    fn boot(rom: Rom, platform: Platform) -> Result<Game, RomError> {
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(platform)?;
+        chip8.boot(&rom)?;
+        let palette = chip8.palette();
+        let size = (chip8.graphics().width(), chip8.graphics().height());
+
+        Ok(Game {
+            quirks: chip8.quirks(),
+            instructions_per_frame: chip8.instructions_per_frame(),
+            timing: chip8.timing(),
+            chip8,
+            rom,
+            platform,
+            filter: VideoFilter::new(palette, 0),
+            pixels: Vec::new(),
+            framebuffer: Vec::new(),
+            size,
+            tone_phase: 0,
+            crashed: false,
+        })
+    }
+
+    unsafe fn apply_options(&mut self, callbacks: &Callbacks) {
+        let mut quirks = self.quirks;
+        for (index, &(key, _)) in QUIRK_OPTIONS.iter().enumerate() {
+            match variable(callbacks, key).as_deref() {
+                Some("enabled") => *quirk_mut(&mut quirks, index) = true,
+                Some("disabled") => *quirk_mut(&mut quirks, index) = false,
+                _ => {},
+            }
+        }
+        self.chip8.set_quirks(quirks);
+
+        let speed = variable(callbacks, SPEED_OPTION);
+        match speed.as_deref() {
+            Some("vip") => self.chip8.set_timing(Timing::CosmacVip),
+            Some(value) if value.parse::<u32>().is_ok() => {
+                self.chip8.set_timing(Timing::InstructionsPerFrame);
+                self.chip8.set_instructions_per_frame(value.parse().unwrap());
+            },
+            _ => {
+                self.chip8.set_timing(self.timing);
+                self.chip8.set_instructions_per_frame(self.instructions_per_frame);
+            },
+        }
+    }
+
+    unsafe fn poll_input(&mut self, callbacks: &Callbacks) {
+        let (poll, state) = match (callbacks.input_poll, callbacks.input_state) {
+            (Some(poll), Some(state)) => (poll, state),
+            _ => return,
+        };
+        poll();
+
+        let mut keys = [[false; 16]; 2];
+        for (port, port_keys) in keys.iter_mut().enumerate() {
+            for &(id, key) in JOYPAD_KEYS.iter() {
+                port_keys[key as usize] |= state(port as c_uint, RETRO_DEVICE_JOYPAD, 0, id) != 0;
+            }
+        }
+        // the RETROK codes of the keys in a keymap are their ASCII codes
+        for key in 0..16u8 {
+            let retrok = self.chip8.keymap().host_key(key) as c_uint;
+            keys[0][key as usize] |= state(0, RETRO_DEVICE_KEYBOARD, 0, retrok) != 0;
+        }
+
+        for (key, (&pressed, &pressed2)) in keys[0].iter().zip(keys[1].iter()).enumerate() {
+            self.chip8.set_key(key as u8, pressed);
+            self.chip8.set_keypad2_key(key as u8, pressed2);
+        }
+    }
+
+    unsafe fn send_video(&mut self, callbacks: &Callbacks) {
+        self.filter.render(self.chip8.graphics(), self.chip8.colours(), &mut self.pixels);
+        self.framebuffer.clear();
+        for rgb in self.pixels.chunks(3) {
+            self.framebuffer.push(((rgb[0] as u32) << 16) | ((rgb[1] as u32) << 8) | rgb[2] as u32);
+        }
+
+        let size = (self.chip8.graphics().width(), self.chip8.graphics().height());
+        if size != self.size {
+            let mut geometry = geometry(size.0, size.1);
+            environment(callbacks, RETRO_ENVIRONMENT_SET_GEOMETRY, &mut geometry as *mut RetroGameGeometry as *mut c_void);
+            self.size = size;
+        }
+        if let Some(video_refresh) = callbacks.video_refresh {
+            video_refresh(self.framebuffer.as_ptr() as *const c_void, size.0 as c_uint, size.1 as c_uint, size.0 * 4);
+        }
+    }
+
+    // a MEGA-CHIP sample when one is playing, otherwise the buzzer
+    unsafe fn send_audio(&mut self, callbacks: &Callbacks) {
+        let mut mono = [0i16; SAMPLES_PER_FRAME];
+        let sample_playing = !self.crashed && self.chip8.sample_mut().is_some_and(|sample| !sample.is_finished());
+        if sample_playing {
+            let mut bytes = [0x80u8; SAMPLES_PER_FRAME];
+            self.chip8.sample_mut().unwrap().fill(&mut bytes, SAMPLE_RATE);
+            for (output, &byte) in mono.iter_mut().zip(bytes.iter()) {
+                *output = (byte as i16 - 0x80) << 8;
+            }
+        } else if !self.crashed && self.chip8.sound_active() {
+            for output in mono.iter_mut() {
+                let high = self.tone_phase * TONE_FREQUENCY % SAMPLE_RATE < SAMPLE_RATE / 2;
+                *output = if high { TONE_VOLUME } else { -TONE_VOLUME };
+                self.tone_phase = (self.tone_phase + 1) % SAMPLE_RATE;
+            }
+        }
+
+        let mut stereo = [0i16; SAMPLES_PER_FRAME * 2];
+        for (frame, &sample) in stereo.chunks_mut(2).zip(mono.iter()) {
+            frame[0] = sample;
+            frame[1] = sample;
+        }
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            audio_sample_batch(stereo.as_ptr(), SAMPLES_PER_FRAME);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(callback: RetroEnvironment) {
+    let mut core = core();
+    core.callbacks.environment = Some(callback);
+
+    let mut variables = vec![RetroVariable {
+        key: SPEED_OPTION.as_ptr() as *const c_char,
+        value: SPEED_VALUES.as_ptr() as *const c_char,
+    }];
+    for &(key, value) in QUIRK_OPTIONS.iter() {
+        variables.push(RetroVariable {
+            key: key.as_ptr() as *const c_char,
+            value: value.as_ptr() as *const c_char,
+        });
+    }
+    variables.push(RetroVariable {
+        key: ptr::null(),
+        value: ptr::null(),
+    });
+    environment(&core.callbacks, RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: RetroVideoRefresh) {
+    core().callbacks.video_refresh = Some(callback);
+}
+
+// everything goes through the batch callback
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: RetroAudioSample) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: RetroAudioSampleBatch) {
+    core().callbacks.audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: RetroInputPoll) {
+    core().callbacks.input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: RetroInputState) {
+    core().callbacks.input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    if let Some(info) = info.as_mut() {
+        info.library_name = b"CHIP-8\0".as_ptr() as *const c_char;
+        info.library_version = concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char;
+        info.valid_extensions = b"ch8|c8|sc8|xo8|c8x|mc8|hex|ihx\0".as_ptr() as *const c_char;
+        info.need_fullpath = true;
+        info.block_extract = false;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    let (width, height) = match core().game {
+        Some(ref game) => game.size,
+        None => Platform::default().display_size(),
+    };
+    if let Some(info) = info.as_mut() {
+        info.geometry = geometry(width, height);
+        info.timing = RetroSystemTiming {
+            fps: FRAMES_PER_SECOND as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_reset() {
+    let mut core = core();
+    let core = &mut *core;
+    if let Some(ref mut game) = core.game {
+        if let Ok(booted) = Game::boot(game.rom.clone(), game.platform) {
+            *game = booted;
+            game.apply_options(&core.callbacks);
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    let mut core = core();
+    let core = &mut *core;
+    let game = match core.game {
+        Some(ref mut game) => game,
+        None => return,
+    };
+
+    let mut updated = false;
+    environment(&core.callbacks, RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void);
+    if updated {
+        game.apply_options(&core.callbacks);
+    }
+
+    game.poll_input(&core.callbacks);
+    if !game.crashed && game.chip8.run_frame().is_err() {
+        game.crashed = true;
+    }
+    game.send_video(&core.callbacks);
+    game.send_audio(&core.callbacks);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize_size() -> usize {
+    match core().game {
+        Some(ref game) => game.chip8.save_state().len(),
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = core();
+    let game = match core.game {
+        Some(ref game) => game,
+        None => return false,
+    };
+    let state = game.chip8.save_state();
+    if data.is_null() || size < state.len() {
+        return false;
+    }
+    ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = core();
+    let game = match core.game {
+        Some(ref mut game) => game,
+        None => return false,
+    };
+    if data.is_null() {
+        return false;
+    }
+    match game.chip8.load_state(slice::from_raw_parts(data as *const u8, size)) {
+        Ok(()) => {
+            game.crashed = false;
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(info: *const RetroGameInfo) -> bool {
+    let info = match info.as_ref() {
+        Some(info) if !info.path.is_null() => info,
+        _ => return false,
+    };
+    let path = CStr::from_ptr(info.path).to_string_lossy().into_owned();
+
+    let mut core = core();
+    let core = &mut *core;
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(&core.callbacks, RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        return false;
+    }
+
+    // booting moves the rom to where the database's platform loads programs
+    let mut game = match Game::load(Path::new(&path)) {
+        Ok(game) => game,
+        Err(_) => return false,
+    };
+    game.apply_options(&core.callbacks);
+    core.game = Some(game);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn megachip_roms_larger_than_a_vip_load() {
+        let path = env::temp_dir().join("chip8_libretro_large.mc8");
+        fs::write(&path, vec![0x12; 0x8000]).unwrap();
+        let game = Game::load(&path).unwrap();
+        assert_eq!(game.chip8.platform(), Platform::MegaChip);
+        assert_eq!(game.chip8.memory().read_byte_at(0x81FF), 0x12);
+
+        // the same image is too large for a CHIP-8 once booted as one
+        let path = env::temp_dir().join("chip8_libretro_large.ch8");
+        fs::write(&path, vec![0x12; 0x8000]).unwrap();
+        match Game::load(&path) {
+            Err(RomError::TooLarge {..}) => {},
+            Err(e) => panic!("{}", e),
+            Ok(_) => panic!("a 32 KiB rom booted on a CHIP-8"),
+        }
+    }
+}
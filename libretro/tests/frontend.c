@@ -0,0 +1,274 @@
+/*
+ * A minimal libretro frontend, built and run by tests/frontend.rs.
+ *
+ *     frontend CORE.so ROM
+ *
+ * Loads the core with dlopen and plays the rom that tests/frontend.rs
+ * writes, checking video, audio, input, core options and save states along
+ * the way. Exits with 0 when everything checks out.
+ */
+
+#include <dlfcn.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* the parts of libretro.h used here */
+#define RETRO_DEVICE_JOYPAD 1
+#define RETRO_DEVICE_ID_JOYPAD_A 8
+#define RETRO_ENVIRONMENT_SET_PIXEL_FORMAT 10
+#define RETRO_ENVIRONMENT_GET_VARIABLE 15
+#define RETRO_ENVIRONMENT_SET_VARIABLES 16
+#define RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE 17
+#define RETRO_ENVIRONMENT_SET_GEOMETRY 37
+#define RETRO_PIXEL_FORMAT_XRGB8888 1
+
+struct retro_system_info {
+    const char *library_name;
+    const char *library_version;
+    const char *valid_extensions;
+    bool need_fullpath;
+    bool block_extract;
+};
+
+struct retro_game_geometry {
+    unsigned base_width;
+    unsigned base_height;
+    unsigned max_width;
+    unsigned max_height;
+    float aspect_ratio;
+};
+
+struct retro_system_timing {
+    double fps;
+    double sample_rate;
+};
+
+struct retro_system_av_info {
+    struct retro_game_geometry geometry;
+    struct retro_system_timing timing;
+};
+
+struct retro_variable {
+    const char *key;
+    const char *value;
+};
+
+struct retro_game_info {
+    const char *path;
+    const void *data;
+    size_t size;
+    const char *meta;
+};
+
+typedef bool (*environment_t)(unsigned, void *);
+typedef void (*video_refresh_t)(const void *, unsigned, unsigned, size_t);
+typedef void (*audio_sample_t)(int16_t, int16_t);
+typedef size_t (*audio_sample_batch_t)(const int16_t *, size_t);
+typedef void (*input_poll_t)(void);
+typedef int16_t (*input_state_t)(unsigned, unsigned, unsigned, unsigned);
+
+#define CHECK(condition) \
+    do { \
+        if (!(condition)) { \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #condition); \
+            exit(1); \
+        } \
+    } while (0)
+
+#define LOAD(symbol) \
+    symbol = dlsym(core, #symbol); \
+    CHECK(symbol != NULL)
+
+static int frame;
+static bool variables_set, pixel_format_set, polled;
+static uint32_t pixels[64 * 32];
+static unsigned video_width, video_height;
+static size_t audio_frames;
+static bool audible;
+
+static bool environment(unsigned cmd, void *data)
+{
+    switch (cmd) {
+    case RETRO_ENVIRONMENT_SET_VARIABLES: {
+        bool speed = false, quirk = false;
+        for (const struct retro_variable *variable = data; variable->key != NULL; variable++) {
+            speed |= strcmp(variable->key, "chip8_speed") == 0;
+            quirk |= strcmp(variable->key, "chip8_quirk_vf_reset") == 0;
+        }
+        variables_set = speed && quirk;
+        return true;
+    }
+    case RETRO_ENVIRONMENT_GET_VARIABLE: {
+        struct retro_variable *variable = data;
+        variable->value = strcmp(variable->key, "chip8_speed") == 0 ? "20" : NULL;
+        return true;
+    }
+    case RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE:
+        *(bool *) data = false;
+        return true;
+    case RETRO_ENVIRONMENT_SET_PIXEL_FORMAT:
+        pixel_format_set = *(unsigned *) data == RETRO_PIXEL_FORMAT_XRGB8888;
+        return true;
+    default:
+        return false;
+    }
+}
+
+static void video_refresh(const void *data, unsigned width, unsigned height, size_t pitch)
+{
+    CHECK(data != NULL && width == 64 && height == 32 && pitch == 64 * 4);
+    memcpy(pixels, data, sizeof pixels);
+    video_width = width;
+    video_height = height;
+}
+
+static void audio_sample(int16_t left, int16_t right)
+{
+    (void) left;
+    (void) right;
+}
+
+static size_t audio_sample_batch(const int16_t *data, size_t frames)
+{
+    audio_frames = frames;
+    audible = false;
+    for (size_t n = 0; n < frames * 2; n++) {
+        audible |= data[n] != 0;
+    }
+    return frames;
+}
+
+static void input_poll(void)
+{
+    polled = true;
+}
+
+/* holds A, which is key 5, from frame 5 to 9 */
+static int16_t input_state(unsigned port, unsigned device, unsigned index, unsigned id)
+{
+    (void) index;
+    return port == 0 && device == RETRO_DEVICE_JOYPAD && id == RETRO_DEVICE_ID_JOYPAD_A
+        && frame >= 5 && frame < 10;
+}
+
+static bool lit(unsigned x, unsigned y)
+{
+    return pixels[y * 64 + x] == 0xFFFFFF;
+}
+
+int main(int argc, char **argv)
+{
+    unsigned (*retro_api_version)(void);
+    void (*retro_set_environment)(environment_t);
+    void (*retro_set_video_refresh)(video_refresh_t);
+    void (*retro_set_audio_sample)(audio_sample_t);
+    void (*retro_set_audio_sample_batch)(audio_sample_batch_t);
+    void (*retro_set_input_poll)(input_poll_t);
+    void (*retro_set_input_state)(input_state_t);
+    void (*retro_init)(void);
+    void (*retro_deinit)(void);
+    void (*retro_get_system_info)(struct retro_system_info *);
+    void (*retro_get_system_av_info)(struct retro_system_av_info *);
+    bool (*retro_load_game)(const struct retro_game_info *);
+    void (*retro_unload_game)(void);
+    void (*retro_run)(void);
+    void (*retro_reset)(void);
+    size_t (*retro_serialize_size)(void);
+    bool (*retro_serialize)(void *, size_t);
+    bool (*retro_unserialize)(const void *, size_t);
+
+    struct retro_system_info system_info;
+    struct retro_system_av_info av_info;
+    struct retro_game_info game = {NULL, NULL, 0, NULL};
+    void *core, *state;
+    size_t state_size;
+
+    CHECK(argc == 3);
+    core = dlopen(argv[1], RTLD_NOW | RTLD_LOCAL);
+    if (core == NULL) {
+        fprintf(stderr, "%s\n", dlerror());
+        return 1;
+    }
+    LOAD(retro_api_version);
+    LOAD(retro_set_environment);
+    LOAD(retro_set_video_refresh);
+    LOAD(retro_set_audio_sample);
+    LOAD(retro_set_audio_sample_batch);
+    LOAD(retro_set_input_poll);
+    LOAD(retro_set_input_state);
+    LOAD(retro_init);
+    LOAD(retro_deinit);
+    LOAD(retro_get_system_info);
+    LOAD(retro_get_system_av_info);
+    LOAD(retro_load_game);
+    LOAD(retro_unload_game);
+    LOAD(retro_run);
+    LOAD(retro_reset);
+    LOAD(retro_serialize_size);
+    LOAD(retro_serialize);
+    LOAD(retro_unserialize);
+
+    CHECK(retro_api_version() == 1);
+    retro_set_environment(environment);
+    CHECK(variables_set);
+    retro_set_video_refresh(video_refresh);
+    retro_set_audio_sample(audio_sample);
+    retro_set_audio_sample_batch(audio_sample_batch);
+    retro_set_input_poll(input_poll);
+    retro_set_input_state(input_state);
+    retro_init();
+
+    retro_get_system_info(&system_info);
+    CHECK(strcmp(system_info.library_name, "CHIP-8") == 0);
+    CHECK(system_info.need_fullpath);
+    CHECK(!retro_load_game(&game));
+    game.path = argv[2];
+    CHECK(retro_load_game(&game));
+    CHECK(pixel_format_set);
+    retro_get_system_av_info(&av_info);
+    CHECK(av_info.geometry.base_width == 64 && av_info.geometry.base_height == 32);
+    CHECK(av_info.timing.fps == 60.0 && av_info.timing.sample_rate == 44100.0);
+
+    /* the rom draws a 0, sounds the buzzer and waits for a key */
+    frame = 1;
+    retro_run();
+    CHECK(polled);
+    CHECK(video_width == 64 && video_height == 32);
+    CHECK(lit(0, 0) && lit(3, 0) && !lit(4, 0));
+    CHECK(audio_frames == 735 && audible);
+
+    state_size = retro_serialize_size();
+    CHECK(state_size > 0);
+    state = malloc(state_size);
+    CHECK(!retro_serialize(state, state_size - 1));
+    CHECK(retro_serialize(state, state_size));
+
+    /* then draws the key, a 5, at x = 8 */
+    for (frame = 2; frame <= 30; frame++) {
+        retro_run();
+        if (frame == 4) {
+            CHECK(!lit(8, 0));
+        }
+    }
+    CHECK(lit(8, 0) && lit(8, 1) && !lit(11, 1));
+    CHECK(!audible);
+
+    CHECK(retro_unserialize(state, state_size));
+    CHECK(!retro_unserialize(state, 4));
+    retro_run();
+    CHECK(!lit(8, 0) && audible);
+
+    retro_reset();
+    retro_run();
+    CHECK(lit(0, 0) && !lit(8, 0) && audible);
+
+    free(state);
+    retro_unload_game();
+    retro_deinit();
+    dlclose(core);
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,56 @@
+// libretro core test
+//
+// Builds the minimal frontend in tests/frontend.c with the system cc and
+// has it load the core cargo built next to this test and play a small rom.
+// Skipped when there is no cc.
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+
+// draws a 0, sounds the buzzer for 16 frames, waits for a key and draws it
+const ROM: [u8; 20] = [
+    0x60, 0x00, // LD V0, 0
+    0xF0, 0x29, // LD F, V0
+    0xD0, 0x05, // DRW V0, V0, 5
+    0x61, 0x10, // LD V1, 16
+    0xF1, 0x18, // LD ST, V1
+    0xF2, 0x0A, // LD V2, K
+    0xF2, 0x29, // LD F, V2
+    0x63, 0x08, // LD V3, 8
+    0xD3, 0x05, // DRW V3, V0, 5
+    0x12, 0x12, // JP 0x212
+];
+
+#[test]
+fn frontend_plays_a_rom() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        println!("no cc, skipping");
+        return;
+    }
+
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // target/debug/deps/frontend-... -> target/debug
+    let target = env::current_exe().unwrap().parent().unwrap().parent().unwrap().to_path_buf();
+    let core = target.join("libchip8_libretro.so");
+    assert!(core.exists(), "{} was not built", core.display());
+
+    let rom = target.join("libretro_test.ch8");
+    File::create(&rom).unwrap().write_all(&ROM).unwrap();
+
+    let frontend = target.join("libretro_frontend");
+    let status = Command::new("cc")
+        .arg(root.join("tests/frontend.c"))
+        .arg("-std=c99")
+        .arg("-ldl")
+        .arg("-o").arg(&frontend)
+        .status()
+        .unwrap();
+    assert!(status.success(), "frontend.c did not compile");
+
+    let output = Command::new(&frontend).arg(&core).arg(&rom).output().unwrap();
+    assert!(output.status.success(), "frontend failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
@@ -0,0 +1,451 @@
+use chip8::memory::Chip8Memory;
+use chip8::keypad::Chip8Keypad;
+
+// RCA CDP1802, the cpu of the COSMAC VIP
+//
+// Runs the machine code subroutines VIP programs call with 0NNN. The
+// interpreter enters them with SEP R3 and they return to it with SEP R4
+// (a D4 byte). Interrupts and DMA aren't modelled, IDL is a no-op.
+//
+// Of the VIP's i/o only the keypad is wired up: OUT 2 latches a key and
+// EF3 is set while that key is held.
+
+// every instruction takes 2 machine cycles, long branches and skips 3
+pub const CDP1802_CYCLES_PER_INSTRUCTION: u32 = 2;
+pub const CDP1802_CYCLES_PER_LONG_INSTRUCTION: u32 = 3;
+
+// subroutines that run longer than this are assumed to never return
+pub const CDP1802_MAX_INSTRUCTIONS: u32 = 1_000_000;
+
+// Where the VIP interpreter keeps its state in a 4K machine. R2 is the
+// 1802 stack pointer, V0-VF live in memory and the display is a 256 byte
+// bitmap, 8 bytes to the row with the leftmost pixel in the high bit.
+pub const VIP_STACK_POINTER: u16 = 0x0ECF;
+pub const VIP_VARIABLES: u16 = 0x0EF0;
+pub const VIP_DISPLAY: u16 = 0x0F00;
+
+#[derive(Debug, Clone)]
+pub struct Cdp1802 {
+    // 16 scratchpad registers, any of them can be the program counter
+    pub r: [u16; 16],
+    // accumulator and carry
+    pub d: u8,
+    pub df: bool,
+    // program counter and data pointer register designators
+    pub p: u8,
+    pub x: u8,
+    // X and P saved by MARK or an interrupt
+    pub t: u8,
+    pub ie: bool,
+    pub q: bool,
+
+    // key selected with OUT 2
+    key_latch: u8,
+}
+
+impl Cdp1802 {
+    pub fn new() -> Cdp1802 {
+        Cdp1802 {
+            r: [0; 16],
+            d: 0,
+            df: false,
+            p: 0,
+            x: 0,
+            t: 0,
+            ie: true,
+            q: false,
+            key_latch: 0,
+        }
+    }
+
+    fn fetch(&mut self, memory: &Chip8Memory) -> u8 {
+        let p = self.p as usize;
+        let byte = memory.read_byte(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        byte
+    }
+
+    fn rx(&self) -> u16 {
+        self.r[self.x as usize]
+    }
+
+    // the byte R(X) points at
+    fn mx(&self, memory: &Chip8Memory) -> u8 {
+        memory.read_byte(self.rx())
+    }
+
+    // 1802 subtraction, DF is set when there is no borrow
+    fn subtract(&mut self, minuend: u8, subtrahend: u8, borrow_in: bool) {
+        let result = minuend as i16 - subtrahend as i16 - if borrow_in { 1 } else { 0 };
+        self.d = result as u8;
+        self.df = result >= 0;
+    }
+
+    fn add(&mut self, a: u8, b: u8, carry_in: bool) {
+        let sum = a as u16 + b as u16 + if carry_in { 1 } else { 0 };
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    fn short_branch(&mut self, memory: &Chip8Memory, condition: bool) {
+        let p = self.p as usize;
+        if condition {
+            let target = memory.read_byte(self.r[p]);
+            self.r[p] = (self.r[p] & 0xFF00) | target as u16;
+        } else {
+            self.r[p] = self.r[p].wrapping_add(1);
+        }
+    }
+
+    fn long_branch(&mut self, memory: &Chip8Memory, condition: bool) {
+        let p = self.p as usize;
+        if condition {
+            let high = memory.read_byte(self.r[p]) as u16;
+            let low = memory.read_byte(self.r[p].wrapping_add(1)) as u16;
+            self.r[p] = (high << 8) | low;
+        } else {
+            self.r[p] = self.r[p].wrapping_add(2);
+        }
+    }
+
+    fn long_skip(&mut self, condition: bool) {
+        if condition {
+            let p = self.p as usize;
+            self.r[p] = self.r[p].wrapping_add(2);
+        }
+    }
+
+    fn ef(&self, flag: u8, keypad: &Chip8Keypad) -> bool {
+        match flag {
+            3 => keypad.is_pressed(self.key_latch),
+            _ => false,
+        }
+    }
+
+    // Executes one instruction and returns the machine cycles it took
+    pub fn step(&mut self, memory: &mut Chip8Memory, keypad: &Chip8Keypad) -> u32 {
+        let opcode = self.fetch(memory);
+        let n = (opcode & 0xF) as usize;
+
+        match opcode >> 4 {
+            // IDL waits for an interrupt or DMA, neither of which happens
+            0x0 if n == 0 => {},
+            // LDN
+            0x0 => self.d = memory.read_byte(self.r[n]),
+            // INC
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            // DEC
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => {
+                let condition = match n & 0x7 {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    0x3 => self.df,
+                    flag => self.ef(flag as u8 - 3, keypad),
+                };
+                // 38-3F are the inverses, 38 (SKP) never branches and so
+                // skips the byte after it
+                let condition = if n & 0x8 != 0 { !condition } else { condition };
+                self.short_branch(memory, condition);
+            },
+            // LDA
+            0x4 => {
+                self.d = memory.read_byte(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            },
+            // STR
+            0x5 => memory.write_byte(self.r[n], self.d),
+            0x6 => match n {
+                // IRX
+                0x0 => {
+                    let x = self.x as usize;
+                    self.r[x] = self.r[x].wrapping_add(1);
+                },
+                // OUT 1-7
+                0x1..=0x7 => {
+                    let byte = self.mx(memory);
+                    if n == 2 {
+                        self.key_latch = byte & 0xF;
+                    }
+                    let x = self.x as usize;
+                    self.r[x] = self.r[x].wrapping_add(1);
+                },
+                // 68 isn't an 1802 instruction
+                0x8 => {},
+                // INP 1-7, nothing is connected so the bus reads as 0
+                _ => {
+                    self.d = 0;
+                    let rx = self.rx();
+                    memory.write_byte(rx, 0);
+                },
+            },
+            0x7 => match n {
+                // RET and DIS
+                0x0 | 0x1 => {
+                    let byte = self.mx(memory);
+                    let x = self.x as usize;
+                    self.r[x] = self.r[x].wrapping_add(1);
+                    self.x = byte >> 4;
+                    self.p = byte & 0xF;
+                    self.ie = n == 0x0;
+                },
+                // LDXA
+                0x2 => {
+                    self.d = self.mx(memory);
+                    let x = self.x as usize;
+                    self.r[x] = self.r[x].wrapping_add(1);
+                },
+                // STXD
+                0x3 => {
+                    let rx = self.rx();
+                    memory.write_byte(rx, self.d);
+                    let x = self.x as usize;
+                    self.r[x] = self.r[x].wrapping_sub(1);
+                },
+                // ADC
+                0x4 => {
+                    let (m, d, df) = (self.mx(memory), self.d, self.df);
+                    self.add(m, d, df);
+                },
+                // SDB
+                0x5 => {
+                    let (m, d, df) = (self.mx(memory), self.d, self.df);
+                    self.subtract(m, d, !df);
+                },
+                // SHRC
+                0x6 => {
+                    let carry = self.d & 1 != 0;
+                    self.d = (self.d >> 1) | if self.df { 0x80 } else { 0 };
+                    self.df = carry;
+                },
+                // SMB
+                0x7 => {
+                    let (m, d, df) = (self.mx(memory), self.d, self.df);
+                    self.subtract(d, m, !df);
+                },
+                // SAV
+                0x8 => {
+                    let rx = self.rx();
+                    memory.write_byte(rx, self.t);
+                },
+                // MARK
+                0x9 => {
+                    self.t = (self.x << 4) | self.p;
+                    memory.write_byte(self.r[2], self.t);
+                    self.x = self.p;
+                    self.r[2] = self.r[2].wrapping_sub(1);
+                },
+                // REQ and SEQ
+                0xA => self.q = false,
+                0xB => self.q = true,
+                // ADCI
+                0xC => {
+                    let (m, d, df) = (self.fetch(memory), self.d, self.df);
+                    self.add(m, d, df);
+                },
+                // SDBI
+                0xD => {
+                    let (m, d, df) = (self.fetch(memory), self.d, self.df);
+                    self.subtract(m, d, !df);
+                },
+                // SHLC
+                0xE => {
+                    let carry = self.d & 0x80 != 0;
+                    self.d = (self.d << 1) | if self.df { 1 } else { 0 };
+                    self.df = carry;
+                },
+                // SMBI
+                _ => {
+                    let (m, d, df) = (self.fetch(memory), self.d, self.df);
+                    self.subtract(d, m, !df);
+                },
+            },
+            // GLO, GHI, PLO, PHI
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.r[n] = (self.r[n] & 0xFF00) | self.d as u16,
+            0xB => self.r[n] = (self.r[n] & 0x00FF) | ((self.d as u16) << 8),
+            0xC => {
+                match n {
+                    // LBR, LBQ, LBZ, LBDF and their inverses
+                    0x0 => self.long_branch(memory, true),
+                    0x1 => { let q = self.q; self.long_branch(memory, q) },
+                    0x2 => { let z = self.d == 0; self.long_branch(memory, z) },
+                    0x3 => { let df = self.df; self.long_branch(memory, df) },
+                    0x8 => self.long_skip(true),
+                    0x9 => { let q = !self.q; self.long_branch(memory, q) },
+                    0xA => { let z = self.d != 0; self.long_branch(memory, z) },
+                    0xB => { let df = !self.df; self.long_branch(memory, df) },
+                    // NOP
+                    0x4 => {},
+                    // LSNQ, LSNZ, LSNF, LSIE, LSQ, LSZ, LSDF
+                    0x5 => { let q = !self.q; self.long_skip(q) },
+                    0x6 => { let z = self.d != 0; self.long_skip(z) },
+                    0x7 => { let df = !self.df; self.long_skip(df) },
+                    0xC => { let ie = self.ie; self.long_skip(ie) },
+                    0xD => { let q = self.q; self.long_skip(q) },
+                    0xE => { let z = self.d == 0; self.long_skip(z) },
+                    _ => { let df = self.df; self.long_skip(df) },
+                }
+                return CDP1802_CYCLES_PER_LONG_INSTRUCTION;
+            },
+            // SEP
+            0xD => self.p = n as u8,
+            // SEX
+            0xE => self.x = n as u8,
+            _ => match n {
+                // LDX, OR, AND, XOR
+                0x0 => self.d = self.mx(memory),
+                0x1 => self.d |= self.mx(memory),
+                0x2 => self.d &= self.mx(memory),
+                0x3 => self.d ^= self.mx(memory),
+                // ADD, SD
+                0x4 => {
+                    let (m, d) = (self.mx(memory), self.d);
+                    self.add(m, d, false);
+                },
+                0x5 => {
+                    let (m, d) = (self.mx(memory), self.d);
+                    self.subtract(m, d, false);
+                },
+                // SHR
+                0x6 => {
+                    self.df = self.d & 1 != 0;
+                    self.d >>= 1;
+                },
+                // SM
+                0x7 => {
+                    let (m, d) = (self.mx(memory), self.d);
+                    self.subtract(d, m, false);
+                },
+                // LDI, ORI, ANI, XRI
+                0x8 => self.d = self.fetch(memory),
+                0x9 => self.d |= self.fetch(memory),
+                0xA => self.d &= self.fetch(memory),
+                0xB => self.d ^= self.fetch(memory),
+                // ADI, SDI
+                0xC => {
+                    let (m, d) = (self.fetch(memory), self.d);
+                    self.add(m, d, false);
+                },
+                0xD => {
+                    let (m, d) = (self.fetch(memory), self.d);
+                    self.subtract(m, d, false);
+                },
+                // SHL
+                0xE => {
+                    self.df = self.d & 0x80 != 0;
+                    self.d <<= 1;
+                },
+                // SMI
+                _ => {
+                    let (m, d) = (self.fetch(memory), self.d);
+                    self.subtract(d, m, false);
+                },
+            },
+        }
+        CDP1802_CYCLES_PER_INSTRUCTION
+    }
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Cdp1802 {
+        Cdp1802::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // loads program at 0x100 and runs it from there with P = 0 until it
+    // hands over to R4 with SEP R4
+    fn run_with_keypad(program: &[u8], keypad: &Chip8Keypad) -> (Cdp1802, Chip8Memory) {
+        let mut memory = Chip8Memory::new();
+        memory.load(0x100, program);
+        let mut cdp1802 = Cdp1802::new();
+        cdp1802.r[0] = 0x100;
+        for _ in 0..1000 {
+            if cdp1802.p == 4 {
+                return (cdp1802, memory);
+            }
+            cdp1802.step(&mut memory, keypad);
+        }
+        panic!("program did not end with SEP R4");
+    }
+
+    fn run(program: &[u8]) -> (Cdp1802, Chip8Memory) {
+        run_with_keypad(program, &Chip8Keypad::new())
+    }
+
+    #[test]
+    fn add_sets_the_carry() {
+        // LDI #10, ADI #F5
+        let (cdp1802, _) = run(&[0xF8, 0x10, 0xFC, 0xF5, 0xD4]);
+        assert_eq!(cdp1802.d, 0x05);
+        assert!(cdp1802.df);
+    }
+
+    #[test]
+    fn subtract_clears_df_on_a_borrow() {
+        // LDI #05, SMI #06
+        let (cdp1802, _) = run(&[0xF8, 0x05, 0xFF, 0x06, 0xD4]);
+        assert_eq!(cdp1802.d, 0xFF);
+        assert!(!cdp1802.df);
+
+        // and SMBI takes the borrow in
+        let (cdp1802, _) = run(&[0xF8, 0x05, 0xFF, 0x06, 0x7F, 0x00, 0xD4]);
+        assert_eq!(cdp1802.d, 0xFE);
+        assert!(cdp1802.df);
+    }
+
+    #[test]
+    fn registers_are_loaded_a_byte_at_a_time() {
+        // LDI #12, PHI R5, LDI #34, PLO R5, INC R5, GLO R5
+        let (cdp1802, _) = run(&[0xF8, 0x12, 0xB5, 0xF8, 0x34, 0xA5, 0x15, 0x85, 0xD4]);
+        assert_eq!(cdp1802.r[5], 0x1235);
+        assert_eq!(cdp1802.d, 0x35);
+    }
+
+    #[test]
+    fn stack_push_and_pop() {
+        // R2 = #180, SEX R2, LDI #AA, STXD, IRX, LDI #00, LDX
+        let (cdp1802, memory) = run(&[0xF8, 0x01, 0xB2, 0xF8, 0x80, 0xA2, 0xE2,
+                                      0xF8, 0xAA, 0x73, 0x60, 0xF8, 0x00, 0xF0, 0xD4]);
+        assert_eq!(memory.read_byte(0x180), 0xAA);
+        assert_eq!(cdp1802.r[2], 0x180);
+        assert_eq!(cdp1802.d, 0xAA);
+    }
+
+    #[test]
+    fn short_and_long_branches() {
+        // LDI #00, BZ #06, LDI #FF, (06) LBR #010B, LDI #EE, (0B) SEP R4
+        let (cdp1802, _) = run(&[0xF8, 0x00, 0x32, 0x06, 0xF8, 0xFF, 0xC0, 0x01, 0x0B, 0xF8, 0xEE, 0xD4]);
+        assert_eq!(cdp1802.d, 0x00);
+    }
+
+    #[test]
+    fn sep_calls_a_subroutine() {
+        // R3 = #110, SEP R3, SEP R4; at #110 LDI #42, SEP R0
+        let mut program = vec![0xF8, 0x01, 0xB3, 0xF8, 0x10, 0xA3, 0xD3, 0xD4];
+        program.resize(0x10, 0);
+        program.extend_from_slice(&[0xF8, 0x42, 0xD0]);
+        let (cdp1802, _) = run(&program);
+        assert_eq!(cdp1802.d, 0x42);
+    }
+
+    #[test]
+    fn ef3_reads_the_latched_key() {
+        // R2 = #180 holding key 7, SEX R2, OUT 2, LDI #00, B3 #0F, LDI #FF, (0F) SEP R4
+        let program = [0xF8, 0x01, 0xB2, 0xF8, 0x80, 0xA2, 0xE2, 0x62, 0xF8, 0x00, 0x36, 0x0F, 0xF8, 0xFF, 0xC4, 0xD4];
+        let mut keypad = Chip8Keypad::new();
+        let mut memory_with_key = program.to_vec();
+        memory_with_key.resize(0x80, 0);
+        memory_with_key.push(0x07);
+
+        assert_eq!(run_with_keypad(&memory_with_key, &keypad).0.d, 0xFF);
+        keypad.keys[7] = true;
+        assert_eq!(run_with_keypad(&memory_with_key, &keypad).0.d, 0x00);
+    }
+}
@@ -0,0 +1,356 @@
+// RAM search and cheats
+//
+// A RAM search starts with every address as a candidate and narrows them
+// down by comparing memory with the snapshot taken at the last filter,
+// e.g. "decreased" after losing a life. The address that is left can be
+// turned into a cheat that freezes it at a value.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use chip8::Chip8;
+use chip8::memory::Chip8Memory;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchFilter {
+    // compared with the last snapshot
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    // the byte is this value now
+    Value(u8),
+}
+
+impl SearchFilter {
+    // unchanged (or equal), changed, increased, decreased or a number
+    pub fn parse(name: &str) -> Result<SearchFilter, String> {
+        match name.trim() {
+            "unchanged" | "equal" => Ok(SearchFilter::Unchanged),
+            "changed" => Ok(SearchFilter::Changed),
+            "increased" => Ok(SearchFilter::Increased),
+            "decreased" => Ok(SearchFilter::Decreased),
+            value => parse_number(value).and_then(|n| if n <= 0xFF { Some(n as u8) } else { None })
+                .map(SearchFilter::Value)
+                .ok_or_else(|| format!("unknown search filter \"{}\"", value)),
+        }
+    }
+
+    fn keeps(&self, previous: u8, current: u8) -> bool {
+        match *self {
+            SearchFilter::Unchanged => current == previous,
+            SearchFilter::Changed => current != previous,
+            SearchFilter::Increased => current > previous,
+            SearchFilter::Decreased => current < previous,
+            SearchFilter::Value(value) => current == value,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RamSearch {
+    // clones share memory until it is written to, so this is cheap
+    snapshot: Chip8Memory,
+    candidates: Vec<u32>,
+}
+
+impl RamSearch {
+    // every address is a candidate to start with
+    pub fn new(memory: &Chip8Memory) -> RamSearch {
+        RamSearch {
+            snapshot: memory.clone(),
+            candidates: (0..memory.size() as u32).collect(),
+        }
+    }
+
+    // keeps the candidates that pass and takes a new snapshot
+    pub fn filter(&mut self, memory: &Chip8Memory, filter: SearchFilter) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&addr| filter.keeps(snapshot.read_byte_at(addr), memory.read_byte_at(addr)));
+        self.snapshot = memory.clone();
+    }
+
+    pub fn candidates(&self) -> &[u32] {
+        &self.candidates
+    }
+
+    // what a candidate held at the last snapshot
+    pub fn snapshot_value(&self, addr: u32) -> u8 {
+        self.snapshot.read_byte_at(addr)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheatKind {
+    // written before every frame
+    Freeze,
+    // written once when the cheat is switched on, the program can change
+    // it afterwards, e.g. to patch an instruction
+    Patch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub kind: CheatKind,
+    pub address: u32,
+    pub value: u8,
+    pub enabled: bool,
+    pub description: String,
+}
+
+impl Cheat {
+    pub fn new(kind: CheatKind, address: u32, value: u8, description: &str) -> Cheat {
+        Cheat {
+            kind,
+            address,
+            value,
+            enabled: true,
+            description: String::from(description),
+        }
+    }
+
+    // "on freeze 0x2F0 0x09 Infinite lives", as in a cheat file
+    pub fn parse(line: &str) -> Result<Cheat, String> {
+        let mut words = line.split_whitespace();
+        let enabled = match words.next() {
+            Some("on") => true,
+            Some("off") => false,
+            _ => return Err(String::from("a cheat starts with on or off")),
+        };
+        let kind = match words.next() {
+            Some("freeze") => CheatKind::Freeze,
+            Some("patch") => CheatKind::Patch,
+            _ => return Err(String::from("a cheat is either freeze or patch")),
+        };
+        let address = words.next().and_then(parse_number)
+            .ok_or_else(|| String::from("a cheat needs an address"))?;
+        let value = words.next().and_then(parse_number).filter(|&value| value <= 0xFF)
+            .ok_or_else(|| String::from("a cheat needs a byte value"))?;
+        Ok(Cheat {
+            kind,
+            address,
+            value: value as u8,
+            enabled,
+            description: words.collect::<Vec<&str>>().join(" "),
+        })
+    }
+
+    fn write(&self, memory: &mut Chip8Memory) {
+        memory.write_byte_at(self.address, self.value);
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {:#05X} {:#04X}",
+               if self.enabled { "on" } else { "off" },
+               match self.kind { CheatKind::Freeze => "freeze", CheatKind::Patch => "patch" },
+               self.address,
+               self.value)?;
+        if !self.description.is_empty() {
+            write!(f, " {}", self.description)?;
+        }
+        Ok(())
+    }
+}
+
+// The cheats for one rom
+#[derive(Debug, Clone, Default)]
+pub struct CheatList {
+    pub cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    // writes the patches that are on, for after the rom has booted
+    pub fn apply_patches(&self, chip8: &mut Chip8) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled && cheat.kind == CheatKind::Patch) {
+            cheat.write(chip8.memory_mut());
+        }
+    }
+
+    // call before every frame
+    pub fn apply_frame(&self, chip8: &mut Chip8) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled && cheat.kind == CheatKind::Freeze) {
+            cheat.write(chip8.memory_mut());
+        }
+    }
+
+    // a patch is written as soon as it is switched on
+    pub fn set_enabled(&mut self, index: usize, enabled: bool, chip8: &mut Chip8) {
+        let cheat = &mut self.cheats[index];
+        cheat.enabled = enabled;
+        if enabled {
+            cheat.write(chip8.memory_mut());
+        }
+    }
+}
+
+// Cheats for any number of roms, keyed by the SHA-1 of the rom
+//
+// The file looks like the rom database, with a cheat per line:
+//
+//     [0123456789abcdef0123456789abcdef01234567]
+//     on freeze 0x2F0 0x09 Infinite lives
+//     off patch 0x3A2 0x00 No collisions
+#[derive(Debug, Clone, Default)]
+pub struct CheatFile {
+    roms: HashMap<String, CheatList>,
+}
+
+impl CheatFile {
+    pub fn parse(text: &str) -> Result<CheatFile, String> {
+        let mut file = CheatFile::default();
+        let mut current = None;
+
+        for (i, raw_line) in text.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let sha1 = line[1..line.len() - 1].trim().to_ascii_lowercase();
+                if sha1.len() != 40 || !sha1.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!("line {}: \"{}\" is not a SHA-1", i + 1, sha1));
+                }
+                file.roms.entry(sha1.clone()).or_default();
+                current = Some(sha1);
+                continue;
+            }
+
+            let sha1 = current.as_ref()
+                .ok_or_else(|| format!("line {}: cheat comes before any [sha1] section", i + 1))?;
+            let cheat = Cheat::parse(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            file.roms.get_mut(sha1).unwrap().cheats.push(cheat);
+        }
+        Ok(file)
+    }
+
+    // a missing file has no cheats
+    pub fn load(path: &Path) -> io::Result<CheatFile> {
+        let mut text = String::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_string(&mut text)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(CheatFile::default()),
+            Err(e) => return Err(e),
+        };
+        CheatFile::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        File::create(path)?.write_all(self.to_string().as_bytes())
+    }
+
+    pub fn cheats(&self, sha1_hex: &str) -> CheatList {
+        self.roms.get(&sha1_hex.to_ascii_lowercase()).cloned().unwrap_or_default()
+    }
+
+    pub fn set_cheats(&mut self, sha1_hex: &str, cheats: CheatList) {
+        self.roms.insert(sha1_hex.to_ascii_lowercase(), cheats);
+    }
+}
+
+impl fmt::Display for CheatFile {
+    // sorted so the file doesn't churn between saves
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut hashes: Vec<&String> = self.roms.keys().collect();
+        hashes.sort();
+        for sha1 in hashes {
+            writeln!(f, "[{}]", sha1)?;
+            for cheat in self.roms[sha1].cheats.iter() {
+                writeln!(f, "{}", cheat)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+// 0x prefixed hex or decimal
+fn parse_number(value: &str) -> Option<u32> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        u32::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse::<u32>().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::rom::Rom;
+
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    // counts V0 up and keeps it at 0x300, with 7 at 0x301
+    fn counting() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(vec![0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00]).unwrap()).unwrap();
+        chip8.memory_mut().write_byte(0x301, 7);
+        chip8
+    }
+
+    #[test]
+    fn searches_narrow_down_to_the_counter() {
+        let mut chip8 = counting();
+        let mut search = RamSearch::new(chip8.memory());
+        assert_eq!(search.candidates().len(), 4096);
+
+        chip8.run_frame().unwrap();
+        search.filter(chip8.memory(), SearchFilter::Increased);
+        chip8.run_frame().unwrap();
+        search.filter(chip8.memory(), SearchFilter::Changed);
+        assert_eq!(search.candidates(), &[0x300]);
+        assert_eq!(search.snapshot_value(0x300), chip8.memory().read_byte(0x300));
+
+        let mut search = RamSearch::new(chip8.memory());
+        chip8.run_frame().unwrap();
+        search.filter(chip8.memory(), SearchFilter::Unchanged);
+        search.filter(chip8.memory(), SearchFilter::Value(7));
+        assert_eq!(search.candidates(), &[0x301]);
+    }
+
+    #[test]
+    fn cheats_freeze_and_patch() {
+        let mut chip8 = counting();
+        let mut list = CheatList {
+            cheats: vec![Cheat::new(CheatKind::Freeze, 0x300, 0x40, "counter"),
+                         Cheat::new(CheatKind::Patch, 0x301, 0x99, "")],
+        };
+        list.apply_patches(&mut chip8);
+        for _ in 0..3 {
+            list.apply_frame(&mut chip8);
+            assert_eq!(chip8.memory().read_byte(0x300), 0x40);
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.memory().read_byte(0x301), 0x99);
+
+        list.set_enabled(0, false, &mut chip8);
+        list.apply_frame(&mut chip8);
+        chip8.run_frame().unwrap();
+        assert!(chip8.memory().read_byte(0x300) != 0x40);
+    }
+
+    #[test]
+    fn cheat_files_round_trip() {
+        let text = format!("# lives\n[{}]\non freeze 0x2F0 0x09 Infinite lives\noff patch 0x3A2 0 \n", SHA1.to_uppercase());
+        let file = CheatFile::parse(&text).unwrap();
+        let cheats = file.cheats(SHA1);
+        assert_eq!(cheats.cheats, vec![Cheat::new(CheatKind::Freeze, 0x2F0, 9, "Infinite lives"),
+                                       Cheat {enabled: false, ..Cheat::new(CheatKind::Patch, 0x3A2, 0, "")}]);
+        assert!(file.cheats("ffffffffffffffffffffffffffffffffffffffff").cheats.is_empty());
+
+        let saved = file.to_string();
+        assert_eq!(saved, format!("[{}]\non freeze 0x2F0 0x09 Infinite lives\noff patch 0x3A2 0x00\n\n", SHA1));
+        assert_eq!(CheatFile::parse(&saved).unwrap().cheats(SHA1).cheats, cheats.cheats);
+
+        assert!(CheatFile::parse("on freeze 0x2F0 9").is_err());
+        assert!(CheatFile::parse(&format!("[{}]\non freeze 0x2F0 256", SHA1)).is_err());
+        assert!(CheatFile::parse(&format!("[{}]\nmaybe freeze 0x2F0 1", SHA1)).is_err());
+        assert_eq!(SearchFilter::parse("decreased"), Ok(SearchFilter::Decreased));
+        assert_eq!(SearchFilter::parse("0x10"), Ok(SearchFilter::Value(0x10)));
+        assert!(SearchFilter::parse("sideways").is_err());
+    }
+}
@@ -0,0 +1,223 @@
+// Pausing, stepping and running faster or slower than real time
+//
+// Frontends draw 60 times a second whatever the speed. Before each draw
+// they ask tick what to run: some whole number of frames, one instruction
+// when single stepping, or as many frames as fit before the next draw
+// when uncapped. Slow motion runs a frame on some ticks and none on
+// the rest.
+
+use std::fmt;
+
+// the steps faster and slower go through, in frames per tick
+const SPEEDS: [f64; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+// the fastest speed that can be asked for, "max" goes faster still
+const MAX_SPEED: f64 = 64.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    // frames for every 60Hz tick, below 1 is slow motion
+    Times(f64),
+    // as many frames as the host can run
+    Uncapped,
+}
+
+impl Speed {
+    // a multiplier like 2 or 0.5 up to 64, or "max"
+    pub fn parse(text: &str) -> Result<Speed, String> {
+        match text.trim() {
+            "max" => Ok(Speed::Uncapped),
+            value => match value.parse::<f64>() {
+                Ok(times) if times > MAX_SPEED => Err(format!("speed \"{}\" is faster than {}, use \"max\"", text, MAX_SPEED)),
+                Ok(times) if times > 0.0 => Ok(Speed::Times(times)),
+                _ => Err(format!("invalid speed \"{}\"", text)),
+            },
+        }
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Speed {
+        Speed::Times(1.0)
+    }
+}
+
+// "2X", "1/4X", "1.5X" or "MAX"
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Speed::Uncapped => write!(f, "MAX"),
+            Speed::Times(times) if times < 1.0 && (1.0 / times).fract() == 0.0 => write!(f, "1/{}X", 1.0 / times),
+            Speed::Times(times) => write!(f, "{}X", times),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tick {
+    Frames(u32),
+    Instruction,
+    // frames until it is time to draw
+    Uncapped,
+}
+
+#[derive(Debug, Clone)]
+pub struct RunControl {
+    pub paused: bool,
+    pub speed: Speed,
+    // the speed while fast forward is held
+    pub fast_forward_speed: Speed,
+    pub fast_forwarding: bool,
+    // slow motion and fractional speeds carry part frames to the next tick
+    owed: f64,
+    // a frame or instruction asked for while paused
+    queued: Option<Tick>,
+}
+
+impl RunControl {
+    pub fn new() -> RunControl {
+        RunControl {
+            paused: false,
+            speed: Speed::default(),
+            fast_forward_speed: Speed::Uncapped,
+            fast_forwarding: false,
+            owed: 0.0,
+            queued: None,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.queued = None;
+    }
+
+    // pauses, then runs one frame on the next tick
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.queued = Some(Tick::Frames(1));
+    }
+
+    // pauses, then runs one instruction on the next tick
+    pub fn step_instruction(&mut self) {
+        self.paused = true;
+        self.queued = Some(Tick::Instruction);
+    }
+
+    // the next step up, past the fastest is uncapped
+    pub fn faster(&mut self) {
+        if let Speed::Times(times) = self.speed {
+            self.speed = match SPEEDS.iter().find(|&&speed| speed > times) {
+                Some(&speed) => Speed::Times(speed),
+                None => Speed::Uncapped,
+            };
+        }
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = match self.speed {
+            Speed::Uncapped => Speed::Times(SPEEDS[SPEEDS.len() - 1]),
+            Speed::Times(times) => Speed::Times(SPEEDS.iter().rev().find(|&&speed| speed < times).cloned().unwrap_or(SPEEDS[0])),
+        };
+    }
+
+    pub fn current_speed(&self) -> Speed {
+        if self.fast_forwarding { self.fast_forward_speed } else { self.speed }
+    }
+
+    // what to run before the next draw
+    pub fn tick(&mut self) -> Tick {
+        if self.paused {
+            return self.queued.take().unwrap_or(Tick::Frames(0));
+        }
+        match self.current_speed() {
+            Speed::Uncapped => Tick::Uncapped,
+            Speed::Times(times) => {
+                self.owed += times;
+                let frames = self.owed.floor();
+                self.owed -= frames;
+                Tick::Frames(frames as u32)
+            },
+        }
+    }
+
+    // what to show on screen, nothing at normal speed
+    pub fn indicator(&self) -> Option<String> {
+        if self.paused {
+            return Some(String::from("PAUSED"));
+        }
+        let speed = self.current_speed();
+        if speed == Speed::default() { None } else { Some(format!("SPEED {}", speed)) }
+    }
+}
+
+impl Default for RunControl {
+    fn default() -> RunControl {
+        RunControl::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(control: &mut RunControl, ticks: usize) -> Vec<Tick> {
+        (0..ticks).map(|_| control.tick()).collect()
+    }
+
+    #[test]
+    fn speeds_spread_frames_over_ticks() {
+        let mut control = RunControl::new();
+        assert_eq!(frames(&mut control, 2), vec![Tick::Frames(1), Tick::Frames(1)]);
+        control.speed = Speed::Times(0.5);
+        assert_eq!(frames(&mut control, 4), vec![Tick::Frames(0), Tick::Frames(1), Tick::Frames(0), Tick::Frames(1)]);
+        control.speed = Speed::Times(1.5);
+        assert_eq!(frames(&mut control, 2), vec![Tick::Frames(1), Tick::Frames(2)]);
+        control.fast_forwarding = true;
+        assert_eq!(control.tick(), Tick::Uncapped);
+        control.fast_forward_speed = Speed::Times(4.0);
+        assert_eq!(control.tick(), Tick::Frames(4));
+        assert_eq!(control.indicator(), Some(String::from("SPEED 4X")));
+    }
+
+    #[test]
+    fn paused_runs_only_what_is_asked_for() {
+        let mut control = RunControl::new();
+        control.toggle_pause();
+        assert_eq!(frames(&mut control, 2), vec![Tick::Frames(0), Tick::Frames(0)]);
+        control.advance_frame();
+        control.step_instruction();
+        assert_eq!(frames(&mut control, 2), vec![Tick::Instruction, Tick::Frames(0)]);
+        control.advance_frame();
+        assert_eq!(frames(&mut control, 2), vec![Tick::Frames(1), Tick::Frames(0)]);
+        assert_eq!(control.indicator(), Some(String::from("PAUSED")));
+        control.toggle_pause();
+        assert_eq!(control.tick(), Tick::Frames(1));
+        assert_eq!(control.indicator(), None);
+    }
+
+    #[test]
+    fn faster_and_slower_step_through_the_speeds() {
+        let mut control = RunControl::new();
+        control.slower();
+        control.slower();
+        assert_eq!(control.speed, Speed::Times(0.25));
+        assert_eq!(control.speed.to_string(), "1/4X");
+        for _ in 0..5 {
+            control.faster();
+        }
+        assert_eq!(control.speed, Speed::Times(8.0));
+        control.faster();
+        assert_eq!(control.speed, Speed::Uncapped);
+        control.slower();
+        assert_eq!(control.speed, Speed::Times(8.0));
+
+        assert_eq!(Speed::parse("max"), Ok(Speed::Uncapped));
+        assert_eq!(Speed::parse("1.5").map(|speed| speed.to_string()), Ok(String::from("1.5X")));
+        assert!(Speed::parse("0").is_err());
+        assert!(Speed::parse("fast").is_err());
+        assert_eq!(Speed::parse("64"), Ok(Speed::Times(64.0)));
+        assert!(Speed::parse("65").is_err());
+        assert!(Speed::parse("1e12").is_err());
+        assert!(Speed::parse("inf").is_err());
+        assert!(Speed::parse("NaN").is_err());
+    }
+}
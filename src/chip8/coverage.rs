@@ -0,0 +1,360 @@
+// Code coverage and memory access tracking
+//
+// Every byte of memory is counted as executed when an instruction is
+// fetched from it, read when a sprite is drawn from it or FX65 and LDPAL
+// load it, and written by FX33 and FX55. The accesses are worked out from
+// the instruction and registers just before it runs, so machine code run
+// by SYS is not tracked. Skip instructions are branches, counted by
+// whether the next instruction was skipped.
+//
+// The report is in lcov's tracefile format so genhtml and editors can show
+// it. Without a line map each byte of the rom is a "line" numbered by its
+// address, with a line map the counts are gathered onto source lines.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use chip8::Chip8;
+use chip8::cpu::{decode_for, CpuError, IntermediateAsm};
+use chip8::graphics::Chip8Graphics;
+use chip8::memory::CHIP8_MEMORY_SIZE;
+use chip8::rom::Rom;
+
+// bytes per row of the heatmap, and how many pixels wide each one is
+const HEATMAP_COLUMNS: usize = 64;
+const HEATMAP_SCALE: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Access {
+    pub executed: u64,
+    pub read: u64,
+    pub written: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Branch {
+    pub not_skipped: u64,
+    pub skipped: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    // only the bytes that were touched, MEGA-CHIP memory is 16 MiB
+    bytes: HashMap<u32, Access>,
+    // skip instructions by address
+    branches: HashMap<u16, Branch>,
+    // the skip that ran last, resolved by the next instruction
+    pending_skip: Option<u16>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    // runs a frame, recording every instruction
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Result<(), CpuError> {
+        chip8.run_frame_with(|chip8, pc| self.record(chip8, pc))
+    }
+
+    // records the instruction at pc, which is about to run, for use from
+    // a Chip8::run_frame_with hook
+    pub fn record(&mut self, chip8: &Chip8, pc: u16) {
+        if let Some(skip) = self.pending_skip.take() {
+            let branch = self.branches.entry(skip).or_default();
+            if pc == skip.wrapping_add(2) {
+                branch.not_skipped += 1;
+            } else {
+                branch.skipped += 1;
+            }
+        }
+
+        let memory = chip8.memory();
+        let opcode = ((memory.read_byte(pc) as u16) << 8) | memory.read_byte(pc.wrapping_add(1)) as u16;
+        let instruction = decode_for(chip8.platform(), opcode);
+        let length = match instruction {
+            Some(IntermediateAsm::LOAD_LONG_I {..}) => 4,
+            _ => 2,
+        };
+        for offset in 0..length {
+            self.access(pc as u32 + offset).executed += 1;
+        }
+
+        let cpu = chip8.cpu();
+        let i = cpu.i();
+        match instruction {
+            Some(IntermediateAsm::DRW {nibble, ..}) => {
+                let length = match *chip8.graphics() {
+                    Chip8Graphics::Mono(_) => nibble as u32,
+                    Chip8Graphics::MegaChip(ref display) => (display.sprite_width * display.sprite_height) as u32,
+                };
+                self.read(i, length);
+            },
+            Some(IntermediateAsm::LOAD_REG_ARR {reg_x_index}) => self.read(i, reg_x_index as u32 + 1),
+            Some(IntermediateAsm::LOAD_PALETTE {count}) => self.read(i, count as u32 * 4),
+            Some(IntermediateAsm::STORE_REG_ARR {reg_x_index}) => self.write(i, reg_x_index as u32 + 1),
+            Some(IntermediateAsm::STORE_BCD {..}) => self.write(i, 3),
+            Some(IntermediateAsm::SE_CONST {..}) | Some(IntermediateAsm::SNE_CONST {..}) |
+            Some(IntermediateAsm::SE_REG {..}) | Some(IntermediateAsm::SNE_REG {..}) |
+            Some(IntermediateAsm::SKP {..}) | Some(IntermediateAsm::SKNP {..}) |
+            Some(IntermediateAsm::SKP_KEYPAD2 {..}) | Some(IntermediateAsm::SKNP_KEYPAD2 {..}) =>
+                self.pending_skip = Some(pc),
+            _ => {},
+        }
+    }
+
+    fn access(&mut self, addr: u32) -> &mut Access {
+        self.bytes.entry(addr).or_default()
+    }
+
+    fn read(&mut self, start: u32, length: u32) {
+        for offset in 0..length {
+            self.access(start.wrapping_add(offset)).read += 1;
+        }
+    }
+
+    fn write(&mut self, start: u32, length: u32) {
+        for offset in 0..length {
+            self.access(start.wrapping_add(offset)).written += 1;
+        }
+    }
+
+    pub fn at(&self, addr: u32) -> Access {
+        self.bytes.get(&addr).cloned().unwrap_or_default()
+    }
+
+    pub fn branch(&self, addr: u16) -> Option<Branch> {
+        self.branches.get(&addr).cloned()
+    }
+
+    // An lcov tracefile for the bytes of the rom. Lines count how often
+    // their code ran, or for data how often it was read or written, so
+    // lines left at 0 are dead code or unused data.
+    pub fn lcov(&self, source: &str, rom: &Rom, line_map: Option<&LineMap>) -> String {
+        let start = rom.load_address() as u32;
+        let end = start + rom.data().len() as u32;
+
+        // line -> (executed, data accesses) and the skips on it
+        let mut lines: BTreeMap<u32, (u64, u64)> = BTreeMap::new();
+        let mut branches: BTreeMap<u32, Vec<Branch>> = BTreeMap::new();
+        for addr in start..end {
+            let line = match line_map {
+                Some(map) => match map.line(addr) {
+                    Some(line) => line,
+                    None => continue,
+                },
+                None => addr,
+            };
+            let access = self.at(addr);
+            let counts = lines.entry(line).or_insert((0, 0));
+            counts.0 = counts.0.max(access.executed);
+            counts.1 += access.read + access.written;
+            if let Some(branch) = self.branch(addr as u16) {
+                branches.entry(line).or_default().push(branch);
+            }
+        }
+
+        let source = line_map.and_then(|map| map.source.as_ref()).map(|s| s.as_str()).unwrap_or(source);
+        let mut out = String::new();
+        writeln!(out, "TN:").unwrap();
+        writeln!(out, "SF:{}", source).unwrap();
+        let mut branches_found = 0;
+        let mut branches_hit = 0;
+        for (&line, skips) in branches.iter() {
+            for (block, branch) in skips.iter().enumerate() {
+                for (n, &count) in [branch.not_skipped, branch.skipped].iter().enumerate() {
+                    writeln!(out, "BRDA:{},{},{},{}", line, block, n, count).unwrap();
+                    branches_found += 1;
+                    if count > 0 {
+                        branches_hit += 1;
+                    }
+                }
+            }
+        }
+        writeln!(out, "BRF:{}", branches_found).unwrap();
+        writeln!(out, "BRH:{}", branches_hit).unwrap();
+        let mut lines_hit = 0;
+        for (&line, &(executed, data)) in lines.iter() {
+            let count = if executed > 0 { executed } else { data };
+            if count > 0 {
+                lines_hit += 1;
+            }
+            writeln!(out, "DA:{},{}", line, count).unwrap();
+        }
+        writeln!(out, "LF:{}", lines.len()).unwrap();
+        writeln!(out, "LH:{}", lines_hit).unwrap();
+        writeln!(out, "end_of_record").unwrap();
+        out
+    }
+
+    // The first 4 KiB as RGB pixels, HEATMAP_COLUMNS bytes to a row, green
+    // for executed, blue for read and red for written, brighter the more
+    // often. Returns the width, height and pixels.
+    pub fn heatmap(&self) -> (usize, usize, Vec<u8>) {
+        let rows = CHIP8_MEMORY_SIZE / HEATMAP_COLUMNS;
+        let width = HEATMAP_COLUMNS * HEATMAP_SCALE;
+        let height = rows * HEATMAP_SCALE;
+
+        let most = |count: fn(&Access) -> u64| (0..CHIP8_MEMORY_SIZE as u32).map(|addr| count(&self.at(addr))).max().unwrap_or(0);
+        let most_executed = most(|access| access.executed);
+        let most_read = most(|access| access.read);
+        let most_written = most(|access| access.written);
+
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let addr = ((y / HEATMAP_SCALE) * HEATMAP_COLUMNS + x / HEATMAP_SCALE) as u32;
+                let access = self.at(addr);
+                pixels.push(intensity(access.written, most_written));
+                pixels.push(intensity(access.executed, most_executed));
+                pixels.push(intensity(access.read, most_read));
+            }
+        }
+        (width, height, pixels)
+    }
+}
+
+// Anything touched is at least dim, then brighter on a log scale so a
+// loop run millions of times doesn't wash out code run once
+fn intensity(count: u64, most: u64) -> u8 {
+    if count == 0 {
+        return 0;
+    }
+    let scale = ((count as f64).ln_1p() / (most as f64).ln_1p()).min(1.0);
+    (64.0 + scale * 191.0) as u8
+}
+
+// Addresses of an assembled rom mapped back to its source lines
+//
+// One "ADDRESS LINE" pair per line, in hex with a 0x prefix or decimal,
+// with the address of the first byte assembled from each source line. A
+// "source FILE" line names the source for the report. Blank lines and
+// lines starting with # are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineMap {
+    pub source: Option<String>,
+    lines: BTreeMap<u32, u32>,
+}
+
+impl LineMap {
+    pub fn parse(text: &str) -> Result<LineMap, String> {
+        let mut map = LineMap::default();
+        for (i, raw_line) in text.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(source) = line.strip_prefix("source ") {
+                map.source = Some(source.trim().to_string());
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            match (fields.next().and_then(parse_number), fields.next().and_then(parse_number), fields.next()) {
+                (Some(addr), Some(source_line), None) => {
+                    map.lines.insert(addr, source_line);
+                },
+                _ => return Err(format!("line {}: expected an address and a line number", i + 1)),
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn load(path: &Path) -> io::Result<LineMap> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        LineMap::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // the line of the last address mapped at or before addr
+    pub fn line(&self, addr: u32) -> Option<u32> {
+        self.lines.range(..=addr).next_back().map(|(_, &line)| line)
+    }
+}
+
+// 0x prefixed hex or decimal
+fn parse_number(value: &str) -> Option<u32> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        u32::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse::<u32>().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 200 LD I 20C, 202 DRW V0 V0 2, 204 LD B V0, 206 SE V0 0,
+    // 208 JP 200, 20A JP 20A, 20C sprite, 20E unused
+    fn program() -> Rom {
+        Rom::new(vec![0xA2, 0x0C, 0xD0, 0x02, 0xF0, 0x33, 0x30, 0x00,
+                      0x12, 0x00, 0x12, 0x0A, 0xFF, 0x81, 0x00, 0x00]).unwrap()
+    }
+
+    fn covered(frames: usize) -> (Chip8, Coverage) {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&program()).unwrap();
+        chip8.set_instructions_per_frame(8);
+        let mut coverage = Coverage::new();
+        for _ in 0..frames {
+            coverage.run_frame(&mut chip8).unwrap();
+        }
+        (chip8, coverage)
+    }
+
+    #[test]
+    fn tracks_executed_read_and_written_bytes() {
+        let (chip8, coverage) = covered(1);
+        assert_eq!(coverage.at(0x200).executed, 1);
+        assert_eq!(coverage.at(0x201).executed, 1);
+        assert_eq!(coverage.at(0x208).executed, 0);
+        assert_eq!(coverage.at(0x20B).executed, 4);
+        // the sprite is drawn and then overwritten by LD B
+        assert_eq!(coverage.at(0x20C), Access { executed: 0, read: 1, written: 1 });
+        assert_eq!(coverage.at(0x20E), Access { executed: 0, read: 0, written: 1 });
+        // V0 is 0, so SE skips the jump back
+        assert_eq!(coverage.branch(0x206), Some(Branch { not_skipped: 0, skipped: 1 }));
+        assert_eq!(chip8.cpu().pc(), 0x20A);
+    }
+
+    #[test]
+    fn lcov_lines_and_branches() {
+        let (_, coverage) = covered(1);
+        let lcov = coverage.lcov("game.ch8", &program(), None);
+        assert!(lcov.starts_with("TN:\nSF:game.ch8\n"));
+        assert!(lcov.contains("BRDA:518,0,0,0\nBRDA:518,0,1,1\nBRF:2\nBRH:1\n"));
+        assert!(lcov.contains("DA:512,1\n"));
+        assert!(lcov.contains("DA:520,0\nDA:521,0\nDA:522,4\n"));
+        // the sprite is data, 0x20F is never touched
+        assert!(lcov.contains("DA:524,2\nDA:525,2\nDA:526,1\nDA:527,0\n"));
+        assert!(lcov.ends_with("LF:16\nLH:13\nend_of_record\n"));
+    }
+
+    #[test]
+    fn line_map_gathers_bytes_onto_source_lines() {
+        let map = LineMap::parse("# assembled by hand\nsource game.8o\n0x200 3\n0x202 4\n0x208 7\n0x20C 10\n").unwrap();
+        assert_eq!(map.line(0x1FF), None);
+        assert_eq!(map.line(0x205), Some(4));
+        assert!(LineMap::parse("0x200").is_err());
+
+        let (_, coverage) = covered(1);
+        let lcov = coverage.lcov("game.ch8", &program(), Some(&map));
+        assert!(lcov.starts_with("TN:\nSF:game.8o\nBRDA:4,0,0,0\n"));
+        assert!(lcov.contains("DA:3,1\nDA:4,1\nDA:7,4\nDA:10,5\nLF:4\nLH:4\n"));
+    }
+
+    #[test]
+    fn heatmap_covers_4k() {
+        let (_, coverage) = covered(1);
+        let (width, height, pixels) = coverage.heatmap();
+        assert_eq!((width, height), (256, 256));
+        assert_eq!(pixels.len(), width * height * 3);
+        // 0x20A is row 8, column 10, executed the most
+        let offset = (8 * HEATMAP_SCALE * width + 10 * HEATMAP_SCALE) * 3;
+        assert_eq!(&pixels[offset..offset + 3], &[0, 255, 0]);
+        assert_eq!(&pixels[0..3], &[0, 0, 0]);
+    }
+}
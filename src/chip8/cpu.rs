@@ -1,13 +1,25 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
 use chip8::rand::random;
 
 use super::memory::*;
+use super::quirks::Quirks;
 use super::graphics::*;
 use super::keypad::*;
+use super::cdp1802::*;
+use super::megachip::*;
+use super::platform::Platform;
+use super::state::*;
+use super::font::Font;
+use super::symbols::{self, Symbols};
 use super::Chip8;
 use super::Chip8Bus;
 
-type Opcode = u16;
+pub type Opcode = u16;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IntermediateAsm {
     CLS,
     RET,
@@ -45,16 +57,206 @@ pub enum IntermediateAsm {
     STORE_REG_ARR {reg_x_index: u8},
     LOAD_REG_ARR {reg_x_index: u8},
 
+    // CHIP-8X
+    CYCLE_BACKGROUND,
+    ADD_NIBBLES {reg_x_index: u8, reg_y_index: u8},
+    SET_COLOUR_ZONE {reg_x_index: u8, reg_y_index: u8},
+    SET_COLOUR_ROWS {reg_x_index: u8, reg_y_index: u8, nibble: u8},
+    SKP_KEYPAD2 {reg_x_index: u8},
+    SKNP_KEYPAD2 {reg_x_index: u8},
+    OUTPUT_PORT {reg_x_index: u8},
+    INPUT_PORT {reg_x_index: u8},
+
+    // MEGA-CHIP
+    MEGA_OFF,
+    MEGA_ON,
+    // the low 16 bits of the address are the next word
+    LOAD_LONG_I {high: u8},
+    LOAD_PALETTE {count: u8},
+    SPRITE_WIDTH {width: u8},
+    SPRITE_HEIGHT {height: u8},
+    SET_ALPHA {alpha: u8},
+    PLAY_SAMPLE {nibble: u8},
+    STOP_SAMPLE,
+    BLEND_MODE {nibble: u8},
+    COLLISION_COLOUR {index: u8},
+}
+
+// Disassembly in the usual Cowgod mnemonics
+impl fmt::Display for IntermediateAsm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IntermediateAsm::CLS => write!(f, "CLS"),
+            IntermediateAsm::RET => write!(f, "RET"),
+            IntermediateAsm::SYS {addr} => write!(f, "SYS #{:03X}", addr),
+            IntermediateAsm::JUMP {addr} => write!(f, "JP #{:03X}", addr),
+            IntermediateAsm::CALL {addr} => write!(f, "CALL #{:03X}", addr),
+            IntermediateAsm::SE_CONST {reg_index, constant} => write!(f, "SE V{:X}, #{:02X}", reg_index, constant),
+            IntermediateAsm::SNE_CONST {reg_index, constant} => write!(f, "SNE V{:X}, #{:02X}", reg_index, constant),
+            IntermediateAsm::SE_REG {reg_x_index, reg_y_index} => write!(f, "SE V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::LOAD_CONST {reg_index, constant} => write!(f, "LD V{:X}, #{:02X}", reg_index, constant),
+            IntermediateAsm::ADD_CONST {reg_index, constant} => write!(f, "ADD V{:X}, #{:02X}", reg_index, constant),
+            IntermediateAsm::LOAD_REG {reg_x_index, reg_y_index} => write!(f, "LD V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::OR {reg_x_index, reg_y_index} => write!(f, "OR V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::AND {reg_x_index, reg_y_index} => write!(f, "AND V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::XOR {reg_x_index, reg_y_index} => write!(f, "XOR V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::ADD_REG {reg_x_index, reg_y_index} => write!(f, "ADD V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::SUB_REG {reg_x_index, reg_y_index} => write!(f, "SUB V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::SHR {reg_x_index, reg_y_index} => write!(f, "SHR V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::SUBN {reg_x_index, reg_y_index} => write!(f, "SUBN V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::SHL {reg_x_index, reg_y_index} => write!(f, "SHL V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::SNE_REG {reg_x_index, reg_y_index} => write!(f, "SNE V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::LOAD_ADDR {addr} => write!(f, "LD I, #{:03X}", addr),
+            IntermediateAsm::JUMP_V0 {addr} => write!(f, "JP V0, #{:03X}", addr),
+            IntermediateAsm::RND {reg_x_index, bitmask} => write!(f, "RND V{:X}, #{:02X}", reg_x_index, bitmask),
+            IntermediateAsm::DRW {reg_x_index, reg_y_index, nibble} => write!(f, "DRW V{:X}, V{:X}, {}", reg_x_index, reg_y_index, nibble),
+            IntermediateAsm::SKP {reg_x_index} => write!(f, "SKP V{:X}", reg_x_index),
+            IntermediateAsm::SKNP {reg_x_index} => write!(f, "SKNP V{:X}", reg_x_index),
+            IntermediateAsm::LOAD_DELAY_TIMER {reg_x_index} => write!(f, "LD V{:X}, DT", reg_x_index),
+            IntermediateAsm::WAIT_FOR_KEY_PRESS {reg_x_index} => write!(f, "LD V{:X}, K", reg_x_index),
+            IntermediateAsm::SET_DELAY_TIMER {reg_x_index} => write!(f, "LD DT, V{:X}", reg_x_index),
+            IntermediateAsm::SET_SOUND_TIMER {reg_x_index} => write!(f, "LD ST, V{:X}", reg_x_index),
+            IntermediateAsm::ADD_I {reg_x_index} => write!(f, "ADD I, V{:X}", reg_x_index),
+            IntermediateAsm::LOAD_SPRITE_LOCATION {reg_x_index} => write!(f, "LD F, V{:X}", reg_x_index),
+            IntermediateAsm::STORE_BCD {reg_x_index} => write!(f, "LD B, V{:X}", reg_x_index),
+            IntermediateAsm::STORE_REG_ARR {reg_x_index} => write!(f, "LD [I], V{:X}", reg_x_index),
+            IntermediateAsm::LOAD_REG_ARR {reg_x_index} => write!(f, "LD V{:X}, [I]", reg_x_index),
+            IntermediateAsm::CYCLE_BACKGROUND => write!(f, "BGC"),
+            IntermediateAsm::ADD_NIBBLES {reg_x_index, reg_y_index} => write!(f, "ADDN V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::SET_COLOUR_ZONE {reg_x_index, reg_y_index} => write!(f, "COL V{:X}, V{:X}", reg_x_index, reg_y_index),
+            IntermediateAsm::SET_COLOUR_ROWS {reg_x_index, reg_y_index, nibble} => write!(f, "COL V{:X}, V{:X}, {}", reg_x_index, reg_y_index, nibble),
+            IntermediateAsm::SKP_KEYPAD2 {reg_x_index} => write!(f, "SKP2 V{:X}", reg_x_index),
+            IntermediateAsm::SKNP_KEYPAD2 {reg_x_index} => write!(f, "SKNP2 V{:X}", reg_x_index),
+            IntermediateAsm::OUTPUT_PORT {reg_x_index} => write!(f, "OUT V{:X}", reg_x_index),
+            IntermediateAsm::INPUT_PORT {reg_x_index} => write!(f, "IN V{:X}", reg_x_index),
+            IntermediateAsm::MEGA_OFF => write!(f, "MEGAOFF"),
+            IntermediateAsm::MEGA_ON => write!(f, "MEGAON"),
+            IntermediateAsm::LOAD_LONG_I {high} => write!(f, "LDHI I, #{:02X}....", high),
+            IntermediateAsm::LOAD_PALETTE {count} => write!(f, "LDPAL {}", count),
+            IntermediateAsm::SPRITE_WIDTH {width} => write!(f, "SPRW {}", width),
+            IntermediateAsm::SPRITE_HEIGHT {height} => write!(f, "SPRH {}", height),
+            IntermediateAsm::SET_ALPHA {alpha} => write!(f, "ALPHA #{:02X}", alpha),
+            IntermediateAsm::PLAY_SAMPLE {nibble} => write!(f, "DIGISND {}", nibble),
+            IntermediateAsm::STOP_SAMPLE => write!(f, "STOPSND"),
+            IntermediateAsm::BLEND_MODE {nibble} => write!(f, "BMODE {}", nibble),
+            IntermediateAsm::COLLISION_COLOUR {index} => write!(f, "CCOL {}", index),
+        }
+    }
+}
+
+// Something a program did that the machine can't carry on from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    IllegalInstruction(Opcode),
+    // RET with nothing on the stack
+    StackUnderflow,
+    // CALL with the stack full
+    StackOverflow,
+    // 0NNN without an RCA 1802 to run the machine code
+    MachineCode(u16),
+    // machine code at the address that never went back to the interpreter
+    MachineCodeRunaway(u16),
 }
 
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::IllegalInstruction(opcode) => write!(f, "{:04X} is not a Chip-8 instruction", opcode),
+            Fault::StackUnderflow => write!(f, "returned with an empty stack"),
+            Fault::StackOverflow => write!(f, "called a subroutine with a full stack"),
+            Fault::MachineCode(addr) => write!(f, "called machine code at {:03X} without the RCA 1802", addr),
+            Fault::MachineCodeRunaway(addr) => write!(f, "machine code called at {:03X} did not return to the interpreter", addr),
+        }
+    }
+}
 
-#[derive(Debug)]
+// A fault and where the program was when it happened. The CPU is left
+// at the instruction that faulted, except after runaway machine code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuError {
+    pub fault: Fault,
+    // the program counter and call stack, labelled when there are symbols
+    pub location: String,
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n{}", self.fault, self.location)
+    }
+}
+
+impl Error for CpuError {}
+
+
+impl IntermediateAsm {
+    // the address a jump, call or LD I refers to
+    pub fn target(&self) -> Option<u16> {
+        match *self {
+            IntermediateAsm::SYS {addr} | IntermediateAsm::JUMP {addr} | IntermediateAsm::CALL {addr} |
+            IntermediateAsm::LOAD_ADDR {addr} | IntermediateAsm::JUMP_V0 {addr} => Some(addr),
+            _ => None,
+        }
+    }
+
+    // Machine cycles the instruction takes in the COSMAC VIP interpreter
+    //
+    // Approximations of the measured times, averaged where they depend on
+    // the data, e.g. whether a skip is taken or a sprite is byte aligned.
+    // The 40 cycles the interpreter spends fetching and decoding are
+    // included. DXYN doesn't include the wait for the display interrupt.
+    pub fn vip_cycles(&self) -> u32 {
+        const FETCH: u32 = 40;
+        FETCH + match *self {
+            IntermediateAsm::CLS => 1000,
+            IntermediateAsm::RET => 10,
+            IntermediateAsm::SYS {..} => 10,
+            IntermediateAsm::JUMP {..} => 12,
+            IntermediateAsm::CALL {..} => 26,
+            IntermediateAsm::SE_CONST {..} | IntermediateAsm::SNE_CONST {..} => 10,
+            IntermediateAsm::SE_REG {..} | IntermediateAsm::SNE_REG {..} => 14,
+            IntermediateAsm::LOAD_CONST {..} => 6,
+            IntermediateAsm::ADD_CONST {..} => 10,
+            IntermediateAsm::LOAD_REG {..} | IntermediateAsm::OR {..} | IntermediateAsm::AND {..}
+                | IntermediateAsm::XOR {..} | IntermediateAsm::ADD_REG {..} | IntermediateAsm::SUB_REG {..}
+                | IntermediateAsm::SHR {..} | IntermediateAsm::SUBN {..} | IntermediateAsm::SHL {..} => 44,
+            IntermediateAsm::LOAD_ADDR {..} => 12,
+            IntermediateAsm::JUMP_V0 {..} => 22,
+            IntermediateAsm::RND {..} => 36,
+            IntermediateAsm::DRW {nibble, ..} => 26 + nibble as u32 * 34,
+            IntermediateAsm::SKP {..} | IntermediateAsm::SKNP {..} => 14,
+            IntermediateAsm::LOAD_DELAY_TIMER {..} => 10,
+            IntermediateAsm::WAIT_FOR_KEY_PRESS {..} => 14,
+            IntermediateAsm::SET_DELAY_TIMER {..} | IntermediateAsm::SET_SOUND_TIMER {..} => 10,
+            IntermediateAsm::ADD_I {..} => 18,
+            IntermediateAsm::LOAD_SPRITE_LOCATION {..} => 20,
+            IntermediateAsm::STORE_BCD {..} => 164,
+            IntermediateAsm::STORE_REG_ARR {reg_x_index} | IntermediateAsm::LOAD_REG_ARR {reg_x_index} =>
+                14 + (reg_x_index as u32 + 1) * 14,
+            IntermediateAsm::CYCLE_BACKGROUND => 20,
+            IntermediateAsm::ADD_NIBBLES {..} => 30,
+            IntermediateAsm::SET_COLOUR_ZONE {..} => 100,
+            IntermediateAsm::SET_COLOUR_ROWS {nibble, ..} => 20 + nibble as u32 * 8,
+            IntermediateAsm::SKP_KEYPAD2 {..} | IntermediateAsm::SKNP_KEYPAD2 {..} => 14,
+            IntermediateAsm::OUTPUT_PORT {..} | IntermediateAsm::INPUT_PORT {..} => 10,
+            // the MEGA-CHIP never ran on a VIP, these are guesses
+            IntermediateAsm::LOAD_LONG_I {..} => 24,
+            IntermediateAsm::MEGA_OFF | IntermediateAsm::MEGA_ON | IntermediateAsm::LOAD_PALETTE {..}
+                | IntermediateAsm::SPRITE_WIDTH {..} | IntermediateAsm::SPRITE_HEIGHT {..}
+                | IntermediateAsm::SET_ALPHA {..} | IntermediateAsm::PLAY_SAMPLE {..}
+                | IntermediateAsm::STOP_SAMPLE | IntermediateAsm::BLEND_MODE {..}
+                | IntermediateAsm::COLLISION_COLOUR {..} => 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Chip8CPU {
     // general purpose registers
-    pub reg_gp: Box<[u8]>,
+    pub reg_gp: [u8; 16],
 
-    // Usually used to store memory addresses
-    reg_i: u16,
+    // Usually used to store memory addresses, 16 bits wide but 24 on
+    // the MEGA-CHIP
+    reg_i: u32,
 
     // Special 8-bit registers
     reg_delay: u8,
@@ -66,10 +268,16 @@ pub struct Chip8CPU {
     // Stack Pointer
     reg_sp: u16,
     // Stack
-    stack: Box<[u16]>,
+    stack: [u16; 16],
 
-    // Flag to denote if the timer needs to be updated
-    timer_update_flag: u8,
+    // Interpreter differences this cpu should follow
+    pub quirks: Quirks,
+    // picks the instruction set
+    pub platform: Platform,
+
+    // set by DXYN when the display wait quirk is on, the rest of the
+    // frame is skipped
+    pub waiting_for_vblank: bool,
 
     // a flag to halt operation until a event that wakes the
     // cpu (i.e. keydown)
@@ -78,13 +286,24 @@ pub struct Chip8CPU {
 
     pub draw_to_screen_flag: bool,
 
+    // runs the machine code 0NNN calls, without it SYS faults
+    pub rca1802: Option<Cdp1802>,
+    // machine cycles the last SYS spent in machine code
+    pub machine_code_cycles: u32,
+
+    // labels for the addresses in error messages
+    pub symbols: Option<Arc<Symbols>>,
+
+    // what FX29 points at
+    // shared between clones, it only changes on a hard reset
+    pub font: Arc<Font>,
 }
 
 
 impl Chip8CPU {
     pub fn new() -> Chip8CPU {
         Chip8CPU {
-            reg_gp: vec![0; 16 as usize].into_boxed_slice(),
+            reg_gp: [0; 16],
             reg_i: 0,
 
             reg_delay: 0,
@@ -93,9 +312,12 @@ impl Chip8CPU {
             reg_pc: 0x200,
 
             reg_sp: 0,
-            stack: vec![0; 16 as usize].into_boxed_slice(),
+            stack: [0; 16],
 
-            timer_update_flag: 9,
+            quirks: Quirks::default(),
+            platform: Platform::default(),
+
+            waiting_for_vblank: false,
 
             is_halted_flag: false,
 
@@ -103,831 +325,1991 @@ impl Chip8CPU {
 
             draw_to_screen_flag: true,
 
+            rca1802: None,
+            machine_code_cycles: 0,
+
+            symbols: None,
 
+            font: Arc::new(Font::default()),
         }
     }
 
-    pub fn step(&mut self, bus_ref: &mut Chip8Bus) 
-    {
-        let opcode = self.fetch_opcode(&mut bus_ref.memory);
-        let instruction = self.decode_opcode(&mut bus_ref.memory, opcode);
-        self.execute_opcode(bus_ref, instruction);
-        self.update_timer();
+    // back to how the cpu powers on, keeping the quirks, platform, 1802,
+    // symbols and font
+    pub fn reset(&mut self) {
+        *self = Chip8CPU {
+            quirks: self.quirks,
+            platform: self.platform,
+            rca1802: self.rca1802.take(),
+            symbols: self.symbols.take(),
+            font: ::std::mem::take(&mut self.font),
+            ..Chip8CPU::new()
+        };
     }
 
-    pub fn fetch_opcode(&mut self, memory_ref: &mut Chip8Memory) -> Opcode {
-        let mut opcode: Opcode = 0x0;
-        opcode = memory_ref.read_byte(self.reg_pc) as u16;
-        opcode = (opcode << 8) | memory_ref.read_byte(self.reg_pc + 1) as u16;
-        println!("{:x}", opcode);
-        opcode
+    pub fn pc(&self) -> u16 {
+        self.reg_pc
     }
 
-    pub fn decode_opcode(&mut self, memory_ref: &mut Chip8Memory, opcode: Opcode) -> IntermediateAsm {
-        let lowest_4_bits: u8 = (opcode & 0xF) as u8;
-        let middle_lower_4_bits: u8 = ((opcode >> 4 ) & 0xF) as u8;
-        let middle_upper_4_bits: u8 = ((opcode >> 8 ) & 0xF) as u8;
-        let highest_4_bits: u8 = ((opcode >> 12 ) & 0xF) as u8;
-        match (highest_4_bits, 
-               middle_upper_4_bits, 
-               middle_lower_4_bits, 
-               lowest_4_bits)
-        {
-            (0x0, 0x0, 0xE, 0x0) => {
-                // Opcode: 00E0
-                // Type: Display
-                // C Psuedo:
-                // disp_clear();
-                // TODO Clear the screen
-                println!("CLS");
-                IntermediateAsm::CLS
-            },
-            (0x0, 0x0, 0xE, 0xE) => {
-                // Opcode: 00EE
-                // Type: Flow
-                // C Psuedo:
-                // return;
-                // TODO Return from a subroutine
-                println!("RET");
-                IntermediateAsm::RET
-            },
-            (0x0, N1, N2, N3) => {
-                // Opcode: 00EE
-                // Type: Call
-                // C Psuedo:
-                // Not Applicable
-                // TODO Call RCA 1802 program at address N1N2N3
-                println!("SYS");
-                IntermediateAsm::SYS {addr: opcode & 0x0fff}
-            },
-            (0x1, N1, N2, N3) => {
-                // Opcode: 1NNN
-                // Type: Flow
-                // C Psuedo:
-                // goto NNN;
-                // TODO jump to address N1N2N3
-                let addr = opcode & 0x0fff;
-                println!("JUMP {:x}", addr);
-                IntermediateAsm::JUMP {addr: addr}
-            },
-            (0x2, N1, N2, N3) => {
-                // Opcode: 2NNN
-                // Type: Flow
-                // C Psuedo:
-                // *(0xNNN)()
-                // TODO Call subroutine at N1N2N3
-                let addr = opcode & 0x0fff;
-                println!("CALL {:x}", addr);
-                IntermediateAsm::CALL {addr: addr}
-
-            },
-            (0x3, X, N1, N2) => {
-                // Opcode: 3XNN
-                // Type: Cond
-                // C Psuedo:
-                // if(Vx==NN)
-                // TODO Skip the next instruction if register VX equals NN.
-                let reg_index = ((opcode & 0x0f00) >> 8) as u8 ; 
-                let constant = (opcode & 0x00ff) as u8;
-                println!("SE_CONST X{:?}, {:?}", reg_index, constant);
-                IntermediateAsm::SE_CONST {
-                    reg_index: reg_index,
-                    constant: constant
-                }
-            },
-            (0x4, X, N1, N2) => {
-                // Opcode: 4XNN
-                // Type: Cond
-                // C Psuedo:
-                // if(Vx!=NN)
-                // TODO Skip the next instruction if register VX does not equal NN.
-                let reg_index = ((opcode & 0x0f00) >> 8) as u8;
-                let constant = (opcode & 0x00ff) as u8;
-                println!("SNE_CONST X{:?}, {:?}", reg_index, constant);
-                IntermediateAsm::SNE_CONST {
-                    reg_index: reg_index,
-                    constant: constant,
-                }
-            }, 
-            (0x5, X, Y, 0x0) => {
-                // Opcode: 5XY0
-                // Type: Cond
-                // C Psuedo:
-                // if(Vx==Vy)
-                // TODO Skip the next instruction if register VX does not 
-                // equal register VY.
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("SE_REG X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::SE_REG {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-            }, 
-            (0x6, X, N1, N2) => {
-                // Opcode: 6XNN
-                // Type: Const
-                // C Psuedo:
-                // Vx = NN
-                // TODO Set register VX to NN
-                let reg_index = ((opcode & 0x0f00) >> 8) as u8;
-                let constant = (opcode & 0x00ff) as u8;
-                println!("LOAD_CONST X{:?}, {:?}", reg_index, constant);
-                IntermediateAsm::LOAD_CONST {
-                    reg_index: reg_index,
-                    constant: constant,
-                }
-            },
-            (0x7, X, N1, N2) => {
-                // Opcode: 7XNN
-                // Type: Const
-                // C Psuedo:
-                // Vx += NN
-                // TODO Add NN to Vx (carry flag is not changed)
-                let reg_index = ((opcode & 0x0f00) >> 8) as u8;
-                let constant = (opcode & 0x00ff) as u8;
-                println!("ADD_CONST X{:?}, {:?}", reg_index, constant);
-                IntermediateAsm::ADD_CONST {
-                    reg_index: reg_index,
-                    constant: constant,
-                }
-            }, 
-            (0x8, X, Y, 0x0) => {
-                // Opcode: 8XY0
-                // Type: Assign
-                // C Psuedo:
-                // Vx = Vy
-                // TODO set register Vx to the value in register Vy
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("LOAD_REG X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::LOAD_REG {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-
-            },
-            (0x8, X, Y, 0x1) => {
-                // Opcode: 8XY1
-                // Type: BitOp
-                // C Psuedo:
-                // Vx=Vx|Vy
-                // TODO Set register Vx to Vx | Vy 
-                // (bitwise OR)
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("OR X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::OR {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-
-            },
-            (0x8, X, Y, 0x2) => {
-                // Opcode: 8XY2
-                // Type: BitOp
-                // C Psuedo:
-                // Vx=Vx&Vy
-                // TODO Set register Vx to Vx & Vy 
-                // (bitwise AND)
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("AND X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::AND {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-            },
-            (0x8, X, Y, 0x3) => {
-                // Opcode: 8XY3
-                // Type: BitOp
-                // C Psuedo:
-                // Vx=Vx^Vy
-                // TODO Set register Vx to Vx ^ Vy 
-                // (bitwise XOR)
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("XOR X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::XOR {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-
-            },
-            (0x8, X, Y, 0x4) => {
-                // Opcode: 8XY4
-                // Type: Math
-                // C Psuedo:
-                // Vx+=Vy
-                // TODO Set register Vx to Vx + Vy 
-                // If there is a carry, set register VF to 1
-                // else, set register VF to 0
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("ADD_REG X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::ADD_REG {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-            },
-            (0x8, X, Y, 0x5) => {
-                // Opcode: 8XY5
-                // Type: Math
-                // C Psuedo:
-                // Vx-=Vy
-                // TODO Set register Vx to Vx - Vy 
-                // If there is a borrow, set register VF to 0
-                // else, set register VF to 1
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("SUB_REG X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::SUB_REG {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-
-            },
-            (0x8, X, Y, 0x6) => {
-                // Opcode: 8XY6
-                // Type: BitOp 
-                // C Psuedo:
-                // Vx=Vy=Vy>>1
-                // TODO Shift Vy right by one and copy the result
-                // to Vx.
-                // VF is set to the value of the least significant bit
-                // of Vy before the shift
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("SHR X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::SHR {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-
-            },
-            (0x8, X, Y, 0x7) => {
-                // Opcode: 8XY7
-                // Type: Math
-                // C Psuedo:
-                // Vx=Vy-Vx
-                // TODO Set Vx to Vy minux Vx. VF is set to 0 when there
-                // is a borrow and 1 when there isn't
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("SUBN X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::SUBN {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-
-            },
-            (0x8, X, Y, 0xE) => {
-                // Opcode: 8XYE
-                // Type: BitOp
-                // C Psuedo:
-                // Vx=Vy=Vy<<1
-                // TODO Shift Vy left by one and copy the result to Vx.
-                // Set VF to the value of the most significant bit
-                // of Vy before the shift
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("SHL X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::SHL {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-            },
-            (0x9, X, Y, 0) => {
-                // Opcode: 9XY0
-                // Type: Cond
-                // C Psuedo:
-                // if(Vx != Vy)
-                // TODO Skip the next instruction if Vx doesn't euqal Vy.
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                println!("SNE_REG X{:?}, X{:?}", reg_x_index, reg_y_index);
-                IntermediateAsm::SNE_REG {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                }
-            },
-            (0xA, N1, N2, N3) => {
-                // Opcode: ANNN
-                // Type: MEM
-                // C Psuedo:
-                // I=NNN
-                // TODO set reg I to the address NNN
-                let addr = opcode & 0x0fff;
-                println!("LOAD_ADDR {:x}", addr);
-                IntermediateAsm::LOAD_ADDR {addr: addr}
-            },
-            (0xB, N1, N2, N3) => {
-                // Opcode: BNNN
-                // Type: Flow
-                // C Psuedo:
-                // PC=V0+NNN
-                // TODO jump to the address NNN plus V0
-                let addr = opcode & 0x0fff;
-                println!("JUMP_V0 {:x}", addr);
-                IntermediateAsm::JUMP_V0 {addr: addr}
-            },
-            (0xC, X, N1, N2) => {
-                // Opcode: CXNN
-                // Type: Rand
-                // C Psuedo:
-                // Vx=rand()&NN
-                // TODO set Vx to a random number (typically 0 to 255) 
-                // that is bitwise and'd with NN
-                let reg_index = ((opcode & 0x0f00) >> 8) as u8;
-                let constant = (opcode & 0x00ff) as u8;
-                println!("RND X{:?}, {:?}", reg_index, constant);
-                IntermediateAsm::RND {
-                    reg_x_index: reg_index,
-                    bitmask: constant
-                }
-            },
-            (0xD, X, Y, N) => {
-                // Opcode: DXYN
-                // Type: Disp
-                // C Psuedo:
-                // draw(Vx,Vy,N)
-                // TODO Draws a sprite at coordinate (VX, VY) that has 
-                // a width of 8 pixels and a height of N pixels. 
-                // Each row of 8 pixels is read as bit-coded starting 
-                // from memory location I; I value doesn’t change after 
-                // the execution of this instruction. 
-                // As described above, VF is set to 1 if any screen pixels 
-                // are flipped from set to unset when the sprite is drawn, 
-                // and to 0 if that doesn’t happen
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
-                let nibble = (opcode & 0x000f) as u8;
-                println!("DRW X{:?}, X{:?}, n: {:?}", reg_x_index, reg_y_index, nibble);
-                IntermediateAsm::DRW {
-                    reg_x_index: reg_x_index,
-                    reg_y_index: reg_y_index,
-                    nibble: nibble,
-                }
+    pub fn set_pc(&mut self, addr: u16) {
+        self.reg_pc = addr;
+    }
 
-            },
-            (0xE, X, 0x9, 0xE) => {
-                // Opcode: EX9E
-                // Type: KeyOp
-                // C Psuedo:
-                // if(key()==Vx)
-                // TODO Skips the next instruction if the key stored in 
-                // VX is pressed.
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("SKP X{:?}", reg_x_index);
-                IntermediateAsm::SKP {
-                    reg_x_index: reg_x_index,
-                }
+    pub fn i(&self) -> u32 {
+        self.reg_i
+    }
 
-            },
-            (0xE, X, 0xA, 0x1) => {
-                // Opcode: EXA1
-                // Type: KeyOp
-                // C Psuedo:
-                // if(key()!=Vx)
-                // TODO Skips the next instruction if the key stored in 
-                // VX isn't pressed.
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("SKNP X{:?}", reg_x_index);
-                IntermediateAsm::SKNP {
-                    reg_x_index: reg_x_index,
-                }
+    pub fn set_i(&mut self, addr: u32) {
+        let mask = if self.platform == Platform::MegaChip { 0xFFFFFF } else { 0xFFFF };
+        self.reg_i = addr & mask;
+    }
 
-            },
-            (0xF, X, 0x0, 0x7) => {
-                // Opcode: FX07
-                // Type: Timer
-                // C Psuedo:
-                // Vx = get_delay()
-                // TODO Sets Vx to the value of the delay timer
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("LOAD_DELAY_TIMER X{:?}", reg_x_index);
-                IntermediateAsm::LOAD_DELAY_TIMER {
-                    reg_x_index: reg_x_index,
-                }
+    pub fn sp(&self) -> u16 {
+        self.reg_sp
+    }
 
-            },
-            (0xF, X, 0x0, 0xA) => {
-                // Opcode: FX0A
-                // Type: KeyOp
-                // C Psuedo:
-                // Vx = get_key()
-                // TODO A key press is awaited, and then stored in VX. 
-                // NOTE!!! Blocking Operation. 
-                // All instruction halted until next key event
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("WAIT_FOR_KEY_PRESS X{:?}", reg_x_index);
-                IntermediateAsm::WAIT_FOR_KEY_PRESS {
-                    reg_x_index: reg_x_index,
-                }
+    pub fn v(&self, index: usize) -> u8 {
+        self.reg_gp[index]
+    }
 
-            },
-            (0xF, X, 0x1, 0x5) => {
-                // Opcode: FX15
-                // Type: Timer
-                // C Psuedo:
-                // delay_timer(Vx)
-                // TODO set delay timer to Vx
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("SET_DELAY_TIMER X{:?}", reg_x_index);
-                IntermediateAsm::SET_DELAY_TIMER {
-                    reg_x_index: reg_x_index,
-                }
+    pub fn delay_timer(&self) -> u8 {
+        self.reg_delay
+    }
 
-            },
-            (0xF, X, 0x1, 0x8) => {
-                // Opcode: FX18
-                // Type: Sound
-                // C Psuedo:
-                // sound_timer(Vx)
-                // TODO set sound timer to Vx
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("SET_SOUND_TIMER X{:?}", reg_x_index);
-                IntermediateAsm::SET_SOUND_TIMER {
-                    reg_x_index: reg_x_index,
-                }
+    pub fn sound_timer(&self) -> u8 {
+        self.reg_sound
+    }
 
-            },
-            (0xF, X, 0x1, 0xE) => {
-                // Opcode: FX1E
-                // Type: MEM
-                // C Psuedo:
-                // I += Vx
-                // TODO Add Vx to I
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("ADD_I X{:?}", reg_x_index);
-                IntermediateAsm::ADD_I {
-                    reg_x_index: reg_x_index,
-                }
+    pub fn set_timers(&mut self, delay: u8, sound: u8) {
+        self.reg_delay = delay;
+        self.reg_sound = sound;
+    }
 
-            },
-            (0xF, X, 0x2, 0x9) => {
-                // Opcode: FX29
-                // Type: MEM
-                // C Psuedo:
-                // I=sprite_addr[Vx]
-                // TODO Sets I to the location of the sprite for the 
-                // character in VX. Characters 0-F (in hexadecimal) 
-                // are represented by a 4x5 font.
-                //
-                // TODO Do more research here
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("LOAD_SPRITE_LOCATION X{:?}", reg_x_index);
-                IntermediateAsm::LOAD_SPRITE_LOCATION {
-                    reg_x_index: reg_x_index,
-                }
+    // return addresses currently on the stack, oldest first
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.reg_sp as usize]
+    }
 
-            },
-            (0xF, X, 0x3, 0x3) => {
-                // Opcode: FX33
-                // Type: BCD
-                // C Psuedo:
-                // set_BCD(Vx);
-                // *(I+0)=BCD(3);
-                // *(I+1)=BCD(2);
-                // *(I+2)=BCD(1); 
-                //
-                // TODO Stores the binary-coded decimal representation of VX, 
-                // with the most significant of three digits at the address 
-                // in I, the middle digit at I plus 1, and the least 
-                // significant digit at I plus 2. 
-                // (In other words, take the decimal representation of VX, 
-                // place the hundreds digit in memory at location in I, 
-                // the tens digit at location I+1, and the ones digit at 
-                // location I+2.)
-                //
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("STORE_BCD X{:?}", reg_x_index);
-                IntermediateAsm::STORE_BCD {
-                    reg_x_index: reg_x_index,
-                }
+    // the program counter and call stack, labelled when there are symbols
+    fn location(&self) -> String {
+        let symbols = self.symbols.as_deref();
+        format!("At: {}\nCall stack: {}", symbols::describe(symbols, self.reg_pc as u32),
+                symbols::call_stack(self, symbols).join(" <- "))
+    }
 
-            },
-            (0xF, X, 0x5, 0x5) => {
-                // Opcode: FX55
-                // Type: MEM
-                // C Psuedo:
-                // reg_dump(Vx,&I)
-                // TODO Stores V0 to VX (including VX) in memory starting 
-                // at address I. I is increased by 1 for each value written.
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("STORE_REG_ARR X{:?}", reg_x_index);
-                IntermediateAsm::STORE_REG_ARR {
-                    reg_x_index: reg_x_index,
-                }
+    fn fault(&self, fault: Fault) -> CpuError {
+        CpuError {fault, location: self.location()}
+    }
 
-            },
-            (0xF, X, 0x6, 0x5) => {
-                // Opcode: FX65
-                // Type: MEM
-                // C Psuedo:
-                // reg_load(Vx,&I)
-                // TODO Fills V0 to VX (including VX) with values from 
-                // memory starting at address I. I is increased by 1 for 
-                // each value written. 
-                let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
-                println!("LOAD_REG_ARR X{:?}", reg_x_index);
-                IntermediateAsm::LOAD_REG_ARR {
-                    reg_x_index: reg_x_index,
-                }
+    // runs one instruction and returns it
+    pub fn step(&mut self, bus_ref: &mut Chip8Bus) -> Result<IntermediateAsm, CpuError>
+    {
+        let opcode = self.fetch_opcode(&mut bus_ref.memory);
+        let instruction = self.decode_opcode(&mut bus_ref.memory, opcode)?;
+        self.execute_opcode(bus_ref, instruction)?;
+        Ok(instruction)
+    }
 
-            },
+    pub fn fetch_opcode(&mut self, memory_ref: &mut Chip8Memory) -> Opcode {
+        let mut opcode: Opcode = 0x0;
+        opcode = memory_ref.read_byte(self.reg_pc) as u16;
+        opcode = (opcode << 8) | memory_ref.read_byte(self.reg_pc.wrapping_add(1)) as u16;
+        opcode
+    }
 
-            // TODO add error passing to print full debug info on failiure
-            _ => panic!("Error: Illegal Instruction: {:x} is not a Chip-8 Instruction. \n {:?}", opcode, self)
-        }
+    pub fn decode_opcode(&mut self, memory_ref: &mut Chip8Memory, opcode: Opcode) -> Result<IntermediateAsm, CpuError> {
+        decode_for(self.platform, opcode).ok_or_else(|| self.fault(Fault::IllegalInstruction(opcode)))
     }
 
-    pub fn execute_opcode(&mut self, bus_ref: &mut Chip8Bus, instruction: IntermediateAsm) {
+    pub fn execute_opcode(&mut self, bus_ref: &mut Chip8Bus, instruction: IntermediateAsm) -> Result<(), CpuError> {
         match instruction {
             IntermediateAsm::CLS => {
+                // on the MEGA-CHIP display this also shows what was drawn
                 bus_ref.graphics.clear();
                 self.draw_to_screen_flag = true;
                 // TODO check this is the proper program counter advancement
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::RET => {
                 if self.reg_sp == 0 {
-                    panic!("Error: Attempted to return with no return with a empty stack\nCPU State: \n{:?}", self);
+                    return Err(self.fault(Fault::StackUnderflow));
                 }
-                // TODO do return
-                self.reg_pc = (*self.stack)[self.reg_sp as usize];
                 self.reg_sp = self.reg_sp - 1;
+                self.reg_pc = self.stack[self.reg_sp as usize];
             },
             IntermediateAsm::SYS { addr } => {
-                panic!("Error: Unimplemented Instruction \nAttempted to call SYS instruction. \nCPU State: \n{:?}", self);
+                let mut cdp1802 = match self.rca1802.take() {
+                    Some(cdp1802) => cdp1802,
+                    None => return Err(self.fault(Fault::MachineCode(addr))),
+                };
+                let cycles = self.call_machine_code(&mut cdp1802, bus_ref, addr);
+                self.rca1802 = Some(cdp1802);
+                self.machine_code_cycles = cycles?;
             },
             IntermediateAsm::JUMP { addr } => {
                 self.reg_pc = addr;
             },
             IntermediateAsm::CALL { addr } => {
+                if self.reg_sp as usize == self.stack.len() {
+                    return Err(self.fault(Fault::StackOverflow));
+                }
+                self.stack[self.reg_sp as usize] = self.reg_pc.wrapping_add(2);
                 self.reg_sp = self.reg_sp + 1;
-                (*self.stack)[self.reg_sp as usize] = self.reg_pc + 2;
                 self.reg_pc = addr;
             },
             IntermediateAsm::SE_CONST { reg_index, constant } => {
-                if (*self.reg_gp)[reg_index as usize] == constant  {
-                    self.reg_pc = self.reg_pc + 4;
+                if self.reg_gp[reg_index as usize] == constant  {
+                    self.reg_pc = self.reg_pc.wrapping_add(4);
                 } else {
-                    self.reg_pc = self.reg_pc + 2;
+                    self.reg_pc = self.reg_pc.wrapping_add(2);
                 }
             },
             IntermediateAsm::SNE_CONST {reg_index, constant} => {
-                if (*self.reg_gp)[reg_index as usize] == constant {
-                    self.reg_pc = self.reg_pc + 2;
+                if self.reg_gp[reg_index as usize] == constant {
+                    self.reg_pc = self.reg_pc.wrapping_add(2);
                 } else {
-                    self.reg_pc = self.reg_pc + 4;
+                    self.reg_pc = self.reg_pc.wrapping_add(4);
                 }
             },
             IntermediateAsm::SE_REG { reg_x_index, reg_y_index } => {
-                if (*self.reg_gp)[reg_x_index as usize] 
-                        == (*self.reg_gp)[reg_y_index as usize]  {
-                    self.reg_pc = self.reg_pc + 4;
+                if self.reg_gp[reg_x_index as usize] 
+                        == self.reg_gp[reg_y_index as usize]  {
+                    self.reg_pc = self.reg_pc.wrapping_add(4);
                 } else {
-                    self.reg_pc = self.reg_pc + 2;
+                    self.reg_pc = self.reg_pc.wrapping_add(2);
                 }
             },
             IntermediateAsm::LOAD_CONST {reg_index, constant} => {
-                (*self.reg_gp)[reg_index as usize] = constant;
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_gp[reg_index as usize] = constant;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::ADD_CONST {reg_index, constant} => {
-                (*self.reg_gp)[reg_index as usize] = ((*self.reg_gp)[reg_index as usize] as u16 + constant as u16) as u8;
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_gp[reg_index as usize] = (self.reg_gp[reg_index as usize] as u16 + constant as u16) as u8;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::LOAD_REG {reg_x_index, reg_y_index} => {
-                (*self.reg_gp)[reg_x_index as usize] = (*self.reg_gp)[reg_y_index as usize];
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_gp[reg_x_index as usize] = self.reg_gp[reg_y_index as usize];
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::OR {reg_x_index, reg_y_index} => {
-                (*self.reg_gp)[reg_x_index as usize] = (*self.reg_gp)[reg_y_index as usize] 
-                                                | (*self.reg_gp)[reg_x_index as usize];
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_gp[reg_x_index as usize] = self.reg_gp[reg_y_index as usize] 
+                                                | self.reg_gp[reg_x_index as usize];
+                if self.quirks.vf_reset {
+                    self.reg_gp[0xf] = 0;
+                }
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::AND {reg_x_index, reg_y_index} => {
-                (*self.reg_gp)[reg_x_index as usize] = (*self.reg_gp)[reg_y_index as usize] 
-                                                & (*self.reg_gp)[reg_x_index as usize];
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_gp[reg_x_index as usize] = self.reg_gp[reg_y_index as usize] 
+                                                & self.reg_gp[reg_x_index as usize];
+                if self.quirks.vf_reset {
+                    self.reg_gp[0xf] = 0;
+                }
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::XOR {reg_x_index, reg_y_index} => {
-                (*self.reg_gp)[reg_x_index as usize] = (*self.reg_gp)[reg_y_index as usize] 
-                                                ^ (*self.reg_gp)[reg_x_index as usize];
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_gp[reg_x_index as usize] = self.reg_gp[reg_y_index as usize] 
+                                                ^ self.reg_gp[reg_x_index as usize];
+                if self.quirks.vf_reset {
+                    self.reg_gp[0xf] = 0;
+                }
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
+            // VF is always written last so it holds the flag even when
+            // it is also the destination register
             IntermediateAsm::ADD_REG {reg_x_index, reg_y_index} => {
-                let src: u16 = (*self.reg_gp)[reg_x_index as usize] as u16;
-                let dst: u16 = (*self.reg_gp)[reg_x_index as usize] as u16;
+                let src: u16 = self.reg_gp[reg_y_index as usize] as u16;
+                let dst: u16 = self.reg_gp[reg_x_index as usize] as u16;
                 let sum: u16 = src + dst;
-                if (sum & 0xffffff00) != 0  {
-                    (*self.reg_gp)[0xf] = 1;
+
+                self.reg_gp[reg_x_index as usize] = sum as u8;
+                if (sum & 0xff00) != 0  {
+                    self.reg_gp[0xf] = 1;
                 } else {
-                    (*self.reg_gp)[0xf] = 0;
+                    self.reg_gp[0xf] = 0;
                 }
-
-                (*self.reg_gp)[reg_x_index as usize] = (((*self.reg_gp)[reg_y_index as usize] as u16)
-                                                + ((*self.reg_gp)[reg_x_index as usize] as u16)) as u8;
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
 
             },
             IntermediateAsm::SUB_REG {reg_x_index, reg_y_index} => {
-                let high_val = (*self.reg_gp)[reg_x_index as usize];
-                if (*self.reg_gp)[reg_x_index as usize] > (*self.reg_gp)[reg_y_index as usize]  {
-                    (*self.reg_gp)[0xf] = 1;
+                let high_val = self.reg_gp[reg_x_index as usize];
+                let low_val = self.reg_gp[reg_y_index as usize];
+
+                self.reg_gp[reg_x_index as usize] = high_val.wrapping_sub(low_val);
+                // no borrow when they are equal
+                if high_val >= low_val  {
+                    self.reg_gp[0xf] = 1;
                 } else {
-                    (*self.reg_gp)[0xf] = 0;
+                    self.reg_gp[0xf] = 0;
                 }
-
-                (*self.reg_gp)[reg_x_index as usize] = ((*self.reg_gp)[reg_x_index as usize] as i16
-                                                - (*self.reg_gp)[reg_y_index as usize] as i16) as u8;
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::SHR {reg_x_index, reg_y_index} => {
-                if ((*self.reg_gp)[reg_y_index as usize] % 2 ) == 1  {
-                    (*self.reg_gp)[0xf] = 1;
+                let reg_y_index = if self.quirks.shift_ignores_vy { reg_x_index } else { reg_y_index };
+                let value = self.reg_gp[reg_y_index as usize];
+
+                self.reg_gp[reg_x_index as usize] = value >> 1;
+                if (value % 2 ) == 1  {
+                    self.reg_gp[0xf] = 1;
                 } else {
-                    (*self.reg_gp)[0xf] = 0;
+                    self.reg_gp[0xf] = 0;
                 }
-                (*self.reg_gp)[reg_y_index as usize] = (*self.reg_gp)[reg_y_index as usize] >> 1;
-                (*self.reg_gp)[reg_x_index as usize] = (*self.reg_gp)[reg_y_index as usize];
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::SUBN {reg_x_index, reg_y_index} => {
-                if (*self.reg_gp)[reg_y_index as usize] > (*self.reg_gp)[reg_x_index as usize]  {
-                    (*self.reg_gp)[0xf] = 1;
+                let high_val = self.reg_gp[reg_y_index as usize];
+                let low_val = self.reg_gp[reg_x_index as usize];
+
+                self.reg_gp[reg_x_index as usize] = high_val.wrapping_sub(low_val);
+                if high_val >= low_val  {
+                    self.reg_gp[0xf] = 1;
                 } else {
-                    (*self.reg_gp)[0xf] = 0;
+                    self.reg_gp[0xf] = 0;
                 }
-
-                (*self.reg_gp)[reg_x_index as usize] = ((*self.reg_gp)[reg_y_index as usize] as i16 
-                                                - (*self.reg_gp)[reg_x_index as usize] as i16) as u8;
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
 
             },
             IntermediateAsm::SHL {reg_x_index, reg_y_index} => {
-                if (*self.reg_gp)[reg_y_index as usize] & 0x80 != 0 {
-                    (*self.reg_gp)[0xf] = 1;
+                let reg_y_index = if self.quirks.shift_ignores_vy { reg_x_index } else { reg_y_index };
+                let value = self.reg_gp[reg_y_index as usize];
+
+                self.reg_gp[reg_x_index as usize] = value << 1;
+                if value & 0x80 != 0 {
+                    self.reg_gp[0xf] = 1;
                 } else {
-                    (*self.reg_gp)[0xf] = 0;
+                    self.reg_gp[0xf] = 0;
                 }
-                (*self.reg_gp)[reg_y_index as usize] = (*self.reg_gp)[reg_y_index as usize] << 1;
-                (*self.reg_gp)[reg_x_index as usize] = (*self.reg_gp)[reg_y_index as usize];
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
                 
             },
             IntermediateAsm::SNE_REG {reg_x_index, reg_y_index} => {
-                if (*self.reg_gp)[reg_y_index as usize] == (*self.reg_gp)[reg_x_index as usize]  {
-                    self.reg_pc = self.reg_pc + 2;
+                if self.reg_gp[reg_y_index as usize] == self.reg_gp[reg_x_index as usize]  {
+                    self.reg_pc = self.reg_pc.wrapping_add(2);
                 } else {
-                    self.reg_pc = self.reg_pc + 4;
+                    self.reg_pc = self.reg_pc.wrapping_add(4);
                 }
             },
             IntermediateAsm::LOAD_ADDR {addr} => {
-                self.reg_i = addr;
+                self.reg_i = addr as u32;
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::JUMP_V0 {addr} => {
-                self.reg_pc = addr + ((*self.reg_gp)[0] as u16);
+                // BXNN on the SUPER-CHIP
+                let reg_index = if self.quirks.jump_uses_vx { (addr >> 8) as usize } else { 0 };
+                self.reg_pc = addr + (self.reg_gp[reg_index] as u16);
             },
             IntermediateAsm::RND {reg_x_index, bitmask} => {
                 let rand255:u8 = random::<u8>();
                 let postAND = rand255 & bitmask;
-                (*self.reg_gp)[reg_x_index as usize] = postAND;
+                self.reg_gp[reg_x_index as usize] = postAND;
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::DRW {reg_x_index, reg_y_index, nibble} => {
-                let mut memory_ref = &bus_ref.memory.memory;
-                let x = (*self.reg_gp)[reg_x_index as usize];
-                let y = (*self.reg_gp)[reg_y_index as usize];
-                let hadCollision = bus_ref.graphics.draw_sprite(memory_ref, x as usize, y as usize, self.reg_i, nibble);
+                let x = self.reg_gp[reg_x_index as usize];
+                let y = self.reg_gp[reg_y_index as usize];
+                let hadCollision = match bus_ref.graphics {
+                    Chip8Graphics::Mono(ref mut display) =>
+                        display.draw_sprite(&bus_ref.memory, x as usize, y as usize, self.reg_i, nibble, self.quirks.clipping),
+                    // MEGA-CHIP sprites take their size from 03NN and 04NN
+                    Chip8Graphics::MegaChip(ref mut display) =>
+                        display.draw_sprite(&bus_ref.memory, x as usize, y as usize, self.reg_i),
+                };
 
                 if hadCollision {
-                    (*self.reg_gp)[0xf] = 0x1;
+                    self.reg_gp[0xf] = 0x1;
                 } else {
-                    (*self.reg_gp)[0xf] = 0x0;
+                    self.reg_gp[0xf] = 0x0;
                 }
 
                 self.draw_to_screen_flag = true;
+                self.waiting_for_vblank = self.quirks.display_wait;
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::SKP {reg_x_index} => {
-                if  bus_ref.keypad.is_pressed((*self.reg_gp)[reg_x_index as usize])  {
-                    self.reg_pc = self.reg_pc + 4;
+                if  bus_ref.keypad.is_pressed(self.reg_gp[reg_x_index as usize])  {
+                    self.reg_pc = self.reg_pc.wrapping_add(4);
                 } else {
-                    self.reg_pc = self.reg_pc + 2;
+                    self.reg_pc = self.reg_pc.wrapping_add(2);
                 }
             },
             IntermediateAsm::SKNP {reg_x_index} => {
-                if  bus_ref.keypad.is_pressed((*self.reg_gp)[reg_x_index as usize])  {
-                    self.reg_pc = self.reg_pc + 2;
+                if  bus_ref.keypad.is_pressed(self.reg_gp[reg_x_index as usize])  {
+                    self.reg_pc = self.reg_pc.wrapping_add(2);
                 } else {
-                    self.reg_pc = self.reg_pc + 4;
+                    self.reg_pc = self.reg_pc.wrapping_add(4);
                 }
             },
             IntermediateAsm::LOAD_DELAY_TIMER {reg_x_index} => {
-                (*self.reg_gp)[reg_x_index as usize] = self.reg_delay;
+                self.reg_gp[reg_x_index as usize] = self.reg_delay;
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::WAIT_FOR_KEY_PRESS {reg_x_index} => {
                 self.halted_register = reg_x_index;
                 self.is_halted_flag = true;
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::SET_DELAY_TIMER {reg_x_index} => {
-                self.reg_delay = (*self.reg_gp)[reg_x_index as usize];
+                self.reg_delay = self.reg_gp[reg_x_index as usize];
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::SET_SOUND_TIMER {reg_x_index} => {
-                self.reg_sound = (*self.reg_gp)[reg_x_index as usize];
+                self.reg_sound = self.reg_gp[reg_x_index as usize];
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::ADD_I {reg_x_index} => {
-                self.reg_i = ((*self.reg_gp)[reg_x_index as usize] as u16) + self.reg_i;
+                let addr = self.reg_i.wrapping_add(self.reg_gp[reg_x_index as usize] as u32);
+                self.set_i(addr);
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::LOAD_SPRITE_LOCATION {reg_x_index} => {
-                let hexval = (*self.reg_gp)[reg_x_index as usize];
-
-                self.reg_i = match hexval {
-                    0x0 => 0x0,
-                    0x1 => 0x5,
-                    0x2 => 0xa,
-                    0x3 => 0xf,
-                    0x4 => 0x14,
-                    0x5 => 0x19,
-                    0x6 => 0x1e,
-                    0x7 => 0x23,
-                    0x8 => 0x28,
-                    0x9 => 0x2d,
-                    0xa => 0x32,
-                    0xb => 0x37,
-                    0xc => 0x3c,
-                    0xd => 0x41,
-                    0xe => 0x46,
-                    0xf => 0x4B,
-                    _ => panic!("Error: Attempted to read system character that doesn't exist.")
-                };
+                // only the low nibble selects a character, like on the VIP
+                self.reg_i = self.font.glyph_address(self.reg_gp[reg_x_index as usize]) as u32;
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
             IntermediateAsm::STORE_BCD {reg_x_index} => {
-                let value = (*self.reg_gp)[reg_x_index as usize];
-                (bus_ref.memory.memory)[(self.reg_i as usize)] = value / 100;
-                (bus_ref.memory.memory)[(self.reg_i as usize) + 1] = (value % 100) /10;
-                (bus_ref.memory.memory)[(self.reg_i as usize) + 2] = value % 10;
+                let value = self.reg_gp[reg_x_index as usize];
+                bus_ref.memory.write_byte_at(self.reg_i, value / 100);
+                bus_ref.memory.write_byte_at(self.reg_i.wrapping_add(1), (value % 100) /10);
+                bus_ref.memory.write_byte_at(self.reg_i.wrapping_add(2), value % 10);
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
 
             IntermediateAsm::STORE_REG_ARR {reg_x_index} => {
                 for i in 0..(reg_x_index + 1) {
-                    (*bus_ref.memory.memory)[(self.reg_i as usize) + (i as usize)] = (*self.reg_gp)[i as usize];
+                    bus_ref.memory.write_byte_at(self.reg_i.wrapping_add(i as u32), self.reg_gp[i as usize]);
+                }
+                if self.quirks.memory_increments_i {
+                    let addr = self.reg_i.wrapping_add(reg_x_index as u32 + 1);
+                    self.set_i(addr);
                 }
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
 
             IntermediateAsm::LOAD_REG_ARR {reg_x_index} => {
                 for i in 0..(reg_x_index + 1) {
-                     (*self.reg_gp)[i as usize] = (*bus_ref.memory.memory)[(self.reg_i as usize) + (i as usize)] ;
+                    self.reg_gp[i as usize] = bus_ref.memory.read_byte_at(self.reg_i.wrapping_add(i as u32));
+                }
+                if self.quirks.memory_increments_i {
+                    let addr = self.reg_i.wrapping_add(reg_x_index as u32 + 1);
+                    self.set_i(addr);
                 }
 
-                self.reg_pc = self.reg_pc + 2;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
             },
 
+            IntermediateAsm::CYCLE_BACKGROUND => {
+                bus_ref.colours.cycle_background();
+                self.draw_to_screen_flag = true;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::ADD_NIBBLES {reg_x_index, reg_y_index} => {
+                // each nibble is added on its own and kept below 8
+                let vx = self.reg_gp[reg_x_index as usize];
+                let vy = self.reg_gp[reg_y_index as usize];
+                let high = ((vx >> 4) + (vy >> 4)) % 8;
+                let low = ((vx & 0xF) + (vy & 0xF)) % 8;
+                self.reg_gp[reg_x_index as usize] = (high << 4) | low;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::SET_COLOUR_ZONE {reg_x_index, reg_y_index} => {
+                // VX and VX+1 hold the position of the zone in the low nibble
+                // and the number of extra zones in the high one, zones are 8
+                // pixels wide and 4 tall
+                let horizontal = self.reg_gp[reg_x_index as usize];
+                let vertical = self.reg_gp[(reg_x_index as usize + 1) % 16];
+                let colour = self.reg_gp[reg_y_index as usize];
+                bus_ref.colours.fill((horizontal & 0xF) as usize,
+                                     (vertical & 0xF) as usize * 4,
+                                     (horizontal >> 4) as usize + 1,
+                                     ((vertical >> 4) as usize + 1) * 4,
+                                     colour);
+                self.draw_to_screen_flag = true;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::SET_COLOUR_ROWS {reg_x_index, reg_y_index, nibble} => {
+                // N rows of the 8 pixel column holding VX, from row VX+1
+                let x = self.reg_gp[reg_x_index as usize];
+                let y = self.reg_gp[(reg_x_index as usize + 1) % 16];
+                let colour = self.reg_gp[reg_y_index as usize];
+                bus_ref.colours.fill((x as usize % CHIP8_SCREEN_WIDTH) / 8, y as usize, 1, nibble as usize, colour);
+                self.draw_to_screen_flag = true;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::SKP_KEYPAD2 {reg_x_index} => {
+                if bus_ref.keypad2.is_pressed(self.reg_gp[reg_x_index as usize]) {
+                    self.reg_pc = self.reg_pc.wrapping_add(4);
+                } else {
+                    self.reg_pc = self.reg_pc.wrapping_add(2);
+                }
+            },
+            IntermediateAsm::SKNP_KEYPAD2 {reg_x_index} => {
+                if bus_ref.keypad2.is_pressed(self.reg_gp[reg_x_index as usize]) {
+                    self.reg_pc = self.reg_pc.wrapping_add(2);
+                } else {
+                    self.reg_pc = self.reg_pc.wrapping_add(4);
+                }
+            },
+            IntermediateAsm::OUTPUT_PORT {reg_x_index} => {
+                bus_ref.port_output = self.reg_gp[reg_x_index as usize];
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::INPUT_PORT {reg_x_index} => {
+                self.reg_gp[reg_x_index as usize] = bus_ref.port_input;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
 
+            IntermediateAsm::MEGA_OFF => {
+                bus_ref.graphics = Chip8Graphics::for_platform(self.platform);
+                self.draw_to_screen_flag = true;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::MEGA_ON => {
+                bus_ref.graphics = Chip8Graphics::MegaChip(MegaChipDisplay::new());
+                self.draw_to_screen_flag = true;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::LOAD_LONG_I {high} => {
+                let low = bus_ref.memory.read_byte_at(self.reg_pc as u32 + 2) as u32;
+                let low = (low << 8) | bus_ref.memory.read_byte_at(self.reg_pc as u32 + 3) as u32;
+                self.set_i(((high as u32) << 16) | low);
+                self.reg_pc = self.reg_pc.wrapping_add(4);
+            },
+            // the rest only mean something in MEGA-CHIP mode
+            IntermediateAsm::LOAD_PALETTE {count} => {
+                if let Chip8Graphics::MegaChip(ref mut display) = bus_ref.graphics {
+                    display.load_palette(&bus_ref.memory, self.reg_i, count);
+                }
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::SPRITE_WIDTH {width} => {
+                if let Chip8Graphics::MegaChip(ref mut display) = bus_ref.graphics {
+                    // 0 is 256 pixels
+                    display.sprite_width = if width == 0 { 256 } else { width as usize };
+                }
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::SPRITE_HEIGHT {height} => {
+                if let Chip8Graphics::MegaChip(ref mut display) = bus_ref.graphics {
+                    display.sprite_height = if height == 0 { 256 } else { height as usize };
+                }
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::SET_ALPHA {alpha} => {
+                if let Chip8Graphics::MegaChip(ref mut display) = bus_ref.graphics {
+                    display.alpha = alpha;
+                }
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::PLAY_SAMPLE {nibble} => {
+                // 0 loops the sound, anything else plays it once
+                bus_ref.sample = Some(MegaChipSample::load(&bus_ref.memory, self.reg_i, nibble == 0));
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::STOP_SAMPLE => {
+                bus_ref.sample = None;
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::BLEND_MODE {nibble} => {
+                if let Chip8Graphics::MegaChip(ref mut display) = bus_ref.graphics {
+                    display.blend_mode = BlendMode::from_nibble(nibble);
+                }
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+            IntermediateAsm::COLLISION_COLOUR {index} => {
+                if let Chip8Graphics::MegaChip(ref mut display) = bus_ref.graphics {
+                    display.collision_index = index;
+                }
+                self.reg_pc = self.reg_pc.wrapping_add(2);
+            },
+        }
+        Ok(())
+    }
 
+    
+
+    // Runs the machine code subroutine at addr the way the VIP interpreter
+    // does: the registers are laid out in memory where the interpreter keeps
+    // them, the 1802 is entered with P = 3 and the subroutine returns with
+    // SEP R4. Returns the machine cycles it took.
+    fn call_machine_code(&mut self, cdp1802: &mut Cdp1802, bus_ref: &mut Chip8Bus, addr: u16) -> Result<u32, CpuError> {
+        for i in 0..16 {
+            bus_ref.memory.write_byte(VIP_VARIABLES + i as u16, self.reg_gp[i]);
+        }
+        // the display only has a place in memory at the VIP resolution
+        let width = bus_ref.graphics.width();
+        let height = bus_ref.graphics.height();
+        let map_display = width == CHIP8_SCREEN_WIDTH && height == CHIP8_SCREEN_HEIGHT;
+        if map_display {
+            for y in 0..height {
+                for column in 0..(width / 8) {
+                    let mut byte = 0;
+                    for bit in 0..8 {
+                        if bus_ref.graphics.get_pixel_value(column * 8 + bit, y) {
+                            byte |= 0x80 >> bit;
+                        }
+                    }
+                    bus_ref.memory.write_byte(VIP_DISPLAY + (y * width / 8 + column) as u16, byte);
+                }
+            }
+        }
+
+        // R6 and R7 point at VX and VY of the instruction, R8 holds the
+        // timers and RA is I
+        cdp1802.r[2] = VIP_STACK_POINTER;
+        cdp1802.r[3] = addr;
+        cdp1802.r[5] = self.reg_pc.wrapping_add(2);
+        cdp1802.r[6] = VIP_VARIABLES + ((addr >> 8) & 0xF);
+        cdp1802.r[7] = VIP_VARIABLES + ((addr >> 4) & 0xF);
+        cdp1802.r[8] = ((self.reg_delay as u16) << 8) | self.reg_sound as u16;
+        cdp1802.r[0xA] = self.reg_i as u16;
+        cdp1802.r[0xB] = VIP_DISPLAY;
+        cdp1802.p = 3;
+        cdp1802.x = 2;
+
+        let mut cycles = 0;
+        let mut instructions = 0;
+        while cdp1802.p != 4 {
+            if instructions == CDP1802_MAX_INSTRUCTIONS {
+                return Err(self.fault(Fault::MachineCodeRunaway(addr)));
+            }
+            cycles += cdp1802.step(&mut bus_ref.memory, &bus_ref.keypad);
+            instructions += 1;
         }
 
-        
+        for i in 0..16 {
+            self.reg_gp[i] = bus_ref.memory.read_byte(VIP_VARIABLES + i as u16);
+        }
+        self.reg_i = cdp1802.r[0xA] as u32;
+        self.reg_pc = cdp1802.r[5];
+        self.reg_delay = (cdp1802.r[8] >> 8) as u8;
+        self.reg_sound = cdp1802.r[8] as u8;
+        if map_display {
+            for y in 0..height {
+                for x in 0..width {
+                    let byte = bus_ref.memory.read_byte(VIP_DISPLAY + (y * width / 8 + x / 8) as u16);
+                    bus_ref.graphics.set_pixel_value(x, y, byte & (0x80 >> (x % 8)) != 0);
+                }
+            }
+            self.draw_to_screen_flag = true;
+        }
+        Ok(cycles)
+    }
 
+    // registers, stack and flags for a save state
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.slice(&self.reg_gp);
+        out.u32(self.reg_i);
+        out.u8(self.reg_delay);
+        out.u8(self.reg_sound);
+        out.u16(self.reg_pc);
+        out.u16(self.reg_sp);
+        for addr in self.stack.iter() {
+            out.u16(*addr);
+        }
+        out.quirks(self.quirks);
+        out.platform(self.platform);
+        out.bool(self.waiting_for_vblank);
+        out.bool(self.is_halted_flag);
+        out.u8(self.halted_register);
+        out.bool(self.draw_to_screen_flag);
+        // the 1802 starts afresh on every 0NNN, so only whether there is one
+        // matters
+        out.bool(self.rca1802.is_some());
     }
 
-    
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), StateError> {
+        self.reg_gp.copy_from_slice(input.slice_of(16, "V0-VF")?);
+        self.reg_i = input.u32()?;
+        self.reg_delay = input.u8()?;
+        self.reg_sound = input.u8()?;
+        self.reg_pc = input.u16()?;
+        self.reg_sp = input.u16()?;
+        if self.reg_sp as usize > self.stack.len() {
+            return Err(StateError::Invalid(format!("stack pointer {} is past the stack", self.reg_sp)));
+        }
+        for i in 0..self.stack.len() {
+            self.stack[i] = input.u16()?;
+        }
+        self.quirks = input.quirks()?;
+        self.platform = input.platform()?;
+        self.waiting_for_vblank = input.bool()?;
+        self.is_halted_flag = input.bool()?;
+        self.halted_register = input.u8()?;
+        if self.halted_register > 0xF {
+            return Err(StateError::Invalid(format!("V{:X} is not a register", self.halted_register)));
+        }
+        self.draw_to_screen_flag = input.bool()?;
+        self.rca1802 = if input.bool()? { Some(Cdp1802::new()) } else { None };
+        Ok(())
+    }
 
+    // called once per 60Hz frame
     pub fn update_timer(&mut self) {
-        self.timer_update_flag = (self.timer_update_flag + 1) % 9;
-        if self.timer_update_flag == 0 {
-            if self.reg_delay > 0 {
-                self.reg_delay = self.reg_delay - 1;
+        if self.reg_delay > 0 {
+            self.reg_delay = self.reg_delay - 1;
+        }
+        if self.reg_sound > 0 {
+            self.reg_sound = self.reg_sound - 1;
+        }
+    }
+
+}
+
+// Decodes a raw opcode, None if it isn't a Chip-8 instruction
+pub fn decode(opcode: Opcode) -> Option<IntermediateAsm> {
+    let lowest_4_bits: u8 = (opcode & 0xF) as u8;
+    let middle_lower_4_bits: u8 = ((opcode >> 4 ) & 0xF) as u8;
+    let middle_upper_4_bits: u8 = ((opcode >> 8 ) & 0xF) as u8;
+    let highest_4_bits: u8 = ((opcode >> 12 ) & 0xF) as u8;
+    let instruction = match (highest_4_bits, 
+                             middle_upper_4_bits, 
+                             middle_lower_4_bits, 
+                             lowest_4_bits)
+    {
+        (0x0, 0x0, 0xE, 0x0) => {
+            // Opcode: 00E0
+            // Type: Display
+            // C Psuedo:
+            // disp_clear();
+            // TODO Clear the screen
+            IntermediateAsm::CLS
+        },
+        (0x0, 0x0, 0xE, 0xE) => {
+            // Opcode: 00EE
+            // Type: Flow
+            // C Psuedo:
+            // return;
+            // TODO Return from a subroutine
+            IntermediateAsm::RET
+        },
+        (0x0, N1, N2, N3) => {
+            // Opcode: 00EE
+            // Type: Call
+            // C Psuedo:
+            // Not Applicable
+            // TODO Call RCA 1802 program at address N1N2N3
+            IntermediateAsm::SYS {addr: opcode & 0x0fff}
+        },
+        (0x1, N1, N2, N3) => {
+            // Opcode: 1NNN
+            // Type: Flow
+            // C Psuedo:
+            // goto NNN;
+            // TODO jump to address N1N2N3
+            let addr = opcode & 0x0fff;
+            IntermediateAsm::JUMP {addr: addr}
+        },
+        (0x2, N1, N2, N3) => {
+            // Opcode: 2NNN
+            // Type: Flow
+            // C Psuedo:
+            // *(0xNNN)()
+            // TODO Call subroutine at N1N2N3
+            let addr = opcode & 0x0fff;
+            IntermediateAsm::CALL {addr: addr}
+
+        },
+        (0x3, X, N1, N2) => {
+            // Opcode: 3XNN
+            // Type: Cond
+            // C Psuedo:
+            // if(Vx==NN)
+            // TODO Skip the next instruction if register VX equals NN.
+            let reg_index = ((opcode & 0x0f00) >> 8) as u8 ; 
+            let constant = (opcode & 0x00ff) as u8;
+            IntermediateAsm::SE_CONST {
+                reg_index: reg_index,
+                constant: constant
+            }
+        },
+        (0x4, X, N1, N2) => {
+            // Opcode: 4XNN
+            // Type: Cond
+            // C Psuedo:
+            // if(Vx!=NN)
+            // TODO Skip the next instruction if register VX does not equal NN.
+            let reg_index = ((opcode & 0x0f00) >> 8) as u8;
+            let constant = (opcode & 0x00ff) as u8;
+            IntermediateAsm::SNE_CONST {
+                reg_index: reg_index,
+                constant: constant,
+            }
+        }, 
+        (0x5, X, Y, 0x0) => {
+            // Opcode: 5XY0
+            // Type: Cond
+            // C Psuedo:
+            // if(Vx==Vy)
+            // TODO Skip the next instruction if register VX does not 
+            // equal register VY.
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::SE_REG {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+            }
+        }, 
+        (0x6, X, N1, N2) => {
+            // Opcode: 6XNN
+            // Type: Const
+            // C Psuedo:
+            // Vx = NN
+            // TODO Set register VX to NN
+            let reg_index = ((opcode & 0x0f00) >> 8) as u8;
+            let constant = (opcode & 0x00ff) as u8;
+            IntermediateAsm::LOAD_CONST {
+                reg_index: reg_index,
+                constant: constant,
+            }
+        },
+        (0x7, X, N1, N2) => {
+            // Opcode: 7XNN
+            // Type: Const
+            // C Psuedo:
+            // Vx += NN
+            // TODO Add NN to Vx (carry flag is not changed)
+            let reg_index = ((opcode & 0x0f00) >> 8) as u8;
+            let constant = (opcode & 0x00ff) as u8;
+            IntermediateAsm::ADD_CONST {
+                reg_index: reg_index,
+                constant: constant,
+            }
+        }, 
+        (0x8, X, Y, 0x0) => {
+            // Opcode: 8XY0
+            // Type: Assign
+            // C Psuedo:
+            // Vx = Vy
+            // TODO set register Vx to the value in register Vy
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::LOAD_REG {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+            }
+
+        },
+        (0x8, X, Y, 0x1) => {
+            // Opcode: 8XY1
+            // Type: BitOp
+            // C Psuedo:
+            // Vx=Vx|Vy
+            // TODO Set register Vx to Vx | Vy 
+            // (bitwise OR)
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::OR {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+            }
+
+        },
+        (0x8, X, Y, 0x2) => {
+            // Opcode: 8XY2
+            // Type: BitOp
+            // C Psuedo:
+            // Vx=Vx&Vy
+            // TODO Set register Vx to Vx & Vy 
+            // (bitwise AND)
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::AND {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
             }
-            if self.reg_sound > 0 {
-                self.reg_sound = self.reg_sound - 1;
+        },
+        (0x8, X, Y, 0x3) => {
+            // Opcode: 8XY3
+            // Type: BitOp
+            // C Psuedo:
+            // Vx=Vx^Vy
+            // TODO Set register Vx to Vx ^ Vy 
+            // (bitwise XOR)
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::XOR {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
             }
+
+        },
+        (0x8, X, Y, 0x4) => {
+            // Opcode: 8XY4
+            // Type: Math
+            // C Psuedo:
+            // Vx+=Vy
+            // TODO Set register Vx to Vx + Vy 
+            // If there is a carry, set register VF to 1
+            // else, set register VF to 0
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::ADD_REG {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+            }
+        },
+        (0x8, X, Y, 0x5) => {
+            // Opcode: 8XY5
+            // Type: Math
+            // C Psuedo:
+            // Vx-=Vy
+            // TODO Set register Vx to Vx - Vy 
+            // If there is a borrow, set register VF to 0
+            // else, set register VF to 1
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::SUB_REG {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+            }
+
+        },
+        (0x8, X, Y, 0x6) => {
+            // Opcode: 8XY6
+            // Type: BitOp 
+            // C Psuedo:
+            // Vx=Vy=Vy>>1
+            // TODO Shift Vy right by one and copy the result
+            // to Vx.
+            // VF is set to the value of the least significant bit
+            // of Vy before the shift
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::SHR {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+            }
+
+        },
+        (0x8, X, Y, 0x7) => {
+            // Opcode: 8XY7
+            // Type: Math
+            // C Psuedo:
+            // Vx=Vy-Vx
+            // TODO Set Vx to Vy minux Vx. VF is set to 0 when there
+            // is a borrow and 1 when there isn't
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::SUBN {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+            }
+
+        },
+        (0x8, X, Y, 0xE) => {
+            // Opcode: 8XYE
+            // Type: BitOp
+            // C Psuedo:
+            // Vx=Vy=Vy<<1
+            // TODO Shift Vy left by one and copy the result to Vx.
+            // Set VF to the value of the most significant bit
+            // of Vy before the shift
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::SHL {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+            }
+        },
+        (0x9, X, Y, 0) => {
+            // Opcode: 9XY0
+            // Type: Cond
+            // C Psuedo:
+            // if(Vx != Vy)
+            // TODO Skip the next instruction if Vx doesn't euqal Vy.
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            IntermediateAsm::SNE_REG {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+            }
+        },
+        (0xA, N1, N2, N3) => {
+            // Opcode: ANNN
+            // Type: MEM
+            // C Psuedo:
+            // I=NNN
+            // TODO set reg I to the address NNN
+            let addr = opcode & 0x0fff;
+            IntermediateAsm::LOAD_ADDR {addr: addr}
+        },
+        (0xB, N1, N2, N3) => {
+            // Opcode: BNNN
+            // Type: Flow
+            // C Psuedo:
+            // PC=V0+NNN
+            // TODO jump to the address NNN plus V0
+            let addr = opcode & 0x0fff;
+            IntermediateAsm::JUMP_V0 {addr: addr}
+        },
+        (0xC, X, N1, N2) => {
+            // Opcode: CXNN
+            // Type: Rand
+            // C Psuedo:
+            // Vx=rand()&NN
+            // TODO set Vx to a random number (typically 0 to 255) 
+            // that is bitwise and'd with NN
+            let reg_index = ((opcode & 0x0f00) >> 8) as u8;
+            let constant = (opcode & 0x00ff) as u8;
+            IntermediateAsm::RND {
+                reg_x_index: reg_index,
+                bitmask: constant
+            }
+        },
+        (0xD, X, Y, N) => {
+            // Opcode: DXYN
+            // Type: Disp
+            // C Psuedo:
+            // draw(Vx,Vy,N)
+            // TODO Draws a sprite at coordinate (VX, VY) that has 
+            // a width of 8 pixels and a height of N pixels. 
+            // Each row of 8 pixels is read as bit-coded starting 
+            // from memory location I; I value doesn’t change after 
+            // the execution of this instruction. 
+            // As described above, VF is set to 1 if any screen pixels 
+            // are flipped from set to unset when the sprite is drawn, 
+            // and to 0 if that doesn’t happen
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+            let nibble = (opcode & 0x000f) as u8;
+            IntermediateAsm::DRW {
+                reg_x_index: reg_x_index,
+                reg_y_index: reg_y_index,
+                nibble: nibble,
+            }
+
+        },
+        (0xE, X, 0x9, 0xE) => {
+            // Opcode: EX9E
+            // Type: KeyOp
+            // C Psuedo:
+            // if(key()==Vx)
+            // TODO Skips the next instruction if the key stored in 
+            // VX is pressed.
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::SKP {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xE, X, 0xA, 0x1) => {
+            // Opcode: EXA1
+            // Type: KeyOp
+            // C Psuedo:
+            // if(key()!=Vx)
+            // TODO Skips the next instruction if the key stored in 
+            // VX isn't pressed.
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::SKNP {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xF, X, 0x0, 0x7) => {
+            // Opcode: FX07
+            // Type: Timer
+            // C Psuedo:
+            // Vx = get_delay()
+            // TODO Sets Vx to the value of the delay timer
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::LOAD_DELAY_TIMER {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xF, X, 0x0, 0xA) => {
+            // Opcode: FX0A
+            // Type: KeyOp
+            // C Psuedo:
+            // Vx = get_key()
+            // TODO A key press is awaited, and then stored in VX. 
+            // NOTE!!! Blocking Operation. 
+            // All instruction halted until next key event
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::WAIT_FOR_KEY_PRESS {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xF, X, 0x1, 0x5) => {
+            // Opcode: FX15
+            // Type: Timer
+            // C Psuedo:
+            // delay_timer(Vx)
+            // TODO set delay timer to Vx
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::SET_DELAY_TIMER {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xF, X, 0x1, 0x8) => {
+            // Opcode: FX18
+            // Type: Sound
+            // C Psuedo:
+            // sound_timer(Vx)
+            // TODO set sound timer to Vx
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::SET_SOUND_TIMER {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xF, X, 0x1, 0xE) => {
+            // Opcode: FX1E
+            // Type: MEM
+            // C Psuedo:
+            // I += Vx
+            // TODO Add Vx to I
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::ADD_I {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xF, X, 0x2, 0x9) => {
+            // Opcode: FX29
+            // Type: MEM
+            // C Psuedo:
+            // I=sprite_addr[Vx]
+            // TODO Sets I to the location of the sprite for the 
+            // character in VX. Characters 0-F (in hexadecimal) 
+            // are represented by a 4x5 font.
+            //
+            // TODO Do more research here
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::LOAD_SPRITE_LOCATION {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xF, X, 0x3, 0x3) => {
+            // Opcode: FX33
+            // Type: BCD
+            // C Psuedo:
+            // set_BCD(Vx);
+            // *(I+0)=BCD(3);
+            // *(I+1)=BCD(2);
+            // *(I+2)=BCD(1); 
+            //
+            // TODO Stores the binary-coded decimal representation of VX, 
+            // with the most significant of three digits at the address 
+            // in I, the middle digit at I plus 1, and the least 
+            // significant digit at I plus 2. 
+            // (In other words, take the decimal representation of VX, 
+            // place the hundreds digit in memory at location in I, 
+            // the tens digit at location I+1, and the ones digit at 
+            // location I+2.)
+            //
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::STORE_BCD {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xF, X, 0x5, 0x5) => {
+            // Opcode: FX55
+            // Type: MEM
+            // C Psuedo:
+            // reg_dump(Vx,&I)
+            // TODO Stores V0 to VX (including VX) in memory starting 
+            // at address I. I is increased by 1 for each value written.
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::STORE_REG_ARR {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+        (0xF, X, 0x6, 0x5) => {
+            // Opcode: FX65
+            // Type: MEM
+            // C Psuedo:
+            // reg_load(Vx,&I)
+            // TODO Fills V0 to VX (including VX) with values from 
+            // memory starting at address I. I is increased by 1 for 
+            // each value written. 
+            let reg_x_index = ((opcode & 0x0f00) >> 8) as u8; 
+            IntermediateAsm::LOAD_REG_ARR {
+                reg_x_index: reg_x_index,
+            }
+
+        },
+
+        _ => return None
+    };
+    Some(instruction)
+}
+
+// Decodes a raw opcode with the extra instructions of a platform
+pub fn decode_for(platform: Platform, opcode: Opcode) -> Option<IntermediateAsm> {
+    let reg_x_index = ((opcode & 0x0f00) >> 8) as u8;
+    let reg_y_index = ((opcode & 0x00f0) >> 4) as u8;
+    let nibble = (opcode & 0x000f) as u8;
+    let constant = (opcode & 0x00ff) as u8;
+    match platform {
+        // the hi-res interpreter clears all 64 rows with a machine code call
+        Platform::HiresChip8 if opcode == 0x0230 => Some(IntermediateAsm::CLS),
+        Platform::Chip8X => match (opcode >> 12, opcode & 0x00ff) {
+            (0x0, 0xA0) if opcode == 0x02A0 => Some(IntermediateAsm::CYCLE_BACKGROUND),
+            (0x5, _) if nibble == 0x1 => Some(IntermediateAsm::ADD_NIBBLES {reg_x_index, reg_y_index}),
+            // BNNN is gone on the CHIP-8X
+            (0xB, _) if nibble == 0x0 => Some(IntermediateAsm::SET_COLOUR_ZONE {reg_x_index, reg_y_index}),
+            (0xB, _) => Some(IntermediateAsm::SET_COLOUR_ROWS {reg_x_index, reg_y_index, nibble}),
+            (0xE, 0xF2) => Some(IntermediateAsm::SKP_KEYPAD2 {reg_x_index}),
+            (0xE, 0xF5) => Some(IntermediateAsm::SKNP_KEYPAD2 {reg_x_index}),
+            (0xF, 0xF8) => Some(IntermediateAsm::OUTPUT_PORT {reg_x_index}),
+            (0xF, 0xFB) => Some(IntermediateAsm::INPUT_PORT {reg_x_index}),
+            _ => decode(opcode),
+        },
+        Platform::MegaChip => match opcode >> 8 {
+            0x00 if opcode == 0x0010 => Some(IntermediateAsm::MEGA_OFF),
+            0x00 if opcode == 0x0011 => Some(IntermediateAsm::MEGA_ON),
+            0x01 => Some(IntermediateAsm::LOAD_LONG_I {high: constant}),
+            0x02 => Some(IntermediateAsm::LOAD_PALETTE {count: constant}),
+            0x03 => Some(IntermediateAsm::SPRITE_WIDTH {width: constant}),
+            0x04 => Some(IntermediateAsm::SPRITE_HEIGHT {height: constant}),
+            0x05 => Some(IntermediateAsm::SET_ALPHA {alpha: constant}),
+            0x06 if constant < 0x10 => Some(IntermediateAsm::PLAY_SAMPLE {nibble}),
+            0x07 if constant == 0x00 => Some(IntermediateAsm::STOP_SAMPLE),
+            0x08 if constant < 0x10 => Some(IntermediateAsm::BLEND_MODE {nibble}),
+            0x09 => Some(IntermediateAsm::COLLISION_COLOUR {index: constant}),
+            _ => decode(opcode),
+        },
+        _ => decode(opcode),
+    }
+}
+
+// Disassembles the instruction at addr, words that aren't instructions
+// are shown as data
+pub fn disassemble(memory_ref: &Chip8Memory, addr: u16) -> String {
+    disassemble_for(Platform::Chip8, memory_ref, addr)
+}
+
+pub fn disassemble_for(platform: Platform, memory_ref: &Chip8Memory, addr: u16) -> String {
+    let word = |addr: u16| ((memory_ref.read_byte(addr) as u16) << 8) | memory_ref.read_byte(addr.wrapping_add(1)) as u16;
+    let opcode = word(addr);
+    match decode_for(platform, opcode) {
+        Some(IntermediateAsm::LOAD_LONG_I {high}) =>
+            format!("LDHI I, #{:02X}{:04X}", high, word(addr.wrapping_add(2))),
+        Some(instruction) => format!("{}", instruction),
+        None => format!("DW #{:04X}", opcode),
+    }
+}
+
+// disassemble_for with addresses replaced by their labels
+pub fn disassemble_with_symbols(platform: Platform, memory_ref: &Chip8Memory, addr: u16, symbols: Option<&Symbols>) -> String {
+    let text = disassemble_for(platform, memory_ref, addr);
+    let word = ((memory_ref.read_byte(addr) as u16) << 8) | memory_ref.read_byte(addr.wrapping_add(1)) as u16;
+    let target = decode_for(platform, word).and_then(|instruction| instruction.target());
+    match (target, symbols) {
+        (Some(target), Some(symbols)) => match symbols.label(target as u32) {
+            Some(label) => text.replace(&format!("#{:03X}", target), &label),
+            None => text,
+        },
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8;
+    use chip8::platform::Platform;
+    use chip8::quirks::Quirks;
+    use chip8::rom::Rom;
+
+    // boots program at 0x200 and runs the given number of instructions
+    fn run(program: &[u8], steps: usize) -> Chip8 {
+        run_with_quirks(program, steps, Quirks::default())
+    }
+
+    fn run_with_quirks(program: &[u8], steps: usize, quirks: Quirks) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(program.to_vec()).unwrap()).unwrap();
+        chip8.set_quirks(quirks);
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+        chip8
+    }
+
+    // runs steps - 1 instructions, then the one that should fault
+    fn run_to_fault(program: &[u8], steps: usize) -> (Chip8, CpuError) {
+        let mut chip8 = run(program, steps - 1);
+        let error = chip8.step().unwrap_err();
+        (chip8, error)
+    }
+
+    // loads VX and VY with 6XNN then runs a single 8XYN
+    fn run_alu(x: u8, vx: u8, y: u8, vy: u8, n: u8) -> Chip8 {
+        run(&[0x60 | x, vx, 0x60 | y, vy, 0x80 | x, (y << 4) | n], 3)
+    }
+
+    #[test]
+    fn cls_clears_the_display() {
+        // draw the "0" glyph, then clear
+        let chip8 = run(&[0xA0, 0x00, 0xD0, 0x05, 0x00, 0xE0], 3);
+        for y in 0..32 {
+            for x in 0..64 {
+                assert!(!chip8.graphics().get_pixel_value(x, y));
+            }
+        }
+        assert_eq!(chip8.cpu.reg_pc, 0x206);
+        assert!(chip8.cpu.draw_to_screen_flag);
+    }
+
+    #[test]
+    fn call_pushes_the_return_address() {
+        let chip8 = run(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xE0], 1);
+        assert_eq!(chip8.cpu.reg_pc, 0x204);
+        assert_eq!(chip8.cpu.reg_sp, 1);
+        assert_eq!(chip8.cpu.stack(), &[0x202]);
+    }
+
+    #[test]
+    fn ret_pops_the_return_address() {
+        let chip8 = run(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE], 2);
+        assert_eq!(chip8.cpu.reg_pc, 0x202);
+        assert_eq!(chip8.cpu.reg_sp, 0);
+        assert!(chip8.cpu.stack().is_empty());
+    }
+
+    #[test]
+    fn ret_with_an_empty_stack_faults() {
+        let (chip8, error) = run_to_fault(&[0x00, 0xEE], 1);
+        assert_eq!(error.fault, Fault::StackUnderflow);
+        assert_eq!(chip8.cpu.reg_pc, 0x200);
+    }
+
+    #[test]
+    fn the_stack_holds_sixteen_calls() {
+        // every call calls the next word
+        let mut program = Vec::new();
+        for n in 0..16u16 {
+            let addr = 0x202 + n * 2;
+            program.push(0x20 | (addr >> 8) as u8);
+            program.push(addr as u8);
+        }
+        let chip8 = run(&program, 16);
+        assert_eq!(chip8.cpu.reg_sp, 16);
+        assert_eq!(chip8.cpu.stack()[0], 0x202);
+        assert_eq!(chip8.cpu.stack()[15], 0x220);
+    }
+
+    #[test]
+    fn call_with_a_full_stack_faults() {
+        // calls itself forever
+        let (chip8, error) = run_to_fault(&[0x22, 0x00], 17);
+        assert_eq!(error.fault, Fault::StackOverflow);
+        assert_eq!(chip8.cpu.reg_sp, 16);
+    }
+
+    #[test]
+    fn sys_without_the_1802_faults() {
+        let (_, error) = run_to_fault(&[0x01, 0x23], 1);
+        assert_eq!(error.fault, Fault::MachineCode(0x123));
+    }
+
+    #[test]
+    fn illegal_instructions_fault_until_the_program_moves_on() {
+        let (mut chip8, error) = run_to_fault(&[0x60, 0x01, 0xFF, 0xFF], 2);
+        assert_eq!(error.fault, Fault::IllegalInstruction(0xFFFF));
+        assert!(error.to_string().starts_with("FFFF is not a Chip-8 instruction\nAt: "));
+        assert_eq!(chip8.cpu.reg_pc, 0x202);
+        assert_eq!(chip8.run_frame(), Err(error));
+    }
+
+    // boots program with machine code at 0x300 and runs the SYS at 0x200
+    fn run_machine_code(program: &[u8], machine_code: &[u8]) -> Chip8 {
+        let mut rom = program.to_vec();
+        rom.resize(0x100, 0);
+        rom.extend_from_slice(machine_code);
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(rom).unwrap()).unwrap();
+        chip8.set_rca1802(true);
+        for _ in 0..(program.len() / 2) {
+            chip8.step().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn sys_runs_machine_code_on_the_vip_registers() {
+        // LD V5, #10, SYS #300 with
+        // LDI #2A, STR R6 (V3 as X is 3), GLO RA, ADI #01, PLO RA, SEP R4
+        let chip8 = run_machine_code(&[0x65, 0x10, 0x03, 0x00],
+                                     &[0xF8, 0x2A, 0x56, 0x8A, 0xFC, 0x01, 0xAA, 0xD4]);
+        assert_eq!(chip8.cpu.v(3), 0x2A);
+        assert_eq!(chip8.cpu.v(5), 0x10);
+        assert_eq!(chip8.cpu.i(), 0x001);
+        assert_eq!(chip8.cpu.reg_pc, 0x204);
+        assert_eq!(chip8.cpu.machine_code_cycles, 6 * 2);
+    }
+
+    #[test]
+    fn sys_machine_code_that_never_returns_faults() {
+        // SYS #300 with BR #00, a loop on itself
+        let mut rom = vec![0x03, 0x00];
+        rom.resize(0x100, 0);
+        rom.extend_from_slice(&[0x30, 0x00]);
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(rom).unwrap()).unwrap();
+        chip8.set_rca1802(true);
+        assert_eq!(chip8.step().unwrap_err().fault, Fault::MachineCodeRunaway(0x300));
+        assert!(chip8.cpu.rca1802.is_some());
+    }
+
+    #[test]
+    fn sys_machine_code_draws_to_the_vip_display() {
+        // LDI #80, STR RB (top left display byte), SEP R4
+        let chip8 = run_machine_code(&[0x03, 0x00], &[0xF8, 0x80, 0x5B, 0xD4]);
+        assert!(chip8.graphics().get_pixel_value(0, 0));
+        assert!(!chip8.graphics().get_pixel_value(1, 0));
+    }
+
+    #[test]
+    fn jump_sets_the_program_counter() {
+        let chip8 = run(&[0x1A, 0xBC], 1);
+        assert_eq!(chip8.cpu.reg_pc, 0xABC);
+    }
+
+    #[test]
+    fn se_const_skips_when_equal() {
+        assert_eq!(run(&[0x63, 0x42, 0x33, 0x42], 2).cpu.reg_pc, 0x206);
+        assert_eq!(run(&[0x63, 0x41, 0x33, 0x42], 2).cpu.reg_pc, 0x204);
+    }
+
+    #[test]
+    fn sne_const_skips_when_not_equal() {
+        assert_eq!(run(&[0x63, 0x42, 0x43, 0x42], 2).cpu.reg_pc, 0x204);
+        assert_eq!(run(&[0x63, 0x41, 0x43, 0x42], 2).cpu.reg_pc, 0x206);
+    }
+
+    #[test]
+    fn se_reg_skips_when_equal() {
+        assert_eq!(run(&[0x61, 0x07, 0x62, 0x07, 0x51, 0x20], 3).cpu.reg_pc, 0x208);
+        assert_eq!(run(&[0x61, 0x07, 0x62, 0x08, 0x51, 0x20], 3).cpu.reg_pc, 0x206);
+    }
+
+    #[test]
+    fn sne_reg_skips_when_not_equal() {
+        assert_eq!(run(&[0x61, 0x07, 0x62, 0x07, 0x91, 0x20], 3).cpu.reg_pc, 0x206);
+        assert_eq!(run(&[0x61, 0x07, 0x62, 0x08, 0x91, 0x20], 3).cpu.reg_pc, 0x208);
+    }
+
+    #[test]
+    fn load_const_sets_the_register() {
+        let chip8 = run(&[0x6A, 0x5C], 1);
+        assert_eq!(chip8.cpu.v(0xA), 0x5C);
+        assert_eq!(chip8.cpu.reg_pc, 0x202);
+    }
+
+    #[test]
+    fn add_const_wraps_without_touching_vf() {
+        let chip8 = run(&[0x6F, 0x07, 0x62, 0xFF, 0x72, 0x02], 3);
+        assert_eq!(chip8.cpu.v(2), 0x01);
+        assert_eq!(chip8.cpu.v(0xF), 0x07);
+        assert_eq!(chip8.cpu.reg_pc, 0x206);
+    }
+
+    #[test]
+    fn load_reg_copies_vy() {
+        let chip8 = run_alu(1, 0x11, 2, 0x22, 0x0);
+        assert_eq!(chip8.cpu.v(1), 0x22);
+        assert_eq!(chip8.cpu.v(2), 0x22);
+        assert_eq!(chip8.cpu.reg_pc, 0x206);
+    }
+
+    #[test]
+    fn bitwise_ops() {
+        assert_eq!(run_alu(1, 0b1100, 2, 0b1010, 0x1).cpu.v(1), 0b1110);
+        assert_eq!(run_alu(1, 0b1100, 2, 0b1010, 0x2).cpu.v(1), 0b1000);
+        assert_eq!(run_alu(1, 0b1100, 2, 0b1010, 0x3).cpu.v(1), 0b0110);
+    }
+
+    #[test]
+    fn bitwise_ops_reset_vf_with_the_quirk() {
+        for n in 1..4 {
+            let program = [0x6F, 0x01, 0x61, 0x0C, 0x80 | 1, 0x10 | n];
+            let chip8 = run_with_quirks(&program, 3, Quirks { vf_reset: true, ..Quirks::default() });
+            assert_eq!(chip8.cpu.v(0xF), 0);
+            let chip8 = run(&program, 3);
+            assert_eq!(chip8.cpu.v(0xF), 1);
+        }
+    }
+
+    #[test]
+    fn add_reg_adds_vy() {
+        let chip8 = run_alu(1, 0x10, 2, 0x22, 0x4);
+        assert_eq!(chip8.cpu.v(1), 0x32);
+        assert_eq!(chip8.cpu.v(0xF), 0);
+        assert_eq!(chip8.cpu.reg_pc, 0x206);
+    }
+
+    #[test]
+    fn add_reg_carries_from_vx_plus_vy() {
+        // VX + VX would not carry here, VX + VY does
+        let chip8 = run_alu(1, 0x01, 2, 0xFF, 0x4);
+        assert_eq!(chip8.cpu.v(1), 0x00);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+
+        let chip8 = run_alu(1, 0x80, 2, 0x7F, 0x4);
+        assert_eq!(chip8.cpu.v(1), 0xFF);
+        assert_eq!(chip8.cpu.v(0xF), 0);
+    }
+
+    #[test]
+    fn add_reg_into_vf_keeps_the_flag() {
+        let chip8 = run_alu(0xF, 0xFF, 2, 0x02, 0x4);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+    }
+
+    #[test]
+    fn sub_reg_sets_vf_without_borrow() {
+        let chip8 = run_alu(1, 0x30, 2, 0x10, 0x5);
+        assert_eq!(chip8.cpu.v(1), 0x20);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+
+        let chip8 = run_alu(1, 0x10, 2, 0x30, 0x5);
+        assert_eq!(chip8.cpu.v(1), 0xE0);
+        assert_eq!(chip8.cpu.v(0xF), 0);
+    }
+
+    #[test]
+    fn sub_reg_of_equal_values_does_not_borrow() {
+        let chip8 = run_alu(1, 0x42, 2, 0x42, 0x5);
+        assert_eq!(chip8.cpu.v(1), 0x00);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+    }
+
+    #[test]
+    fn subn_subtracts_vx_from_vy() {
+        let chip8 = run_alu(1, 0x10, 2, 0x30, 0x7);
+        assert_eq!(chip8.cpu.v(1), 0x20);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+
+        let chip8 = run_alu(1, 0x30, 2, 0x10, 0x7);
+        assert_eq!(chip8.cpu.v(1), 0xE0);
+        assert_eq!(chip8.cpu.v(0xF), 0);
+
+        let chip8 = run_alu(1, 0x42, 2, 0x42, 0x7);
+        assert_eq!(chip8.cpu.v(1), 0x00);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+    }
+
+    #[test]
+    fn shr_shifts_vy_into_vx() {
+        let chip8 = run_alu(1, 0x00, 2, 0x05, 0x6);
+        assert_eq!(chip8.cpu.v(1), 0x02);
+        assert_eq!(chip8.cpu.v(2), 0x05);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+
+        let chip8 = run_alu(1, 0x00, 2, 0x04, 0x6);
+        assert_eq!(chip8.cpu.v(1), 0x02);
+        assert_eq!(chip8.cpu.v(0xF), 0);
+    }
+
+    #[test]
+    fn shl_shifts_vy_into_vx() {
+        let chip8 = run_alu(1, 0x00, 2, 0x81, 0xE);
+        assert_eq!(chip8.cpu.v(1), 0x02);
+        assert_eq!(chip8.cpu.v(2), 0x81);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+
+        let chip8 = run_alu(1, 0x00, 2, 0x41, 0xE);
+        assert_eq!(chip8.cpu.v(1), 0x82);
+        assert_eq!(chip8.cpu.v(0xF), 0);
+    }
+
+    #[test]
+    fn shifts_ignore_vy_with_the_quirk() {
+        let quirks = Quirks { shift_ignores_vy: true, ..Quirks::default() };
+        let chip8 = run_with_quirks(&[0x61, 0x81, 0x62, 0x10, 0x81, 0x26], 3, quirks);
+        assert_eq!(chip8.cpu.v(1), 0x40);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+        let chip8 = run_with_quirks(&[0x61, 0x81, 0x62, 0x10, 0x81, 0x2E], 3, quirks);
+        assert_eq!(chip8.cpu.v(1), 0x02);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+    }
+
+    #[test]
+    fn shift_into_vf_keeps_the_flag() {
+        let chip8 = run_alu(0xF, 0x00, 2, 0x01, 0x6);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+        let chip8 = run_alu(0xF, 0x00, 2, 0x80, 0xE);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+    }
+
+    #[test]
+    fn load_addr_sets_i() {
+        let chip8 = run(&[0xA1, 0x23], 1);
+        assert_eq!(chip8.cpu.i(), 0x123);
+        assert_eq!(chip8.cpu.reg_pc, 0x202);
+    }
+
+    #[test]
+    fn jump_v0_adds_v0() {
+        let chip8 = run(&[0x60, 0x04, 0xB3, 0x00], 2);
+        assert_eq!(chip8.cpu.reg_pc, 0x304);
+    }
+
+    #[test]
+    fn jump_v0_uses_vx_with_the_quirk() {
+        let quirks = Quirks { jump_uses_vx: true, ..Quirks::default() };
+        let chip8 = run_with_quirks(&[0x60, 0x04, 0x63, 0x08, 0xB3, 0x00], 3, quirks);
+        assert_eq!(chip8.cpu.reg_pc, 0x308);
+    }
+
+    #[test]
+    fn rnd_is_masked() {
+        assert_eq!(run(&[0x61, 0xFF, 0xC1, 0x00], 2).cpu.v(1), 0);
+        for _ in 0..32 {
+            let chip8 = run(&[0xC1, 0x0F], 1);
+            assert_eq!(chip8.cpu.v(1) & 0xF0, 0);
+            assert_eq!(chip8.cpu.reg_pc, 0x202);
         }
     }
 
+    #[test]
+    fn drw_draws_msb_first() {
+        // the "1" glyph at 0x5 is 0x20 0x60 0x20 0x20 0x70
+        let chip8 = run(&[0x60, 0x01, 0x61, 0x02, 0xA0, 0x05, 0xD0, 0x15], 4);
+        let graphics = chip8.graphics();
+        assert!(!graphics.get_pixel_value(1, 2));
+        assert!(!graphics.get_pixel_value(2, 2));
+        assert!(graphics.get_pixel_value(3, 2));
+        assert!(graphics.get_pixel_value(2, 3));
+        assert!(graphics.get_pixel_value(3, 3));
+        assert!(graphics.get_pixel_value(2, 6));
+        assert!(graphics.get_pixel_value(4, 6));
+        assert!(!graphics.get_pixel_value(5, 6));
+        assert_eq!(chip8.cpu.v(0xF), 0);
+        assert_eq!(chip8.cpu.i(), 0x005);
+        assert_eq!(chip8.cpu.reg_pc, 0x208);
+    }
+
+    #[test]
+    fn drw_sets_vf_on_collision() {
+        let chip8 = run(&[0xA0, 0x00, 0xD0, 0x05, 0xD0, 0x05], 3);
+        assert_eq!(chip8.cpu.v(0xF), 1);
+        assert!(!chip8.graphics().get_pixel_value(0, 0));
+    }
+
+    #[test]
+    fn drw_clips_at_the_edge() {
+        // V0 = 60, the right half of the "0" glyph is cut off
+        let chip8 = run(&[0x60, 0x3C, 0xA0, 0x00, 0xD0, 0x15], 3);
+        assert!(chip8.graphics().get_pixel_value(60, 0));
+        assert!(chip8.graphics().get_pixel_value(63, 0));
+        assert!(!chip8.graphics().get_pixel_value(0, 0));
+        assert_eq!(chip8.cpu.v(0xF), 0);
+    }
+
+    #[test]
+    fn drw_wraps_the_position() {
+        // V0 = 64 + 2 starts at column 2
+        let chip8 = run(&[0x60, 0x42, 0xA0, 0x00, 0xD0, 0x15], 3);
+        assert!(chip8.graphics().get_pixel_value(2, 0));
+        assert!(!chip8.graphics().get_pixel_value(1, 0));
+    }
+
+    #[test]
+    fn skp_and_sknp_follow_the_keypad() {
+        let program = [0x61, 0x0A, 0xE1, 0x9E];
+        let mut chip8 = run(&program, 1);
+        chip8.set_key(0xA, true);
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.reg_pc, 0x206);
+        assert_eq!(run(&program, 2).cpu.reg_pc, 0x204);
+
+        let program = [0x61, 0x0A, 0xE1, 0xA1];
+        let mut chip8 = run(&program, 1);
+        chip8.set_key(0xA, true);
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.reg_pc, 0x204);
+        assert_eq!(run(&program, 2).cpu.reg_pc, 0x206);
+    }
+
+    #[test]
+    fn delay_timer_round_trip() {
+        let chip8 = run(&[0x61, 0x20, 0xF1, 0x15, 0xF2, 0x07], 3);
+        assert_eq!(chip8.cpu.delay_timer(), 0x20);
+        assert_eq!(chip8.cpu.v(2), 0x20);
+        assert_eq!(chip8.cpu.reg_pc, 0x206);
+    }
+
+    #[test]
+    fn timers_tick_once_per_frame() {
+        let mut chip8 = run(&[0x61, 0x20, 0xF1, 0x15, 0xF1, 0x18, 0x12, 0x06], 3);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.delay_timer(), 0x1F);
+        assert_eq!(chip8.cpu.sound_timer(), 0x1F);
+    }
+
+    #[test]
+    fn sound_timer_is_set_from_vx() {
+        let chip8 = run(&[0x61, 0x33, 0xF1, 0x18], 2);
+        assert_eq!(chip8.cpu.sound_timer(), 0x33);
+        assert_eq!(chip8.cpu.reg_pc, 0x204);
+    }
+
+    #[test]
+    fn wait_for_key_press_halts_until_a_key() {
+        let mut chip8 = run(&[0xF3, 0x0A, 0x64, 0x01], 1);
+        assert!(chip8.cpu.is_halted_flag);
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.reg_pc, 0x202);
+        assert_eq!(chip8.cpu.v(4), 0);
+
+        chip8.set_key(0x7, true);
+        assert!(!chip8.cpu.is_halted_flag);
+        assert_eq!(chip8.cpu.v(3), 0x7);
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.v(4), 1);
+    }
+
+    #[test]
+    fn add_i_adds_vx() {
+        let chip8 = run(&[0xA1, 0x00, 0x62, 0x23, 0xF2, 0x1E], 3);
+        assert_eq!(chip8.cpu.i(), 0x123);
+        assert_eq!(chip8.cpu.v(0xF), 0);
+        assert_eq!(chip8.cpu.reg_pc, 0x206);
+    }
+
+    #[test]
+    fn load_sprite_location_points_at_the_font() {
+        for digit in 0..16u8 {
+            let chip8 = run(&[0x65, digit, 0xF5, 0x29], 2);
+            assert_eq!(chip8.cpu.i(), digit as u32 * 5);
+        }
+        // the high nibble is ignored
+        assert_eq!(run(&[0x65, 0xAB, 0xF5, 0x29], 2).cpu.i(), 0xB * 5);
+    }
+
+    #[test]
+    fn store_bcd_writes_three_digits() {
+        let chip8 = run(&[0x61, 234, 0xA3, 0x00, 0xF1, 0x33], 3);
+        let memory = &chip8.bus.memory;
+        assert_eq!(memory.read_byte(0x300), 2);
+        assert_eq!(memory.read_byte(0x301), 3);
+        assert_eq!(memory.read_byte(0x302), 4);
+        assert_eq!(chip8.cpu.i(), 0x300);
+        assert_eq!(chip8.cpu.reg_pc, 0x206);
+    }
+
+    #[test]
+    fn store_reg_arr_writes_v0_to_vx() {
+        let chip8 = run(&[0x60, 0x0A, 0x61, 0x0B, 0x62, 0x0C, 0xA3, 0x00, 0xF1, 0x55], 5);
+        let memory = &chip8.bus.memory;
+        assert_eq!(memory.read_byte(0x300), 0x0A);
+        assert_eq!(memory.read_byte(0x301), 0x0B);
+        assert_eq!(memory.read_byte(0x302), 0x00);
+        assert_eq!(chip8.cpu.i(), 0x300);
+    }
+
+    #[test]
+    fn memory_ops_wrap_at_the_end_of_memory() {
+        let chip8 = run(&[0x60, 0x0A, 0x61, 0x0B, 0xAF, 0xFF, 0xF1, 0x55], 4);
+        let memory = &chip8.bus.memory;
+        assert_eq!(memory.read_byte(0xFFF), 0x0A);
+        assert_eq!(memory.read_byte(0x000), 0x0B);
+    }
+
+    #[test]
+    fn load_reg_arr_reads_v0_to_vx() {
+        // reads its own first three bytes back
+        let chip8 = run(&[0xA2, 0x00, 0xF2, 0x65], 2);
+        assert_eq!(chip8.cpu.v(0), 0xA2);
+        assert_eq!(chip8.cpu.v(1), 0x00);
+        assert_eq!(chip8.cpu.v(2), 0xF2);
+        assert_eq!(chip8.cpu.v(3), 0x00);
+        assert_eq!(chip8.cpu.i(), 0x200);
+        assert_eq!(chip8.cpu.reg_pc, 0x204);
+    }
+
+    #[test]
+    fn memory_ops_increment_i_with_the_quirk() {
+        let quirks = Quirks { memory_increments_i: true, ..Quirks::default() };
+        assert_eq!(run_with_quirks(&[0xA3, 0x00, 0xF2, 0x55], 2, quirks).cpu.i(), 0x303);
+        assert_eq!(run_with_quirks(&[0xA3, 0x00, 0xF2, 0x65], 2, quirks).cpu.i(), 0x303);
+    }
+
+    #[test]
+    fn display_wait_ends_the_frame() {
+        let program = [0xA0, 0x00, 0xD0, 0x05, 0x71, 0x01, 0x12, 0x02];
+        let mut chip8 = run_with_quirks(&program, 0, Quirks { display_wait: true, ..Quirks::default() });
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.reg_pc, 0x204);
+        assert_eq!(chip8.cpu.v(1), 0);
+    }
+
+    #[test]
+    fn vip_timing_runs_by_cycles() {
+        use chip8::timing::*;
+        // ADD V1, 1 in a loop costs 50 + 52 cycles a round
+        let mut chip8 = run(&[0x71, 0x01, 0x12, 0x00], 0);
+        chip8.set_timing(Timing::CosmacVip);
+        chip8.run_frame().unwrap();
+        let budget = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+        let rounds = budget.div_ceil(102);
+        assert_eq!(chip8.cpu.v(1) as u32, rounds);
+    }
+
+    #[test]
+    fn vip_timing_waits_for_the_display() {
+        use chip8::timing::Timing;
+        let program = [0xA0, 0x00, 0xD0, 0x05, 0x71, 0x01, 0x12, 0x02];
+        let mut chip8 = run(&program, 0);
+        chip8.set_timing(Timing::CosmacVip);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.reg_pc, 0x204);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu.reg_pc, 0x204);
+        assert_eq!(chip8.cpu.v(1), 1);
+    }
+
+    fn run_chip8x(program: &[u8], steps: usize) -> Chip8 {
+        use chip8::platform::Platform;
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(Platform::Chip8X).unwrap();
+        chip8.boot(&Rom::with_load_address(program.to_vec(), 0x300).unwrap()).unwrap();
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn chip8x_decodes_its_own_instructions() {
+        use chip8::platform::Platform;
+        assert_eq!(decode_for(Platform::Chip8X, 0x02A0), Some(IntermediateAsm::CYCLE_BACKGROUND));
+        assert_eq!(decode_for(Platform::Chip8, 0x02A0), Some(IntermediateAsm::SYS {addr: 0x2A0}));
+        assert_eq!(decode_for(Platform::Chip8X, 0xB120), Some(IntermediateAsm::SET_COLOUR_ZONE {reg_x_index: 1, reg_y_index: 2}));
+        assert_eq!(decode_for(Platform::Chip8, 0xB120), Some(IntermediateAsm::JUMP_V0 {addr: 0x120}));
+        assert_eq!(decode_for(Platform::Chip8, 0x5121), None);
+        assert_eq!(format!("{}", decode_for(Platform::Chip8X, 0xE3F2).unwrap()), "SKP2 V3");
+    }
+
+    #[test]
+    fn chip8x_adds_nibbles_modulo_8() {
+        let chip8 = run_chip8x(&[0x61, 0x35, 0x62, 0x46, 0x51, 0x21], 3);
+        assert_eq!(chip8.cpu.v(1), 0x73);
+        assert_eq!(chip8.cpu.v(2), 0x46);
+    }
+
+    #[test]
+    fn chip8x_colours_zones() {
+        // three zones from column 1, two zones from zone row 2, colour 5
+        let chip8 = run_chip8x(&[0x60, 0x21, 0x61, 0x12, 0x62, 0x05, 0xB0, 0x20], 4);
+        let colours = chip8.colours().unwrap();
+        assert_eq!(colours.foreground_at(8, 8), 5);
+        assert_eq!(colours.foreground_at(31, 15), 5);
+        assert_eq!(colours.foreground_at(32, 8), 1);
+        assert_eq!(colours.foreground_at(8, 16), 1);
+        assert_eq!(colours.foreground_at(7, 8), 1);
+    }
+
+    #[test]
+    fn chip8x_colours_rows() {
+        // three rows from row 3 of the column holding x = 16, colour 4
+        let chip8 = run_chip8x(&[0x64, 0x10, 0x65, 0x03, 0x66, 0x04, 0xB4, 0x63], 4);
+        let colours = chip8.colours().unwrap();
+        assert_eq!(colours.foreground_at(16, 3), 4);
+        assert_eq!(colours.foreground_at(23, 5), 4);
+        assert_eq!(colours.foreground_at(16, 6), 1);
+        assert_eq!(colours.foreground_at(24, 3), 1);
+    }
+
+    #[test]
+    fn chip8x_cycles_the_background() {
+        let chip8 = run_chip8x(&[0x02, 0xA0, 0x02, 0xA0], 2);
+        assert_eq!(chip8.colours().unwrap().background, 2);
+    }
+
+    #[test]
+    fn chip8x_second_keypad() {
+        let mut chip8 = run_chip8x(&[0x60, 0x05, 0xE0, 0xF2], 1);
+        chip8.set_keypad2_key(5, true);
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.reg_pc, 0x306);
+        // the first keypad doesn't count
+        let mut chip8 = run_chip8x(&[0x60, 0x05, 0xE0, 0xF5], 1);
+        chip8.set_key(5, true);
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.reg_pc, 0x306);
+    }
+
+    #[test]
+    fn chip8x_ports() {
+        let mut chip8 = run_chip8x(&[0x63, 0x42, 0xF3, 0xF8, 0xF4, 0xFB], 2);
+        assert_eq!(chip8.bus.port_output, 0x42);
+        chip8.bus.port_input = 0x99;
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.v(4), 0x99);
+    }
+
+    fn run_megachip(program: &[u8], steps: usize) -> Chip8 {
+        use chip8::platform::Platform;
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(Platform::MegaChip).unwrap();
+        chip8.boot(&Rom::new(program.to_vec()).unwrap()).unwrap();
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn megachip_decodes_its_own_instructions() {
+        use chip8::platform::Platform;
+        assert_eq!(decode_for(Platform::MegaChip, 0x0011), Some(IntermediateAsm::MEGA_ON));
+        assert_eq!(decode_for(Platform::MegaChip, 0x0806), Some(IntermediateAsm::BLEND_MODE {nibble: 6}));
+        assert_eq!(decode_for(Platform::MegaChip, 0x0810), Some(IntermediateAsm::SYS {addr: 0x810}));
+        assert_eq!(decode_for(Platform::MegaChip, 0x00E0), Some(IntermediateAsm::CLS));
+        assert_eq!(decode_for(Platform::Chip8, 0x0011), Some(IntermediateAsm::SYS {addr: 0x011}));
+
+        let mut memory = Chip8Memory::new();
+        for (offset, byte) in [0x01, 0x12, 0x34, 0x56].iter().enumerate() {
+            memory.write_byte(0x200 + offset as u16, *byte);
+        }
+        assert_eq!(disassemble_for(Platform::MegaChip, &memory, 0x200), "LDHI I, #123456");
+    }
+
+    #[test]
+    fn megachip_mode_switches_the_display() {
+        let chip8 = run_megachip(&[0x00, 0x11], 1);
+        assert_eq!(chip8.graphics().width(), 256);
+        assert_eq!(chip8.graphics().height(), 192);
+        let chip8 = run_megachip(&[0x00, 0x11, 0x00, 0x10], 2);
+        assert_eq!(chip8.graphics().width(), 64);
+    }
+
+    #[test]
+    fn megachip_loads_24_bit_addresses() {
+        let chip8 = run_megachip(&[0x01, 0x12, 0x34, 0x56, 0x65, 0x01], 2);
+        assert_eq!(chip8.cpu.i(), 0x123456);
+        assert_eq!(chip8.cpu.reg_pc, 0x206);
+        assert_eq!(chip8.memory().size(), 0x100_0000);
+        // everywhere else I stays 16 bits wide
+        let mut chip8 = run(&[0x00, 0xE0], 0);
+        chip8.cpu.set_i(0x123456);
+        assert_eq!(chip8.cpu.i(), 0x3456);
+    }
+
+    #[test]
+    fn megachip_draws_palette_sprites() {
+        let program = [
+            0x00, 0x11,             // MEGAON
+            0xA2, 0x20,             // I = palette
+            0x02, 0x01,             // LDPAL 1
+            0x03, 0x02,             // SPRW 2
+            0x04, 0x01,             // SPRH 1
+            0xA2, 0x24,             // I = sprite
+            0x60, 0x05,
+            0xD0, 0x00,             // DRW V0, V0
+            0x00, 0xE0,             // CLS shows the picture
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0xFF, 0x11, 0x22, 0x33, // colour 1
+            0x01, 0x00,             // sprite
+        ];
+        let chip8 = run_megachip(&program, 9);
+        match *chip8.graphics() {
+            Chip8Graphics::MegaChip(ref display) => {
+                assert_eq!(display.colour_at(5, 5), 0x112233);
+                assert_eq!(display.colour_at(6, 5), 0);
+            },
+            _ => panic!("not in MEGA-CHIP mode"),
+        }
+        assert!(chip8.graphics().get_pixel_value(5, 5));
+    }
+
+    #[test]
+    fn megachip_plays_and_stops_samples() {
+        let program = [0xA2, 0x08, 0x06, 0x01, 0x07, 0x00, 0x00, 0x00,
+                       0x1F, 0x40, 0x00, 0x00, 0x01, 0x00, 0x42];
+        let mut chip8 = run_megachip(&program, 2);
+        {
+            let sample = chip8.sample_mut().unwrap();
+            assert_eq!(*sample.samples, [0x42]);
+            assert!(!sample.looping);
+        }
+        chip8.step().unwrap();
+        assert!(chip8.sample_mut().is_none());
+    }
+
+    fn boot_platform(platform: Platform, program: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(platform).unwrap();
+        chip8.boot(&Rom::with_load_address(program.to_vec(), platform.load_address()).unwrap()).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn hires_programs_skip_the_boot_jump() {
+        let mut program = vec![0x12, 0x60];
+        program.resize(0xC0, 0);
+        // draw the "0" glyph on the bottom rows, then clear the screen
+        program.extend_from_slice(&[0x60, 0x3B, 0xA0, 0x00, 0xD0, 0x05, 0x02, 0x30]);
+        let mut chip8 = boot_platform(Platform::HiresChip8, &program);
+        assert_eq!(chip8.cpu.reg_pc, 0x2C0);
+        assert_eq!(chip8.graphics().width(), 64);
+        assert_eq!(chip8.graphics().height(), 64);
+
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert!(chip8.graphics().get_pixel_value(59, 63));
+        chip8.step().unwrap();
+        assert!(!chip8.graphics().get_pixel_value(59, 63));
+    }
+
+    #[test]
+    fn eti660_layout() {
+        assert_eq!(Platform::parse("eti660"), Ok(Platform::Eti660));
+        let chip8 = boot_platform(Platform::Eti660, &[0x00, 0xE0]);
+        assert_eq!(chip8.cpu.reg_pc, 0x600);
+        assert_eq!(chip8.graphics().width(), 64);
+        assert_eq!(chip8.graphics().height(), 48);
+        // 0x1260 only means something to the hi-res interpreter
+        assert_eq!(boot_platform(Platform::Chip8, &[0x12, 0x60]).cpu.reg_pc, 0x200);
+    }
+
+    #[test]
+    fn decode_and_disassemble() {
+        assert_eq!(decode(0x00E0), Some(IntermediateAsm::CLS));
+        assert_eq!(decode(0x8124), Some(IntermediateAsm::ADD_REG {reg_x_index: 1, reg_y_index: 2}));
+        assert_eq!(decode(0x5121), None);
+        assert_eq!(decode(0xFFFF), None);
+        assert_eq!(format!("{}", decode(0xD12F).unwrap()), "DRW V1, V2, 15");
+        assert_eq!(format!("{}", decode(0xA2F0).unwrap()), "LD I, #2F0");
+        assert_eq!(format!("{}", decode(0xF365).unwrap()), "LD V3, [I]");
+    }
+
+    #[test]
+    fn disassemble_with_labels() {
+        let mut memory = Chip8Memory::new();
+        for (offset, byte) in [0x22, 0xA0, 0xA2, 0xB4, 0x12, 0x00, 0x60, 0x01].iter().enumerate() {
+            memory.write_byte(0x200 + offset as u16, *byte);
+        }
+        let symbols = Symbols::parse("2A0 draw_player
+2B0 sprites
+").unwrap();
+        let listing: Vec<String> = (0..4).map(|i| disassemble_with_symbols(Platform::Chip8, &memory, 0x200 + i * 2, Some(&symbols))).collect();
+        assert_eq!(listing, vec!["CALL draw_player", "LD I, sprites+4", "JP #200", "LD V0, #01"]);
+        assert_eq!(disassemble_with_symbols(Platform::Chip8, &memory, 0x200, None), "CALL #2A0");
+    }
 }
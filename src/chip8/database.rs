@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use chip8::json::Json;
+use chip8::keymap::Keymap;
+use chip8::platform::Platform;
+use chip8::quirks::Quirks;
+use chip8::video::Palette;
+
+// the database that ships with the emulator
+const BUILTIN_DATABASE: &str = include_str!("database.txt");
+
+// What is known about a particular rom
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub author: Option<String>,
+    // the machine the program was written for
+    pub platform: Option<Platform>,
+    pub instructions_per_frame: Option<u32>,
+    pub quirks: Option<Quirks>,
+    pub keymap: Option<Keymap>,
+    pub palette: Option<Palette>,
+}
+
+impl RomInfo {
+    fn new(title: String) -> RomInfo {
+        RomInfo {
+            title,
+            author: None,
+            platform: None,
+            instructions_per_frame: None,
+            quirks: None,
+            keymap: None,
+            palette: None,
+        }
+    }
+}
+
+// Rom metadata keyed by the SHA-1 of the rom image
+//
+// The format is a list of sections, one per rom:
+//
+//     # comment
+//     [0123456789abcdef0123456789abcdef01234567]
+//     title = Some Game
+//     author = Someone
+//     platform = chip8
+//     ipf = 15
+//     quirks = vf_reset,memory,clipping
+//     keymap = x123qweasdzc4rfv
+//     palette = #000000,#ffffff
+//
+// Every key but title is optional.
+#[derive(Debug, Clone)]
+pub struct Database {
+    // shared with the machines running the roms
+    entries: HashMap<String, Arc<RomInfo>>,
+}
+
+impl Database {
+    pub fn new() -> Database {
+        Database {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn builtin() -> Database {
+        Database::parse(BUILTIN_DATABASE).expect("the builtin rom database is invalid")
+    }
+
+    pub fn parse(text: &str) -> Result<Database, String> {
+        let mut database = Database::new();
+        let mut current: Option<(String, RomInfo)> = None;
+
+        for (i, raw_line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                if let Some((sha1, info)) = current.take() {
+                    database.insert(sha1, info)?;
+                }
+                let sha1 = line[1..line.len() - 1].trim().to_ascii_lowercase();
+                if !is_sha1(&sha1) {
+                    return Err(format!("line {}: \"{}\" is not a SHA-1", line_number, sha1));
+                }
+                current = Some((sha1, RomInfo::new(String::new())));
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(split) => (line[..split].trim(), line[split + 1..].trim()),
+                None => return Err(format!("line {}: expected key = value", line_number)),
+            };
+            let info = match current {
+                Some((_, ref mut info)) => info,
+                None => return Err(format!("line {}: {} comes before any [sha1] section", line_number, key)),
+            };
+            let error = |e: String| format!("line {}: {}", line_number, e);
+
+            match key {
+                "title" => info.title = String::from(value),
+                "author" => info.author = Some(String::from(value)),
+                "platform" => info.platform = Some(Platform::parse(value).map_err(&error)?),
+                "ipf" => info.instructions_per_frame = Some(value.parse::<u32>()
+                    .map_err(|_| error(format!("invalid ipf \"{}\"", value)))?),
+                "quirks" => info.quirks = Some(Quirks::parse(value).map_err(&error)?),
+                "keymap" => info.keymap = Some(Keymap::new(value).map_err(&error)?),
+                "palette" => info.palette = Some(Palette::from_name(value).map_err(&error)?),
+                _ => return Err(error(format!("unknown key \"{}\"", key))),
+            }
+        }
+
+        if let Some((sha1, info)) = current.take() {
+            database.insert(sha1, info)?;
+        }
+        Ok(database)
+    }
+
+    // Converts the programs.json of the community CHIP-8 database,
+    // https://github.com/chip-8/chip-8-database
+    //
+    // Every rom of a program gets the first of its platforms the emulator
+    // has, with that platform's quirks as the database lists them. Roms
+    // only made for other platforms just get their title.
+    pub fn from_chip8_database(json: &str) -> Result<Database, String> {
+        let mut database = Database::new();
+        let programs = Json::parse(json)?;
+        for program in programs.as_array().ok_or_else(|| String::from("expected a list of programs"))? {
+            let title = program.get("title").and_then(Json::as_str)
+                .ok_or_else(|| String::from("a program has no title"))?;
+            let authors: Vec<&str> = program.get("authors").and_then(Json::as_array).unwrap_or(&[])
+                .iter().filter_map(Json::as_str).collect();
+            let roms = program.get("roms").and_then(Json::as_object).unwrap_or(&[]);
+
+            for (sha1, rom) in roms {
+                let sha1 = sha1.to_ascii_lowercase();
+                if !is_sha1(&sha1) {
+                    return Err(format!("{}: \"{}\" is not a SHA-1", title, sha1));
+                }
+                let mut info = RomInfo::new(String::from(title));
+                if !authors.is_empty() {
+                    info.author = Some(authors.join(", "));
+                }
+                let platforms = rom.get("platforms").and_then(Json::as_array).unwrap_or(&[]);
+                let known = platforms.iter().filter_map(Json::as_str)
+                    .find_map(|id| community_platform(id).map(|(platform, quirks)| (id, platform, quirks)));
+                if let Some((id, platform, mut quirks)) = known {
+                    if let Some(quirky) = rom.get("quirkyPlatforms").and_then(|quirky| quirky.get(id)) {
+                        apply_community_quirks(&mut quirks, quirky);
+                    }
+                    info.platform = Some(platform);
+                    info.quirks = Some(quirks);
+                }
+                if let Some(tickrate) = rom.get("tickrate").and_then(Json::as_f64) {
+                    info.instructions_per_frame = Some(tickrate as u32);
+                }
+                let colours = rom.get("colors").and_then(|colours| colours.get("pixels")).and_then(Json::as_array).unwrap_or(&[]);
+                if let (Some(background), Some(foreground)) = (colours.first().and_then(Json::as_str), colours.get(1).and_then(Json::as_str)) {
+                    info.palette = Some(Palette::from_name(&format!("{},{}", background, foreground))
+                        .map_err(|e| format!("{}: {}", title, e))?);
+                }
+                database.insert(sha1, info)?;
+            }
+        }
+        Ok(database)
+    }
+
+    // .json files are read as the community database, anything else in
+    // the format above
+    pub fn load_file(path: &Path) -> io::Result<Database> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        let database = if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            Database::from_chip8_database(&text)
+        } else {
+            Database::parse(&text)
+        };
+        database.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn insert(&mut self, sha1: String, info: RomInfo) -> Result<(), String> {
+        if info.title.is_empty() {
+            return Err(format!("[{}] has no title", sha1));
+        }
+        self.entries.insert(sha1, Arc::new(info));
+        Ok(())
+    }
+
+    // entries of other replace the ones already here
+    pub fn merge(&mut self, other: Database) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn lookup(&self, sha1_hex: &str) -> Option<&Arc<RomInfo>> {
+        self.entries.get(&sha1_hex.to_ascii_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for Database {
+    fn default() -> Database {
+        Database::new()
+    }
+}
+
+fn is_sha1(text: &str) -> bool {
+    text.len() == 40 && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// The community database's platform ids with the quirks it gives each.
+// SUPER-CHIP programs run on the plain chip-8 with the SUPER-CHIP quirks.
+fn community_platform(id: &str) -> Option<(Platform, Quirks)> {
+    let modern = Quirks {memory_increments_i: true, clipping: true, ..Quirks::none()};
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::Chip8, Quirks::cosmac_vip())),
+        "modernChip8" => Some((Platform::Chip8, modern)),
+        "chip48" => Some((Platform::Chip8, Quirks {memory_increments_i: false, ..Quirks::super_chip()})),
+        "superchip1" | "superchip" => Some((Platform::Chip8, Quirks::super_chip())),
+        "chip8x" => Some((Platform::Chip8X, Quirks::cosmac_vip())),
+        "megachip8" => Some((Platform::MegaChip, Quirks::super_chip())),
+        _ => None,
+    }
+}
+
+// quirkyPlatforms overrides single quirks of a platform for one rom
+fn apply_community_quirks(quirks: &mut Quirks, overrides: &Json) {
+    let flag = |name: &str| overrides.get(name).and_then(Json::as_bool);
+    if let Some(logic) = flag("logic") {
+        quirks.vf_reset = logic;
+    }
+    if let Some(shift) = flag("shift") {
+        quirks.shift_ignores_vy = shift;
+    }
+    if let Some(jump) = flag("jump") {
+        quirks.jump_uses_vx = jump;
+    }
+    if let Some(wrap) = flag("wrap") {
+        quirks.clipping = !wrap;
+    }
+    if let Some(vblank) = flag("vblank") {
+        quirks.display_wait = vblank;
+    }
+    // I ends up past the last register unless it is left alone or only
+    // moved by X
+    match (flag("memoryIncrementByX"), flag("memoryLeaveIUnchanged")) {
+        (Some(true), _) | (_, Some(true)) => quirks.memory_increments_i = false,
+        (Some(false), Some(false)) => quirks.memory_increments_i = true,
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = "
+# a comment
+[0123456789ABCDEF0123456789abcdef01234567]
+title = Some Game
+author = Someone
+platform = chip8x
+ipf = 15
+quirks = vf_reset, memory
+keymap = x123qweasdzc4rfv
+palette = #102030,#ffffff
+
+[89abcdef0123456789abcdef0123456789abcdef]
+title = Another = Game
+";
+
+    #[test]
+    fn sections_are_keyed_by_sha1() {
+        let database = Database::parse(ENTRY).unwrap();
+        assert_eq!(database.len(), 2);
+
+        let info = database.lookup("0123456789abcdef0123456789ABCDEF01234567").unwrap();
+        assert_eq!(info.title, "Some Game");
+        assert_eq!(info.author.as_deref(), Some("Someone"));
+        assert_eq!(info.platform, Some(Platform::Chip8X));
+        assert_eq!(info.instructions_per_frame, Some(15));
+        let quirks = info.quirks.unwrap();
+        assert!(quirks.vf_reset && quirks.memory_increments_i && !quirks.clipping);
+        assert_eq!(info.keymap.as_ref().unwrap().chip8_key('x'), Some(0));
+        assert_eq!(info.palette.unwrap().background, ::chip8::video::Rgb::new(0x10, 0x20, 0x30));
+
+        // only the first = splits, the rest belongs to the value
+        let other = database.lookup("89abcdef0123456789abcdef0123456789abcdef").unwrap();
+        assert_eq!(other.title, "Another = Game");
+        assert_eq!(other.platform, None);
+        assert!(database.lookup("0000000000000000000000000000000000000000").is_none());
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_line() {
+        let error = |text: &str| Database::parse(text).unwrap_err();
+        let section = "[0123456789abcdef0123456789abcdef01234567]\n";
+
+        assert_eq!(error("[0123]\ntitle = x\n"), "line 1: \"0123\" is not a SHA-1");
+        assert_eq!(error("title = x\n"), "line 1: title comes before any [sha1] section");
+        assert_eq!(error(&format!("{}title\n", section)), "line 2: expected key = value");
+        assert_eq!(error(&format!("{}title = x\nspeed = 9\n", section)), "line 3: unknown key \"speed\"");
+        assert_eq!(error(&format!("{}title = x\nipf = fast\n", section)), "line 3: invalid ipf \"fast\"");
+        assert_eq!(error(&format!("{}title = x\nplatform = nes\n", section)), "line 3: unknown platform \"nes\"");
+        assert_eq!(error(&format!("{}title = x\nquirks = wrapping\n", section)), "line 3: unknown quirk \"wrapping\"");
+        assert!(error(&format!("{}title = x\nkeymap = 1234\n", section)).starts_with("line 3: keymap \"1234\""));
+        assert!(error(&format!("{}title = x\npalette = pink\n", section)).starts_with("line 3: unknown palette"));
+        assert_eq!(error(&format!("{}author = x\n", section)), "[0123456789abcdef0123456789abcdef01234567] has no title");
+    }
+
+    #[test]
+    fn reads_the_community_database() {
+        let json = r##"[
+            {
+                "title": "Some Game",
+                "authors": ["Someone", "Someone Else"],
+                "roms": {
+                    "0123456789ABCDEF0123456789abcdef01234567": {
+                        "file": "game.ch8",
+                        "platforms": ["xochip", "superchip", "originalChip8"],
+                        "quirkyPlatforms": {"superchip": {"wrap": true, "vblank": true}},
+                        "tickrate": 30,
+                        "colors": {"pixels": ["#102030", "#FFFFFF"]}
+                    },
+                    "89abcdef0123456789abcdef0123456789abcdef": {"platforms": ["xochip"]}
+                }
+            },
+            {"title": "Mega", "roms": {"fedcba9876543210fedcba9876543210fedcba98": {"platforms": ["megachip8"]}}}
+        ]"##;
+        let database = Database::from_chip8_database(json).unwrap();
+        assert_eq!(database.len(), 3);
+
+        let info = database.lookup("0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert_eq!(info.title, "Some Game");
+        assert_eq!(info.author.as_deref(), Some("Someone, Someone Else"));
+        assert_eq!(info.platform, Some(Platform::Chip8));
+        assert_eq!(info.quirks, Some(Quirks {clipping: false, display_wait: true, ..Quirks::super_chip()}));
+        assert_eq!(info.instructions_per_frame, Some(30));
+        assert_eq!(info.palette.unwrap().background, ::chip8::video::Rgb::new(0x10, 0x20, 0x30));
+
+        // XO-CHIP programs aren't runnable, but are still named
+        let xochip = database.lookup("89abcdef0123456789abcdef0123456789abcdef").unwrap();
+        assert_eq!((xochip.platform, xochip.quirks), (None, None));
+        assert_eq!(database.lookup("fedcba9876543210fedcba9876543210fedcba98").unwrap().platform, Some(Platform::MegaChip));
+
+        assert!(Database::from_chip8_database("{}").is_err());
+        assert!(Database::from_chip8_database(r#"[{"title": "x", "roms": {"0123": {}}}]"#).is_err());
+    }
+
+    #[test]
+    fn merged_entries_replace_the_builtin_ones() {
+        let mut database = Database::builtin();
+        let builtin = database.len();
+        assert!(builtin > 0);
+        let sha1 = "27a552aa4faea9be9772b487578f53159a38c68d";
+        assert_eq!(database.lookup(sha1).unwrap().title, "Digits Test");
+        let flags = database.lookup("c325b5a375ff5f9c8ed3506058bf09080aabdddb").unwrap();
+        assert_eq!((flags.platform, flags.quirks), (Some(Platform::Chip8), Some(Quirks::cosmac_vip())));
+
+        database.merge(Database::parse(&format!("[{}]\ntitle = Mine\n", sha1)).unwrap());
+        assert_eq!(database.len(), builtin);
+        assert_eq!(database.lookup(sha1).unwrap().title, "Mine");
+    }
+}
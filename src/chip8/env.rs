@@ -0,0 +1,420 @@
+// A reinforcement learning environment around a booted Chip8
+//
+// Actions are a mask of the keys held down, bit N for key N, and
+// observations are a 64x32 picture of the display with one byte per
+// pixel, larger displays are scaled down to it. Rewards and the
+// end of an episode come from expressions over the machine state, e.g.
+// "bcd[0x2F0]" for a score the program keeps as BCD at 0x2F0 or
+// "v5 == 0" for a lives counter in V5. Chip8 is cheap to clone, so
+// environments can be forked for tree search.
+
+use chip8::Chip8;
+use chip8::graphics::{CHIP8_SCREEN_HEIGHT, CHIP8_SCREEN_WIDTH};
+
+// Integer expressions over memory and registers
+//
+//   42, 0x2F0          numbers
+//   v0 - vf            registers
+//   mem[ADDR]          the byte at ADDR
+//   bcd[ADDR]          three bytes of BCD from ADDR, as FX33 stores them
+//   + - * ( )          arithmetic
+//   == != < <= > >=    comparisons, 1 when true and 0 when false
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(i64),
+    Register(u8),
+    Memory(u16),
+    Bcd(u16),
+    Add(Box<Expression>, Box<Expression>),
+    Sub(Box<Expression>, Box<Expression>),
+    Mul(Box<Expression>, Box<Expression>),
+    Compare(Comparison, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let expression = parser.comparison()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => Err(format!("unexpected \"{}\" in \"{}\"", token, text)),
+        }
+    }
+
+    pub fn evaluate(&self, chip8: &Chip8) -> i64 {
+        match *self {
+            Expression::Number(value) => value,
+            Expression::Register(index) => chip8.cpu().v(index as usize) as i64,
+            Expression::Memory(addr) => chip8.memory().read_byte(addr) as i64,
+            Expression::Bcd(addr) => {
+                let digit = |offset: u16| chip8.memory().read_byte(addr.wrapping_add(offset)) as i64;
+                digit(0) * 100 + digit(1) * 10 + digit(2)
+            },
+            Expression::Add(ref a, ref b) => a.evaluate(chip8).wrapping_add(b.evaluate(chip8)),
+            Expression::Sub(ref a, ref b) => a.evaluate(chip8).wrapping_sub(b.evaluate(chip8)),
+            Expression::Mul(ref a, ref b) => a.evaluate(chip8).wrapping_mul(b.evaluate(chip8)),
+            Expression::Compare(comparison, ref a, ref b) => {
+                let (a, b) = (a.evaluate(chip8), b.evaluate(chip8));
+                let result = match comparison {
+                    Comparison::Equal => a == b,
+                    Comparison::NotEqual => a != b,
+                    Comparison::Less => a < b,
+                    Comparison::LessEqual => a <= b,
+                    Comparison::Greater => a > b,
+                    Comparison::GreaterEqual => a >= b,
+                };
+                result as i64
+            },
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else if i + 1 < chars.len() && ["==", "!=", "<=", ">="].contains(&&*format!("{}{}", c, chars[i + 1])) {
+            tokens.push(format!("{}{}", c, chars[i + 1]));
+            i += 2;
+        } else if "+-*()[]<>".contains(c) {
+            tokens.push(c.to_string());
+            i += 1;
+        } else {
+            return Err(format!("unexpected \"{}\" in \"{}\"", c, text));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|token| token.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or_else(|| String::from("expression ends too soon"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(format!("expected \"{}\" but found \"{}\"", expected, token))
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expression, String> {
+        let left = self.sum()?;
+        let comparison = match self.peek() {
+            Some("==") => Comparison::Equal,
+            Some("!=") => Comparison::NotEqual,
+            Some("<") => Comparison::Less,
+            Some("<=") => Comparison::LessEqual,
+            Some(">") => Comparison::Greater,
+            Some(">=") => Comparison::GreaterEqual,
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let right = self.sum()?;
+        Ok(Expression::Compare(comparison, Box::new(left), Box::new(right)))
+    }
+
+    fn sum(&mut self) -> Result<Expression, String> {
+        let mut left = self.product()?;
+        loop {
+            match self.peek() {
+                Some("+") => {
+                    self.position += 1;
+                    left = Expression::Add(Box::new(left), Box::new(self.product()?));
+                },
+                Some("-") => {
+                    self.position += 1;
+                    left = Expression::Sub(Box::new(left), Box::new(self.product()?));
+                },
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Expression, String> {
+        let mut left = self.atom()?;
+        while self.peek() == Some("*") {
+            self.position += 1;
+            left = Expression::Mul(Box::new(left), Box::new(self.atom()?));
+        }
+        Ok(left)
+    }
+
+    fn atom(&mut self) -> Result<Expression, String> {
+        let token = self.next()?;
+        let lower = token.to_ascii_lowercase();
+        if token == "(" {
+            let expression = self.comparison()?;
+            self.expect(")")?;
+            Ok(expression)
+        } else if lower == "mem" || lower == "bcd" {
+            self.expect("[")?;
+            let addr = parse_number(&self.next()?)?;
+            self.expect("]")?;
+            if !(0..=0xFFFF).contains(&addr) {
+                return Err(format!("address {} is outside of memory", addr));
+            }
+            Ok(if lower == "mem" { Expression::Memory(addr as u16) } else { Expression::Bcd(addr as u16) })
+        } else if lower.len() == 2 && lower.starts_with('v') {
+            u8::from_str_radix(&lower[1..], 16)
+                .map(Expression::Register)
+                .map_err(|_| format!("\"{}\" is not a register", token))
+        } else {
+            parse_number(&token).map(Expression::Number)
+        }
+    }
+}
+
+fn parse_number(token: &str) -> Result<i64, String> {
+    let result = if token.starts_with("0x") || token.starts_with("0X") {
+        i64::from_str_radix(&token[2..], 16)
+    } else {
+        token.parse()
+    };
+    result.map_err(|_| format!("\"{}\" is not a number", token))
+}
+
+#[derive(Debug, Clone)]
+pub struct EnvConfig {
+    // frames run for every action
+    pub frame_skip: u32,
+    // the reward of a step is how much this went up during it
+    pub reward: Option<Expression>,
+    // the episode is over once this is non-zero
+    pub done: Option<Expression>,
+    // ends episodes that run this many frames
+    pub max_frames: Option<u32>,
+}
+
+impl Default for EnvConfig {
+    fn default() -> EnvConfig {
+        EnvConfig {
+            frame_skip: 4,
+            reward: None,
+            done: None,
+            max_frames: None,
+        }
+    }
+}
+
+// The display as one byte per pixel, 1 when lit, indexed [y][x]
+pub type Observation = [[u8; CHIP8_SCREEN_WIDTH]; CHIP8_SCREEN_HEIGHT];
+
+// Hi-res and MEGA-CHIP displays are sampled down to 64x32, every
+// observation has the same shape whatever the program does
+pub fn observe(chip8: &Chip8) -> Observation {
+    let graphics = chip8.graphics();
+    let (width, height) = (graphics.width(), graphics.height());
+    let mut observation = [[0; CHIP8_SCREEN_WIDTH]; CHIP8_SCREEN_HEIGHT];
+    for (y, row) in observation.iter_mut().enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = graphics.get_pixel_value(x * width / CHIP8_SCREEN_WIDTH, y * height / CHIP8_SCREEN_HEIGHT) as u8;
+        }
+    }
+    observation
+}
+
+#[derive(Debug, Clone)]
+pub struct Chip8Env {
+    // the machine as it was booted, reset() goes back to it
+    initial: Chip8,
+    chip8: Chip8,
+    config: EnvConfig,
+    // what the reward expression was after the last step
+    score: i64,
+    frames: u32,
+}
+
+impl Chip8Env {
+    // chip8 should be booted and configured already
+    pub fn new(chip8: Chip8, config: EnvConfig) -> Chip8Env {
+        let score = config.reward.as_ref().map_or(0, |reward| reward.evaluate(&chip8));
+        Chip8Env {
+            initial: chip8.clone(),
+            chip8,
+            config,
+            score,
+            frames: 0,
+        }
+    }
+
+    pub fn reset(&mut self) -> Observation {
+        self.chip8 = self.initial.clone();
+        self.score = self.config.reward.as_ref().map_or(0, |reward| reward.evaluate(&self.chip8));
+        self.frames = 0;
+        observe(&self.chip8)
+    }
+
+    // holds the keys in action for frame_skip frames, a fault ends the
+    // episode
+    pub fn step(&mut self, action: u16) -> (Observation, i64, bool) {
+        for key in 0..16 {
+            self.chip8.set_key(key, action & (1 << key) != 0);
+        }
+        let mut faulted = false;
+        for _ in 0..self.config.frame_skip.max(1) {
+            if self.chip8.run_frame().is_err() {
+                faulted = true;
+                break;
+            }
+            self.frames += 1;
+        }
+
+        let reward = match self.config.reward {
+            Some(ref expression) => {
+                let score = expression.evaluate(&self.chip8);
+                let reward = score - self.score;
+                self.score = score;
+                reward
+            },
+            None => 0,
+        };
+        let done = faulted
+            || self.config.done.as_ref().is_some_and(|done| done.evaluate(&self.chip8) != 0)
+            || self.config.max_frames.is_some_and(|max| self.frames >= max);
+        (observe(&self.chip8), reward, done)
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::rom::Rom;
+    use chip8::platform::Platform;
+
+    // adds 1 to the BCD score at 0x300 while key 5 is held, and counts
+    // lives down in V5 while key 6 is
+    const GAME: [u8; 22] = [
+        0x65, 0x03,             // 200: V5 = 3
+        0x61, 0x05,             // 202: V1 = 5
+        0x62, 0x06,             // 204: V2 = 6
+        0xE1, 0xA1,             // 206: skip unless key 5
+        0x74, 0x01,             // 208: V4 += 1
+        0xE2, 0xA1,             // 20A: skip unless key 6
+        0x75, 0xFF,             // 20C: V5 -= 1
+        0xA3, 0x00,             // 20E: I = 0x300
+        0xF4, 0x33,             // 210: BCD V4
+        0x00, 0xE0,             // 212: CLS
+        0x12, 0x06,             // 214: loop
+    ];
+
+    fn env() -> Chip8Env {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(GAME.to_vec()).unwrap()).unwrap();
+        let config = EnvConfig {
+            frame_skip: 1,
+            reward: Some(Expression::parse("bcd[0x300]").unwrap()),
+            done: Some(Expression::parse("v5 == 0").unwrap()),
+            max_frames: None,
+        };
+        Chip8Env::new(chip8, config)
+    }
+
+    #[test]
+    fn expressions() {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(vec![0x63, 0x07, 0xA3, 0x00, 0xF3, 0x33]).unwrap()).unwrap();
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        let evaluate = |text: &str| Expression::parse(text).unwrap().evaluate(&chip8);
+        assert_eq!(evaluate("v3"), 7);
+        assert_eq!(evaluate("mem[0x302] * 2 + 1"), 15);
+        assert_eq!(evaluate("bcd[768]"), 7);
+        assert_eq!(evaluate("(v3 - 10) * 2"), -6);
+        assert_eq!(evaluate("v3 >= 7"), 1);
+        assert_eq!(evaluate("v3 != 7"), 0);
+        assert!(Expression::parse("mem[0x300").is_err());
+        assert!(Expression::parse("vg").is_err());
+        assert!(Expression::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn rewards_and_termination() {
+        let mut env = env();
+        let (observation, reward, done) = env.step(0);
+        assert_eq!(observation, [[0; 64]; 32]);
+        assert_eq!((reward, done), (0, false));
+
+        let (_, reward, _) = env.step(1 << 5);
+        assert!(reward > 0);
+
+        let mut steps = 0;
+        while !env.step(1 << 6).2 {
+            steps += 1;
+            assert!(steps < 10);
+        }
+        assert_eq!(env.chip8().cpu().v(5), 0);
+    }
+
+    #[test]
+    fn hires_observations_are_scaled_down() {
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(Platform::HiresChip8).unwrap();
+        // the top of a 0 glyph, four pixels wide, on line 62 of 64
+        let program = vec![0x60, 0x08, 0x61, 0x3E, 0xF2, 0x29, 0xD0, 0x11];
+        chip8.boot(&Rom::with_load_address(program, Platform::HiresChip8.load_address()).unwrap()).unwrap();
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+
+        let observation = observe(&chip8);
+        assert_eq!(observation[31][4..14], [0, 0, 0, 0, 1, 1, 1, 1, 0, 0]);
+        assert_eq!(observation[30], [0; 64]);
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut env = env();
+        env.step(1 << 5);
+        let mut fork = env.clone();
+        fork.step(1 << 5);
+        assert!(fork.chip8().cpu().v(4) > env.chip8().cpu().v(4));
+
+        env.reset();
+        assert_eq!(env.chip8().cpu().v(4), 0);
+        assert_eq!(env.frames(), 0);
+    }
+}
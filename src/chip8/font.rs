@@ -0,0 +1,289 @@
+// The hex digits FX29 points I at
+//
+// Every interpreter drew its own, so programs written for one can look
+// odd with another's. The small glyphs are 4x5 for 0-F. SCHIP and Octo
+// also have 8x10 glyphs, SCHIP for 0-9 only, which are installed straight
+// after the small ones.
+//
+// A font file is the small glyphs as they are in memory, 80 bytes,
+// optionally followed by 100 or 160 bytes of big glyphs.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use chip8::memory::Chip8Memory;
+
+const SMALL_HEIGHT: u8 = 5;
+const BIG_HEIGHT: u8 = 10;
+
+const VIP: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+const DREAM_6800: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+const ETI_660: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x20, 0x20, 0x20, 0x20, 0x20, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+// SCHIP and Octo share their small glyphs, which are the ones this
+// emulator has always used
+const SCHIP: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+const SCHIP_BIG: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+const OCTO_BIG: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Font {
+    // glyphs for 0-F, SMALL_HEIGHT bytes each
+    pub small: Vec<u8>,
+    // glyphs for 0-9 or 0-F, BIG_HEIGHT bytes each, or none
+    pub big: Vec<u8>,
+    // where the small glyphs start in memory
+    pub address: u16,
+}
+
+impl Font {
+    pub fn new(small: &[u8], big: &[u8]) -> Font {
+        Font {
+            small: small.to_vec(),
+            big: big.to_vec(),
+            address: 0x000,
+        }
+    }
+
+    pub fn vip() -> Font {
+        Font::new(&VIP, &[])
+    }
+
+    pub fn dream_6800() -> Font {
+        Font::new(&DREAM_6800, &[])
+    }
+
+    pub fn eti_660() -> Font {
+        Font::new(&ETI_660, &[])
+    }
+
+    pub fn schip() -> Font {
+        Font::new(&SCHIP, &SCHIP_BIG)
+    }
+
+    pub fn octo() -> Font {
+        Font::new(&SCHIP, &OCTO_BIG)
+    }
+
+    // vip, dream6800, eti660, schip or octo
+    pub fn from_name(name: &str) -> Result<Font, String> {
+        match name.trim() {
+            "vip" => Ok(Font::vip()),
+            "dream6800" => Ok(Font::dream_6800()),
+            "eti660" => Ok(Font::eti_660()),
+            "schip" => Ok(Font::schip()),
+            "octo" => Ok(Font::octo()),
+            _ => Err(format!("unknown font \"{}\"", name)),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Font, String> {
+        let small_len = 16 * SMALL_HEIGHT as usize;
+        let big_len = bytes.len().saturating_sub(small_len);
+        if bytes.len() < small_len || !(big_len == 0 || big_len == 10 * BIG_HEIGHT as usize || big_len == 16 * BIG_HEIGHT as usize) {
+            return Err(format!("a font is 80, 180 or 240 bytes, not {}", bytes.len()));
+        }
+        Ok(Font::new(&bytes[..small_len], &bytes[small_len..]))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Font> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Font::from_bytes(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // what FX29 points I at, only the low nibble picks the digit
+    pub fn glyph_address(&self, digit: u8) -> u16 {
+        self.address.wrapping_add((digit & 0xF) as u16 * SMALL_HEIGHT as u16)
+    }
+
+    // the big glyph for digit, when the font has one
+    pub fn big_glyph_address(&self, digit: u8) -> Option<u16> {
+        let index = (digit & 0xF) as usize;
+        if index * (BIG_HEIGHT as usize) < self.big.len() {
+            Some(self.address.wrapping_add(self.small.len() as u16)
+                             .wrapping_add((index as u16).wrapping_mul(BIG_HEIGHT as u16)))
+        } else {
+            None
+        }
+    }
+
+    // bytes of memory the font takes up
+    pub fn len(&self) -> usize {
+        self.small.len() + self.big.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // written straight into memory, a script watching writes doesn't
+    // see them
+    pub fn install(&self, memory: &mut Chip8Memory) {
+        memory.load(self.address as usize, &self.small);
+        memory.load(self.address as usize + self.small.len(), &self.big);
+    }
+}
+
+impl Default for Font {
+    fn default() -> Font {
+        Font::schip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_fonts_have_every_digit() {
+        for name in ["vip", "dream6800", "eti660", "schip", "octo"].iter() {
+            let font = Font::from_name(name).unwrap();
+            assert_eq!(font.small.len(), 80, "{}", name);
+            // every glyph has something in its top row
+            assert!(font.small.chunks(5).all(|glyph| glyph[0] != 0), "{}", name);
+        }
+        assert!(Font::from_name("fish").is_err());
+        // "E" is three bars, the default used to start it with 0xD0
+        assert_eq!(&Font::default().small[70..75], &[0xF0, 0x80, 0xF0, 0x80, 0xF0]);
+    }
+
+    #[test]
+    fn glyph_addresses_follow_the_base_address() {
+        let mut font = Font::schip();
+        font.address = 0x50;
+        assert_eq!(font.glyph_address(0xA), 0x50 + 50);
+        assert_eq!(font.glyph_address(0xFA), 0x50 + 50);
+        assert_eq!(font.big_glyph_address(9), Some(0x50 + 80 + 90));
+        assert_eq!(font.big_glyph_address(0xA), None);
+        assert_eq!(Font::octo().big_glyph_address(0xF), Some(80 + 150));
+        assert_eq!(Font::vip().big_glyph_address(0), None);
+
+        let mut memory = Chip8Memory::new();
+        font.install(&mut memory);
+        assert_eq!(memory.read_byte(0x50 + 70), 0xF0);
+        assert_eq!(memory.read_byte(0x50 + 80), 0x3C);
+    }
+
+    #[test]
+    fn glyph_addresses_wrap_at_the_top_of_the_address_space() {
+        let mut font = Font::octo();
+        font.address = 0xFFF0;
+        assert_eq!(font.glyph_address(0xF), 0xFFF0u16.wrapping_add(75));
+        assert_eq!(font.big_glyph_address(0), Some(0xFFF0u16.wrapping_add(80)));
+        assert_eq!(font.big_glyph_address(0xF), Some(0xFFF0u16.wrapping_add(80 + 150)));
+    }
+
+    #[test]
+    fn fonts_load_from_their_bytes() {
+        let font = Font::from_bytes(&SCHIP).unwrap();
+        assert_eq!((font.small.len(), font.big.len()), (80, 0));
+        let mut bytes = SCHIP.to_vec();
+        bytes.extend_from_slice(&OCTO_BIG);
+        assert_eq!(Font::from_bytes(&bytes), Ok(Font::octo()));
+        assert!(Font::from_bytes(&SCHIP[..79]).is_err());
+        bytes.push(0);
+        assert!(Font::from_bytes(&bytes).is_err());
+    }
+}
@@ -1,74 +1,281 @@
 use chip8::memory::Chip8Memory;
+use chip8::megachip::MegaChipDisplay;
+use chip8::platform::Platform;
+use chip8::state::{StateError, StateReader, StateWriter};
 
 pub const CHIP8_SCREEN_WIDTH: usize = 64;
 pub const CHIP8_SCREEN_HEIGHT: usize = 32;
 
-#[derive(Debug)]
-pub struct Chip8Graphics {
-    // Graphics Memory
-    //
-    memory: Box<[bool]>,
+// the largest 1-bit display, the 64x64 hi-res mode
+pub const MONO_DISPLAY_MAX_PIXELS: usize = CHIP8_SCREEN_WIDTH * 64;
+
+// The display the cpu draws to
+//
+// Most platforms have a 1-bit display, MEGA-CHIP mode swaps it for a
+// palette indexed one until it is switched off again. The 1-bit display
+// is kept inline, it is the one most programs use and cloning it then
+// needs no allocation.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Chip8Graphics {
+    Mono(MonoDisplay),
+    MegaChip(MegaChipDisplay),
 }
 
 impl Chip8Graphics {
     pub fn new() -> Chip8Graphics {
-        Chip8Graphics {
-            memory: vec![false; CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT].into_boxed_slice(),
+        Chip8Graphics::Mono(MonoDisplay::new())
+    }
+
+    pub fn for_platform(platform: Platform) -> Chip8Graphics {
+        let (width, height) = platform.display_size();
+        Chip8Graphics::Mono(MonoDisplay::with_resolution(width, height))
+    }
+
+    pub fn width(&self) -> usize {
+        match *self {
+            Chip8Graphics::Mono(ref display) => display.width(),
+            Chip8Graphics::MegaChip(ref display) => display.width(),
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match *self {
+            Chip8Graphics::Mono(ref display) => display.height(),
+            Chip8Graphics::MegaChip(ref display) => display.height(),
         }
     }
 
     pub fn clear(&mut self) {
-        for i in 0..(CHIP8_SCREEN_HEIGHT * CHIP8_SCREEN_WIDTH) {
-            (*self.memory)[i] = false;
+        match *self {
+            Chip8Graphics::Mono(ref mut display) => display.clear(),
+            Chip8Graphics::MegaChip(ref mut display) => display.clear(),
         }
     }
 
+    // whether the pixel is lit, on the MEGA-CHIP display any colour but
+    // black counts
     pub fn get_pixel_value(&self, x: usize, y: usize) -> bool {
-        (*self.memory)[self.get_index(x, y)]
+        match *self {
+            Chip8Graphics::Mono(ref display) => display.get_pixel_value(x, y),
+            Chip8Graphics::MegaChip(ref display) => display.colour_at(x, y) & 0xFFFFFF != 0,
+        }
+    }
+
+    pub fn set_pixel_value(&mut self, x: usize, y: usize, value: bool) {
+        match *self {
+            Chip8Graphics::Mono(ref mut display) => display.set_pixel_value(x, y, value),
+            Chip8Graphics::MegaChip(ref mut display) => display.set_pixel_index(x, y, value as u8),
+        }
+    }
+
+    pub fn save_state(&self, out: &mut StateWriter) {
+        match *self {
+            Chip8Graphics::Mono(ref display) => {
+                out.u8(0);
+                out.u32(display.width as u32);
+                out.u32(display.height as u32);
+                out.bools(display.pixels());
+            },
+            Chip8Graphics::MegaChip(ref display) => {
+                out.u8(1);
+                display.save_state(out);
+            },
+        }
+    }
+
+    pub fn load_state(input: &mut StateReader) -> Result<Chip8Graphics, StateError> {
+        match input.u8()? {
+            0 => {
+                let width = input.u32()? as usize;
+                let height = input.u32()? as usize;
+                if width == 0 || height == 0 || width * height > MONO_DISPLAY_MAX_PIXELS {
+                    return Err(StateError::Invalid(format!("display is {}x{}", width, height)));
+                }
+                let mut display = MonoDisplay::with_resolution(width, height);
+                input.bools(&mut display.memory[..width * height], "display")?;
+                Ok(Chip8Graphics::Mono(display))
+            },
+            1 => Ok(Chip8Graphics::MegaChip(MegaChipDisplay::load_state(input)?)),
+            kind => Err(StateError::Invalid(format!("unknown display {}", kind))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MonoDisplay {
+    // Graphics Memory
+    //
+    // Sized for the largest resolution so a clone copies it inline, only
+    // the first width * height pixels are used.
+    memory: [bool; MONO_DISPLAY_MAX_PIXELS],
+
+    // Display size in pixels, 64x32 unless a hi-res mode
+    // has switched it
+    width: usize,
+    height: usize,
+}
+
+impl MonoDisplay {
+    pub fn new() -> MonoDisplay {
+        MonoDisplay::with_resolution(CHIP8_SCREEN_WIDTH, CHIP8_SCREEN_HEIGHT)
+    }
+
+    pub fn with_resolution(width: usize, height: usize) -> MonoDisplay {
+        assert!(width * height <= MONO_DISPLAY_MAX_PIXELS, "display is {}x{}", width, height);
+        MonoDisplay {
+            memory: [false; MONO_DISPLAY_MAX_PIXELS],
+            width,
+            height,
+        }
+    }
+
+    // switches the display size, clearing the screen
+    pub fn set_resolution(&mut self, width: usize, height: usize) {
+        *self = MonoDisplay::with_resolution(width, height);
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn clear(&mut self) {
+        self.memory = [false; MONO_DISPLAY_MAX_PIXELS];
+    }
+
+    // the pixels in use, row by row
+    pub fn pixels(&self) -> &[bool] {
+        &self.memory[..self.width * self.height]
+    }
+
+    pub fn get_pixel_value(&self, x: usize, y: usize) -> bool {
+        self.memory[self.get_index(x, y)]
+    }
+
+    pub fn set_pixel_value(&mut self, x: usize, y: usize, value: bool) {
+        let index = self.get_index(x, y);
+        self.memory[index] = value;
     }
 
     fn get_index(&self, x: usize, y: usize) -> usize {
-        y * CHIP8_SCREEN_WIDTH + x
+        y * self.width + x
     }
 
     // returns if there was a collision
+    //
+    // The sprite's position wraps around the screen, the parts of it that
+    // go past the edge are either clipped or wrapped to the other side.
     pub fn draw_sprite(&mut self, 
-                       memory: &Box<[u8]>, 
+                       memory: &Chip8Memory, 
                        x:usize, 
                        y:usize, 
-                       mem_location: u16, 
-                       length: u8) -> bool
+                       mem_location: u32, 
+                       length: u8,
+                       clip: bool) -> bool
     {
         let mut collision = false;
+        let x = x % self.width;
+        let y = y % self.height;
 
         for i in 0..length {
-            let unseperatedBits = (*memory)[(mem_location + (i as u16)) as usize];
+            // sprites that run past the end of memory wrap to the start
+            let unseperatedBits = memory.read_byte_at(mem_location + i as u32);
             let mut bits: [bool; 8] = [false; 8];
 
-            bits[0] = 0x1 & unseperatedBits != 0;
-            bits[1] = ((0x2 & unseperatedBits)  >> 1) != 0;
-            bits[2] = ((0x4 & unseperatedBits)  >> 2) != 0;
-            bits[3] = ((0x8 & unseperatedBits)  >> 3) != 0;
-            bits[4] = ((0x10 & unseperatedBits) >> 4) != 0;
-            bits[5] = ((0x20 & unseperatedBits) >> 5) != 0;
-            bits[6] = ((0x40 & unseperatedBits) >> 6) != 0;
-            bits[7] = ((0x80 & unseperatedBits) >> 7) != 0;
+            // the most significant bit is the leftmost pixel
+            bits[0] = ((0x80 & unseperatedBits) >> 7) != 0;
+            bits[1] = ((0x40 & unseperatedBits) >> 6) != 0;
+            bits[2] = ((0x20 & unseperatedBits) >> 5) != 0;
+            bits[3] = ((0x10 & unseperatedBits) >> 4) != 0;
+            bits[4] = ((0x8 & unseperatedBits)  >> 3) != 0;
+            bits[5] = ((0x4 & unseperatedBits)  >> 2) != 0;
+            bits[6] = ((0x2 & unseperatedBits)  >> 1) != 0;
+            bits[7] = 0x1 & unseperatedBits != 0;
 
             for j in 0..8 {
-                if (self.get_index(x + (j as usize), y + (i as usize)) < 2048) {
-                    if (self.get_pixel_value(x + (j as usize), y + (i as usize))) && bits[j] {
+                let mut pixel_x = x + j;
+                let mut pixel_y = y + (i as usize);
+                if pixel_x >= self.width || pixel_y >= self.height {
+                    if clip {
+                        continue;
+                    }
+                    pixel_x = pixel_x % self.width;
+                    pixel_y = pixel_y % self.height;
+                }
+
+                if bits[j] {
+                    let index = self.get_index(pixel_x, pixel_y);
+                    if self.memory[index] {
                         collision = true;
-                        (*self.memory)[self.get_index(x + (j as usize), y + (i as usize))] = false;
-                    } else if bits[j] {
-                        (*self.memory)[self.get_index(x + (j as usize), y + (i as usize))] = true;
+                        self.memory[index] = false;
+                    } else {
+                        self.memory[index] = true;
                     }
-                } else {
-                    collision = true;
                 }
-                
             }
         }
 
         collision
     }
 }
+
+// colour attributes of the CHIP-8X display, one for every 8 pixels of a row
+pub const CHIP8X_COLOUR_COLUMNS: usize = CHIP8_SCREEN_WIDTH / 8;
+pub const CHIP8X_COLOUR_ROWS: usize = CHIP8_SCREEN_HEIGHT;
+
+// Colour layer of the VP-590 colour board, used by CHIP-8X
+//
+// Set pixels take the foreground colour of their attribute, unset pixels
+// the background colour of the whole screen. Foreground colours are 0-7:
+// black, red, blue, violet, green, yellow, aqua and white. The background
+// is 0-3: blue, black, green and red.
+#[derive(Debug, Clone)]
+pub struct Chip8ColourLayer {
+    foreground: [u8; CHIP8X_COLOUR_COLUMNS * CHIP8X_COLOUR_ROWS],
+    pub background: u8,
+}
+
+impl Chip8ColourLayer {
+    pub fn new() -> Chip8ColourLayer {
+        Chip8ColourLayer {
+            // red on blue after reset
+            foreground: [1; CHIP8X_COLOUR_COLUMNS * CHIP8X_COLOUR_ROWS],
+            background: 0,
+        }
+    }
+
+    // foreground colour of the pixel at x, y
+    pub fn foreground_at(&self, x: usize, y: usize) -> u8 {
+        self.foreground[(y % CHIP8X_COLOUR_ROWS) * CHIP8X_COLOUR_COLUMNS + (x / 8) % CHIP8X_COLOUR_COLUMNS]
+    }
+
+    // colours columns of 8 pixels and rows of 1 pixel, both wrap around
+    pub fn fill(&mut self, column: usize, row: usize, columns: usize, rows: usize, colour: u8) {
+        for y in row..(row + rows) {
+            for x in column..(column + columns) {
+                let index = (y % CHIP8X_COLOUR_ROWS) * CHIP8X_COLOUR_COLUMNS + x % CHIP8X_COLOUR_COLUMNS;
+                self.foreground[index] = colour & 0x7;
+            }
+        }
+    }
+
+    // 02A0 steps through the four background colours
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % 4;
+    }
+
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.slice(&self.foreground);
+        out.u8(self.background);
+    }
+
+    pub fn load_state(&mut self, input: &mut StateReader) -> Result<(), StateError> {
+        self.foreground.copy_from_slice(input.slice_of(CHIP8X_COLOUR_COLUMNS * CHIP8X_COLOUR_ROWS, "colour layer")?);
+        self.background = input.u8()?;
+        Ok(())
+    }
+}
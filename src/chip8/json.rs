@@ -0,0 +1,228 @@
+// A small JSON reader
+//
+// Enough for the files other CHIP-8 tools exchange, the community rom
+// database and the payload of Octo cartridges. Objects keep their keys in
+// the order they were written.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut reader = Reader { chars: text.chars().collect(), position: 0 };
+        let value = reader.value()?;
+        if reader.peek().is_some() {
+            return Err(reader.error("there is more after the value"));
+        }
+        Ok(value)
+    }
+
+    // the value of key in an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter().find(|member| member.0 == key).map(|member| &member.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self {
+            Json::Array(ref values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Json)]> {
+        match *self {
+            Json::Object(ref members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+struct Reader {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Reader {
+    fn error(&self, reason: &str) -> String {
+        format!("invalid JSON at character {}: {}", self.position, reason)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.position < self.chars.len() && self.chars[self.position].is_whitespace() {
+            self.position += 1;
+        }
+        self.chars.get(self.position).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn next_char(&mut self) -> Result<char, String> {
+        let c = self.chars.get(self.position).cloned().ok_or_else(|| self.error("the text ends too soon"))?;
+        self.position += 1;
+        Ok(c)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some('{') => {
+                self.position += 1;
+                let mut members = Vec::new();
+                if self.eat('}') {
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    members.push((key, self.value()?));
+                    if !self.eat(',') {
+                        self.expect('}')?;
+                        return Ok(Json::Object(members));
+                    }
+                }
+            },
+            Some('[') => {
+                self.position += 1;
+                let mut values = Vec::new();
+                if self.eat(']') {
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    if !self.eat(',') {
+                        self.expect(']')?;
+                        return Ok(Json::Array(values));
+                    }
+                }
+            },
+            Some('"') => self.string().map(Json::String),
+            Some(_) => self.literal(),
+            None => Err(self.error("the text ends too soon")),
+        }
+    }
+
+    // numbers, true, false and null
+    fn literal(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while let Some(&c) = self.chars.get(self.position) {
+            if !(c.is_ascii_alphanumeric() || c == '-' || c == '+' || c == '.') {
+                break;
+            }
+            self.position += 1;
+        }
+        let word: String = self.chars[start..self.position].iter().collect();
+        match word.as_str() {
+            "true" => Ok(Json::Bool(true)),
+            "false" => Ok(Json::Bool(false)),
+            "null" => Ok(Json::Null),
+            _ => word.parse().map(Json::Number).map_err(|_| self.error(&format!("\"{}\" is not a value", word))),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit = self.next_char()?.to_digit(16).ok_or_else(|| self.error("bad \\u escape"))?;
+            value = (value << 4) | digit;
+        }
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            match self.next_char()? {
+                '"' => return Ok(text),
+                '\\' => {
+                    let c = match self.next_char()? {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // characters outside the BMP are surrogate pairs
+                            if (0xD800..0xDC00).contains(&code) && self.chars.get(self.position) == Some(&'\\') {
+                                self.position += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            ::std::char::from_u32(code).unwrap_or('\u{FFFD}')
+                        },
+                        other => other,
+                    };
+                    text.push(c);
+                },
+                c => text.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_values() {
+        let json = Json::parse(" {\"a\": [1, -2.5e1, true, null], \"b\": {\"c\": \"x\\\"\\u00e9\\ud83d\\ude00\"}, \"a\": false} ").unwrap();
+        assert_eq!(json.get("a").and_then(Json::as_array).map(|values| values.len()), Some(4));
+        assert_eq!(json.get("a").unwrap().as_array().unwrap()[1].as_f64(), Some(-25.0));
+        assert_eq!(json.get("b").and_then(|b| b.get("c")).and_then(Json::as_str), Some("x\"\u{e9}\u{1F600}"));
+        // the first of repeated keys
+        assert_eq!(json.get("a").and_then(Json::as_bool), None);
+        assert_eq!(json.get("missing"), None);
+
+        assert!(Json::parse("{\"a\": 1").is_err());
+        assert!(Json::parse("[1, 2] 3").is_err());
+        assert!(Json::parse("nope").is_err());
+        assert_eq!(Json::parse("[]"), Ok(Json::Array(Vec::new())));
+    }
+}
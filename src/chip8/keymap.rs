@@ -0,0 +1,72 @@
+// Maps characters typed on the host keyboard onto the 16 chip-8 keys
+//
+// A keymap is written as 16 characters, the first is chip-8 key 0x0
+// and the last is key 0xF.
+pub const DEFAULT_KEYMAP: &str = "1234qwerasdfzxcv";
+// the CHIP-8X second keypad, on the right hand side of the keyboard
+pub const DEFAULT_KEYMAP2: &str = "7890uiopjkl;m,./";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    keys: [char; 16],
+}
+
+impl Keymap {
+    pub fn new(layout: &str) -> Result<Keymap, String> {
+        let keys: Vec<char> = layout.chars().map(|c| c.to_ascii_lowercase()).collect();
+        if keys.len() != 16 {
+            return Err(format!("keymap \"{}\" must have exactly 16 keys", layout));
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].contains(key) {
+                return Err(format!("keymap \"{}\" uses '{}' twice", layout, key));
+            }
+        }
+        let mut map = Keymap { keys: ['\0'; 16] };
+        map.keys.copy_from_slice(&keys);
+        Ok(map)
+    }
+
+    pub fn chip8_key(&self, c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+        self.keys.iter().position(|&key| key == c).map(|i| i as u8)
+    }
+
+    // the character for a chip-8 key, the inverse of chip8_key
+    pub fn host_key(&self, key: u8) -> char {
+        self.keys[key as usize & 0xF]
+    }
+}
+
+impl Keymap {
+    pub fn keypad2() -> Keymap {
+        Keymap::new(DEFAULT_KEYMAP2).unwrap()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::new(DEFAULT_KEYMAP).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keymaps_list_the_keys_from_0_to_f() {
+        let keymap = Keymap::new("X123qweasdzc4rfv").unwrap();
+        assert_eq!(keymap.chip8_key('x'), Some(0x0));
+        assert_eq!(keymap.chip8_key('Q'), Some(0x4));
+        assert_eq!(keymap.chip8_key('v'), Some(0xF));
+        assert_eq!(keymap.chip8_key('p'), None);
+        assert_eq!(keymap.host_key(0x0), 'x');
+        assert_eq!(keymap.host_key(0xC), '4');
+
+        assert_eq!(Keymap::default().chip8_key('1'), Some(0x0));
+        assert_eq!(Keymap::keypad2().chip8_key(';'), Some(0xB));
+        assert!(Keymap::new("1234").is_err());
+        assert!(Keymap::new("1134qwerasdfzxcv").is_err());
+    }
+}
@@ -1,19 +1,20 @@
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chip8Keypad {
     // keypad on off state
-    pub keys: Box<[bool]>,
+    pub keys: [bool; 16],
 }
 
 impl Chip8Keypad {
     pub fn new() -> Chip8Keypad {
         Chip8Keypad {
-            keys: vec![false; 16 as usize].into_boxed_slice(),
+            keys: [false; 16],
         }
 
     }
 
+    // only the low nibble picks the key, like on the VIP
     pub fn is_pressed(&self, key_index: u8) -> bool {
-        (*self.keys)[key_index as usize]
+        self.keys[(key_index & 0xF) as usize]
     }
 }
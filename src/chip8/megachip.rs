@@ -0,0 +1,378 @@
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use chip8::memory::Chip8Memory;
+use chip8::state::{StateError, StateReader, StateWriter};
+
+pub const MEGACHIP_SCREEN_WIDTH: usize = 256;
+pub const MEGACHIP_SCREEN_HEIGHT: usize = 192;
+// all that a 24-bit I can reach
+pub const MEGACHIP_MEMORY_SIZE: usize = 0x100_0000;
+const MEGACHIP_PIXELS: usize = MEGACHIP_SCREEN_WIDTH * MEGACHIP_SCREEN_HEIGHT;
+
+// How 080N mixes a sprite with what is already on screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    Normal,
+    // the sprite at 25%, 50% and 75% opacity
+    Percent25,
+    Percent50,
+    Percent75,
+    Add,
+    Multiply,
+}
+
+impl BlendMode {
+    // unknown modes draw normally
+    pub fn from_nibble(nibble: u8) -> BlendMode {
+        match nibble {
+            1 => BlendMode::Percent25,
+            2 => BlendMode::Percent50,
+            3 => BlendMode::Percent75,
+            4 => BlendMode::Add,
+            5 => BlendMode::Multiply,
+            _ => BlendMode::Normal,
+        }
+    }
+
+    pub fn nibble(&self) -> u8 {
+        match *self {
+            BlendMode::Normal => 0,
+            BlendMode::Percent25 => 1,
+            BlendMode::Percent50 => 2,
+            BlendMode::Percent75 => 3,
+            BlendMode::Add => 4,
+            BlendMode::Multiply => 5,
+        }
+    }
+
+    // mixes two 0xRRGGBB colours
+    fn apply(&self, below: u32, above: u32) -> u32 {
+        match *self {
+            BlendMode::Normal => above,
+            BlendMode::Percent25 => mix(below, above, 64),
+            BlendMode::Percent50 => mix(below, above, 128),
+            BlendMode::Percent75 => mix(below, above, 192),
+            BlendMode::Add => per_channel(below, above, |b, a| (b + a).min(255)),
+            BlendMode::Multiply => per_channel(below, above, |b, a| b * a / 255),
+        }
+    }
+}
+
+fn per_channel<F: Fn(u32, u32) -> u32>(below: u32, above: u32, f: F) -> u32 {
+    let mut colour = 0;
+    for shift in [0, 8, 16].iter() {
+        colour |= f((below >> shift) & 0xFF, (above >> shift) & 0xFF) << shift;
+    }
+    colour
+}
+
+// linear blend between below (amount 0) and above (amount 255)
+fn mix(below: u32, above: u32, amount: u32) -> u32 {
+    per_channel(below, above, |b, a| (b * (255 - amount) + a * amount) / 255)
+}
+
+// The 256x192 display of MEGA-CHIP mode
+//
+// Every pixel holds a palette index and the colour it was blended to.
+// Drawing happens off screen, 00E0 shows the finished picture and starts
+// a new one, which is how MEGA-CHIP programs avoid flicker.
+//
+// The pixels are boxed, at over 400 KiB they are too large for the stack.
+#[derive(Debug, Clone)]
+pub struct MegaChipDisplay {
+    indices: Box<[u8; MEGACHIP_PIXELS]>,
+    // colours as 0xRRGGBB of the picture being drawn and the one on screen
+    back: Box<[u32; MEGACHIP_PIXELS]>,
+    front: Box<[u32; MEGACHIP_PIXELS]>,
+
+    // 0xAARRGGBB, index 0 is transparent
+    pub palette: [u32; 256],
+    // of DXYN, set by 03NN and 04NN
+    pub sprite_width: usize,
+    pub sprite_height: usize,
+    // opacity of everything drawn, set by 05NN
+    pub alpha: u8,
+    pub blend_mode: BlendMode,
+    // DXYN sets VF when it draws over a pixel of this index, 0 never
+    // collides
+    pub collision_index: u8,
+}
+
+impl MegaChipDisplay {
+    pub fn new() -> MegaChipDisplay {
+        let mut palette = [0xFFFFFFFF; 256];
+        palette[0] = 0;
+        MegaChipDisplay {
+            indices: pixels(),
+            back: pixels(),
+            front: pixels(),
+            palette,
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xFF,
+            blend_mode: BlendMode::Normal,
+            collision_index: 0,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        MEGACHIP_SCREEN_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        MEGACHIP_SCREEN_HEIGHT
+    }
+
+    // the on screen colour as 0xRRGGBB
+    pub fn colour_at(&self, x: usize, y: usize) -> u32 {
+        self.front[y * MEGACHIP_SCREEN_WIDTH + x]
+    }
+
+    pub fn index_at(&self, x: usize, y: usize) -> u8 {
+        self.indices[y * MEGACHIP_SCREEN_WIDTH + x]
+    }
+
+    // sets a pixel on and off screen, ignoring the blend mode
+    pub fn set_pixel_index(&mut self, x: usize, y: usize, index: u8) {
+        let position = y * MEGACHIP_SCREEN_WIDTH + x;
+        let colour = self.palette[index as usize] & 0xFFFFFF;
+        self.indices[position] = index;
+        self.back[position] = colour;
+        self.front[position] = colour;
+    }
+
+    // 00E0 shows what has been drawn and clears the drawing
+    pub fn clear(&mut self) {
+        self.front.copy_from_slice(&self.back[..]);
+        self.back.iter_mut().for_each(|colour| *colour = 0);
+        self.indices.iter_mut().for_each(|index| *index = 0);
+    }
+
+    // 02NN, count colours of four bytes, alpha, red, green and blue,
+    // starting at palette index 1
+    pub fn load_palette(&mut self, memory: &Chip8Memory, addr: u32, count: u8) {
+        for n in 0..count as u32 {
+            let mut colour = 0;
+            for byte in 0..4 {
+                colour = (colour << 8) | memory.read_byte_at(addr.wrapping_add(n * 4 + byte)) as u32;
+            }
+            self.palette[n as usize + 1] = colour;
+        }
+    }
+
+    // Draws a sprite of one palette index per byte, sprite_width by
+    // sprite_height of them from addr. Index 0 is transparent and the
+    // parts past the edge of the screen are clipped. Returns if there was
+    // a collision.
+    pub fn draw_sprite(&mut self, memory: &Chip8Memory, x: usize, y: usize, addr: u32) -> bool {
+        let mut collision = false;
+        for row in 0..self.sprite_height {
+            for column in 0..self.sprite_width {
+                let index = memory.read_byte_at(addr.wrapping_add((row * self.sprite_width + column) as u32));
+                let pixel_x = x + column;
+                let pixel_y = y + row;
+                if index == 0 || pixel_x >= MEGACHIP_SCREEN_WIDTH || pixel_y >= MEGACHIP_SCREEN_HEIGHT {
+                    continue;
+                }
+
+                let position = pixel_y * MEGACHIP_SCREEN_WIDTH + pixel_x;
+                if self.collision_index != 0 && self.indices[position] == self.collision_index {
+                    collision = true;
+                }
+                let colour = self.palette[index as usize];
+                let below = self.back[position];
+                let blended = self.blend_mode.apply(below, colour & 0xFFFFFF);
+                let opacity = (colour >> 24) * self.alpha as u32 / 255;
+                self.indices[position] = index;
+                self.back[position] = mix(below, blended, opacity);
+            }
+        }
+        collision
+    }
+
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.slice(&self.indices[..]);
+        for colours in [&self.back[..], &self.front[..], &self.palette[..]].iter() {
+            out.u32(colours.len() as u32);
+            for colour in colours.iter() {
+                out.u32(*colour);
+            }
+        }
+        out.u32(self.sprite_width as u32);
+        out.u32(self.sprite_height as u32);
+        out.u8(self.alpha);
+        out.u8(self.blend_mode.nibble());
+        out.u8(self.collision_index);
+    }
+
+    pub fn load_state(input: &mut StateReader) -> Result<MegaChipDisplay, StateError> {
+        let mut display = MegaChipDisplay::new();
+        display.indices.copy_from_slice(input.slice_of(MEGACHIP_PIXELS, "MEGA-CHIP display")?);
+        for colours in [&mut display.back[..], &mut display.front[..], &mut display.palette[..]].iter_mut() {
+            if input.u32()? as usize != colours.len() {
+                return Err(StateError::Invalid(String::from("MEGA-CHIP colours are the wrong size")));
+            }
+            for colour in colours.iter_mut() {
+                *colour = input.u32()?;
+            }
+        }
+        display.sprite_width = input.u32()? as usize;
+        display.sprite_height = input.u32()? as usize;
+        if display.sprite_width > 256 || display.sprite_height > 256 {
+            return Err(StateError::Invalid(String::from("MEGA-CHIP sprites are larger than 256 pixels")));
+        }
+        display.alpha = input.u8()?;
+        display.blend_mode = BlendMode::from_nibble(input.u8()?);
+        display.collision_index = input.u8()?;
+        Ok(display)
+    }
+}
+
+impl Default for MegaChipDisplay {
+    fn default() -> MegaChipDisplay {
+        MegaChipDisplay::new()
+    }
+}
+
+// a zeroed screen of pixels, built on the heap
+fn pixels<T: Copy + Default + ::std::fmt::Debug>() -> Box<[T; MEGACHIP_PIXELS]> {
+    vec![T::default(); MEGACHIP_PIXELS].into_boxed_slice().try_into().unwrap()
+}
+
+// A digitised sound started by 060N
+//
+// The sound at I starts with a header of its sample rate in 16 bits and
+// its length in 24 bits, both big endian, and a reserved byte. The 8-bit
+// unsigned samples follow. They are shared between clones of the machine.
+#[derive(Debug, Clone)]
+pub struct MegaChipSample {
+    pub rate: u32,
+    pub samples: Arc<[u8]>,
+    pub looping: bool,
+    // in samples of the sound
+    position: f64,
+}
+
+impl MegaChipSample {
+    pub fn load(memory: &Chip8Memory, addr: u32, looping: bool) -> MegaChipSample {
+        let byte = |offset: u32| memory.read_byte_at(addr.wrapping_add(offset)) as u32;
+        let rate = (byte(0) << 8) | byte(1);
+        let length = (byte(2) << 16) | (byte(3) << 8) | byte(4);
+        let samples = (0..length).map(|n| byte(6 + n) as u8).collect::<Vec<u8>>().into();
+        MegaChipSample {
+            rate,
+            samples,
+            looping,
+            position: 0.0,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.samples.is_empty() || (!self.looping && self.position as usize >= self.samples.len())
+    }
+
+    // Resamples the sound into output, silence once it has finished
+    pub fn fill(&mut self, output: &mut [u8], output_rate: u32) {
+        let step = self.rate as f64 / output_rate as f64;
+        for sample in output.iter_mut() {
+            if self.is_finished() {
+                *sample = 0x80;
+                continue;
+            }
+            if self.position as usize >= self.samples.len() {
+                self.position %= self.samples.len() as f64;
+            }
+            *sample = self.samples[self.position as usize];
+            self.position += step;
+        }
+    }
+
+    pub fn save_state(&self, out: &mut StateWriter) {
+        out.u32(self.rate);
+        out.slice(&self.samples);
+        out.bool(self.looping);
+        out.u64(self.position.to_bits());
+    }
+
+    pub fn load_state(input: &mut StateReader) -> Result<MegaChipSample, StateError> {
+        let sample = MegaChipSample {
+            rate: input.u32()?,
+            samples: input.slice()?.into(),
+            looping: input.bool()?,
+            position: f64::from_bits(input.u64()?),
+        };
+        if !sample.position.is_finite() || sample.position < 0.0 {
+            return Err(StateError::Invalid(String::from("sample position is not a position")));
+        }
+        Ok(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blend_modes() {
+        assert_eq!(BlendMode::Normal.apply(0x102030, 0x405060), 0x405060);
+        assert_eq!(BlendMode::Percent50.apply(0x000000, 0xFEFEFE), 0x7F7F7F);
+        assert_eq!(BlendMode::Add.apply(0x80F000, 0x902001), 0xFFFF01);
+        assert_eq!(BlendMode::Multiply.apply(0xFF8000, 0x80FFFF), 0x808000);
+    }
+
+    #[test]
+    fn drawing_shows_after_clear() {
+        let mut memory = Chip8Memory::new();
+        memory.write_byte(0x300, 1);
+        memory.write_byte(0x301, 0);
+        let mut display = MegaChipDisplay::new();
+        display.palette[1] = 0xFF123456;
+        display.sprite_width = 2;
+        display.sprite_height = 1;
+
+        display.draw_sprite(&memory, 10, 20, 0x300);
+        assert_eq!(display.index_at(10, 20), 1);
+        assert_eq!(display.colour_at(10, 20), 0);
+        display.clear();
+        assert_eq!(display.colour_at(10, 20), 0x123456);
+        assert_eq!(display.colour_at(11, 20), 0);
+        assert_eq!(display.index_at(10, 20), 0);
+    }
+
+    #[test]
+    fn collisions_only_with_the_collision_index() {
+        let mut memory = Chip8Memory::new();
+        memory.write_byte(0x300, 2);
+        let mut display = MegaChipDisplay::new();
+        display.sprite_width = 1;
+        display.sprite_height = 1;
+
+        assert!(!display.draw_sprite(&memory, 0, 0, 0x300));
+        assert!(!display.draw_sprite(&memory, 0, 0, 0x300));
+        display.collision_index = 2;
+        assert!(display.draw_sprite(&memory, 0, 0, 0x300));
+        // clipped at the edge
+        assert!(!display.draw_sprite(&memory, 256, 0, 0x300));
+    }
+
+    #[test]
+    fn samples_resample_and_finish() {
+        let mut memory = Chip8Memory::new();
+        for (offset, byte) in [0x1F, 0x40, 0x00, 0x00, 0x02, 0x00, 0x10, 0x20].iter().enumerate() {
+            memory.write_byte(0x400 + offset as u16, *byte);
+        }
+        let mut sample = MegaChipSample::load(&memory, 0x400, false);
+        assert_eq!(sample.rate, 8000);
+        assert_eq!(*sample.samples, [0x10, 0x20]);
+
+        let mut output = [0; 6];
+        sample.fill(&mut output, 16000);
+        assert_eq!(output, [0x10, 0x10, 0x20, 0x20, 0x80, 0x80]);
+        assert!(sample.is_finished());
+
+        let mut sample = MegaChipSample::load(&memory, 0x400, true);
+        sample.fill(&mut output, 8000);
+        assert_eq!(output, [0x10, 0x20, 0x10, 0x20, 0x10, 0x20]);
+    }
+}
@@ -1,4 +1,37 @@
 
+use std::sync::Arc;
+
+use chip8::rand::random;
+
+pub const CHIP8_MEMORY_SIZE: usize = 4096;
+
+// What RAM holds at power on, before the font and rom are loaded
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RamInit {
+    #[default]
+    Zeros,
+    // different every time
+    Random,
+    // Blocks of 0x00 and 0xFF with the odd bit flipped, which is what the
+    // static RAM in machines like the COSMAC VIP tends to come up with.
+    // The same every time.
+    Vip,
+}
+
+impl RamInit {
+    pub fn parse(name: &str) -> Result<RamInit, String> {
+        match name.trim() {
+            "zeros" => Ok(RamInit::Zeros),
+            "random" => Ok(RamInit::Random),
+            "vip" => Ok(RamInit::Vip),
+            _ => Err(format!("unknown RAM init \"{}\"", name)),
+        }
+    }
+}
+
+// MEGA-CHIP memory past the first 4 KiB is kept in pages of this size
+const PAGE_SIZE: usize = 0x1000;
+
 #[derive(Debug)]
 pub struct Chip8Memory {
     // Main Memory 
@@ -6,9 +39,29 @@ pub struct Chip8Memory {
     //
     // Memory Map
     // 0x000-0x1FF - Chip 8 interpreter (contains font set in emu)
-    // 0x050-0x0A0 - Used for the built in 4x5 pixel font set (0-F)
+    // 0x000-0x04F - The 4x5 hex font (0-F) unless it was moved, see font.rs
     // 0x200-0xFFF - Program ROM and work RAM
-    pub memory: Box<[u8]>,
+    ram: [u8; CHIP8_MEMORY_SIZE],
+
+    // The rest of a MEGA-CHIP's 16 MiB. Clones share the pages and a page
+    // is only copied when one of them writes to it, so forking a machine
+    // doesn't copy all of it.
+    pages: Vec<Arc<[u8; PAGE_SIZE]>>,
+
+    // addresses written through write_byte since the last take_writes,
+    // only kept while logging is on
+    write_log: Option<Vec<u32>>,
+}
+
+// a clone logs writes if the original does, but starts with an empty log
+impl Clone for Chip8Memory {
+    fn clone(&self) -> Chip8Memory {
+        Chip8Memory {
+            ram: self.ram,
+            pages: self.pages.clone(),
+            write_log: self.write_log.as_ref().map(|_| Vec::new()),
+        }
+    }
 }
 
 impl Chip8Memory {
@@ -17,12 +70,164 @@ impl Chip8Memory {
         Chip8Memory {
             // initializing with all 0s this could be wrong
             // TODO check this
-            memory: vec![0; 4096 as usize].into_boxed_slice(),
+            ram: [0; CHIP8_MEMORY_SIZE],
+            pages: Vec::new(),
+            write_log: None,
+        }
+    }
+
+    // MEGA-CHIP programs get far more memory than the 4 KiB of a VIP,
+    // sizes are rounded up to a whole number of 4 KiB
+    pub fn with_size(size: usize) -> Chip8Memory {
+        let pages = size.saturating_sub(CHIP8_MEMORY_SIZE).div_ceil(PAGE_SIZE);
+        // every page starts out as the same page of zeros
+        let zeros = Arc::new([0; PAGE_SIZE]);
+        Chip8Memory {
+            ram: [0; CHIP8_MEMORY_SIZE],
+            pages: (0..pages).map(|_| zeros.clone()).collect(),
+            write_log: None,
         }
     }
 
+    pub fn size(&self) -> usize {
+        CHIP8_MEMORY_SIZE + self.pages.len() * PAGE_SIZE
+    }
+
+    // addresses past the end of memory wrap around to the start
     pub fn read_byte(&self, location: u16) -> u8 {
-        let byte: u8 = self.memory[location as usize];
-        byte
+        self.read_byte_at(location as u32)
+    }
+
+    pub fn write_byte(&mut self, location: u16, byte: u8) {
+        self.write_byte_at(location as u32, byte);
+    }
+
+    // I is 24 bits wide on the MEGA-CHIP
+    pub fn read_byte_at(&self, location: u32) -> u8 {
+        let addr = location as usize % self.size();
+        if addr < CHIP8_MEMORY_SIZE {
+            self.ram[addr]
+        } else {
+            let addr = addr - CHIP8_MEMORY_SIZE;
+            self.pages[addr / PAGE_SIZE][addr % PAGE_SIZE]
+        }
+    }
+
+    pub fn write_byte_at(&mut self, location: u32, byte: u8) {
+        let addr = location as usize % self.size();
+        self.poke(addr, byte);
+        if let Some(ref mut log) = self.write_log {
+            log.push(addr as u32);
+        }
+    }
+
+    // writes without logging, addr has to be inside memory
+    fn poke(&mut self, addr: usize, byte: u8) {
+        if addr < CHIP8_MEMORY_SIZE {
+            self.ram[addr] = byte;
+        } else {
+            let addr = addr - CHIP8_MEMORY_SIZE;
+            Arc::make_mut(&mut self.pages[addr / PAGE_SIZE])[addr % PAGE_SIZE] = byte;
+        }
+    }
+
+    // Copies data in from addr, wrapping around at the end of memory.
+    // Used for loading roms and fonts, so the writes aren't logged.
+    pub fn load(&mut self, addr: usize, data: &[u8]) {
+        let size = self.size();
+        for (offset, &byte) in data.iter().enumerate() {
+            self.poke((addr + offset) % size, byte);
+        }
+    }
+
+    // the first 4 KiB, which is all of it on every platform but MEGA-CHIP
+    pub fn ram(&self) -> &[u8; CHIP8_MEMORY_SIZE] {
+        &self.ram
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = self.ram.to_vec();
+        for page in self.pages.iter() {
+            bytes.extend_from_slice(&page[..]);
+        }
+        bytes
+    }
+
+    // every byte as it would be at power on, without logging the writes
+    pub fn fill(&mut self, init: RamInit) {
+        match init {
+            RamInit::Zeros => {
+                self.ram = [0; CHIP8_MEMORY_SIZE];
+                let zeros = Arc::new([0; PAGE_SIZE]);
+                for page in self.pages.iter_mut() {
+                    *page = zeros.clone();
+                }
+            },
+            RamInit::Random => {
+                for addr in 0..self.size() {
+                    self.poke(addr, random::<u8>());
+                }
+            },
+            RamInit::Vip => {
+                // xorshift with a fixed seed picks the flipped bits
+                let mut state: u32 = 0x2468ACE1;
+                for addr in 0..self.size() {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    let block = if (addr / 0x80) % 2 == 0 { 0x00 } else { 0xFF };
+                    let flipped = if state & 0xF == 0 { 1 << ((state >> 4) % 8) } else { 0 };
+                    self.poke(addr, block ^ flipped);
+                }
+            },
+        }
+    }
+
+    pub fn log_writes(&mut self, enabled: bool) {
+        self.write_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn is_logging_writes(&self) -> bool {
+        self.write_log.is_some()
+    }
+
+    // the addresses written since the last call, oldest first
+    pub fn take_writes(&mut self) -> Vec<u32> {
+        match self.write_log {
+            Some(ref mut log) => log.split_off(0),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_pages_until_they_are_written() {
+        let mut memory = Chip8Memory::with_size(0x100_0000);
+        memory.write_byte_at(0x12345, 0xAA);
+        let mut fork = memory.clone();
+        assert!(memory.pages.iter().zip(fork.pages.iter()).all(|(a, b)| Arc::ptr_eq(a, b)));
+
+        fork.write_byte_at(0x12345, 0xBB);
+        assert_eq!(memory.read_byte_at(0x12345), 0xAA);
+        assert_eq!(fork.read_byte_at(0x12345), 0xBB);
+        let copied = memory.pages.iter().zip(fork.pages.iter()).filter(|&(a, b)| !Arc::ptr_eq(a, b)).count();
+        assert_eq!(copied, 1);
+    }
+
+    #[test]
+    fn loads_wrap_and_are_not_logged() {
+        let mut memory = Chip8Memory::new();
+        memory.log_writes(true);
+        memory.load(0xFFF, &[1, 2]);
+        assert_eq!((memory.read_byte(0xFFF), memory.read_byte(0)), (1, 2));
+        assert!(memory.take_writes().is_empty());
+
+        memory.write_byte(0x300, 3);
+        assert_eq!(memory.clone().take_writes(), vec![]);
+        assert_eq!(memory.take_writes(), vec![0x300]);
     }
 }
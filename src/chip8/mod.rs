@@ -1,31 +1,96 @@
-extern crate sdl2;
 extern crate rand;
 
-mod cpu;
-mod memory;
-mod graphics;
-mod keypad;
+pub mod cdp1802;
+pub mod cheats;
+pub mod control;
+pub mod coverage;
+pub mod cpu;
+pub mod database;
+pub mod font;
+pub mod env;
+pub mod megachip;
+pub mod memory;
+pub mod octo;
+pub mod platform;
+pub mod profiler;
+pub mod graphics;
+pub mod json;
+pub mod keymap;
+pub mod keypad;
+pub mod quirks;
+pub mod rom;
+pub mod script;
+pub mod state;
+pub mod symbols;
+pub mod timing;
+pub mod video;
+
+use std::sync::Arc;
 
 use self::cpu::*;
 use self::memory::*;
 use self::graphics::*;
 use self::keypad::*;
-
-use chip8::graphics::CHIP8_SCREEN_HEIGHT;
-use chip8::graphics::CHIP8_SCREEN_WIDTH;
-
-use self::sdl2::rect::Rect;
-use self::sdl2::pixels::Color;
-
-// TODO add global constants for CPU clock speed and screen refresh rate
-
-#[derive(Debug)]
+use self::megachip::MegaChipSample;
+use self::rom::{Rom, RomError};
+use self::database::{Database, RomInfo};
+use self::font::Font;
+use self::keymap::Keymap;
+use self::quirks::Quirks;
+use self::platform::Platform;
+use self::timing::*;
+use self::state::{StateError, StateReader, StateWriter};
+use self::symbols::Symbols;
+use self::video::Palette;
+
+// The timers tick at 60Hz and by default the cpu runs 9 instructions
+// for every tick, which is the 540Hz the frontend used to sleep for
+pub const CHIP8_FRAMES_PER_SECOND: u32 = 60;
+pub const CHIP8_INSTRUCTIONS_PER_FRAME: u32 = 9;
+
+#[derive(Debug, Clone)]
 pub struct Chip8 {
     cpu: Chip8CPU,
     bus: Chip8Bus,
+
+    instructions_per_frame: u32,
+    timing: Timing,
+    // cycles the last instruction of a VIP timed frame ran over by,
+    // they are taken from the next frame
+    cycles_overrun: u32,
+
+    // database entry of the booted rom, if it is a known one
+    rom_info: Option<Arc<RomInfo>>,
+    // reloaded by a hard reset
+    rom: Option<Rom>,
+    ram_init: RamInit,
+    // how the frontends should show the display and map the keyboard
+    palette: Palette,
+    keymap: Keymap,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetKind {
+    // a warm reset: the cpu, display and keys start over but RAM, the
+    // program in it included, is left as it was
+    Soft,
+    // a power cycle: RAM is filled with the RAM init pattern again and
+    // the font and rom reloaded
+    Hard,
+}
+
+
 
+// a rom at the load address of one platform moves to the load address
+// of the other, one placed anywhere else stays where it is
+fn rom_for_platform(rom: &Rom, from: Platform, to: Platform) -> Result<Rom, RomError> {
+    if rom.load_address() == from.load_address() && from.load_address() != to.load_address() {
+        Rom::with_memory_size(rom.data().to_vec(), to.load_address(), to.memory_size())
+    } else {
+        rom.check_fits(to.memory_size())?;
+        Ok(rom.clone())
+    }
+}
 
 impl Chip8 {
 
@@ -34,208 +99,451 @@ impl Chip8 {
         let bus = Chip8Bus::new();
         Chip8 {
             cpu,
-            bus
-        }
-    }
-
-    pub fn boot(&mut self, rom: &Vec<u8>) {
-        let mut i = 0x200;
-        for byte in rom {
-            (*self.bus.memory.memory)[i] = *byte;
-            i = i + 1;
-        }
-
-        // hex character font
-        
-        // "0" character
-        // 0x0 address
-        (*self.bus.memory.memory)[0] = 0xF0; 
-        (*self.bus.memory.memory)[1] = 0x90; 
-        (*self.bus.memory.memory)[2] = 0x90; 
-        (*self.bus.memory.memory)[3] = 0x90; 
-        (*self.bus.memory.memory)[4] = 0xF0; 
-
-        // "1" character
-        // 0x5
-        (*self.bus.memory.memory)[5] = 0x20; 
-        (*self.bus.memory.memory)[6] = 0x60; 
-        (*self.bus.memory.memory)[7] = 0x20; 
-        (*self.bus.memory.memory)[8] = 0x20; 
-        (*self.bus.memory.memory)[9] = 0x70; 
-
-        // "2" character
-        // 0xa
-        (*self.bus.memory.memory)[10] = 0xF0; 
-        (*self.bus.memory.memory)[11] = 0x10; 
-        (*self.bus.memory.memory)[12] = 0xF0; 
-        (*self.bus.memory.memory)[13] = 0x80; 
-        (*self.bus.memory.memory)[14] = 0xF0; 
-
-        // "3" character
-        // 0xf
-        (*self.bus.memory.memory)[15] = 0xF0; 
-        (*self.bus.memory.memory)[16] = 0x10; 
-        (*self.bus.memory.memory)[17] = 0xF0; 
-        (*self.bus.memory.memory)[18] = 0x10; 
-        (*self.bus.memory.memory)[19] = 0xF0; 
-        
-        // "4" character
-        // 0x14
-        (*self.bus.memory.memory)[20] = 0x90; 
-        (*self.bus.memory.memory)[21] = 0x90; 
-        (*self.bus.memory.memory)[22] = 0xF0; 
-        (*self.bus.memory.memory)[23] = 0x10; 
-        (*self.bus.memory.memory)[24] = 0x10; 
-        
-        // "5" character
-        // 0x19
-        (*self.bus.memory.memory)[25] = 0xF0; 
-        (*self.bus.memory.memory)[26] = 0x80; 
-        (*self.bus.memory.memory)[27] = 0xF0; 
-        (*self.bus.memory.memory)[28] = 0x10; 
-        (*self.bus.memory.memory)[29] = 0xF0; 
-
-        // "6" character
-        // 0x1E
-        (*self.bus.memory.memory)[30] = 0xF0; 
-        (*self.bus.memory.memory)[31] = 0x80; 
-        (*self.bus.memory.memory)[32] = 0xF0; 
-        (*self.bus.memory.memory)[33] = 0x90; 
-        (*self.bus.memory.memory)[34] = 0xF0; 
-
-        // "7" character
-        // 0x23
-        (*self.bus.memory.memory)[35] = 0xF0; 
-        (*self.bus.memory.memory)[36] = 0x10; 
-        (*self.bus.memory.memory)[37] = 0x20; 
-        (*self.bus.memory.memory)[38] = 0x40; 
-        (*self.bus.memory.memory)[39] = 0x40; 
-
-        // "8" character
-        // 0x28
-        (*self.bus.memory.memory)[40] = 0xF0; 
-        (*self.bus.memory.memory)[41] = 0x90; 
-        (*self.bus.memory.memory)[42] = 0xF0; 
-        (*self.bus.memory.memory)[43] = 0x90; 
-        (*self.bus.memory.memory)[44] = 0xF0; 
-
-        // "9" character
-        // 0x2D
-        (*self.bus.memory.memory)[45] = 0xF0; 
-        (*self.bus.memory.memory)[46] = 0x90; 
-        (*self.bus.memory.memory)[47] = 0xF0; 
-        (*self.bus.memory.memory)[48] = 0x10; 
-        (*self.bus.memory.memory)[49] = 0xF0; 
-
-        // "A" character
-        // 0x32
-        (*self.bus.memory.memory)[50] = 0xF0; 
-        (*self.bus.memory.memory)[51] = 0x90; 
-        (*self.bus.memory.memory)[52] = 0xF0; 
-        (*self.bus.memory.memory)[53] = 0x90; 
-        (*self.bus.memory.memory)[54] = 0x90; 
-
-        // "B" character
-        // 0x37
-        (*self.bus.memory.memory)[55] = 0xE0; 
-        (*self.bus.memory.memory)[56] = 0x90; 
-        (*self.bus.memory.memory)[57] = 0xE0; 
-        (*self.bus.memory.memory)[58] = 0x90; 
-        (*self.bus.memory.memory)[59] = 0xE0; 
-
-        // "C" character
-        // 0x3C
-        (*self.bus.memory.memory)[60] = 0xF0; 
-        (*self.bus.memory.memory)[61] = 0x80; 
-        (*self.bus.memory.memory)[62] = 0x80; 
-        (*self.bus.memory.memory)[63] = 0x80; 
-        (*self.bus.memory.memory)[64] = 0xF0; 
-
-        // "D" character
-        // 0x41
-        (*self.bus.memory.memory)[65] = 0xE0; 
-        (*self.bus.memory.memory)[66] = 0x90; 
-        (*self.bus.memory.memory)[67] = 0x90; 
-        (*self.bus.memory.memory)[68] = 0x90; 
-        (*self.bus.memory.memory)[69] = 0xE0; 
-
-        // "E" character
-        // 0x46
-        (*self.bus.memory.memory)[70] = 0xD0; 
-        (*self.bus.memory.memory)[71] = 0x80; 
-        (*self.bus.memory.memory)[72] = 0xF0; 
-        (*self.bus.memory.memory)[73] = 0x80; 
-        (*self.bus.memory.memory)[74] = 0xF0; 
-
-        // "F" character
-        // 0x4B
-        (*self.bus.memory.memory)[75] = 0xF0; 
-        (*self.bus.memory.memory)[76] = 0x80; 
-        (*self.bus.memory.memory)[77] = 0xF0; 
-        (*self.bus.memory.memory)[78] = 0x80; 
-        (*self.bus.memory.memory)[79] = 0x80; 
-
-
-    }
-
-    pub fn step(&mut self) {
+            bus,
+            instructions_per_frame: CHIP8_INSTRUCTIONS_PER_FRAME,
+            timing: Timing::default(),
+            cycles_overrun: 0,
+            rom_info: None,
+            rom: None,
+            ram_init: RamInit::default(),
+            palette: Palette::black_and_white(),
+            keymap: Keymap::default(),
+        }
+    }
+
+    // boots a rom, configuring the machine from the builtin database
+    // when the rom is a known one
+    pub fn boot(&mut self, rom: &Rom) -> Result<(), RomError> {
+        self.boot_with_database(rom, &Database::builtin())
+    }
+
+    // The database entry of a known rom picks the platform, speed,
+    // quirks, palette and keymap. A rom at the load address of the
+    // current platform moves to where the database's platform loads
+    // programs. Nothing changes when the rom doesn't fit.
+    pub fn boot_with_database(&mut self, rom: &Rom, database: &Database) -> Result<(), RomError> {
+        let info = database.lookup(&rom.sha1_hex()).cloned();
+        let platform = info.as_ref().and_then(|info| info.platform).unwrap_or(self.cpu.platform);
+        let rom = rom_for_platform(rom, self.cpu.platform, platform)?;
+
+        self.rom = None;
+        self.set_platform(platform)?;
+        self.load_rom(&rom)?;
+        self.rom_info = info;
+        if let Some(info) = self.rom_info.clone() {
+            if let Some(ipf) = info.instructions_per_frame {
+                self.set_instructions_per_frame(ipf);
+            }
+            if let Some(quirks) = info.quirks {
+                self.set_quirks(quirks);
+            }
+            if let Some(palette) = info.palette {
+                self.palette = palette;
+            }
+            if let Some(keymap) = info.keymap.clone() {
+                self.keymap = keymap;
+            }
+        }
+        Ok(())
+    }
+
+    // Power cycles the machine with a different rom in it. The platform,
+    // quirks and timing stay as they were, the database isn't consulted.
+    // A rom that doesn't fit the platform's memory leaves the machine as
+    // it was.
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), RomError> {
+        rom.check_fits(self.bus.memory.size())?;
+        self.rom = Some(rom.clone());
+        self.rom_info = None;
+        self.reset(ResetKind::Hard);
+        Ok(())
+    }
+
+    // power cycles the machine with no rom in it
+    pub fn unload(&mut self) {
+        self.rom = None;
+        self.rom_info = None;
+        self.reset(ResetKind::Hard);
+    }
+
+    pub fn rom(&self) -> Option<&Rom> {
+        self.rom.as_ref()
+    }
+
+    pub fn reset(&mut self, kind: ResetKind) {
+        self.cpu.reset();
+        self.cycles_overrun = 0;
+        self.bus.graphics = Chip8Graphics::for_platform(self.cpu.platform);
+        self.bus.colours = Chip8ColourLayer::new();
+        self.bus.keypad = Chip8Keypad::new();
+        self.bus.keypad2 = Chip8Keypad::new();
+        self.bus.port_output = 0;
+        self.bus.port_input = 0;
+        self.bus.sample = None;
+
+        if kind == ResetKind::Hard {
+            self.bus.memory.fill(self.ram_init);
+            self.cpu.font.install(&mut self.bus.memory);
+            if let Some(ref rom) = self.rom {
+                self.bus.memory.load(rom.load_address() as usize, rom.data());
+            }
+        }
+
+        let start = match self.rom {
+            Some(ref rom) => rom.load_address(),
+            None => self.cpu.platform.load_address(),
+        };
+        self.cpu.set_pc(start);
+
+        // hi-res programs start by jumping to the code at 0x260 that sets
+        // up the 64x64 display, which the platform has done already
+        if self.cpu.platform == Platform::HiresChip8 && self.bus.memory.read_byte(start) == 0x12
+            && self.bus.memory.read_byte(start + 1) == 0x60 {
+            self.cpu.set_pc(0x2C0);
+        }
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.cpu.font
+    }
+
+    // the font a hard reset installs, set it before booting
+    pub fn set_font(&mut self, font: Font) {
+        self.cpu.font = Arc::new(font);
+    }
+
+    pub fn ram_init(&self) -> RamInit {
+        self.ram_init
+    }
+
+    // what a hard reset fills RAM with, set it before booting
+    pub fn set_ram_init(&mut self, ram_init: RamInit) {
+        self.ram_init = ram_init;
+    }
+
+    // a fault leaves the CPU at the instruction that caused it
+    pub fn step(&mut self) -> Result<(), CpuError> {
         if !self.cpu.is_halted_flag  {
-            self.cpu.step(&mut self.bus);
-            //println!("{:?}", self);
+            self.cpu.step(&mut self.bus)?;
         }
+        Ok(())
+    }
+
+    // runs one 60Hz frame worth of instructions and ticks the timers
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        self.run_frame_with(|_, _| {})
+    }
 
+    // run_frame, calling hook with the address of each instruction just
+    // before it runs
+    pub fn run_frame_with<F: FnMut(&mut Chip8, u16)>(&mut self, mut hook: F) -> Result<(), CpuError> {
+        self.run_frame_until(|chip8, pc| {
+            hook(chip8, pc);
+            false
+        }).map(|_| ())
     }
 
-    pub fn render(&mut self, canvas: &mut sdl2::render::Canvas<sdl2::video::Window>) {
-        if self.cpu.draw_to_screen_flag {
-            // TODO render graphics memory state to screen
-            canvas.set_draw_color(Color::RGB(0,0,0));
-            canvas.clear();
+    // run_frame_with, except that the frame ends early, before the
+    // instruction runs, when hook returns true. The timers still tick.
+    // Returns whether the frame was ended early. A fault ends the frame
+    // straight away, without ticking the timers.
+    pub fn run_frame_until<F: FnMut(&mut Chip8, u16) -> bool>(&mut self, mut hook: F) -> Result<bool, CpuError> {
+        let stopped = match self.timing {
+            Timing::InstructionsPerFrame => self.run_instructions_frame(&mut hook)?,
+            Timing::CosmacVip => self.run_vip_frame(&mut hook)?,
+        };
+        self.cpu.waiting_for_vblank = false;
+        self.cpu.update_timer();
+        Ok(stopped)
+    }
 
-            canvas.set_draw_color(Color::RGB(255,255,255));
-            for y in 0..CHIP8_SCREEN_HEIGHT {
-                for x in 0..CHIP8_SCREEN_WIDTH {
-                    if self.bus.graphics.get_pixel_value(x, y) {
-                        canvas.fill_rect(Rect::new((x * 10) as i32, (y * 10) as i32, 10, 10));
-                    }
+    fn run_instructions_frame<F: FnMut(&mut Chip8, u16) -> bool>(&mut self, hook: &mut F) -> Result<bool, CpuError> {
+        for _ in 0..self.instructions_per_frame {
+            if !self.cpu.is_halted_flag {
+                let pc = self.cpu.pc();
+                if hook(self, pc) {
+                    return Ok(true);
                 }
             }
+            self.step()?;
+            if self.cpu.waiting_for_vblank {
+                break;
+            }
+        }
+        Ok(false)
+    }
+
+    // Runs instructions until their cycles use up what the VIP had left
+    // for the interpreter in a frame. The VIP interpreter always waits for
+    // the display interrupt before drawing, so DXYN ends the frame whatever
+    // the display wait quirk says.
+    fn run_vip_frame<F: FnMut(&mut Chip8, u16) -> bool>(&mut self, hook: &mut F) -> Result<bool, CpuError> {
+        let budget = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+        let mut cycles = self.cycles_overrun;
+        while cycles < budget && !self.cpu.is_halted_flag {
+            let pc = self.cpu.pc();
+            if hook(self, pc) {
+                self.cycles_overrun = 0;
+                return Ok(true);
+            }
+            let instruction = self.cpu.step(&mut self.bus)?;
+            cycles += instruction.vip_cycles();
+            match instruction {
+                IntermediateAsm::DRW {..} => cycles = budget,
+                IntermediateAsm::SYS {..} => cycles += self.cpu.machine_code_cycles,
+                _ => {},
+            }
+        }
+        self.cycles_overrun = cycles.saturating_sub(budget);
+        Ok(false)
+    }
+
+    // the whole machine, see state.rs for the format
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        out.u32(self.instructions_per_frame);
+        out.timing(self.timing);
+        out.u32(self.cycles_overrun);
+        self.cpu.save_state(&mut out);
+        out.slice(&self.bus.memory.to_vec());
+        self.bus.graphics.save_state(&mut out);
+        out.bools(&self.bus.keypad.keys);
+        out.bools(&self.bus.keypad2.keys);
+        self.bus.colours.save_state(&mut out);
+        out.u8(self.bus.port_output);
+        out.u8(self.bus.port_input);
+        match self.bus.sample {
+            Some(ref sample) => {
+                out.bool(true);
+                sample.save_state(&mut out);
+            },
+            None => out.bool(false),
+        }
+        out.bytes
+    }
+
+    // the machine is left as it was if the state can't be loaded
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut input = StateReader::new(bytes)?;
+        let mut chip8 = Chip8::new();
+        chip8.rom_info = self.rom_info.clone();
+        chip8.rom = self.rom.clone();
+        chip8.ram_init = self.ram_init;
+        chip8.palette = self.palette;
+        chip8.keymap = self.keymap.clone();
+        chip8.cpu.symbols = self.cpu.symbols.clone();
+        chip8.cpu.font = self.cpu.font.clone();
+        chip8.instructions_per_frame = input.u32()?;
+        chip8.timing = input.timing()?;
+        chip8.cycles_overrun = input.u32()?;
+        chip8.cpu.load_state(&mut input)?;
+        let memory = input.slice_of(chip8.cpu.platform.memory_size(), "memory")?;
+        chip8.bus.memory = Chip8Memory::with_size(memory.len());
+        chip8.bus.memory.load(0, memory);
+        // a script watching writes carries on watching
+        chip8.bus.memory.log_writes(self.bus.memory.is_logging_writes());
+        chip8.bus.graphics = Chip8Graphics::load_state(&mut input)?;
+        input.bools(&mut chip8.bus.keypad.keys, "keypad")?;
+        input.bools(&mut chip8.bus.keypad2.keys, "second keypad")?;
+        chip8.bus.colours.load_state(&mut input)?;
+        chip8.bus.port_output = input.u8()?;
+        chip8.bus.port_input = input.u8()?;
+        if input.bool()? {
+            chip8.bus.sample = Some(MegaChipSample::load_state(&mut input)?);
+        }
+        input.finish()?;
+
+        *self = chip8;
+        Ok(())
+    }
+
+    // labels for addresses, used in error messages and by the frontends
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.cpu.symbols = Some(Arc::new(symbols));
+    }
+
+    pub fn symbols(&self) -> Option<&Symbols> {
+        self.cpu.symbols.as_deref()
+    }
+
+    pub fn rom_info(&self) -> Option<&RomInfo> {
+        self.rom_info.as_deref()
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.cycles_overrun = 0;
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.cpu.platform
+    }
 
-            canvas.present();
+    // Also sizes memory and the display for the platform. A loaded rom
+    // is moved as boot_with_database does and the machine power cycled.
+    // Fails, changing nothing, when the rom wouldn't fit the platform's
+    // memory.
+    pub fn set_platform(&mut self, platform: Platform) -> Result<(), RomError> {
+        let rom = match self.rom {
+            Some(ref rom) => Some(rom_for_platform(rom, self.cpu.platform, platform)?),
+            None => None,
+        };
+        self.cpu.platform = platform;
+        self.bus.graphics = Chip8Graphics::for_platform(platform);
+        self.bus.colours = Chip8ColourLayer::new();
+        if self.bus.memory.size() != platform.memory_size() {
+            self.bus.memory = Chip8Memory::with_size(platform.memory_size());
+        }
+        if rom.is_some() {
+            self.rom = rom;
+            self.reset(ResetKind::Hard);
+        }
+        Ok(())
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
 
-            self.cpu.draw_to_screen_flag = false;
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
 
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
 
-            //println!("{:?}", self.bus.graphics);
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    // the colour attributes when the platform has them
+    pub fn colours(&self) -> Option<&Chip8ColourLayer> {
+        match self.cpu.platform {
+            Platform::Chip8X => Some(&self.bus.colours),
+            _ => None,
         }
-        
+    }
+
+    // whether the buzzer is sounding or a MEGA-CHIP sound is playing
+    pub fn sound_active(&self) -> bool {
+        self.cpu.sound_timer() > 0 || self.bus.sample.as_ref().is_some_and(|sample| !sample.is_finished())
+    }
+
+    // the MEGA-CHIP sound that is playing, frontends pull samples from it
+    pub fn sample_mut(&mut self) -> Option<&mut MegaChipSample> {
+        self.bus.sample.as_mut()
+    }
+
+    // whether 0NNN runs RCA 1802 machine code, as on the COSMAC VIP
+    pub fn set_rca1802(&mut self, enabled: bool) {
+        self.cpu.rca1802 = if enabled { Some(cdp1802::Cdp1802::new()) } else { None };
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.cpu.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.quirks = quirks;
+    }
+
+    pub fn graphics(&self) -> &Chip8Graphics {
+        &self.bus.graphics
+    }
+
+    pub fn cpu(&self) -> &Chip8CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Chip8CPU {
+        &mut self.cpu
+    }
+
+    pub fn memory(&self) -> &Chip8Memory {
+        &self.bus.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Chip8Memory {
+        &mut self.bus.memory
+    }
+
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        self.bus.keypad.is_pressed(key)
+    }
+
+    // returns true once after the display has changed so frontends
+    // only have to upload a new frame when there is something to show
+    pub fn take_draw_flag(&mut self) -> bool {
+        let flag = self.cpu.draw_to_screen_flag;
+        self.cpu.draw_to_screen_flag = false;
+        flag
     }
 
     // !!!!NOTE!!!! this is the only interface to wake the CPU!!!!
-    pub fn do_key_event(&mut self, key: u8) {
-        let keyLong = key as usize;
-        if (*self.bus.keypad.keys)[keyLong] {
-            self.bus.keypad.keys[keyLong] = false;
-        } else {
-            if self.cpu.is_halted_flag  {
-                self.cpu.is_halted_flag = false;
-                (*self.cpu.reg_gp)[self.cpu.halted_register as usize] = key;
+    // Keys past 0xF don't exist and are ignored.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key_index = key as usize;
+        if key_index >= self.bus.keypad.keys.len() || self.bus.keypad.keys[key_index] == pressed {
+            return;
+        }
+        if pressed && self.cpu.is_halted_flag {
+            self.cpu.is_halted_flag = false;
+            self.cpu.reg_gp[self.cpu.halted_register as usize] = key;
+        }
+        self.bus.keypad.keys[key_index] = pressed;
+    }
 
-            }
-            self.bus.keypad.keys[keyLong] = true;
+    // the CHIP-8X second keypad, which FX0A doesn't wait on. Keys past
+    // 0xF are ignored.
+    pub fn set_keypad2_key(&mut self, key: u8, pressed: bool) {
+        if let Some(state) = self.bus.keypad2.keys.get_mut(key as usize) {
+            *state = pressed;
         }
     }
+
+    // flips the state of a key
+    pub fn do_key_event(&mut self, key: u8) {
+        let pressed = self.bus.keypad.is_pressed(key);
+        self.set_key(key, !pressed);
+    }
 }
 
-#[derive(Debug)]
+// Everything the cpu is connected to
+//
+// Public so a Chip8CPU can be driven on its own, as the differential
+// fuzzer in tests/differential.rs does.
+#[derive(Debug, Clone)]
 pub struct Chip8Bus {
-    memory: Chip8Memory,
-    graphics: Chip8Graphics,
-    keypad: Chip8Keypad,
+    pub memory: Chip8Memory,
+    pub graphics: Chip8Graphics,
+    pub keypad: Chip8Keypad,
+
+    // CHIP-8X hardware, unused on other platforms
+    pub keypad2: Chip8Keypad,
+    pub colours: Chip8ColourLayer,
+    // last byte written with FXF8 and what FXFB reads
+    pub port_output: u8,
+    pub port_input: u8,
+
+    // the MEGA-CHIP sound started by 060N
+    pub sample: Option<MegaChipSample>,
 }
 
 impl Chip8Bus {
-    fn new() -> Chip8Bus {
+    pub fn new() -> Chip8Bus {
         let memory = Chip8Memory::new();
         let graphics = Chip8Graphics::new();
         let keypad = Chip8Keypad::new();
@@ -243,7 +551,231 @@ impl Chip8Bus {
             memory,
             graphics,
             keypad,
+            keypad2: Chip8Keypad::new(),
+            colours: Chip8ColourLayer::new(),
+            port_output: 0,
+            port_input: 0,
+            sample: None,
+
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::quirks::Quirks;
+
+    // 200 LD V0 5, 202 LD I 300, 204 LD [I] V0, 206 JP 206
+    fn stored_rom() -> Rom {
+        Rom::new(vec![0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06]).unwrap()
+    }
+
+    #[test]
+    fn soft_reset_keeps_ram_and_hard_reset_reloads_it() {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&stored_rom()).unwrap();
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        chip8.memory_mut().write_byte(0x200, 0x61);
+        assert_eq!((chip8.cpu().v(0), chip8.cpu().i(), chip8.memory().read_byte(0x300)), (5, 0x300, 5));
+
+        chip8.reset(ResetKind::Soft);
+        assert_eq!((chip8.cpu().v(0), chip8.cpu().i(), chip8.cpu().pc()), (0, 0, 0x200));
+        assert_eq!((chip8.memory().read_byte(0x200), chip8.memory().read_byte(0x300)), (0x61, 5));
+
+        chip8.reset(ResetKind::Hard);
+        assert_eq!((chip8.memory().read_byte(0x200), chip8.memory().read_byte(0x300)), (0x60, 0));
+        assert_eq!(chip8.memory().read_byte(chip8.font().glyph_address(0xE)), 0xF0);
+    }
+
+    #[test]
+    fn load_rom_and_unload_keep_the_configuration() {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&stored_rom()).unwrap();
+        chip8.set_quirks(Quirks::cosmac_vip());
+        chip8.run_frame().unwrap();
+
+        chip8.load_rom(&Rom::new(vec![0x12, 0x00]).unwrap()).unwrap();
+        assert_eq!((chip8.memory().read_byte(0x201), chip8.memory().read_byte(0x202)), (0x00, 0x00));
+        assert_eq!((chip8.cpu().pc(), chip8.quirks()), (0x200, Quirks::cosmac_vip()));
+        assert!(chip8.rom_info().is_none());
+
+        chip8.unload();
+        assert!(chip8.rom().is_none());
+        assert_eq!((chip8.memory().read_byte(0x200), chip8.cpu().pc()), (0x00, 0x200));
+        assert_eq!(chip8.memory().read_byte(chip8.font().glyph_address(0xE)), 0xF0);
+    }
+
+    #[test]
+    fn roms_too_big_for_the_platform_are_refused() {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&stored_rom()).unwrap();
+        let megachip_rom = Rom::with_memory_size(vec![0x12; 0x8000], 0x200, Platform::MegaChip.memory_size()).unwrap();
+        match chip8.load_rom(&megachip_rom) {
+            Err(RomError::TooLarge {size, max}) => assert_eq!((size, max), (0x8000, 0xE00)),
+            other => panic!("loaded a rom that doesn't fit: {:?}", other),
+        }
+        assert_eq!(chip8.rom().map(|rom| rom.data().len()), Some(8));
+
+        chip8.set_platform(Platform::MegaChip).unwrap();
+        chip8.load_rom(&megachip_rom).unwrap();
+        assert!(chip8.set_platform(Platform::Chip8).is_err());
+        assert_eq!(chip8.platform(), Platform::MegaChip);
+        chip8.reset(ResetKind::Hard);
+        assert_eq!(chip8.memory().read_byte(0x81FF), 0x12);
+    }
+
+    #[test]
+    fn the_database_platform_decides_if_a_rom_fits() {
+        let rom = Rom::with_memory_size(vec![0x12; 0x8000], 0x200, Platform::MegaChip.memory_size()).unwrap();
+        let database = Database::parse(&format!("[{}]\ntitle = Large\nplatform = megachip\n", rom.sha1_hex())).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.boot_with_database(&rom, &database).unwrap();
+        assert_eq!(chip8.platform(), Platform::MegaChip);
+        assert_eq!(chip8.memory().read_byte_at(0x81FF), 0x12);
+
+        match Chip8::new().boot_with_database(&rom, &Database::new()) {
+            Err(RomError::TooLarge {size, max}) => assert_eq!((size, max), (0x8000, 0xE00)),
+            other => panic!("booted a rom that doesn't fit: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_database_configures_known_roms() {
+        let rom = stored_rom();
+        let database = Database::parse(&format!("[{}]
+title = Stored
+platform = chip8x
+ipf = 15
+quirks = vip
+keymap = x123qweasdzc4rfv
+palette = amber
+", rom.sha1_hex())).unwrap();
+
+        let mut chip8 = Chip8::new();
+        chip8.boot_with_database(&rom, &database).unwrap();
+        assert_eq!(chip8.rom_info().map(|info| info.title.as_str()), Some("Stored"));
+        assert_eq!(chip8.platform(), Platform::Chip8X);
+        // moved to where the CHIP-8X loads programs
+        assert_eq!((chip8.cpu().pc(), chip8.memory().read_byte(0x300)), (0x300, 0x60));
+        assert_eq!((chip8.instructions_per_frame(), chip8.quirks()), (15, Quirks::cosmac_vip()));
+        assert_eq!(chip8.palette(), Palette::amber());
+        assert_eq!(chip8.keymap().chip8_key('x'), Some(0x0));
+
+        // the frontends let the command line win
+        chip8.set_platform(Platform::Chip8).unwrap();
+        assert_eq!((chip8.cpu().pc(), chip8.memory().read_byte(0x200)), (0x200, 0x60));
+        assert!(chip8.rom_info().is_some());
+
+        let mut unknown = Chip8::new();
+        unknown.boot_with_database(&Rom::new(vec![0x12, 0x00]).unwrap(), &database).unwrap();
+        assert!(unknown.rom_info().is_none());
+        assert_eq!((unknown.platform(), unknown.instructions_per_frame()), (Platform::Chip8, CHIP8_INSTRUCTIONS_PER_FRAME));
+        assert_eq!(unknown.palette(), Palette::black_and_white());
+        assert_eq!(unknown.keymap(), &Keymap::default());
+    }
+
+    #[test]
+    fn frames_run_nine_instructions_and_tick_the_timers_once() {
+        // 200 LD DT, V0 with V0 = 0 and then ADD V1, 1 all the way down
+        let mut program = vec![0x60, 0x3C, 0xF0, 0x15];
+        for _ in 0..600 {
+            program.extend_from_slice(&[0x71, 0x01]);
+        }
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(program).unwrap()).unwrap();
+        assert_eq!(chip8.instructions_per_frame(), 9);
+
+        chip8.run_frame().unwrap();
+        assert_eq!((chip8.cpu().pc(), chip8.cpu().v(1), chip8.cpu().delay_timer()), (0x212, 7, 59));
+
+        // 540 instructions a second, as the frontend used to sleep 1/540s
+        // after each one, and the delay timer counts down 60 a second
+        for _ in 1..CHIP8_FRAMES_PER_SECOND {
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.cpu().pc() as u32, 0x200 + 2 * CHIP8_FRAMES_PER_SECOND * CHIP8_INSTRUCTIONS_PER_FRAME);
+        assert_eq!(chip8.cpu().delay_timer(), 0);
+    }
+
+    #[test]
+    fn held_keys_only_satisfy_a_key_wait_once() {
+        // 200 LD V0, K  202 ADD V1, 1  204 JP 200
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(vec![0xF0, 0x0A, 0x71, 0x01, 0x12, 0x00]).unwrap()).unwrap();
+        chip8.run_frame().unwrap();
+        chip8.set_key(0x5, true);
+        chip8.run_frame().unwrap();
+        assert_eq!((chip8.cpu().v(0), chip8.cpu().v(1)), (0x5, 1));
+
+        // a key held down repeats, which must not count as more presses
+        for _ in 0..10 {
+            chip8.set_key(0x5, true);
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.cpu().v(1), 1);
+
+        chip8.set_key(0x5, false);
+        chip8.set_key(0x5, true);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu().v(1), 2);
+    }
+
+    #[test]
+    fn keys_past_f_are_ignored() {
+        // 200 LD V0, K
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(vec![0xF0, 0x0A]).unwrap()).unwrap();
+        chip8.run_frame().unwrap();
+        chip8.set_key(0x10, true);
+        chip8.set_keypad2_key(0xFF, true);
+        assert!(chip8.cpu().is_halted_flag);
+        assert!((0..16).all(|key| !chip8.is_key_pressed(key)));
+    }
+
+    #[test]
+    fn ram_init_fills_everything_but_the_font_and_rom() {
+        let power_on = |ram_init| {
+            let mut chip8 = Chip8::new();
+            chip8.set_ram_init(ram_init);
+            chip8.boot(&stored_rom()).unwrap();
+            chip8
+        };
+        let vip = power_on(RamInit::Vip);
+        assert_eq!(vip.memory().ram()[..], power_on(RamInit::Vip).memory().ram()[..]);
+        assert_eq!(vip.memory().read_byte(0x200), 0x60);
+        assert_eq!(vip.memory().read_byte(vip.font().glyph_address(0xE)), 0xF0);
+        assert!(vip.memory().ram()[0x280..0x300].iter().filter(|&&byte| byte == 0xFF).count() > 64);
+
+        let random = power_on(RamInit::Random);
+        assert!(random.memory().ram()[0x300..].iter().any(|&byte| byte != 0));
+        assert!(power_on(RamInit::Zeros).memory().ram()[0x300..].iter().all(|&byte| byte == 0));
+
+        assert_eq!(RamInit::parse("vip"), Ok(RamInit::Vip));
+        assert!(RamInit::parse("ones").is_err());
+    }
 
+    #[test]
+    fn forking_a_megachip_machine_is_cheap() {
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(Platform::MegaChip).unwrap();
+        // 0011 switches MEGA-CHIP mode on, then the program waits
+        let rom = Rom::with_memory_size(vec![0x00, 0x11, 0x12, 0x02], 0x200, Platform::MegaChip.memory_size()).unwrap();
+        chip8.boot(&rom).unwrap();
+        chip8.run_frame().unwrap();
+
+        // copying 16 MiB per fork would take seconds for this many
+        let forks = 1000;
+        let start = ::std::time::Instant::now();
+        for _ in 0..forks {
+            let mut fork = chip8.clone();
+            fork.memory_mut().write_byte_at(0x10000, 1);
+            assert_eq!(chip8.memory().read_byte_at(0x10000), 0);
         }
+        let elapsed = start.elapsed();
+        println!("{} forks a second", (forks as f64 / elapsed.as_secs_f64()) as u64);
+        assert!(elapsed.as_secs() < 2, "{} forks took {:?}", forks, elapsed);
     }
 }
@@ -0,0 +1,806 @@
+extern crate gif;
+
+use std::collections::HashMap;
+
+use chip8::json::Json;
+
+// Octo cartridges and the assembler for the source they carry
+//
+// A cartridge is a gif with the program hidden in its pixels. Every pixel
+// carries two bits of payload in the low bits of its colour index, four
+// pixels to the byte with the most significant bits first. The payload
+// starts with a 32-bit big endian length followed by that many bytes of
+// JSON, whose "program" is the Octo source.
+//
+// The assembler covers Octo's statements, conditionals, loops and the
+// :const, :alias, :org, :unpack, :byte and :call directives. Programs
+// using :macro, :calc or :stringmode are refused rather than assembled
+// wrongly.
+
+// the Octo source in a cartridge
+pub fn cartridge_source(bytes: &[u8]) -> Result<String, String> {
+    let json = cartridge_payload(bytes)?;
+    program_of(&json)
+}
+
+// Extracts the JSON payload of an Octo cartridge
+pub fn cartridge_payload(bytes: &[u8]) -> Result<String, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(bytes).map_err(|e| e.to_string())?;
+
+    let mut payload = Vec::new();
+    let mut byte = 0u8;
+    let mut bits = 0;
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| e.to_string())? {
+        for index in frame.buffer.iter() {
+            byte = (byte << 2) | (index & 0x3);
+            bits += 2;
+            if bits == 8 {
+                payload.push(byte);
+                byte = 0;
+                bits = 0;
+            }
+        }
+    }
+
+    if payload.len() < 4 {
+        return Err(String::from("no payload"));
+    }
+    let length = ((payload[0] as usize) << 24) | ((payload[1] as usize) << 16)
+        | ((payload[2] as usize) << 8) | payload[3] as usize;
+    if payload.len() - 4 < length {
+        return Err(String::from("payload is truncated"));
+    }
+
+    String::from_utf8(payload[4..4 + length].to_vec())
+        .map_err(|_| String::from("payload is not text"))
+}
+
+// The "program" string of the payload, the options Octo keeps next to it
+// are skipped
+fn program_of(json: &str) -> Result<String, String> {
+    let payload = Json::parse(json)?;
+    payload.get("program").and_then(Json::as_str).map(String::from)
+        .ok_or_else(|| String::from("payload has no program"))
+}
+
+// Assembles Octo source into a program image that starts at origin
+//
+// Like Octo, the image starts with a jump to the main label.
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, String> {
+    let mut tokens = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        for token in code.split_whitespace() {
+            tokens.push((token.to_string(), i + 1));
+        }
+    }
+
+    let mut assembler = Assembler {
+        tokens,
+        position: 0,
+        line: 0,
+        origin: origin as usize,
+        here: origin as usize,
+        image: Vec::new(),
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    assembler.run().map_err(|e| format!("line {}: {}", assembler.line, e))?;
+    Ok(assembler.image)
+}
+
+// where a label's address goes once it is known
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    // the low 12 bits of an instruction
+    Nnn,
+    // the 16-bit word after F000
+    Long,
+    // the low nibble and byte of :unpack's two loads
+    UnpackHigh,
+    UnpackLow,
+}
+
+struct Fixup {
+    addr: usize,
+    field: Field,
+    label: String,
+    line: usize,
+}
+
+// the open begin and loop blocks, holding the jumps to patch
+enum Block {
+    If(usize),
+    Else(usize),
+    Loop {start: u16, breaks: Vec<usize>},
+}
+
+// the right hand side of a comparison
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Value(u8),
+}
+
+// the test at the end of a condition, after any instructions it needs
+#[derive(Debug, Clone, Copy)]
+enum Test {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Test {
+    fn negate(self) -> Test {
+        match self {
+            Test::Equal(x, operand) => Test::NotEqual(x, operand),
+            Test::NotEqual(x, operand) => Test::Equal(x, operand),
+            Test::Key(x) => Test::NotKey(x),
+            Test::NotKey(x) => Test::Key(x),
+        }
+    }
+
+    // the instruction that skips the next one when the test fails
+    fn skip_unless(self) -> u16 {
+        let x = |x: u8| (x as u16) << 8;
+        match self {
+            Test::Equal(vx, Operand::Value(n)) => 0x4000 | x(vx) | n as u16,
+            Test::Equal(vx, Operand::Register(vy)) => 0x9000 | x(vx) | (vy as u16) << 4,
+            Test::NotEqual(vx, Operand::Value(n)) => 0x3000 | x(vx) | n as u16,
+            Test::NotEqual(vx, Operand::Register(vy)) => 0x5000 | x(vx) | (vy as u16) << 4,
+            Test::Key(vx) => 0xE0A1 | x(vx),
+            Test::NotKey(vx) => 0xE09E | x(vx),
+        }
+    }
+}
+
+struct Assembler {
+    tokens: Vec<(String, usize)>,
+    position: usize,
+    // of the last token read, for errors
+    line: usize,
+    origin: usize,
+    // the address of the next byte
+    here: usize,
+    image: Vec<u8>,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, u16>,
+    aliases: HashMap<String, u8>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+impl Assembler {
+    fn run(&mut self) -> Result<(), String> {
+        // the jump to main
+        self.emit(0x1000);
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+
+        if !self.blocks.is_empty() {
+            return Err(String::from("a begin or loop is missing its end or again"));
+        }
+        let main = *self.labels.get("main").ok_or_else(|| String::from("the program has no main label"))?;
+        self.patch(self.origin, Field::Nnn, main)?;
+        for fixup in ::std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let addr = *self.labels.get(&fixup.label)
+                .ok_or_else(|| format!("\"{}\" is not defined", fixup.label))?;
+            self.patch(fixup.addr, fixup.field, addr)?;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let (token, line) = self.tokens.get(self.position).cloned().ok_or_else(|| String::from("the program ends too soon"))?;
+        self.position += 1;
+        self.line = line;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|token| token.0.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(format!("expected \"{}\" but found \"{}\"", expected, token))
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        let index = self.here - self.origin;
+        if index >= self.image.len() {
+            self.image.resize(index + 1, 0);
+        }
+        self.image[index] = byte;
+        self.here += 1;
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        self.byte((opcode >> 8) as u8);
+        self.byte(opcode as u8);
+    }
+
+    fn patch(&mut self, addr: usize, field: Field, value: u16) -> Result<(), String> {
+        let index = addr - self.origin;
+        match field {
+            Field::Nnn => {
+                if value > 0xFFF {
+                    return Err(format!("address {:#x} is out of reach, use i := long", value));
+                }
+                self.image[index] = (self.image[index] & 0xF0) | (value >> 8) as u8;
+                self.image[index + 1] = value as u8;
+            },
+            Field::Long => {
+                self.image[index] = (value >> 8) as u8;
+                self.image[index + 1] = value as u8;
+            },
+            Field::UnpackHigh => self.image[index] |= (value >> 8) as u8 & 0xF,
+            Field::UnpackLow => self.image[index] = value as u8,
+        }
+        Ok(())
+    }
+
+    // An address that goes into fields, labels that aren't defined yet
+    // are patched in at the end
+    fn address(&mut self, fields: &[(Field, usize)]) -> Result<(), String> {
+        let token = self.next()?;
+        let value = match self.labels.get(&token).or_else(|| self.constants.get(&token)) {
+            Some(&value) => Some(value),
+            None => parse_number(&token).map(|value| value as u16),
+        };
+        for &(field, addr) in fields {
+            match value {
+                Some(value) => self.patch(addr, field, value)?,
+                None => self.fixups.push(Fixup {addr, field, label: token.clone(), line: self.line}),
+            }
+        }
+        Ok(())
+    }
+
+    // an instruction ending in an address
+    fn emit_address(&mut self, opcode: u16) -> Result<(), String> {
+        let addr = self.here;
+        self.emit(opcode);
+        self.address(&[(Field::Nnn, addr)])
+    }
+
+    // a number or constant
+    fn value(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        match self.constants.get(&token).or_else(|| self.labels.get(&token)) {
+            Some(&value) => Ok(value),
+            None => parse_number(&token).map(|value| value as u16)
+                .ok_or_else(|| format!("\"{}\" is not a number", token)),
+        }
+    }
+
+    fn byte_value(&mut self) -> Result<u8, String> {
+        Ok(self.value()? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        let value = self.value()?;
+        if value > 0xF {
+            return Err(format!("{} doesn't fit in 4 bits", value));
+        }
+        Ok(value)
+    }
+
+    fn register_of(&self, token: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+        let lower = token.to_ascii_lowercase();
+        if lower.len() == 2 && lower.starts_with('v') {
+            u8::from_str_radix(&lower[1..], 16).ok()
+        } else {
+            None
+        }
+    }
+
+    fn register(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        self.register_of(&token).map(|register| register as u16)
+            .ok_or_else(|| format!("\"{}\" is not a register", token))
+    }
+
+    // the jump to fill in when the block ends
+    fn jump_placeholder(&mut self) -> usize {
+        let addr = self.here;
+        self.emit(0x1000);
+        addr
+    }
+
+    fn land(&mut self, jump: usize) -> Result<(), String> {
+        let here = self.here as u16;
+        self.patch(jump, Field::Nnn, here)
+    }
+
+    fn condition(&mut self) -> Result<Test, String> {
+        let x = self.register()? as u8;
+        let comparison = self.next()?;
+        match comparison.as_str() {
+            "key" => return Ok(Test::Key(x)),
+            "-key" => return Ok(Test::NotKey(x)),
+            _ => {},
+        }
+
+        let token = self.peek().unwrap_or("").to_string();
+        let right = match self.register_of(&token) {
+            Some(y) => {
+                self.position += 1;
+                Operand::Register(y)
+            },
+            None => Operand::Value(self.byte_value()?),
+        };
+        match comparison.as_str() {
+            "==" => Ok(Test::Equal(x, right)),
+            "!=" => Ok(Test::NotEqual(x, right)),
+            "<" | ">" | "<=" | ">=" => {
+                // VF ends up as the flag of a subtraction, 1 when there was
+                // no borrow
+                let x = x as u16;
+                let no_borrow = match (comparison.as_str(), right) {
+                    // vf := vx - vy and vf := vy - vx
+                    ("<", Operand::Register(y)) | (">=", Operand::Register(y)) => {
+                        self.emit(0x8F00 | x << 4);
+                        self.emit(0x8F05 | (y as u16) << 4);
+                        comparison == ">="
+                    },
+                    (_, Operand::Register(y)) => {
+                        self.emit(0x8F00 | (y as u16) << 4);
+                        self.emit(0x8F05 | x << 4);
+                        comparison == "<="
+                    },
+                    // vf := vx - n and vf := n - vx
+                    ("<", Operand::Value(n)) | (">=", Operand::Value(n)) => {
+                        self.emit(0x6F00 | n as u16);
+                        self.emit(0x8F07 | x << 4);
+                        comparison == ">="
+                    },
+                    (_, Operand::Value(n)) => {
+                        self.emit(0x6F00 | n as u16);
+                        self.emit(0x8F05 | x << 4);
+                        comparison == "<="
+                    },
+                };
+                Ok(if no_borrow { Test::NotEqual(0xF, Operand::Value(0)) } else { Test::Equal(0xF, Operand::Value(0)) })
+            },
+            _ => Err(format!("\"{}\" is not a comparison", comparison)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        if let Some(x) = self.register_of(&token) {
+            return self.register_statement(x as u16);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name.clone(), self.here as u16).is_some() {
+                    return Err(format!("\"{}\" is defined twice", name));
+                }
+            },
+            ":const" => {
+                let name = self.next()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+            },
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()? as u8;
+                self.aliases.insert(name, register);
+            },
+            ":org" => {
+                let addr = self.value()? as usize;
+                if addr < self.origin {
+                    return Err(format!(":org {:#x} is below the start of the program", addr));
+                }
+                self.here = addr;
+            },
+            ":byte" => {
+                let byte = self.byte_value()?;
+                self.byte(byte);
+            },
+            ":call" => self.emit_address(0x2000)?,
+            ":unpack" => {
+                // v0 := high nibble and address bits 8-11, v1 := the low byte
+                let high = self.nibble()?;
+                let addr = self.here;
+                self.emit(0x6000 | high << 4);
+                self.emit(0x6100);
+                self.address(&[(Field::UnpackHigh, addr + 1), (Field::UnpackLow, addr + 3)])?;
+            },
+            ":breakpoint" => {
+                self.next()?;
+            },
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            },
+            ";" | "return" => self.emit(0x00EE),
+            "clear" => self.emit(0x00E0),
+            "hires" => self.emit(0x00FF),
+            "lores" => self.emit(0x00FE),
+            "exit" => self.emit(0x00FD),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n);
+            },
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n);
+            },
+            "audio" => self.emit(0xF002),
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8);
+            },
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | x << 8);
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.position += 1;
+                    let y = self.register()?;
+                    self.emit(if token == "save" { 0x5002 } else { 0x5003 } | x << 8 | y << 4);
+                } else {
+                    self.emit(if token == "save" { 0xF055 } else { 0xF065 } | x << 8);
+                }
+            },
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(0xF075 | x << 8);
+            },
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(0xF085 | x << 8);
+            },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n);
+            },
+            "jump" => self.emit_address(0x1000)?,
+            "jump0" => self.emit_address(0xB000)?,
+            "native" => self.emit_address(0x0000)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let low = match token.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | x << 8 | low);
+            },
+            "i" => {
+                match self.next()?.as_str() {
+                    ":=" => match self.peek() {
+                        Some("long") => {
+                            self.position += 1;
+                            self.emit(0xF000);
+                            let addr = self.here;
+                            self.emit(0);
+                            self.address(&[(Field::Long, addr)])?;
+                        },
+                        Some("hex") | Some("bighex") => {
+                            let low = if self.next()? == "hex" { 0x29 } else { 0x30 };
+                            let x = self.register()?;
+                            self.emit(0xF000 | x << 8 | low);
+                        },
+                        _ => self.emit_address(0xA000)?,
+                    },
+                    "+=" => {
+                        let x = self.register()?;
+                        self.emit(0xF01E | x << 8);
+                    },
+                    other => return Err(format!("\"i {}\" is not an instruction", other)),
+                }
+            },
+            "if" => {
+                let test = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => self.emit(test.skip_unless()),
+                    "begin" => {
+                        self.emit(test.negate().skip_unless());
+                        let jump = self.jump_placeholder();
+                        self.blocks.push(Block::If(jump));
+                    },
+                    other => return Err(format!("expected \"then\" or \"begin\" but found \"{}\"", other)),
+                }
+            },
+            "else" => match self.blocks.pop() {
+                Some(Block::If(jump)) => {
+                    let end = self.jump_placeholder();
+                    self.land(jump)?;
+                    self.blocks.push(Block::Else(end));
+                },
+                _ => return Err(String::from("else without if ... begin")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If(jump)) | Some(Block::Else(jump)) => self.land(jump)?,
+                _ => return Err(String::from("end without if ... begin")),
+            },
+            "loop" => self.blocks.push(Block::Loop {start: self.here as u16, breaks: Vec::new()}),
+            "while" => {
+                let test = self.condition()?;
+                self.emit(test.negate().skip_unless());
+                let jump = self.jump_placeholder();
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop {..})) {
+                    Some(&mut Block::Loop {ref mut breaks, ..}) => breaks.push(jump),
+                    _ => return Err(String::from("while outside of a loop")),
+                }
+            },
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop {start, breaks}) => {
+                    self.emit(0x1000 | start);
+                    for jump in breaks {
+                        self.land(jump)?;
+                    }
+                },
+                _ => return Err(String::from("again without loop")),
+            },
+            ":macro" | ":calc" | ":stringmode" | ":assert" | ":next" | ":pointer" =>
+                return Err(format!("{} is not supported", token)),
+            _ => {
+                if let Some(&value) = self.constants.get(&token) {
+                    self.byte(value as u8);
+                } else if let Some(value) = parse_number(&token) {
+                    if !(-128..=255).contains(&value) {
+                        return Err(format!("{} doesn't fit in a byte", value));
+                    }
+                    self.byte(value as u8);
+                } else if token.starts_with(':') {
+                    return Err(format!("{} is not a directive", token));
+                } else {
+                    // calling a subroutine by its name
+                    self.position -= 1;
+                    self.emit_address(0x2000)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.next()?;
+        let token = self.peek().unwrap_or("").to_string();
+        if let Some(y) = self.register_of(&token) {
+            self.position += 1;
+            let low = match operator.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return Err(format!("\"{}\" doesn't work on two registers", operator)),
+            };
+            self.emit(0x8000 | x << 8 | (y as u16) << 4 | low);
+            return Ok(());
+        }
+
+        match (operator.as_str(), token.as_str()) {
+            (":=", "random") => {
+                self.position += 1;
+                let mask = self.byte_value()?;
+                self.emit(0xC000 | x << 8 | mask as u16);
+            },
+            (":=", "key") => {
+                self.position += 1;
+                self.emit(0xF00A | x << 8);
+            },
+            (":=", "delay") => {
+                self.position += 1;
+                self.emit(0xF007 | x << 8);
+            },
+            (":=", _) => {
+                let n = self.byte_value()?;
+                self.emit(0x6000 | x << 8 | n as u16);
+            },
+            ("+=", _) => {
+                let n = self.byte_value()?;
+                self.emit(0x7000 | x << 8 | n as u16);
+            },
+            ("-=", _) => {
+                let n = self.byte_value()?;
+                self.emit(0x7000 | x << 8 | n.wrapping_neg() as u16);
+            },
+            _ => return Err(format!("\"{}\" is not an instruction", operator)),
+        }
+        Ok(())
+    }
+}
+
+// decimal, hex with 0x and binary with 0b, optionally negative
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()
+    } else {
+        None
+    };
+    value.map(|value| if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::memory::CHIP8_MEMORY_SIZE;
+    use chip8::rom::Rom;
+
+    // a cartridge of the payload, 64 pixels wide like a small label
+    fn cartridge(json: &str) -> Vec<u8> {
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json.as_bytes());
+        let mut pixels = Vec::new();
+        for byte in payload {
+            for shift in [6, 4, 2, 0].iter() {
+                // the colour bits above the payload don't matter
+                pixels.push(0x4 | (byte >> shift) & 0x3);
+            }
+        }
+        let height = pixels.len().div_ceil(64);
+        pixels.resize(64 * height, 0);
+
+        let palette: Vec<u8> = (0..8).flat_map(|i| vec![i * 32, i * 32, i * 32]).collect();
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, 64, height as u16, &palette).unwrap();
+            let frame = gif::Frame::from_indexed_pixels(64, height as u16, &pixels, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+        gif
+    }
+
+    fn words(image: &[u8]) -> Vec<u16> {
+        image.chunks(2).map(|pair| (pair[0] as u16) << 8 | *pair.get(1).unwrap_or(&0) as u16).collect()
+    }
+
+    #[test]
+    fn cartridges_carry_the_program_source() {
+        let json = "{\"options\":{\"tickrate\":20,\"fillColor\":\"#FFCC00\",\"touchInputMode\":[\"none\"]},\
+                    \"program\":\": main\\n\\tclear # \\\"hi\\\" \\u00e9\\n\"}";
+        assert_eq!(cartridge_payload(&cartridge(json)).unwrap(), json);
+        assert_eq!(cartridge_source(&cartridge(json)).unwrap(), ": main\n\tclear # \"hi\" \u{e9}\n");
+        assert_eq!(cartridge_source(&cartridge("{\"options\":{}}")), Err(String::from("payload has no program")));
+        assert!(cartridge_source(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn cartridges_load_as_assembled_roms() {
+        let json = "{\"program\":\": main\\n  v0 := 7\\n  jump main\\n\"}";
+        let rom = Rom::from_octo_cartridge(&cartridge(json), 0x200, CHIP8_MEMORY_SIZE).unwrap();
+        assert_eq!(rom.data(), &[0x12, 0x02, 0x60, 0x07, 0x12, 0x02]);
+
+        let broken = "{\"program\":\": main jump nowhere\"}";
+        match Rom::from_octo_cartridge(&cartridge(broken), 0x200, CHIP8_MEMORY_SIZE) {
+            Err(e) => assert_eq!(e.to_string(), "invalid octo cartridge: line 1: \"nowhere\" is not defined"),
+            Ok(rom) => panic!("loaded {:?}", rom),
+        }
+    }
+
+    #[test]
+    fn assembles_statements() {
+        let source = "
+            :const speed 3
+            :alias x v4
+            : draw
+                sprite x v5 8
+            ;
+            : main
+                clear
+                i := sprite-data
+                x := 10
+                x += speed
+                x -= 1
+                v5 := random 0xF0
+                v6 := key
+                v6 := delay
+                delay := v6
+                buzzer := v6
+                x ^= v5
+                x =- v5
+                i := hex x
+                i += x
+                bcd x
+                save v2
+                load v2
+                draw
+                jump main
+            : sprite-data
+                0xF0 0b10010000 -1 speed
+        ";
+        let image = assemble(source, 0x200).unwrap();
+        assert_eq!(words(&image[..24]), vec![
+            0x1206,                                  // jump main
+            0xD458, 0x00EE,                          // draw
+            0x00E0, 0xA22C, 0x640A, 0x7403, 0x74FF,  // main
+            0xC5F0, 0xF60A, 0xF607, 0xF615,
+        ]);
+        assert_eq!(words(&image[24..0x2C]), vec![0xF618, 0x8453, 0x8457, 0xF429, 0xF41E, 0xF433, 0xF255, 0xF265, 0x2202, 0x1206]);
+        assert_eq!(&image[0x2C..], &[0xF0, 0x90, 0xFF, 0x03]);
+    }
+
+    #[test]
+    fn assembles_conditions_and_blocks() {
+        let source = "
+            : main
+                if v1 == 2 then v0 := 1
+                if v1 != v2 then v0 := 1
+                if v1 key then v0 := 1
+                if v1 < v2 then v0 := 1
+                if v1 >= 5 begin
+                    v0 := 2
+                else
+                    v0 := 3
+                end
+                loop
+                    v3 += 1
+                    while v3 != 9
+                again
+        ";
+        let image = assemble(source, 0x200).unwrap();
+        assert_eq!(words(&image), vec![
+            0x1202,
+            0x4102, 0x6001,
+            0x5120, 0x6001,
+            0xE1A1, 0x6001,
+            0x8F10, 0x8F25, 0x4F00, 0x6001,
+            // vf := v1 - 5 leaves the flag at 1 when v1 >= 5, which skips
+            // the jump to else
+            0x6F05, 0x8F17, 0x4F00, 0x1222, 0x6002, 0x1224, 0x6003,
+            0x7301, 0x4309, 0x122C, 0x1224,
+        ]);
+    }
+
+    #[test]
+    fn long_addresses_and_unpack() {
+        let image = assemble(": main\n:unpack 0xA data\ni := long data\n:org 0x1234\n: data 1 2", 0x200).unwrap();
+        assert_eq!(words(&image[..10]), vec![0x1202, 0x60A2, 0x6134, 0xF000, 0x1234]);
+        assert_eq!(image.len(), 0x1036);
+        assert_eq!(&image[0x1034..], &[1, 2]);
+    }
+
+    #[test]
+    fn reports_what_it_cannot_assemble() {
+        assert_eq!(assemble("clear", 0x200), Err(String::from("line 1: the program has no main label")));
+        assert_eq!(assemble(": main\n  jump nowhere", 0x200), Err(String::from("line 2: \"nowhere\" is not defined")));
+        assert_eq!(assemble(": main\n:macro twice X { X X }", 0x200), Err(String::from("line 2: :macro is not supported")));
+        assert_eq!(assemble(": main\nloop\nv0 += 1", 0x200), Err(String::from("line 3: a begin or loop is missing its end or again")));
+        assert_eq!(assemble(": main\nv0 := 1 v1 |= 2", 0x200), Err(String::from("line 2: \"|=\" is not an instruction")));
+    }
+}
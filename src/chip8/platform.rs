@@ -0,0 +1,65 @@
+use chip8::graphics::{CHIP8_SCREEN_WIDTH, CHIP8_SCREEN_HEIGHT};
+use chip8::megachip::MEGACHIP_MEMORY_SIZE;
+use chip8::memory::CHIP8_MEMORY_SIZE;
+use chip8::rom::DEFAULT_LOAD_ADDRESS;
+
+// The machine a program was written for
+//
+// Platforms add instructions to chip-8 and change where programs are
+// loaded and the size of the display, the quirks and font are chosen
+// separately.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    // the two page 64x64 variant of the VIP interpreter, programs start by
+    // jumping to it at 0x260
+    HiresChip8,
+    // the ETI-660 kit computer, with its interpreter below 0x600 and a
+    // 64x48 display
+    Eti660,
+    // RCA's CHIP-8X for the VIP with the VP-590 colour board and the
+    // VP-580 second keypad
+    Chip8X,
+    // Revival Studios' MEGA-CHIP, a SUPER-CHIP successor with a 256x192
+    // colour display and 16 MiB of memory
+    MegaChip,
+}
+
+impl Platform {
+    pub fn parse(name: &str) -> Result<Platform, String> {
+        match name.trim() {
+            "chip8" => Ok(Platform::Chip8),
+            "hires" => Ok(Platform::HiresChip8),
+            "eti660" => Ok(Platform::Eti660),
+            "chip8x" => Ok(Platform::Chip8X),
+            "megachip" => Ok(Platform::MegaChip),
+            _ => Err(format!("unknown platform \"{}\"", name)),
+        }
+    }
+
+    // the CHIP-8X interpreter is larger and programs start after it
+    pub fn load_address(&self) -> u16 {
+        match *self {
+            Platform::Chip8 | Platform::HiresChip8 | Platform::MegaChip => DEFAULT_LOAD_ADDRESS,
+            Platform::Eti660 => 0x600,
+            Platform::Chip8X => 0x300,
+        }
+    }
+
+    // width and height of the display after reset
+    pub fn display_size(&self) -> (usize, usize) {
+        match *self {
+            Platform::HiresChip8 => (CHIP8_SCREEN_WIDTH, 64),
+            Platform::Eti660 => (CHIP8_SCREEN_WIDTH, 48),
+            _ => (CHIP8_SCREEN_WIDTH, CHIP8_SCREEN_HEIGHT),
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match *self {
+            Platform::MegaChip => MEGACHIP_MEMORY_SIZE,
+            _ => CHIP8_MEMORY_SIZE,
+        }
+    }
+}
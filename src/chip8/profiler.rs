@@ -0,0 +1,325 @@
+// Instruction level profiler
+//
+// Counts how often each address runs and the VIP cycles it costs, which
+// subroutines the time goes into and how many instructions each frame
+// runs. Subroutines are followed through Chip8CPU::stack: when the stack
+// grows the instruction about to run is the start of a subroutine, and
+// when it shrinks the subroutine has returned. Cycles spent in machine
+// code called by SYS are not counted.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use chip8::Chip8;
+use chip8::cpu::{decode_for, disassemble_with_symbols, CpuError};
+use chip8::symbols::Symbols;
+
+// width of the bars in the histogram
+const HISTOGRAM_WIDTH: u64 = 50;
+// rows in the histogram
+const HISTOGRAM_BUCKETS: u64 = 20;
+
+// instructions and VIP cycles
+pub type Time = (u64, u64);
+
+#[derive(Debug, Clone)]
+struct CallStack {
+    // start addresses of the subroutines being run, outermost first
+    entries: Vec<u16>,
+    instructions: u64,
+    cycles: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    // per address
+    counts: Vec<u64>,
+    cycles: Vec<u64>,
+    // times each subroutine was called
+    calls: HashMap<u16, u64>,
+    // every call stack seen, with what ran while it was current
+    stacks: Vec<CallStack>,
+    stack_ids: HashMap<Vec<u16>, usize>,
+    current_stack: usize,
+    frame_instructions: u32,
+    // instructions run in each finished frame
+    pub frames: Vec<u32>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        let mut stack_ids = HashMap::new();
+        stack_ids.insert(Vec::new(), 0);
+        Profiler {
+            counts: vec![0; 0x10000],
+            cycles: vec![0; 0x10000],
+            calls: HashMap::new(),
+            stacks: vec![CallStack { entries: Vec::new(), instructions: 0, cycles: 0 }],
+            stack_ids,
+            current_stack: 0,
+            frame_instructions: 0,
+            frames: Vec::new(),
+        }
+    }
+
+    // runs a frame, recording every instruction
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Result<(), CpuError> {
+        let result = chip8.run_frame_with(|chip8, pc| self.record(chip8, pc));
+        self.end_frame();
+        result
+    }
+
+    // records the instruction at pc, which is about to run, for use from
+    // a Chip8::run_frame_with hook
+    pub fn record(&mut self, chip8: &Chip8, pc: u16) {
+        let depth = chip8.cpu().stack().len();
+        let current_depth = self.stacks[self.current_stack].entries.len();
+        if depth != current_depth {
+            let mut entries = self.stacks[self.current_stack].entries.clone();
+            entries.truncate(depth);
+            while entries.len() < depth {
+                entries.push(pc);
+                *self.calls.entry(pc).or_insert(0) += 1;
+            }
+            self.current_stack = self.stack_id(entries);
+        }
+
+        let memory = chip8.memory();
+        let opcode = ((memory.read_byte(pc) as u16) << 8) | memory.read_byte(pc.wrapping_add(1)) as u16;
+        let cycles = decode_for(chip8.platform(), opcode).map(|instruction| instruction.vip_cycles()).unwrap_or(0) as u64;
+
+        self.counts[pc as usize] += 1;
+        self.cycles[pc as usize] += cycles;
+        let stack = &mut self.stacks[self.current_stack];
+        stack.instructions += 1;
+        stack.cycles += cycles;
+        self.frame_instructions += 1;
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frames.push(self.frame_instructions);
+        self.frame_instructions = 0;
+    }
+
+    fn stack_id(&mut self, entries: Vec<u16>) -> usize {
+        if let Some(&id) = self.stack_ids.get(&entries) {
+            return id;
+        }
+        let id = self.stacks.len();
+        self.stacks.push(CallStack { entries: entries.clone(), instructions: 0, cycles: 0 });
+        self.stack_ids.insert(entries, id);
+        id
+    }
+
+    pub fn count(&self, addr: u16) -> u64 {
+        self.counts[addr as usize]
+    }
+
+    pub fn calls(&self, addr: u16) -> u64 {
+        self.calls.get(&addr).cloned().unwrap_or(0)
+    }
+
+    pub fn total_instructions(&self) -> u64 {
+        self.stacks.iter().map(|stack| stack.instructions).sum()
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.stacks.iter().map(|stack| stack.cycles).sum()
+    }
+
+    // time spent in the subroutine itself and in it together with
+    // everything it called, the main program is None
+    pub fn subroutine_time(&self, entry: Option<u16>) -> (Time, Time) {
+        let mut own = (0, 0);
+        let mut total = (0, 0);
+        for stack in self.stacks.iter() {
+            let inside = match entry {
+                Some(addr) => stack.entries.contains(&addr),
+                None => true,
+            };
+            if inside {
+                total.0 += stack.instructions;
+                total.1 += stack.cycles;
+            }
+            if stack.entries.last().cloned() == entry {
+                own.0 += stack.instructions;
+                own.1 += stack.cycles;
+            }
+        }
+        (own, total)
+    }
+
+    // Hot spots and subroutines sorted by time, with the instructions
+    // disassembled from the machine's memory as it is now and labelled
+    // with its symbols
+    pub fn report(&self, chip8: &Chip8) -> String {
+        let mut out = String::new();
+        let instructions = self.total_instructions();
+        let cycles = self.total_cycles();
+        writeln!(out, "{} instructions, {} VIP cycles over {} frames", instructions, cycles, self.frames.len()).unwrap();
+        if let (Some(min), Some(max)) = (self.frames.iter().min(), self.frames.iter().max()) {
+            let mean = self.frames.iter().map(|&n| n as u64).sum::<u64>() as f64 / self.frames.len() as f64;
+            writeln!(out, "instructions per frame: min {}, mean {:.1}, max {}", min, mean, max).unwrap();
+        }
+
+        writeln!(out).unwrap();
+        writeln!(out, "Hot spots").unwrap();
+        writeln!(out, "address        count      %      cycles  instruction              label").unwrap();
+        let symbols = chip8.symbols();
+        let mut addresses: Vec<usize> = (0..self.counts.len()).filter(|&addr| self.counts[addr] > 0).collect();
+        addresses.sort_by(|&a, &b| self.counts[b].cmp(&self.counts[a]).then(a.cmp(&b)));
+        for addr in addresses {
+            let label = symbols.and_then(|symbols| symbols.label(addr as u32)).unwrap_or_default();
+            writeln!(out, "{:#06X} {:>12} {:>6.2} {:>11}  {:<24} {}", addr, self.counts[addr],
+                     percent(self.counts[addr], instructions), self.cycles[addr],
+                     disassemble_with_symbols(chip8.platform(), chip8.memory(), addr as u16, symbols), label)
+                .unwrap();
+        }
+
+        writeln!(out).unwrap();
+        writeln!(out, "Subroutines").unwrap();
+        writeln!(out, "name            calls   self instr    total instr    self cycles   total cycles      %").unwrap();
+        let mut entries: Vec<Option<u16>> = vec![None];
+        let mut called: Vec<u16> = self.calls.keys().cloned().collect();
+        called.sort();
+        entries.extend(called.into_iter().map(Some));
+        let mut rows: Vec<(Option<u16>, Time, Time)> = entries.into_iter()
+            .map(|entry| {
+                let (own, total) = self.subroutine_time(entry);
+                (entry, own, total)
+            })
+            .collect();
+        // most cycles first
+        rows.sort_by(|a, b| b.2.1.cmp(&a.2.1).then(b.2.0.cmp(&a.2.0)));
+        for (entry, own, total) in rows {
+            let calls = entry.map(|addr| self.calls(addr).to_string()).unwrap_or_else(|| String::from("-"));
+            writeln!(out, "{:<12} {:>8} {:>12} {:>14} {:>14} {:>14} {:>6.2}", subroutine_name(entry, symbols), calls,
+                     own.0, total.0, own.1, total.1, percent(total.1, cycles)).unwrap();
+        }
+        out
+    }
+
+    // One line per call stack with the instructions run in it, the
+    // format flamegraph.pl and inferno read
+    pub fn folded(&self, symbols: Option<&Symbols>) -> String {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .filter(|stack| stack.instructions > 0)
+            .map(|stack| {
+                let mut names = vec![subroutine_name(None, symbols)];
+                names.extend(stack.entries.iter().map(|&addr| subroutine_name(Some(addr), symbols)));
+                format!("{} {}", names.join(";"), stack.instructions)
+            })
+            .collect();
+        lines.sort();
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+
+    // How many frames ran each number of instructions, in up to 20
+    // equal ranges between the quietest and busiest frames
+    pub fn histogram(&self) -> String {
+        let mut out = String::new();
+        let (min, max) = match (self.frames.iter().min(), self.frames.iter().max()) {
+            (Some(&min), Some(&max)) => (min as u64, max as u64),
+            _ => return out,
+        };
+        let width = (max - min) / HISTOGRAM_BUCKETS + 1;
+        let mut buckets = vec![0u64; ((max - min) / width + 1) as usize];
+        for &n in self.frames.iter() {
+            buckets[((n as u64 - min) / width) as usize] += 1;
+        }
+        let most = buckets.iter().cloned().max().unwrap_or(0).max(1);
+
+        writeln!(out, "instructions     frames").unwrap();
+        for (i, &frames) in buckets.iter().enumerate() {
+            let low = min + i as u64 * width;
+            let range = if width == 1 { low.to_string() } else { format!("{}-{}", low, low + width - 1) };
+            let bar = (frames * HISTOGRAM_WIDTH).div_ceil(most);
+            writeln!(out, "{:>12} {:>10} {}", range, frames, "#".repeat(bar as usize)).unwrap();
+        }
+        out
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+// the subroutine's label, or sub_ and its address
+fn subroutine_name(entry: Option<u16>, symbols: Option<&Symbols>) -> String {
+    match entry {
+        Some(addr) => symbols.and_then(|symbols| symbols.name(addr as u32))
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("sub_{:03X}", addr)),
+        None => String::from("main"),
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 { 0.0 } else { part as f64 * 100.0 / whole as f64 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::rom::Rom;
+
+    // main loop calls 0x208 which calls 0x20C:
+    // 200 CALL 208, 202 JP 200, 208 CALL 20C, 20A RET, 20C ADD V0 1, 20E RET
+    fn nested_calls() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(vec![0x22, 0x08, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00,
+                                  0x22, 0x0C, 0x00, 0xEE, 0x70, 0x01, 0x00, 0xEE]).unwrap()).unwrap();
+        chip8.set_instructions_per_frame(12);
+        chip8
+    }
+
+    #[test]
+    fn counts_instructions_and_calls() {
+        let mut chip8 = nested_calls();
+        let mut profiler = Profiler::new();
+        profiler.run_frame(&mut chip8).unwrap();
+        profiler.run_frame(&mut chip8).unwrap();
+
+        // 6 instructions a loop, 4 loops
+        assert_eq!(profiler.frames, vec![12, 12]);
+        assert_eq!(profiler.total_instructions(), 24);
+        assert_eq!(profiler.count(0x200), 4);
+        assert_eq!(profiler.count(0x20C), 4);
+        assert_eq!(profiler.calls(0x208), 4);
+        assert_eq!(profiler.calls(0x20C), 4);
+        assert_eq!(profiler.calls(0x200), 0);
+    }
+
+    #[test]
+    fn subroutine_time_includes_callees() {
+        let mut chip8 = nested_calls();
+        let mut profiler = Profiler::new();
+        profiler.run_frame(&mut chip8).unwrap();
+
+        // CALL 20C and RET run in 0x208, ADD and RET in 0x20C
+        let (own, total) = profiler.subroutine_time(Some(0x208));
+        assert_eq!((own.0, total.0), (4, 8));
+        let (own, total) = profiler.subroutine_time(Some(0x20C));
+        assert_eq!((own.0, total.0), (4, 4));
+        let (own, total) = profiler.subroutine_time(None);
+        assert_eq!((own.0, total.0), (4, 12));
+        assert_eq!(total.1, profiler.total_cycles());
+    }
+
+    #[test]
+    fn folded_stacks_and_histogram() {
+        let mut chip8 = nested_calls();
+        let mut profiler = Profiler::new();
+        profiler.run_frame(&mut chip8).unwrap();
+
+        assert_eq!(profiler.folded(None), "main 4\nmain;sub_208 4\nmain;sub_208;sub_20C 4\n");
+        let symbols = Symbols::parse("20C inner\n").unwrap();
+        assert_eq!(profiler.folded(Some(&symbols)), "main 4\nmain;sub_208 4\nmain;sub_208;inner 4\n");
+        assert!(profiler.histogram().ends_with(&format!("{:>12} {:>10} {}\n", 12, 1, "#".repeat(50))));
+        assert!(profiler.report(&chip8).contains("CALL #20C"));
+    }
+}
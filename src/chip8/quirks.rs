@@ -0,0 +1,89 @@
+// Behaviours that differ between chip-8 interpreters
+//
+// Programs written for one interpreter often break on another, so these
+// are switched per rom. The names follow Timendus' quirks test rom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    // 8XY1, 8XY2 and 8XY3 reset VF to 0, as on the COSMAC VIP
+    pub vf_reset: bool,
+    // FX55 and FX65 leave I pointing past the last register
+    pub memory_increments_i: bool,
+    // 8XY6 and 8XYE shift VX in place and ignore VY (SUPER-CHIP)
+    pub shift_ignores_vy: bool,
+    // BNNN jumps to XNN plus VX rather than NNN plus V0 (SUPER-CHIP)
+    pub jump_uses_vx: bool,
+    // sprites are cut off at the edge of the screen instead of wrapping
+    pub clipping: bool,
+    // DXYN waits for the next frame, so only one sprite is drawn per frame
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub fn none() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            memory_increments_i: false,
+            shift_ignores_vy: false,
+            jump_uses_vx: false,
+            clipping: false,
+            display_wait: false,
+        }
+    }
+
+    // how the original interpreter on the COSMAC VIP behaves
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            vf_reset: true,
+            memory_increments_i: true,
+            shift_ignores_vy: false,
+            jump_uses_vx: false,
+            clipping: true,
+            display_wait: true,
+        }
+    }
+
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            memory_increments_i: false,
+            shift_ignores_vy: true,
+            jump_uses_vx: true,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+
+    // parses a comma separated list of the quirks to switch on, e.g.
+    // "vf_reset,memory,clipping", or one of the presets vip and schip
+    pub fn parse(list: &str) -> Result<Quirks, String> {
+        match list.trim() {
+            "vip" => return Ok(Quirks::cosmac_vip()),
+            "schip" => return Ok(Quirks::super_chip()),
+            _ => {}
+        }
+
+        let mut quirks = Quirks::none();
+        for name in list.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            match name {
+                "vf_reset" => quirks.vf_reset = true,
+                "memory" => quirks.memory_increments_i = true,
+                "shifting" => quirks.shift_ignores_vy = true,
+                "jumping" => quirks.jump_uses_vx = true,
+                "clipping" => quirks.clipping = true,
+                "display_wait" => quirks.display_wait = true,
+                _ => return Err(format!("unknown quirk \"{}\"", name)),
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+impl Default for Quirks {
+    // the behaviour this emulator has always had
+    fn default() -> Quirks {
+        Quirks {
+            clipping: true,
+            ..Quirks::none()
+        }
+    }
+}
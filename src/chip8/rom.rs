@@ -0,0 +1,302 @@
+extern crate sha1;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use chip8::memory::CHIP8_MEMORY_SIZE;
+use chip8::octo;
+
+// where programs are loaded on the COSMAC VIP and most later machines
+pub const DEFAULT_LOAD_ADDRESS: u16 = 0x200;
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    Empty,
+    TooLarge {size: usize, max: usize},
+    InvalidLoadAddress(u16),
+    InvalidHex {line: usize, reason: String},
+    InvalidCartridge(String),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Io(ref e) => write!(f, "could not read rom: {}", e),
+            RomError::Empty => write!(f, "rom is empty"),
+            RomError::TooLarge {size, max} =>
+                write!(f, "rom is {} bytes but only {} bytes fit after the load address", size, max),
+            RomError::InvalidLoadAddress(addr) =>
+                write!(f, "load address {:#x} is outside of memory", addr),
+            RomError::InvalidHex {line, ref reason} =>
+                write!(f, "invalid intel hex on line {}: {}", line, reason),
+            RomError::InvalidCartridge(ref reason) =>
+                write!(f, "invalid octo cartridge: {}", reason),
+        }
+    }
+}
+
+impl Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> RomError {
+        RomError::Io(e)
+    }
+}
+
+fn check_fits(size: usize, load_address: u16, memory_size: usize) -> Result<(), RomError> {
+    if load_address as usize >= memory_size {
+        return Err(RomError::InvalidLoadAddress(load_address));
+    }
+    let max = memory_size - load_address as usize;
+    if size > max {
+        return Err(RomError::TooLarge {size, max});
+    }
+    Ok(())
+}
+
+// A validated program image
+//
+// A Rom is never empty and always fits in memory at its load address.
+// The SHA-1 of the image identifies the program for per rom settings.
+//
+// The image is shared, cloning a Rom doesn't copy it.
+#[derive(Debug, Clone)]
+pub struct Rom {
+    data: Arc<[u8]>,
+    load_address: u16,
+    sha1: [u8; 20],
+}
+
+impl Rom {
+    pub fn new(data: Vec<u8>) -> Result<Rom, RomError> {
+        Rom::with_load_address(data, DEFAULT_LOAD_ADDRESS)
+    }
+
+    pub fn with_load_address(data: Vec<u8>, load_address: u16) -> Result<Rom, RomError> {
+        Rom::with_memory_size(data, load_address, CHIP8_MEMORY_SIZE)
+    }
+
+    // for platforms with more memory than the VIP
+    pub fn with_memory_size(data: Vec<u8>, load_address: u16, memory_size: usize) -> Result<Rom, RomError> {
+        check_fits(data.len(), load_address, memory_size)?;
+        if data.is_empty() {
+            return Err(RomError::Empty);
+        }
+
+        let sha1 = sha1::Sha1::from(&data).digest().bytes();
+        Ok(Rom {
+            data: data.into(),
+            load_address,
+            sha1,
+        })
+    }
+
+    // whether the rom fits at its load address in memory_size bytes, a rom
+    // made for a machine with more memory may not
+    pub fn check_fits(&self, memory_size: usize) -> Result<(), RomError> {
+        check_fits(self.data.len(), self.load_address, memory_size)
+    }
+
+    // Loads a rom, picking the format from the file extension and contents
+    //
+    // .hex and .ihx files are read as intel hex, gifs as Octo cartridges
+    // and anything else as a raw .ch8 image.
+    pub fn from_file(path: &Path, load_address: u16) -> Result<Rom, RomError> {
+        Rom::from_file_with_memory_size(path, load_address, CHIP8_MEMORY_SIZE)
+    }
+
+    pub fn from_file_with_memory_size(path: &Path, load_address: u16, memory_size: usize) -> Result<Rom, RomError> {
+        let mut file = File::open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let extension = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        if extension == "hex" || extension == "ihx" {
+            let text = String::from_utf8(bytes)
+                .map_err(|_| RomError::InvalidHex {line: 0, reason: String::from("file is not text")})?;
+            Rom::from_intel_hex_with_memory_size(&text, load_address, memory_size)
+        } else if extension == "gif" || bytes.starts_with(b"GIF8") {
+            Rom::from_octo_cartridge(&bytes, load_address, memory_size)
+        } else {
+            Rom::with_memory_size(bytes, load_address, memory_size)
+        }
+    }
+
+    // Intel HEX records hold absolute addresses, the image starts at the
+    // load address and data below it is rejected
+    pub fn from_intel_hex(text: &str, load_address: u16) -> Result<Rom, RomError> {
+        Rom::from_intel_hex_with_memory_size(text, load_address, CHIP8_MEMORY_SIZE)
+    }
+
+    pub fn from_intel_hex_with_memory_size(text: &str, load_address: u16, memory_size: usize) -> Result<Rom, RomError> {
+        check_fits(0, load_address, memory_size)?;
+        // what fits between the load address and the end of memory
+        let space = memory_size - load_address as usize;
+        let mut image: Vec<u8> = Vec::new();
+        let mut upper_address: u32 = 0;
+
+        for (i, raw_line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let invalid = |reason: &str| RomError::InvalidHex {line: line_number, reason: String::from(reason)};
+
+            let line = raw_line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if !line.starts_with(':') {
+                return Err(invalid("record does not start with ':'"));
+            }
+
+            let digits = &line[1..];
+            // checked first, the slicing below only works on ascii
+            if !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid("record is not hexadecimal"));
+            }
+            if digits.len() % 2 != 0 || digits.len() < 10 {
+                return Err(invalid("record is too short"));
+            }
+            let mut record = Vec::new();
+            for j in 0..(digits.len() / 2) {
+                record.push(u8::from_str_radix(&digits[j * 2..j * 2 + 2], 16).unwrap());
+            }
+
+            let checksum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            if checksum != 0 {
+                return Err(invalid("checksum mismatch"));
+            }
+
+            let length = record[0] as usize;
+            if record.len() != length + 5 {
+                return Err(invalid("record length does not match its byte count"));
+            }
+            let offset = ((record[1] as u32) << 8) | record[2] as u32;
+            let record_type = record[3];
+            let payload = &record[4..4 + length];
+
+            match record_type {
+                // data
+                0x00 => {
+                    let address = upper_address + offset;
+                    if address < load_address as u32 {
+                        return Err(invalid("data below the load address"));
+                    }
+                    let start = (address - load_address as u32) as usize;
+                    if start + length > space {
+                        return Err(invalid("data outside of memory"));
+                    }
+                    if image.len() < start + length {
+                        image.resize(start + length, 0);
+                    }
+                    image[start..start + length].copy_from_slice(payload);
+                },
+                // end of file
+                0x01 => break,
+                // extended segment and linear addresses
+                0x02 | 0x04 => {
+                    if length != 2 {
+                        return Err(invalid("address record must hold two bytes"));
+                    }
+                    let base = ((payload[0] as u32) << 8) | payload[1] as u32;
+                    upper_address = if record_type == 0x02 { base << 4 } else { base << 16 };
+                },
+                // start addresses mean nothing to a chip-8
+                0x03 | 0x05 => {},
+                _ => return Err(invalid("unknown record type")),
+            }
+        }
+
+        Rom::with_memory_size(image, load_address, memory_size)
+    }
+
+    // Octo cartridges are gifs carrying Octo source, which is assembled
+    // to run from the load address
+    pub fn from_octo_cartridge(bytes: &[u8], load_address: u16, memory_size: usize) -> Result<Rom, RomError> {
+        let source = octo::cartridge_source(bytes).map_err(RomError::InvalidCartridge)?;
+        let program = octo::assemble(&source, load_address).map_err(RomError::InvalidCartridge)?;
+        Rom::with_memory_size(program, load_address, memory_size)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    pub fn sha1(&self) -> &[u8; 20] {
+        &self.sha1
+    }
+
+    pub fn sha1_hex(&self) -> String {
+        self.sha1.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the reason for an InvalidHex error, or the error itself
+    fn hex_error(text: &str, load_address: u16) -> String {
+        match Rom::from_intel_hex(text, load_address) {
+            Err(RomError::InvalidHex {line, reason}) => format!("line {}: {}", line, reason),
+            Err(e) => e.to_string(),
+            Ok(rom) => panic!("loaded {:?}", rom),
+        }
+    }
+
+    #[test]
+    fn intel_hex_fills_the_image_from_the_load_address() {
+        let rom = Rom::from_intel_hex(":03020000616263D5\n:0202040000E018\n:00000001FF\n", 0x200).unwrap();
+        assert_eq!(rom.data(), &[0x61, 0x62, 0x63, 0x00, 0x00, 0xE0]);
+        assert_eq!(rom.load_address(), 0x200);
+
+        assert_eq!(hex_error(":03020000616263D6\n", 0x200), "line 1: checksum mismatch");
+        assert_eq!(hex_error(":03020000616263D5\n:0201FE001234B9\n", 0x200), "line 2: data below the load address");
+        // 0xFFE is inside the 4K but runs off its end
+        assert_eq!(hex_error(":040FFE0001020304E5\n", 0x200), "line 1: data outside of memory");
+        // an extended linear address of 0x10000
+        assert_eq!(hex_error(":020000040001F9\n:01000000AA55\n", 0x200), "line 2: data outside of memory");
+        assert_eq!(hex_error(":030\u{e9}000616263D5\n", 0x200), "line 1: record is not hexadecimal");
+        assert_eq!(hex_error("03020000616263D5\n", 0x200), "line 1: record does not start with ':'");
+        assert_eq!(hex_error(":00000001FF\n", 0x200), "rom is empty");
+        assert_eq!(hex_error(":00000001FF\n", 0x1000), "load address 0x1000 is outside of memory");
+    }
+
+    #[test]
+    fn images_have_to_fit_after_the_load_address() {
+        assert!(Rom::new(vec![0; 0xE00]).is_ok());
+        match Rom::new(vec![0; 0xE01]) {
+            Err(RomError::TooLarge {size, max}) => assert_eq!((size, max), (0xE01, 0xE00)),
+            other => panic!("{:?}", other),
+        }
+        match Rom::new(Vec::new()) {
+            Err(RomError::Empty) => {},
+            other => panic!("{:?}", other),
+        }
+        let rom = Rom::with_memory_size(vec![0; 0x2000], 0x200, 0x10000).unwrap();
+        assert!(rom.check_fits(0x10000).is_ok());
+        assert!(rom.check_fits(CHIP8_MEMORY_SIZE).is_err());
+    }
+
+    #[test]
+    fn the_sha1_identifies_the_program_however_it_was_loaded() {
+        let rom = Rom::new(b"abc".to_vec()).unwrap();
+        assert_eq!(rom.sha1_hex(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(rom.sha1()[0], 0xa9);
+        assert_eq!(Rom::with_load_address(b"abc".to_vec(), 0x600).unwrap().sha1(), rom.sha1());
+        assert_eq!(Rom::from_intel_hex(":03020000616263D5\n", 0x200).unwrap().sha1(), rom.sha1());
+        assert_ne!(Rom::new(b"abd".to_vec()).unwrap().sha1(), rom.sha1());
+    }
+}
@@ -0,0 +1,396 @@
+extern crate rhai;
+
+// Scripts that run alongside a rom
+//
+// Scripts are written in Rhai (https://rhai.rs) and can define any of
+//
+//     fn on_start() { }              before the first frame
+//     fn on_frame() { }              after every frame
+//     fn on_instruction(pc) { }      before every instruction
+//     fn on_memory_write(addr) { }   after the program writes a byte
+//
+// which get these functions to look at and change the machine:
+//
+//     peek(addr)  poke(addr, byte)
+//     v(x)  set_v(x, byte)  i()  set_i(addr)  pc()  set_pc(addr)
+//     dt()  st()  set_dt(n)  set_st(n)
+//     press(key)  release(key)  frame()
+//     overlay(text)       a line of text over the display for this frame
+//     screenshot(path)    saves the display as a png
+//
+// Rhai functions can't see variables outside them, so anything a script
+// keeps between calls goes in `this`, an object map shared by every call.
+// Writes the script makes itself aren't reported to on_memory_write.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+
+use self::rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST, INT};
+
+use chip8::Chip8;
+use chip8::video::{self, Palette, VideoFilter};
+
+// What the functions registered with the engine work on
+struct Host {
+    // the machine while a callback runs, a spare one the rest of the time
+    machine: Chip8,
+    overlay: Vec<String>,
+    palette: Palette,
+    frame: INT,
+}
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    this: Dynamic,
+    host: Rc<RefCell<Host>>,
+    started: bool,
+
+    // which callbacks the script defines
+    on_start: bool,
+    on_frame: bool,
+    on_instruction: bool,
+    on_memory_write: bool,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn nibble(value: INT, what: &str) -> ScriptResult<usize> {
+    if (0..=0xF).contains(&value) {
+        Ok(value as usize)
+    } else {
+        Err(format!("{} {} is out of range", what, value).into())
+    }
+}
+
+impl Script {
+    pub fn new(source: &str) -> Result<Script, String> {
+        let host = Rc::new(RefCell::new(Host {
+            machine: Chip8::new(),
+            overlay: Vec::new(),
+            palette: Palette::black_and_white(),
+            frame: 0,
+        }));
+        let mut engine = Engine::new();
+        register_functions(&mut engine, &host);
+
+        let ast = engine.compile(source).map_err(|e| format!("could not compile script: {}", e))?;
+        let defines = |name: &str| ast.iter_functions().any(|function| function.name == name);
+        let (on_start, on_frame) = (defines("on_start"), defines("on_frame"));
+        let (on_instruction, on_memory_write) = (defines("on_instruction"), defines("on_memory_write"));
+
+        Ok(Script {
+            engine,
+            ast,
+            scope: Scope::new(),
+            this: Dynamic::from_map(Map::new()),
+            host,
+            started: false,
+            on_start,
+            on_frame,
+            on_instruction,
+            on_memory_write,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Script, String> {
+        let mut source = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut source))
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        Script::new(&source)
+    }
+
+    // what screenshot() draws with
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.host.borrow_mut().palette = palette;
+    }
+
+    // the lines of text the script showed this frame
+    pub fn overlay(&self) -> Vec<String> {
+        self.host.borrow().overlay.clone()
+    }
+
+    // Runs a frame of the machine with the script's callbacks. When a
+    // callback fails the rest of the frame runs without the script.
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        self.run_frame_until(chip8, |_, _| false).map(|_| ())
+    }
+
+    // run_frame, also calling hook before each instruction and ending the
+    // frame early when it returns true, the way Chip8::run_frame_until
+    // does. Returns whether the frame was ended early.
+    pub fn run_frame_until<F: FnMut(&mut Chip8, u16) -> bool>(&mut self, chip8: &mut Chip8, mut hook: F) -> Result<bool, String> {
+        self.host.borrow_mut().overlay.clear();
+        self.start(chip8)?;
+
+        let stopped = if self.on_instruction || self.on_memory_write {
+            let mut error = None;
+            let stopped = chip8.run_frame_until(|chip8, pc| {
+                if hook(chip8, pc) {
+                    return true;
+                }
+                if error.is_none() {
+                    error = self.report_writes(chip8)
+                        .and_then(|_| if self.on_instruction {
+                            self.call(chip8, "on_instruction", (pc as INT,))
+                        } else {
+                            Ok(())
+                        })
+                        .err();
+                }
+                false
+            });
+            if let Some(error) = error {
+                return Err(error);
+            }
+            let stopped = stopped.map_err(|e| e.to_string())?;
+            self.report_writes(chip8)?;
+            stopped
+        } else {
+            chip8.run_frame_until(hook).map_err(|e| e.to_string())?
+        };
+
+        self.host.borrow_mut().frame += 1;
+        if self.on_frame {
+            self.call(chip8, "on_frame", ())?;
+        }
+        Ok(stopped)
+    }
+
+    // Runs the instruction at PC with on_instruction and on_memory_write,
+    // for single stepping. The frame isn't over, so on_frame isn't called.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        self.start(chip8)?;
+        if self.on_instruction && !chip8.cpu().is_halted_flag {
+            let pc = chip8.cpu().pc();
+            self.call(chip8, "on_instruction", (pc as INT,))?;
+        }
+        chip8.step().map_err(|e| e.to_string())?;
+        self.report_writes(chip8)
+    }
+
+    // runs the top level of the script and on_start the first time
+    fn start(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        if chip8.memory().is_logging_writes() != self.on_memory_write {
+            chip8.memory_mut().log_writes(self.on_memory_write);
+        }
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+        self.with_machine(chip8, |script| {
+            script.engine.run_ast_with_scope(&mut script.scope, &script.ast)
+        }).map_err(|e| format!("script error: {}", e))?;
+        if self.on_start {
+            self.call(chip8, "on_start", ())?;
+        }
+        Ok(())
+    }
+
+    fn report_writes(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        if !self.on_memory_write {
+            return Ok(());
+        }
+        for addr in chip8.memory_mut().take_writes() {
+            self.call(chip8, "on_memory_write", (addr as INT,))?;
+        }
+        Ok(())
+    }
+
+    fn call<A: FuncArgs>(&mut self, chip8: &mut Chip8, name: &str, args: A) -> Result<(), String> {
+        self.with_machine(chip8, |script| {
+            let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut script.this);
+            script.engine.call_fn_with_options::<Dynamic>(options, &mut script.scope, &script.ast, name, args)
+        }).map(|_| ()).map_err(|e| format!("script error in {}: {}", name, e))
+    }
+
+    // lends the machine to the registered functions while f runs
+    fn with_machine<T, F: FnOnce(&mut Script) -> T>(&mut self, chip8: &mut Chip8, f: F) -> T {
+        mem::swap(chip8, &mut self.host.borrow_mut().machine);
+        let result = f(self);
+        let mut host = self.host.borrow_mut();
+        host.machine.memory_mut().take_writes();
+        mem::swap(chip8, &mut host.machine);
+        result
+    }
+}
+
+fn register_functions(engine: &mut Engine, host: &Rc<RefCell<Host>>) {
+    let h = host.clone();
+    engine.register_fn("peek", move |addr: INT| h.borrow().machine.memory().read_byte_at(addr as u32) as INT);
+    let h = host.clone();
+    engine.register_fn("poke", move |addr: INT, byte: INT| {
+        h.borrow_mut().machine.memory_mut().write_byte_at(addr as u32, byte as u8);
+    });
+
+    let h = host.clone();
+    engine.register_fn("v", move |x: INT| -> ScriptResult<INT> {
+        Ok(h.borrow().machine.cpu().v(nibble(x, "register")?) as INT)
+    });
+    let h = host.clone();
+    engine.register_fn("set_v", move |x: INT, byte: INT| -> ScriptResult<()> {
+        h.borrow_mut().machine.cpu_mut().reg_gp[nibble(x, "register")?] = byte as u8;
+        Ok(())
+    });
+    let h = host.clone();
+    engine.register_fn("i", move || h.borrow().machine.cpu().i() as INT);
+    let h = host.clone();
+    engine.register_fn("set_i", move |addr: INT| h.borrow_mut().machine.cpu_mut().set_i(addr as u32));
+    let h = host.clone();
+    engine.register_fn("pc", move || h.borrow().machine.cpu().pc() as INT);
+    let h = host.clone();
+    engine.register_fn("set_pc", move |addr: INT| h.borrow_mut().machine.cpu_mut().set_pc(addr as u16));
+    let h = host.clone();
+    engine.register_fn("dt", move || h.borrow().machine.cpu().delay_timer() as INT);
+    let h = host.clone();
+    engine.register_fn("st", move || h.borrow().machine.cpu().sound_timer() as INT);
+    let h = host.clone();
+    engine.register_fn("set_dt", move |n: INT| {
+        let mut host = h.borrow_mut();
+        let sound = host.machine.cpu().sound_timer();
+        host.machine.cpu_mut().set_timers(n as u8, sound);
+    });
+    let h = host.clone();
+    engine.register_fn("set_st", move |n: INT| {
+        let mut host = h.borrow_mut();
+        let delay = host.machine.cpu().delay_timer();
+        host.machine.cpu_mut().set_timers(delay, n as u8);
+    });
+
+    let h = host.clone();
+    engine.register_fn("press", move |key: INT| -> ScriptResult<()> {
+        h.borrow_mut().machine.set_key(nibble(key, "key")? as u8, true);
+        Ok(())
+    });
+    let h = host.clone();
+    engine.register_fn("release", move |key: INT| -> ScriptResult<()> {
+        h.borrow_mut().machine.set_key(nibble(key, "key")? as u8, false);
+        Ok(())
+    });
+    let h = host.clone();
+    engine.register_fn("frame", move || h.borrow().frame);
+
+    let h = host.clone();
+    engine.register_fn("overlay", move |text: &str| h.borrow_mut().overlay.push(text.to_string()));
+    let h = host.clone();
+    engine.register_fn("screenshot", move |path: &str| -> ScriptResult<()> {
+        let host = h.borrow();
+        let graphics = host.machine.graphics();
+        let mut pixels = Vec::new();
+        VideoFilter::new(host.palette, 0).render(graphics, host.machine.colours(), &mut pixels);
+        video::write_png(Path::new(path), graphics.width(), graphics.height(), &pixels)
+            .map_err(|e| format!("could not write {}: {}", path, e).into())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use chip8::rom::Rom;
+
+    // counts V0 up, stores it at 0x300 and loops
+    fn counting() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(vec![0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00]).unwrap()).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn callbacks_see_the_machine() {
+        let mut chip8 = counting();
+        let mut script = Script::new("
+            fn on_start() { this.instructions = 0; this.writes = []; }
+            fn on_instruction(pc) { this.instructions += 1; this.last_pc = pc; }
+            fn on_memory_write(addr) { this.writes.push(addr); }
+            fn on_frame() {
+                poke(0x400, frame());
+                set_v(1, this.writes.len());
+                if frame() == 2 { press(5); }
+                overlay(`pc ${this.last_pc}`);
+            }
+        ").unwrap();
+
+        script.run_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.memory().read_byte(0x400), 1);
+        // 9 instructions a frame, two trips round the loop and a bit
+        assert_eq!(chip8.cpu().v(1), 2);
+        assert_eq!(script.overlay(), vec![String::from("pc 512")]);
+        assert!(!chip8.is_key_pressed(5));
+
+        script.run_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.memory().read_byte(0x400), 2);
+        assert!(chip8.is_key_pressed(5));
+        // the script's own poke isn't counted
+        assert_eq!(chip8.cpu().v(1), 4);
+    }
+
+    #[test]
+    fn steps_call_the_instruction_callbacks() {
+        let mut chip8 = counting();
+        let mut script = Script::new("
+            fn on_start() { this.pcs = []; this.writes = []; }
+            fn on_instruction(pc) { this.pcs.push(pc); }
+            fn on_memory_write(addr) { this.writes.push(addr); set_v(1, this.pcs.len()); set_v(2, this.writes.len()); }
+            fn on_frame() { throw \"not the end of a frame\"; }
+        ").unwrap();
+        for _ in 0..3 {
+            script.step(&mut chip8).unwrap();
+        }
+        assert_eq!(chip8.cpu().pc(), 0x206);
+        // FX55 at 0x204 wrote V0 to 0x300
+        assert_eq!(chip8.cpu().v(1), 3);
+        assert_eq!(chip8.cpu().v(2), 1);
+    }
+
+    #[test]
+    fn scripts_change_registers() {
+        let mut chip8 = counting();
+        let mut script = Script::new("
+            fn on_frame() {
+                set_v(0, 100);
+                set_i(0x234);
+                set_pc(0x200);
+                set_dt(30);
+                if v(0) != 100 || i() != 0x234 || pc() != 0x200 || dt() != 30 || st() != 0 {
+                    throw \"registers were not set\";
+                }
+            }
+        ").unwrap();
+        script.run_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.cpu().v(0), 100);
+        assert_eq!(chip8.cpu().i(), 0x234);
+        assert_eq!(chip8.cpu().delay_timer(), 30);
+    }
+
+    #[test]
+    fn screenshots_are_written() {
+        let path = env::temp_dir().join("chip8_script_screenshot.png");
+        let _ = fs::remove_file(&path);
+        let mut chip8 = counting();
+        let mut script = Script::new(&format!("fn on_frame() {{ screenshot({:?}); }}", path.to_str().unwrap())).unwrap();
+        script.run_frame(&mut chip8).unwrap();
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        assert!(Script::new("fn on_frame( {").is_err());
+
+        let mut chip8 = counting();
+        let mut script = Script::new("fn on_frame() { v(16) }").unwrap();
+        let error = script.run_frame(&mut chip8).unwrap_err();
+        assert!(error.contains("on_frame"), "{}", error);
+        assert!(error.contains("register 16 is out of range"), "{}", error);
+        // the machine is handed back all the same
+        assert_eq!(chip8.memory().read_byte(0x200), 0x70);
+    }
+}
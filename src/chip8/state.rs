@@ -0,0 +1,294 @@
+// Save states
+//
+// A state is the magic "C8ST" and a format version followed by the
+// machine field by field, little endian, with byte slices prefixed by
+// their length. Settings like the quirks and the platform are saved too,
+// so loading a state restores the machine exactly as it was. States from
+// another version are refused rather than guessed at.
+
+use std::error::Error;
+use std::fmt;
+
+use chip8::platform::Platform;
+use chip8::quirks::Quirks;
+use chip8::timing::Timing;
+
+pub const STATE_MAGIC: &[u8; 4] = b"C8ST";
+pub const STATE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    Invalid(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) =>
+                write!(f, "save state version {} is not supported, only version {}", version, STATE_VERSION),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(ref reason) => write!(f, "invalid save state: {}", reason),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+#[derive(Debug, Clone)]
+pub struct StateWriter {
+    pub bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut bytes = STATE_MAGIC.to_vec();
+        bytes.push(STATE_VERSION);
+        StateWriter {
+            bytes,
+        }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn slice(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn bools(&mut self, value: &[bool]) {
+        self.u32(value.len() as u32);
+        self.bytes.extend(value.iter().map(|&bit| bit as u8));
+    }
+
+    pub fn platform(&mut self, platform: Platform) {
+        self.u8(match platform {
+            Platform::Chip8 => 0,
+            Platform::HiresChip8 => 1,
+            Platform::Eti660 => 2,
+            Platform::Chip8X => 3,
+            Platform::MegaChip => 4,
+        });
+    }
+
+    pub fn timing(&mut self, timing: Timing) {
+        self.u8(match timing {
+            Timing::InstructionsPerFrame => 0,
+            Timing::CosmacVip => 1,
+        });
+    }
+
+    pub fn quirks(&mut self, quirks: Quirks) {
+        self.bool(quirks.vf_reset);
+        self.bool(quirks.memory_increments_i);
+        self.bool(quirks.shift_ignores_vy);
+        self.bool(quirks.jump_uses_vx);
+        self.bool(quirks.clipping);
+        self.bool(quirks.display_wait);
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    // checks the header
+    pub fn new(bytes: &'a [u8]) -> Result<StateReader<'a>, StateError> {
+        if bytes.len() < 5 || &bytes[..4] != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        if bytes[4] != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(bytes[4]));
+        }
+        Ok(StateReader {
+            bytes,
+            position: 5,
+        })
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() - self.position < length {
+            return Err(StateError::Truncated);
+        }
+        let bytes = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(StateError::Invalid(format!("{} is not a boolean", value))),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let bytes = self.take(8)?;
+        let mut value = [0; 8];
+        value.copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    pub fn slice(&mut self) -> Result<&'a [u8], StateError> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    // a slice that has to be exactly length bytes long
+    pub fn slice_of(&mut self, length: usize, what: &str) -> Result<&'a [u8], StateError> {
+        let bytes = self.slice()?;
+        if bytes.len() != length {
+            return Err(StateError::Invalid(format!("{} is {} bytes rather than {}", what, bytes.len(), length)));
+        }
+        Ok(bytes)
+    }
+
+    pub fn bools(&mut self, output: &mut [bool], what: &str) -> Result<(), StateError> {
+        let bytes = self.slice_of(output.len(), what)?;
+        for (bit, &byte) in output.iter_mut().zip(bytes.iter()) {
+            *bit = byte != 0;
+        }
+        Ok(())
+    }
+
+    pub fn platform(&mut self) -> Result<Platform, StateError> {
+        match self.u8()? {
+            0 => Ok(Platform::Chip8),
+            1 => Ok(Platform::HiresChip8),
+            2 => Ok(Platform::Eti660),
+            3 => Ok(Platform::Chip8X),
+            4 => Ok(Platform::MegaChip),
+            value => Err(StateError::Invalid(format!("unknown platform {}", value))),
+        }
+    }
+
+    pub fn timing(&mut self) -> Result<Timing, StateError> {
+        match self.u8()? {
+            0 => Ok(Timing::InstructionsPerFrame),
+            1 => Ok(Timing::CosmacVip),
+            value => Err(StateError::Invalid(format!("unknown timing {}", value))),
+        }
+    }
+
+    pub fn quirks(&mut self) -> Result<Quirks, StateError> {
+        Ok(Quirks {
+            vf_reset: self.bool()?,
+            memory_increments_i: self.bool()?,
+            shift_ignores_vy: self.bool()?,
+            jump_uses_vx: self.bool()?,
+            clipping: self.bool()?,
+            display_wait: self.bool()?,
+        })
+    }
+
+    // whether every byte was read
+    pub fn finish(&self) -> Result<(), StateError> {
+        if self.position == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid(format!("{} bytes left over", self.bytes.len() - self.position)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8;
+    use chip8::platform::Platform;
+    use chip8::rom::Rom;
+
+    // counts V0 up and draws it, forever
+    fn running(platform: Platform) -> Chip8 {
+        let start = platform.load_address();
+        let program = vec![0x70, 0x01, 0xF0, 0x29, 0xD1, 0x15, 0x10 | (start >> 8) as u8, start as u8];
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(platform).unwrap();
+        chip8.boot(&Rom::with_load_address(program, start).unwrap()).unwrap();
+        chip8.set_key(3, true);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        chip8
+    }
+
+    #[test]
+    fn states_restore_the_machine() {
+        for platform in [Platform::Chip8, Platform::Chip8X, Platform::MegaChip].iter() {
+            let mut chip8 = running(*platform);
+            let state = chip8.save_state();
+            for _ in 0..5 {
+                chip8.run_frame().unwrap();
+            }
+            assert_ne!(chip8.save_state(), state);
+
+            chip8.load_state(&state).unwrap();
+            assert_eq!(chip8.save_state(), state);
+            assert_eq!(chip8.cpu().v(0), 1);
+            assert!(chip8.is_key_pressed(3));
+            assert_eq!(chip8.platform(), *platform);
+        }
+    }
+
+    #[test]
+    fn bad_states_are_refused() {
+        let mut chip8 = running(Platform::Chip8);
+        let state = chip8.save_state();
+        let before = chip8.save_state();
+
+        assert_eq!(chip8.load_state(b"nope"), Err(StateError::BadMagic));
+        let mut other_version = state.clone();
+        other_version[4] = STATE_VERSION + 1;
+        assert_eq!(chip8.load_state(&other_version), Err(StateError::UnsupportedVersion(STATE_VERSION + 1)));
+        assert_eq!(chip8.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+        let mut longer = state.clone();
+        longer.push(0);
+        assert!(chip8.load_state(&longer).is_err());
+
+        assert_eq!(chip8.save_state(), before);
+    }
+}
@@ -0,0 +1,230 @@
+// Labels for addresses
+//
+// Two formats are read, and can be mixed in one file:
+//
+// Octo's symbol files, one ":kind name value" per line. Labels and
+// constants are both ":const", so constants below 0x200 are left out as
+// they are more likely counts than addresses. ":breakpoint" lines are
+// labels that the frontends also stop at.
+//
+//     :const draw_player 0x2A0
+//     :breakpoint player_hit 692
+//
+// Or one "address name" per line with the address in hex, with or
+// without a 0x prefix.
+//
+//     2A0 draw_player
+//
+// Blank lines and lines starting with # or ; are ignored.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use chip8::cpu::Chip8CPU;
+
+// how far past a label an address can be and still be named after it
+const MAX_LABEL_OFFSET: u32 = 0x100;
+
+#[derive(Clone, Default, PartialEq)]
+pub struct Symbols {
+    // the first name given to each address
+    by_address: BTreeMap<u32, String>,
+    by_name: HashMap<String, u32>,
+    // from Octo's :breakpoint lines
+    pub breakpoints: Vec<u32>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::new();
+        for (i, raw_line) in text.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let error = || format!("line {}: \"{}\" is not a symbol", i + 1, line);
+            match fields.as_slice() {
+                [kind, name, value] if kind.starts_with(':') => {
+                    let addr = parse_number(value).ok_or_else(error)?;
+                    match *kind {
+                        ":const" if addr >= 0x200 => symbols.insert(addr, name),
+                        ":breakpoint" => {
+                            symbols.insert(addr, name);
+                            symbols.breakpoints.push(addr);
+                        },
+                        _ => {},
+                    }
+                },
+                [addr, name] => {
+                    let addr = parse_address(addr).ok_or_else(error)?;
+                    symbols.insert(addr, name);
+                },
+                _ => return Err(error()),
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Symbols> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Symbols::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn insert(&mut self, addr: u32, name: &str) {
+        self.by_address.entry(addr).or_insert_with(|| name.to_string());
+        self.by_name.entry(name.to_string()).or_insert(addr);
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    // the name of exactly this address
+    pub fn name(&self, addr: u32) -> Option<&str> {
+        self.by_address.get(&addr).map(|name| name.as_str())
+    }
+
+    pub fn address(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name).cloned()
+    }
+
+    // the address's name, or the closest label before it plus the offset
+    pub fn label(&self, addr: u32) -> Option<String> {
+        let (&start, name) = self.by_address.range(..=addr).next_back()?;
+        match addr - start {
+            0 => Some(name.clone()),
+            offset if offset < MAX_LABEL_OFFSET => Some(format!("{}+{}", name, offset)),
+            _ => None,
+        }
+    }
+
+    // A label, label+offset or an address in hex, the way breakpoints
+    // are given on the command line
+    pub fn resolve(&self, location: &str) -> Result<u32, String> {
+        let location = location.trim();
+        let (name, offset) = match location.find('+') {
+            Some(plus) => (&location[..plus], parse_number(&location[plus + 1..])),
+            None => (location, Some(0)),
+        };
+        match (self.address(name), offset) {
+            (Some(addr), Some(offset)) => Ok(addr + offset),
+            _ => parse_address(location).ok_or_else(|| format!("unknown label \"{}\"", location)),
+        }
+    }
+}
+
+// the symbols can run to thousands of lines, too much for a cpu dump
+impl fmt::Debug for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbols {{ {} labels, {} breakpoints }}", self.len(), self.breakpoints.len())
+    }
+}
+
+// "#2A4 draw_player+4", or just the address when it has no label
+pub fn describe(symbols: Option<&Symbols>, addr: u32) -> String {
+    match symbols.and_then(|symbols| symbols.label(addr)) {
+        Some(label) => format!("#{:03X} {}", addr, label),
+        None => format!("#{:03X}", addr),
+    }
+}
+
+// Where the cpu is, innermost first: the program counter and then the
+// CALL each return address on the stack came from
+pub fn call_stack(cpu: &Chip8CPU, symbols: Option<&Symbols>) -> Vec<String> {
+    let mut frames = vec![describe(symbols, cpu.pc() as u32)];
+    for &return_addr in cpu.stack().iter().rev() {
+        frames.push(describe(symbols, return_addr.wrapping_sub(2) as u32));
+    }
+    frames
+}
+
+// 0x prefixed hex or decimal
+fn parse_number(value: &str) -> Option<u32> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        u32::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse::<u32>().ok()
+    }
+}
+
+// hex with or without 0x
+fn parse_address(value: &str) -> Option<u32> {
+    let digits = if value.starts_with("0x") || value.starts_with("0X") { &value[2..] } else { value };
+    u32::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8;
+    use chip8::rom::Rom;
+
+    const SYMBOLS: &str = "# from octo-cli\n:const main 0x200\n:const lives 3\n:breakpoint hit 520\n\n2A0 draw_player\n0x2B0 draw_enemy\n";
+
+    #[test]
+    fn reads_octo_and_plain_symbols() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.address("main"), Some(0x200));
+        assert_eq!(symbols.address("lives"), None);
+        assert_eq!(symbols.address("hit"), Some(0x208));
+        assert_eq!(symbols.breakpoints, vec![0x208]);
+        assert_eq!(symbols.name(0x2A0), Some("draw_player"));
+        assert_eq!(symbols.address("draw_enemy"), Some(0x2B0));
+        assert!(Symbols::parse("2A0").is_err());
+        assert!(Symbols::parse(":const x y").is_err());
+    }
+
+    #[test]
+    fn labels_and_locations() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.label(0x2A0), Some(String::from("draw_player")));
+        assert_eq!(symbols.label(0x2A4), Some(String::from("draw_player+4")));
+        assert_eq!(symbols.label(0x1FE), None);
+        assert_eq!(symbols.label(0x400), None);
+        assert_eq!(describe(Some(&symbols), 0x2B2), "#2B2 draw_enemy+2");
+        assert_eq!(describe(None, 0x2B2), "#2B2");
+
+        assert_eq!(symbols.resolve("draw_player"), Ok(0x2A0));
+        assert_eq!(symbols.resolve("draw_player+6"), Ok(0x2A6));
+        assert_eq!(symbols.resolve("0x300"), Ok(0x300));
+        assert_eq!(symbols.resolve("2C0"), Ok(0x2C0));
+        assert!(symbols.resolve("draw_boss").is_err());
+    }
+
+    #[test]
+    fn call_stack_names_the_calls() {
+        // 200 CALL 206, 202 JP 202, 204 RET, 206 CALL 204
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(vec![0x22, 0x06, 0x12, 0x02, 0x00, 0xEE, 0x22, 0x04]).unwrap()).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        let symbols = Symbols::parse("200 main\n204 inner\n206 outer\n").unwrap();
+        assert_eq!(call_stack(chip8.cpu(), Some(&symbols)), vec!["#204 inner", "#206 outer", "#200 main"]);
+    }
+
+    #[test]
+    fn breakpoint_ends_the_frame_before_the_instruction() {
+        // 200 ADD V0 1, 202 ADD V1 1, 204 JP 200
+        let mut chip8 = Chip8::new();
+        chip8.boot(&Rom::new(vec![0x70, 0x01, 0x71, 0x01, 0x12, 0x00]).unwrap()).unwrap();
+        let symbols = Symbols::parse("202 count_v1\n").unwrap();
+        let breakpoint = symbols.resolve("count_v1").unwrap() as u16;
+
+        assert!(chip8.run_frame_until(|_, pc| pc == breakpoint).unwrap());
+        assert_eq!((chip8.cpu().pc(), chip8.cpu().v(0), chip8.cpu().v(1)), (0x202, 1, 0));
+        assert!(!chip8.run_frame_until(|_, _| false).unwrap());
+    }
+}
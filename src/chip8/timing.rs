@@ -0,0 +1,31 @@
+// How fast the cpu runs
+//
+// The COSMAC VIP clocks its 1802 at 1.76064 MHz and every 1802 machine
+// cycle takes 8 clocks, which leaves 3668 machine cycles per 60Hz frame.
+// The video DMA and the display interrupt take a share of every frame
+// before the interpreter gets to run.
+pub const VIP_CLOCK_HZ: u32 = 1_760_640;
+pub const VIP_CLOCKS_PER_MACHINE_CYCLE: u32 = 8;
+pub const VIP_CYCLES_PER_FRAME: u32 = VIP_CLOCK_HZ / VIP_CLOCKS_PER_MACHINE_CYCLE / 60;
+// 128 scanlines of 8 bytes of DMA plus the interrupt routine
+pub const VIP_DISPLAY_CYCLES: u32 = 1024 + 46;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Timing {
+    // a fixed number of instructions every frame
+    #[default]
+    InstructionsPerFrame,
+    // every instruction costs as many cycles as it did in the VIP
+    // interpreter and DXYN waits for the display interrupt
+    CosmacVip,
+}
+
+impl Timing {
+    pub fn parse(name: &str) -> Result<Timing, String> {
+        match name.trim() {
+            "ipf" => Ok(Timing::InstructionsPerFrame),
+            "vip" => Ok(Timing::CosmacVip),
+            _ => Err(format!("unknown timing \"{}\"", name)),
+        }
+    }
+}
@@ -0,0 +1,392 @@
+extern crate gif;
+extern crate png;
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use chip8::graphics::{Chip8ColourLayer, Chip8Graphics};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    // parses "#RRGGBB" or "RRGGBB"
+    pub fn from_hex(hex: &str) -> Result<Rgb, String> {
+        let digits = hex.trim_start_matches('#');
+        if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("\"{}\" is not a #RRGGBB colour", hex));
+        }
+        let value = u32::from_str_radix(digits, 16).unwrap();
+        Ok(Rgb::new((value >> 16) as u8, (value >> 8) as u8, value as u8))
+    }
+
+    // linear blend between self (amount 0) and other (amount 255)
+    pub fn blend(&self, other: Rgb, amount: u8) -> Rgb {
+        let mix = |from: u8, to: u8| {
+            ((from as u32 * (255 - amount as u32) + to as u32 * amount as u32) / 255) as u8
+        };
+        Rgb::new(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b))
+    }
+}
+
+// the eight foreground colours of the VP-590 colour board
+pub fn vp590_foreground(colour: u8) -> Rgb {
+    match colour & 0x7 {
+        0 => Rgb::new(0x00, 0x00, 0x00),
+        1 => Rgb::new(0xFF, 0x00, 0x00),
+        2 => Rgb::new(0x00, 0x00, 0xFF),
+        3 => Rgb::new(0xFF, 0x00, 0xFF),
+        4 => Rgb::new(0x00, 0xFF, 0x00),
+        5 => Rgb::new(0xFF, 0xFF, 0x00),
+        6 => Rgb::new(0x00, 0xFF, 0xFF),
+        _ => Rgb::new(0xFF, 0xFF, 0xFF),
+    }
+}
+
+// and its four background colours
+pub fn vp590_background(colour: u8) -> Rgb {
+    match colour & 0x3 {
+        0 => Rgb::new(0x00, 0x00, 0x80),
+        1 => Rgb::new(0x00, 0x00, 0x00),
+        2 => Rgb::new(0x00, 0x80, 0x00),
+        _ => Rgb::new(0x80, 0x00, 0x00),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    // colour of unset pixels
+    pub background: Rgb,
+    // colour of set pixels
+    pub foreground: Rgb,
+}
+
+impl Palette {
+    pub fn new(background: Rgb, foreground: Rgb) -> Palette {
+        Palette { background, foreground }
+    }
+
+    pub fn black_and_white() -> Palette {
+        Palette::new(Rgb::new(0x00, 0x00, 0x00), Rgb::new(0xFF, 0xFF, 0xFF))
+    }
+
+    // P1 phosphor monochrome monitor
+    pub fn classic_green() -> Palette {
+        Palette::new(Rgb::new(0x0A, 0x1A, 0x0A), Rgb::new(0x33, 0xFF, 0x33))
+    }
+
+    // P3 phosphor monochrome monitor
+    pub fn amber() -> Palette {
+        Palette::new(Rgb::new(0x1A, 0x10, 0x00), Rgb::new(0xFF, 0xB0, 0x00))
+    }
+
+    // the default colours of the Octo IDE
+    pub fn octo() -> Palette {
+        Palette::new(Rgb::new(0x99, 0x66, 0x00), Rgb::new(0xFF, 0xCC, 0x00))
+    }
+
+    // accepts one of the preset names or a custom "#background,#foreground" pair
+    pub fn from_name(name: &str) -> Result<Palette, String> {
+        match name {
+            "bw" | "black-and-white" => Ok(Palette::black_and_white()),
+            "green" | "classic-green" => Ok(Palette::classic_green()),
+            "amber" => Ok(Palette::amber()),
+            "octo" => Ok(Palette::octo()),
+            _ => {
+                let colours: Vec<&str> = name.split(',').collect();
+                if colours.len() != 2 {
+                    return Err(format!("unknown palette \"{}\", expected bw, green, amber, octo or #background,#foreground", name));
+                }
+                Ok(Palette::new(Rgb::from_hex(colours[0])?, Rgb::from_hex(colours[1])?))
+            }
+        }
+    }
+}
+
+// Phosphor persistence
+//
+// XOR drawing means moving sprites are erased and redrawn every frame,
+// which flickers badly. Instead of switching off instantly, a pixel that
+// goes dark fades out over a number of frames like the phosphor of an
+// old CRT.
+#[derive(Debug, Clone)]
+pub struct Persistence {
+    // number of frames a pixel takes to fade out, 0 disables the effect
+    frames: u8,
+    // brightness of every pixel, 0 is off and 255 fully lit
+    intensity: Vec<u8>,
+}
+
+impl Persistence {
+    pub fn new(frames: u8) -> Persistence {
+        Persistence {
+            frames,
+            intensity: Vec::new(),
+        }
+    }
+
+    // advances the fade by one frame, must be called once per frame
+    pub fn update(&mut self, graphics: &Chip8Graphics) {
+        let size = graphics.width() * graphics.height();
+        if self.intensity.len() != size {
+            self.intensity = vec![0; size];
+        }
+
+        let decay = match self.frames {
+            0 => 255,
+            frames => 255u16.div_ceil(frames as u16) as u8,
+        };
+        for y in 0..graphics.height() {
+            for x in 0..graphics.width() {
+                let index = y * graphics.width() + x;
+                self.intensity[index] = if graphics.get_pixel_value(x, y) {
+                    255
+                } else {
+                    self.intensity[index].saturating_sub(decay)
+                };
+            }
+        }
+    }
+
+    pub fn intensity(&self, index: usize) -> u8 {
+        self.intensity[index]
+    }
+}
+
+// Turns the 1-bit chip-8 display into RGB24 pixels, shared by every
+// frontend and the headless screenshot output
+#[derive(Debug, Clone)]
+pub struct VideoFilter {
+    pub palette: Palette,
+    persistence: Persistence,
+}
+
+impl VideoFilter {
+    pub fn new(palette: Palette, persistence_frames: u8) -> VideoFilter {
+        VideoFilter {
+            palette,
+            persistence: Persistence::new(persistence_frames),
+        }
+    }
+
+    // call once per frame, the fade advances even if nothing was drawn
+    //
+    // With a colour layer its colours replace the palette.
+    pub fn render(&mut self, graphics: &Chip8Graphics, colours: Option<&Chip8ColourLayer>, pixels: &mut Vec<u8>) {
+        self.persistence.update(graphics);
+
+        pixels.clear();
+        // the MEGA-CHIP display brings its own colours
+        if let Chip8Graphics::MegaChip(ref display) = *graphics {
+            for y in 0..display.height() {
+                for x in 0..display.width() {
+                    let colour = display.colour_at(x, y);
+                    pixels.push((colour >> 16) as u8);
+                    pixels.push((colour >> 8) as u8);
+                    pixels.push(colour as u8);
+                }
+            }
+            return;
+        }
+
+        for index in 0..(graphics.width() * graphics.height()) {
+            let (background, foreground) = match colours {
+                Some(colours) => (vp590_background(colours.background),
+                                  vp590_foreground(colours.foreground_at(index % graphics.width(),
+                                                                         index / graphics.width()))),
+                None => (self.palette.background, self.palette.foreground),
+            };
+            let colour = background.blend(foreground, self.persistence.intensity(index));
+            pixels.push(colour.r);
+            pixels.push(colour.g);
+            pixels.push(colour.b);
+        }
+    }
+}
+
+pub fn write_png(path: &Path, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(pixels).map_err(io::Error::other)
+}
+
+// Records the display as an animated gif
+//
+// Gif delays are in hundredths of a second, too coarse for 60 frames a
+// second, and most viewers slow down anything shorter than 2/100s. So
+// every second frame is kept and the delays alternate between 3/100s and
+// 4/100s, which plays back at the right speed. The gif takes the size of
+// the first frame, frames of another size after a mode switch are scaled
+// to it.
+pub struct GifRecorder<W: Write> {
+    encoder: gif::Encoder<W>,
+    width: usize,
+    height: usize,
+    // 60Hz frames seen so far
+    frames: u32,
+}
+
+impl GifRecorder<BufWriter<File>> {
+    pub fn create(path: &Path, width: usize, height: usize) -> io::Result<GifRecorder<BufWriter<File>>> {
+        GifRecorder::new(BufWriter::new(File::create(path)?), width, height)
+    }
+}
+
+impl<W: Write> GifRecorder<W> {
+    pub fn new(output: W, width: usize, height: usize) -> io::Result<GifRecorder<W>> {
+        let mut encoder = gif::Encoder::new(output, width as u16, height as u16, &[]).map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+        Ok(GifRecorder {
+            encoder,
+            width,
+            height,
+            frames: 0,
+        })
+    }
+
+    // call once per frame with the RGB24 pixels a VideoFilter rendered
+    pub fn add_frame(&mut self, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+        let frame = self.frames;
+        self.frames += 1;
+        if frame & 1 == 1 {
+            return Ok(());
+        }
+
+        let scaled;
+        let pixels = if (width, height) == (self.width, self.height) {
+            pixels
+        } else {
+            scaled = scale(width, height, pixels, self.width, self.height);
+            &scaled
+        };
+        let mut gif_frame = gif::Frame::from_rgb_speed(self.width as u16, self.height as u16, pixels, 10);
+        // until the next kept frame, two 60Hz frames on
+        let centiseconds = |frames: u32| frames * 100 / 60;
+        gif_frame.delay = (centiseconds(frame + 2) - centiseconds(frame)) as u16;
+        self.encoder.write_frame(&gif_frame).map_err(io::Error::other)
+    }
+}
+
+// nearest neighbour scaling of RGB24 pixels
+fn scale(width: usize, height: usize, pixels: &[u8], to_width: usize, to_height: usize) -> Vec<u8> {
+    let mut scaled = Vec::with_capacity(to_width * to_height * 3);
+    for y in 0..to_height {
+        for x in 0..to_width {
+            let index = ((y * height / to_height) * width + x * width / to_width) * 3;
+            scaled.extend_from_slice(&pixels[index..index + 3]);
+        }
+    }
+    scaled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours_parse_from_hex() {
+        assert_eq!(Rgb::from_hex("#FFB000"), Ok(Rgb::new(0xFF, 0xB0, 0x00)));
+        assert_eq!(Rgb::from_hex("0a1a0A"), Ok(Rgb::new(0x0A, 0x1A, 0x0A)));
+        assert!(Rgb::from_hex("#FFB00").is_err());
+        assert!(Rgb::from_hex("#FFB0000").is_err());
+        assert!(Rgb::from_hex("#GGGGGG").is_err());
+        assert!(Rgb::from_hex("+FFFFF").is_err());
+        assert!(Rgb::from_hex("#\u{e9}\u{e9}FF").is_err());
+        assert!(Rgb::from_hex("").is_err());
+    }
+
+    #[test]
+    fn palettes_by_name_or_colour_pair() {
+        assert_eq!(Palette::from_name("bw"), Ok(Palette::black_and_white()));
+        assert_eq!(Palette::from_name("classic-green"), Ok(Palette::classic_green()));
+        assert_eq!(Palette::from_name("amber"), Ok(Palette::amber()));
+        assert_eq!(Palette::from_name("octo"), Ok(Palette::octo()));
+        assert_eq!(Palette::from_name("#000080,#FFFFFF"),
+                   Ok(Palette::new(Rgb::new(0x00, 0x00, 0x80), Rgb::new(0xFF, 0xFF, 0xFF))));
+        assert!(Palette::from_name("purple").is_err());
+        assert!(Palette::from_name("#000000").is_err());
+        assert!(Palette::from_name("#000000,#FFFFFF,#FF0000").is_err());
+        assert!(Palette::from_name("#000000,white").is_err());
+    }
+
+    #[test]
+    fn pixels_fade_out_over_the_persistence_frames() {
+        let mut graphics = Chip8Graphics::new();
+        let mut fade = Persistence::new(4);
+        graphics.set_pixel_value(0, 0, true);
+        fade.update(&graphics);
+        assert_eq!(fade.intensity(0), 255);
+
+        graphics.set_pixel_value(0, 0, false);
+        let mut levels = Vec::new();
+        for _ in 0..5 {
+            fade.update(&graphics);
+            levels.push(fade.intensity(0));
+        }
+        assert_eq!(levels, vec![191, 127, 63, 0, 0]);
+
+        // lit again it is at full brightness straight away
+        graphics.set_pixel_value(0, 0, true);
+        fade.update(&graphics);
+        assert_eq!(fade.intensity(0), 255);
+
+        let mut instant = Persistence::new(0);
+        instant.update(&graphics);
+        graphics.set_pixel_value(0, 0, false);
+        instant.update(&graphics);
+        assert_eq!(instant.intensity(0), 0);
+    }
+
+    #[test]
+    fn rendering_blends_the_palette_by_the_fade() {
+        let mut graphics = Chip8Graphics::new();
+        let mut filter = VideoFilter::new(Palette::black_and_white(), 2);
+        let mut pixels = Vec::new();
+        graphics.set_pixel_value(1, 0, true);
+        filter.render(&graphics, None, &mut pixels);
+        assert_eq!(pixels.len(), 64 * 32 * 3);
+        assert_eq!(&pixels[..6], &[0, 0, 0, 255, 255, 255]);
+        graphics.set_pixel_value(1, 0, false);
+        filter.render(&graphics, None, &mut pixels);
+        assert_eq!(&pixels[3..6], &[127, 127, 127]);
+    }
+
+    #[test]
+    fn gifs_keep_every_second_frame_at_the_right_speed() {
+        let mut bytes = Vec::new();
+        {
+            let mut recorder = GifRecorder::new(&mut bytes, 4, 2).unwrap();
+            let black = vec![0; 4 * 2 * 3];
+            let white = vec![255; 4 * 2 * 3];
+            for n in 0..6 {
+                recorder.add_frame(4, 2, if n % 4 == 0 { &white } else { &black }).unwrap();
+            }
+            // a bigger frame after a mode switch is scaled down
+            recorder.add_frame(8, 4, &[255; 8 * 4 * 3]).unwrap();
+        }
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(&bytes[..]).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (4, 2));
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer[0]));
+        }
+        assert_eq!(frames, vec![(3, 255), (3, 0), (4, 255), (3, 255)]);
+    }
+}
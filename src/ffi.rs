@@ -0,0 +1,279 @@
+// C interface to the emulator core
+//
+// Everything goes through an opaque Chip8Handle made by chip8_new and
+// released with chip8_free. Functions return CHIP8_OK or a negative error
+// code. A fault in the program, e.g. an illegal instruction, leaves the
+// machine crashed until a rom or state is loaded again. A panic in the
+// core is caught before it reaches C and returned as CHIP8_ERROR_PANIC.
+// include/chip8.h declares all of this for C, keep the two in step.
+//
+// The pointers passed in have to be NULL or valid for the lengths given,
+// and a handle can only be used from one thread at a time.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use chip8::Chip8;
+use chip8::platform::Platform;
+use chip8::rom::Rom;
+use chip8::video::Rgb;
+
+pub const CHIP8_OK: i32 = 0;
+pub const CHIP8_ERROR_NULL: i32 = -1;
+pub const CHIP8_ERROR_INVALID_ROM: i32 = -2;
+pub const CHIP8_ERROR_INVALID_KEY: i32 = -3;
+pub const CHIP8_ERROR_BUFFER_TOO_SMALL: i32 = -4;
+pub const CHIP8_ERROR_INVALID_STATE: i32 = -5;
+pub const CHIP8_ERROR_CRASHED: i32 = -6;
+pub const CHIP8_ERROR_PANIC: i32 = -7;
+pub const CHIP8_ERROR_INVALID_PLATFORM: i32 = -8;
+
+pub const CHIP8_PLATFORM_CHIP8: i32 = 0;
+pub const CHIP8_PLATFORM_HIRES: i32 = 1;
+pub const CHIP8_PLATFORM_ETI660: i32 = 2;
+pub const CHIP8_PLATFORM_CHIP8X: i32 = 3;
+pub const CHIP8_PLATFORM_MEGACHIP: i32 = 4;
+
+pub struct Chip8Handle {
+    chip8: Chip8,
+    // what chip8_framebuffer hands out, one byte per pixel
+    framebuffer: Vec<u8>,
+    crashed: bool,
+}
+
+// Unwinding into C is undefined, so every function runs its body through
+// this and returns on_panic instead.
+fn guard<T, F: FnOnce() -> T>(on_panic: T, body: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(on_panic)
+}
+
+fn platform_from_code(code: i32) -> Option<Platform> {
+    match code {
+        CHIP8_PLATFORM_CHIP8 => Some(Platform::Chip8),
+        CHIP8_PLATFORM_HIRES => Some(Platform::HiresChip8),
+        CHIP8_PLATFORM_ETI660 => Some(Platform::Eti660),
+        CHIP8_PLATFORM_CHIP8X => Some(Platform::Chip8X),
+        CHIP8_PLATFORM_MEGACHIP => Some(Platform::MegaChip),
+        _ => None,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8Handle {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(Chip8Handle {
+            chip8: Chip8::new(),
+            framebuffer: Vec::new(),
+            crashed: false,
+        }))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(handle: *mut Chip8Handle) {
+    guard((), || {
+        if !handle.is_null() {
+            drop(Box::from_raw(handle));
+        }
+    })
+}
+
+// boots the rom on a freshly reset CHIP-8, see chip8_load_rom_for_platform
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(handle: *mut Chip8Handle, data: *const u8, length: usize) -> i32 {
+    chip8_load_rom_for_platform(handle, data, length, CHIP8_PLATFORM_CHIP8)
+}
+
+// Boots the rom on a freshly reset machine of the platform, at the
+// platform's load address. The rom database can still move a rom it knows
+// to another platform. Roms can be as large as a MEGA-CHIP's memory, the
+// platform they end up on decides if they fit.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom_for_platform(handle: *mut Chip8Handle, data: *const u8, length: usize, platform: i32) -> i32 {
+    guard(CHIP8_ERROR_PANIC, || {
+        let handle = match handle.as_mut() {
+            Some(handle) => handle,
+            None => return CHIP8_ERROR_NULL,
+        };
+        if data.is_null() {
+            return CHIP8_ERROR_NULL;
+        }
+        let platform = match platform_from_code(platform) {
+            Some(platform) => platform,
+            None => return CHIP8_ERROR_INVALID_PLATFORM,
+        };
+        let data = slice::from_raw_parts(data, length).to_vec();
+        let rom = match Rom::with_memory_size(data, platform.load_address(), Platform::MegaChip.memory_size()) {
+            Ok(rom) => rom,
+            Err(_) => return CHIP8_ERROR_INVALID_ROM,
+        };
+
+        let mut chip8 = Chip8::new();
+        if chip8.set_platform(platform).is_err() || chip8.boot(&rom).is_err() {
+            return CHIP8_ERROR_INVALID_ROM;
+        }
+        handle.chip8 = chip8;
+        handle.crashed = false;
+        CHIP8_OK
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(handle: *mut Chip8Handle) -> i32 {
+    guard(CHIP8_ERROR_PANIC, || {
+        let handle = match handle.as_mut() {
+            Some(handle) => handle,
+            None => return CHIP8_ERROR_NULL,
+        };
+        if handle.crashed {
+            return CHIP8_ERROR_CRASHED;
+        }
+
+        match handle.chip8.run_frame() {
+            Ok(()) => CHIP8_OK,
+            Err(_) => {
+                handle.crashed = true;
+                CHIP8_ERROR_CRASHED
+            },
+        }
+    })
+}
+
+// pressed is 0 for released and anything else for pressed
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(handle: *mut Chip8Handle, key: u8, pressed: i32) -> i32 {
+    guard(CHIP8_ERROR_PANIC, || {
+        let handle = match handle.as_mut() {
+            Some(handle) => handle,
+            None => return CHIP8_ERROR_NULL,
+        };
+        if key > 0xF {
+            return CHIP8_ERROR_INVALID_KEY;
+        }
+        handle.chip8.set_key(key, pressed != 0);
+        CHIP8_OK
+    })
+}
+
+// The display as one byte per pixel, 1 when lit, row by row. The pointer
+// stays valid until the next call on the handle. NULL on a NULL handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(handle: *mut Chip8Handle, width: *mut usize, height: *mut usize) -> *const u8 {
+    guard(ptr::null(), || {
+        let handle = match handle.as_mut() {
+            Some(handle) => handle,
+            None => return ptr::null(),
+        };
+
+        let graphics = handle.chip8.graphics();
+        handle.framebuffer.clear();
+        for y in 0..graphics.height() {
+            for x in 0..graphics.width() {
+                handle.framebuffer.push(graphics.get_pixel_value(x, y) as u8);
+            }
+        }
+        if !width.is_null() {
+            *width = graphics.width();
+        }
+        if !height.is_null() {
+            *height = graphics.height();
+        }
+        handle.framebuffer.as_ptr()
+    })
+}
+
+// The colours the rom database picked for the rom as 0xRRGGBB, black
+// and white by default. Either pointer can be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_palette(handle: *const Chip8Handle, background: *mut u32, foreground: *mut u32) -> i32 {
+    guard(CHIP8_ERROR_PANIC, || {
+        let handle = match handle.as_ref() {
+            Some(handle) => handle,
+            None => return CHIP8_ERROR_NULL,
+        };
+
+        let palette = handle.chip8.palette();
+        let rgb = |colour: Rgb| ((colour.r as u32) << 16) | ((colour.g as u32) << 8) | colour.b as u32;
+        if !background.is_null() {
+            *background = rgb(palette.background);
+        }
+        if !foreground.is_null() {
+            *foreground = rgb(palette.foreground);
+        }
+        CHIP8_OK
+    })
+}
+
+// The chip-8 key a character typed on the host keyboard presses, using
+// the rom database's keymap for the rom or 1234/qwer/asdf/zxcv.
+// CHIP8_ERROR_INVALID_KEY when the character isn't mapped.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_key_for_char(handle: *const Chip8Handle, c: u32) -> i32 {
+    guard(CHIP8_ERROR_PANIC, || {
+        let handle = match handle.as_ref() {
+            Some(handle) => handle,
+            None => return CHIP8_ERROR_NULL,
+        };
+        match ::std::char::from_u32(c).and_then(|c| handle.chip8.keymap().chip8_key(c)) {
+            Some(key) => key as i32,
+            None => CHIP8_ERROR_INVALID_KEY,
+        }
+    })
+}
+
+// 1 while the buzzer sounds, 0 when it is quiet
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_active(handle: *const Chip8Handle) -> i32 {
+    guard(CHIP8_ERROR_PANIC, || {
+        match handle.as_ref() {
+            Some(handle) => handle.chip8.sound_active() as i32,
+            None => CHIP8_ERROR_NULL,
+        }
+    })
+}
+
+// Copies a save state into buffer. size is always set to the size of the
+// state, so calling with a NULL buffer finds out how much room it needs.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(handle: *const Chip8Handle, buffer: *mut u8, capacity: usize, size: *mut usize) -> i32 {
+    guard(CHIP8_ERROR_PANIC, || {
+        let handle = match handle.as_ref() {
+            Some(handle) => handle,
+            None => return CHIP8_ERROR_NULL,
+        };
+
+        let state = handle.chip8.save_state();
+        if !size.is_null() {
+            *size = state.len();
+        }
+        if buffer.is_null() || capacity < state.len() {
+            return CHIP8_ERROR_BUFFER_TOO_SMALL;
+        }
+        ptr::copy_nonoverlapping(state.as_ptr(), buffer, state.len());
+        CHIP8_OK
+    })
+}
+
+// the machine is left alone when the state is refused
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(handle: *mut Chip8Handle, data: *const u8, length: usize) -> i32 {
+    guard(CHIP8_ERROR_PANIC, || {
+        let handle = match handle.as_mut() {
+            Some(handle) => handle,
+            None => return CHIP8_ERROR_NULL,
+        };
+        if data.is_null() {
+            return CHIP8_ERROR_NULL;
+        }
+        match handle.chip8.load_state(slice::from_raw_parts(data, length)) {
+            Ok(()) => {
+                handle.crashed = false;
+                CHIP8_OK
+            },
+            Err(_) => CHIP8_ERROR_INVALID_STATE,
+        }
+    })
+}
@@ -0,0 +1,320 @@
+pub mod panels;
+pub mod sdl;
+pub mod text;
+pub mod tui;
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use chip8_emulator::chip8::{Chip8, ResetKind};
+use chip8_emulator::chip8::cheats::{Cheat, CheatFile, CheatKind, CheatList, RamSearch, SearchFilter};
+use chip8_emulator::chip8::control::{RunControl, Tick};
+use chip8_emulator::chip8::coverage::Coverage;
+use chip8_emulator::chip8::cpu;
+use chip8_emulator::chip8::profiler::Profiler;
+use chip8_emulator::chip8::script::Script;
+use chip8_emulator::chip8::symbols;
+
+// candidates listed in the overlay, the rest are only counted
+const LISTED_CANDIDATES: usize = 8;
+
+// What runs alongside the machine in every frontend: the script, the
+// cheats and the RAM search driven by the function keys, the tools
+// watching each instruction, the breakpoints and how fast it all runs
+pub struct Session {
+    pub script: Option<Script>,
+    pub cheats: CheatList,
+    // where the cheats are saved and the SHA-1 of the rom they are for
+    pub cheat_file: Option<(PathBuf, String)>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    // a line for every instruction run
+    pub trace: Option<Box<dyn Write>>,
+    pub breakpoints: Vec<u32>,
+    // the breakpoint the machine is stopped at, no frames run until
+    // resume is called
+    pub stopped_at: Option<u16>,
+    // the breakpoint to run past on the first instruction after resuming
+    resume_at: Option<u16>,
+    pub control: RunControl,
+    search: Option<RamSearch>,
+    selected_cheat: usize,
+    show_cheats: bool,
+}
+
+// the keys that pause, step and change the speed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hotkey {
+    Pause,
+    AdvanceFrame,
+    StepInstruction,
+    Faster,
+    Slower,
+    Reset(ResetKind),
+}
+
+impl Session {
+    pub fn new(script: Option<Script>, cheats: CheatList, cheat_file: Option<(PathBuf, String)>) -> Session {
+        Session {
+            script,
+            cheats,
+            cheat_file,
+            profiler: None,
+            coverage: None,
+            trace: None,
+            breakpoints: Vec::new(),
+            stopped_at: None,
+            resume_at: None,
+            control: RunControl::new(),
+            search: None,
+            selected_cheat: 0,
+            show_cheats: false,
+        }
+    }
+
+    // Runs a frame with the cheats, through the script when there is one
+    // and showing each instruction to the profiler, coverage and trace.
+    // The frame ends early at a breakpoint, and while stopped at one
+    // nothing runs.
+    pub fn run_frame(&mut self, chip8_vm: &mut Chip8) -> Result<(), String> {
+        if self.stopped_at.is_some() {
+            return Ok(());
+        }
+        self.cheats.apply_frame(chip8_vm);
+        let mut trace_error = None;
+        let result = {
+            let profiler = &mut self.profiler;
+            let coverage = &mut self.coverage;
+            let trace = &mut self.trace;
+            let breakpoints = &self.breakpoints;
+            let stopped_at = &mut self.stopped_at;
+            let resume_at = &mut self.resume_at;
+            let trace_error = &mut trace_error;
+            let hook = |chip8: &mut Chip8, pc: u16| {
+                if resume_at.take() != Some(pc) && breakpoints.contains(&(pc as u32)) {
+                    *stopped_at = Some(pc);
+                    return true;
+                }
+                if let Err(e) = watch(profiler, coverage, trace, chip8, pc) {
+                    trace_error.get_or_insert(e);
+                }
+                false
+            };
+            match self.script {
+                Some(ref mut script) => script.run_frame_until(chip8_vm, hook).map(|_| ()),
+                None => chip8_vm.run_frame_until(hook).map(|_| ()).map_err(|e| e.to_string()),
+            }
+        };
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.end_frame();
+        }
+        if let Some(e) = trace_error {
+            return Err(format!("could not write the trace: {}", e));
+        }
+        result
+    }
+
+    // Runs what the run control says to before the next draw, which is
+    // due at next_draw
+    pub fn tick(&mut self, chip8_vm: &mut Chip8, next_draw: Instant) -> Result<(), String> {
+        match self.control.tick() {
+            Tick::Frames(frames) => for _ in 0..frames {
+                self.run_frame(chip8_vm)?;
+            },
+            Tick::Instruction => self.step_instruction(chip8_vm)?,
+            Tick::Uncapped => while Instant::now() < next_draw && self.stopped_at.is_none() {
+                self.run_frame(chip8_vm)?;
+            },
+        }
+        Ok(())
+    }
+
+    // Runs the instruction at PC whether or not there is a breakpoint on
+    // it, with the cheats and the script as a frame would. Stopped at a
+    // breakpoint it stays stopped, at the next instruction.
+    fn step_instruction(&mut self, chip8_vm: &mut Chip8) -> Result<(), String> {
+        self.cheats.apply_frame(chip8_vm);
+        let pc = chip8_vm.cpu().pc();
+        watch(&mut self.profiler, &mut self.coverage, &mut self.trace, chip8_vm, pc)
+            .map_err(|e| format!("could not write the trace: {}", e))?;
+        match self.script {
+            Some(ref mut script) => script.step(chip8_vm)?,
+            None => chip8_vm.step().map_err(|e| e.to_string())?,
+        }
+        if self.stopped_at.is_some() {
+            self.stopped_at = Some(chip8_vm.cpu().pc());
+            self.resume_at = None;
+        }
+        Ok(())
+    }
+
+    // F11 pauses, F12 runs one frame, page down one instruction, - and =
+    // change the speed, home resets and end power cycles. Fast forward is
+    // held down, so the frontends set it on the run control themselves.
+    pub fn hotkey(&mut self, hotkey: Hotkey, chip8_vm: &mut Chip8) {
+        match hotkey {
+            Hotkey::Pause => {
+                if self.stopped_at.is_some() {
+                    self.resume();
+                    self.control.paused = false;
+                } else {
+                    self.control.toggle_pause();
+                }
+            },
+            Hotkey::AdvanceFrame => {
+                self.resume();
+                self.control.advance_frame();
+            },
+            Hotkey::StepInstruction => self.control.step_instruction(),
+            Hotkey::Faster => self.control.faster(),
+            Hotkey::Slower => self.control.slower(),
+            Hotkey::Reset(kind) => self.reset(kind, chip8_vm),
+        }
+    }
+
+    // the cheats and the tools carry on, and the patches go back into the
+    // reloaded rom after a power cycle
+    pub fn reset(&mut self, kind: ResetKind, chip8_vm: &mut Chip8) {
+        chip8_vm.reset(kind);
+        if kind == ResetKind::Hard {
+            self.cheats.apply_patches(chip8_vm);
+        }
+        self.stopped_at = None;
+        self.resume_at = None;
+    }
+
+    // carries on from the breakpoint the machine stopped at
+    pub fn resume(&mut self) {
+        self.resume_at = self.stopped_at.take();
+    }
+
+    // where the machine stopped, its registers and call stack
+    pub fn break_report(&self, chip8_vm: &Chip8) -> Vec<String> {
+        let pc = match self.stopped_at {
+            Some(pc) => pc,
+            None => return Vec::new(),
+        };
+        let cpu_ref = chip8_vm.cpu();
+        let symbols = chip8_vm.symbols();
+        let mut lines = vec![format!("break at {}: {}", symbols::describe(symbols, pc as u32),
+                                     cpu::disassemble_with_symbols(chip8_vm.platform(), chip8_vm.memory(), pc, symbols))];
+        let registers: Vec<String> = (0..16).map(|i| format!("V{:X}={:02X}", i, cpu_ref.v(i))).collect();
+        lines.push(registers[..8].join(" "));
+        lines.push(registers[8..].join(" "));
+        lines.push(format!("I={}  DT={:02X}  ST={:02X}", symbols::describe(symbols, cpu_ref.i()),
+                           cpu_ref.delay_timer(), cpu_ref.sound_timer()));
+        lines.push(String::from("call stack:"));
+        for frame in symbols::call_stack(cpu_ref, symbols) {
+            lines.push(format!("  {}", frame));
+        }
+        lines
+    }
+
+    // F1 starts a new RAM search, F2 to F5 keep the candidates that
+    // changed, stayed the same, increased or decreased since the last
+    // search, F6 freezes the first candidate, F7 selects the next cheat,
+    // F8 switches it on or off and F9 shows or hides the cheats. F10
+    // carries on from a breakpoint.
+    pub fn function_key(&mut self, n: u8, chip8_vm: &mut Chip8) -> Result<(), String> {
+        const FILTERS: [SearchFilter; 4] = [SearchFilter::Changed, SearchFilter::Unchanged,
+                                            SearchFilter::Increased, SearchFilter::Decreased];
+        match n {
+            1 => self.search = Some(RamSearch::new(chip8_vm.memory())),
+            2..=5 => match self.search {
+                Some(ref mut search) => search.filter(chip8_vm.memory(), FILTERS[n as usize - 2]),
+                None => self.search = Some(RamSearch::new(chip8_vm.memory())),
+            },
+            6 => {
+                let candidate = self.search.as_ref().and_then(|search| search.candidates().first().cloned());
+                if let Some(addr) = candidate {
+                    let value = chip8_vm.memory().read_byte_at(addr);
+                    self.cheats.cheats.push(Cheat::new(CheatKind::Freeze, addr, value, ""));
+                    self.selected_cheat = self.cheats.cheats.len() - 1;
+                    self.save_cheats()?;
+                }
+            },
+            7 => if !self.cheats.cheats.is_empty() {
+                self.selected_cheat = (self.selected_cheat + 1) % self.cheats.cheats.len();
+            },
+            8 => if self.selected_cheat < self.cheats.cheats.len() {
+                let enabled = !self.cheats.cheats[self.selected_cheat].enabled;
+                self.cheats.set_enabled(self.selected_cheat, enabled, chip8_vm);
+                self.save_cheats()?;
+            },
+            9 => {
+                self.show_cheats = !self.show_cheats;
+                return Ok(());
+            },
+            10 => {
+                self.resume();
+                return Ok(());
+            },
+            _ => return Ok(()),
+        }
+        self.show_cheats = true;
+        Ok(())
+    }
+
+    fn save_cheats(&self) -> Result<(), String> {
+        if let Some((ref path, ref sha1)) = self.cheat_file {
+            let mut file = CheatFile::load(path).map_err(|e| format!("could not load {}: {}", path.display(), e))?;
+            file.set_cheats(sha1, self.cheats.clone());
+            file.save(path).map_err(|e| format!("could not save {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    // the lines of text to show over the display
+    pub fn overlay(&self, chip8_vm: &Chip8) -> Vec<String> {
+        let mut lines: Vec<String> = self.control.indicator().into_iter().collect();
+        if let Some(ref script) = self.script {
+            lines.extend(script.overlay());
+        }
+        if self.stopped_at.is_some() {
+            lines.extend(self.break_report(chip8_vm));
+            lines.push(String::from("F10 continues"));
+        }
+        if !self.show_cheats {
+            return lines;
+        }
+
+        if let Some(ref search) = self.search {
+            let candidates = search.candidates();
+            lines.push(format!("search: {} candidates", candidates.len()));
+            if candidates.len() <= LISTED_CANDIDATES {
+                for &addr in candidates {
+                    lines.push(format!("  {:#05X} = {:#04X}", addr, chip8_vm.memory().read_byte_at(addr)));
+                }
+            }
+        }
+        for (i, cheat) in self.cheats.cheats.iter().enumerate() {
+            let marker = if i == self.selected_cheat { ">" } else { " " };
+            lines.push(format!("{}{}", marker, cheat));
+        }
+        lines
+    }
+}
+
+// shows the instruction about to run at pc to the profiler, coverage
+// and trace
+fn watch(profiler: &mut Option<Profiler>, coverage: &mut Option<Coverage>, trace: &mut Option<Box<dyn Write>>,
+         chip8_vm: &Chip8, pc: u16) -> io::Result<()> {
+    if let Some(ref mut profiler) = *profiler {
+        profiler.record(chip8_vm, pc);
+    }
+    if let Some(ref mut coverage) = *coverage {
+        coverage.record(chip8_vm, pc);
+    }
+    match *trace {
+        Some(ref mut trace) => writeln!(trace, "{}", trace_line(chip8_vm, pc)),
+        None => Ok(()),
+    }
+}
+
+// "2A4 draw_player+4      DRW V0, V1, 5"
+pub fn trace_line(chip8_vm: &Chip8, pc: u16) -> String {
+    let symbols = chip8_vm.symbols();
+    let label = symbols.and_then(|symbols| symbols.label(pc as u32)).unwrap_or_default();
+    format!("{:03X} {:<20} {}", pc, label, cpu::disassemble_with_symbols(chip8_vm.platform(), chip8_vm.memory(), pc, symbols))
+}
@@ -0,0 +1,391 @@
+// Debug panels drawn beside the display in the SDL window
+//
+// The left column has the registers, the stack and the keypad, the right
+// one a disassembly around PC and a hex view of the memory around I. Both
+// views follow the machine until the mouse wheel scrolls them, clicking
+// their heading makes them follow again.
+//
+// Clicking a register, timer or byte of memory edits it: type the new
+// value in hex and press return, or escape to leave it as it was.
+// Clicking an instruction sets or clears a breakpoint on it and clicking
+// a key of the keypad holds it down until the button is let go.
+
+use chip8_emulator::chip8::Chip8;
+use chip8_emulator::chip8::cpu::{self, IntermediateAsm};
+
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use frontend::Session;
+use frontend::text;
+
+const SCALE: u32 = 2;
+const MARGIN: i32 = 8;
+// characters in a column
+const COLUMN_CHARS: usize = 30;
+const DISASSEMBLY_LINES: usize = 14;
+const MEMORY_ROWS: usize = 8;
+const MEMORY_COLUMNS: u32 = 8;
+const STACK_LINES: usize = 8;
+
+// the window grows by this much when the panels are shown
+pub const PANEL_WIDTH: u32 = 512;
+pub const PANEL_HEIGHT: u32 = 320;
+
+const KEYPAD: [[u8; 4]; 4] = [[0x1, 0x2, 0x3, 0xC],
+                              [0x4, 0x5, 0x6, 0xD],
+                              [0x7, 0x8, 0x9, 0xE],
+                              [0xA, 0x0, 0xB, 0xF]];
+
+// what a click on part of a panel does
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Register(usize),
+    I,
+    Pc,
+    DelayTimer,
+    SoundTimer,
+    Memory(u32),
+    Instruction(u16),
+    Key(u8),
+    FollowPc,
+    FollowI,
+}
+
+impl Target {
+    // hex digits the value can have
+    fn digits(&self, chip8_vm: &Chip8) -> usize {
+        match *self {
+            Target::I if chip8_vm.memory().size() > 0x10000 => 6,
+            Target::I | Target::Pc => 4,
+            _ => 2,
+        }
+    }
+}
+
+pub struct Panels {
+    pub visible: bool,
+    // first address shown when scrolled, None to follow PC and I
+    disassembly_top: Option<u16>,
+    memory_top: Option<u32>,
+    // the value being typed in
+    editing: Option<(Target, String)>,
+    // the key held down by the mouse
+    pressed_key: Option<u8>,
+    // what was drawn where, from the last draw
+    hits: Vec<(Rect, Target)>,
+    disassembly_area: Rect,
+    memory_area: Rect,
+}
+
+impl Panels {
+    pub fn new(visible: bool) -> Panels {
+        Panels {
+            visible,
+            disassembly_top: None,
+            memory_top: None,
+            editing: None,
+            pressed_key: None,
+            hits: Vec::new(),
+            disassembly_area: Rect::new(0, 0, 1, 1),
+            memory_area: Rect::new(0, 0, 1, 1),
+        }
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, area: Rect, chip8_vm: &Chip8, session: &Session) -> Result<(), String> {
+        self.hits.clear();
+        canvas.set_draw_color(Color::RGB(0x10, 0x10, 0x18));
+        canvas.fill_rect(area)?;
+
+        let left = area.x() + MARGIN;
+        let right = area.x() + MARGIN * 2 + column_width() as i32;
+        let top = area.y() + MARGIN;
+        self.draw_registers(canvas, left, top, chip8_vm)?;
+        self.draw_stack(canvas, left, top + row_y(8), chip8_vm)?;
+        self.draw_keypad(canvas, left, top + row_y(9 + STACK_LINES as i32 + 1), chip8_vm)?;
+        self.draw_disassembly(canvas, right, top, chip8_vm, session)?;
+        self.draw_memory(canvas, right, top + row_y(DISASSEMBLY_LINES as i32 + 2), chip8_vm)
+    }
+
+    fn draw_registers(&mut self, canvas: &mut Canvas<Window>, x: i32, y: i32, chip8_vm: &Chip8) -> Result<(), String> {
+        let cpu_ref = chip8_vm.cpu();
+        heading(canvas, x, y, "REGISTERS")?;
+        for index in 0..16 {
+            let field_x = x + (index % 4) as i32 * 6 * char_width();
+            let field_y = y + row_y(1 + index as i32 / 4);
+            text::draw_text(canvas, field_x, field_y, SCALE, &format!("V{:X}", index), Color::RGB(0x80, 0x80, 0x80))?;
+            self.field(canvas, field_x + 3 * char_width(), field_y, Target::Register(index), &format!("{:02X}", cpu_ref.v(index)), chip8_vm)?;
+        }
+
+        let y = y + row_y(5);
+        label(canvas, x, y, "I")?;
+        self.field(canvas, x + 2 * char_width(), y, Target::I, &format!("{:04X}", cpu_ref.i()), chip8_vm)?;
+        label(canvas, x + 9 * char_width(), y, "PC")?;
+        self.field(canvas, x + 12 * char_width(), y, Target::Pc, &format!("{:04X}", cpu_ref.pc()), chip8_vm)?;
+        label(canvas, x + 18 * char_width(), y, "SP")?;
+        text::draw_text(canvas, x + 21 * char_width(), y, SCALE, &format!("{:X}", cpu_ref.sp()), white())?;
+
+        let y = y + row_y(1);
+        label(canvas, x, y, "DT")?;
+        self.field(canvas, x + 3 * char_width(), y, Target::DelayTimer, &format!("{:02X}", cpu_ref.delay_timer()), chip8_vm)?;
+        label(canvas, x + 9 * char_width(), y, "ST")?;
+        self.field(canvas, x + 12 * char_width(), y, Target::SoundTimer, &format!("{:02X}", cpu_ref.sound_timer()), chip8_vm)
+    }
+
+    fn draw_stack(&mut self, canvas: &mut Canvas<Window>, x: i32, y: i32, chip8_vm: &Chip8) -> Result<(), String> {
+        heading(canvas, x, y, "STACK")?;
+        let symbols = chip8_vm.symbols();
+        let stack = chip8_vm.cpu().stack();
+        if stack.is_empty() {
+            return label(canvas, x, y + row_y(1), "EMPTY");
+        }
+        // newest first, the return addresses with the label they return into
+        for (n, &addr) in stack.iter().rev().enumerate().take(STACK_LINES) {
+            let text = if n == STACK_LINES - 1 && stack.len() > STACK_LINES {
+                format!("... {} MORE", stack.len() - n)
+            } else {
+                match symbols.and_then(|symbols| symbols.label(addr as u32)) {
+                    Some(name) => format!("{:X} {:03X} {}", stack.len() - 1 - n, addr, name),
+                    None => format!("{:X} {:03X}", stack.len() - 1 - n, addr),
+                }
+            };
+            text::draw_text(canvas, x, y + row_y(1 + n as i32), SCALE, &clip(&text), white())?;
+        }
+        Ok(())
+    }
+
+    fn draw_keypad(&mut self, canvas: &mut Canvas<Window>, x: i32, y: i32, chip8_vm: &Chip8) -> Result<(), String> {
+        heading(canvas, x, y, "KEYS")?;
+        for (row, keys) in KEYPAD.iter().enumerate() {
+            for (column, &key) in keys.iter().enumerate() {
+                let rect = Rect::new(x + column as i32 * 3 * char_width() - 2, y + row_y(1 + row as i32) - 2,
+                                     (2 * char_width()) as u32, text::line_height(SCALE) + 2);
+                let pressed = chip8_vm.is_key_pressed(key);
+                canvas.set_draw_color(if pressed { Color::RGB(0x20, 0x80, 0x20) } else { Color::RGB(0x30, 0x30, 0x38) });
+                canvas.fill_rect(rect)?;
+                text::draw_text(canvas, rect.x() + 2 + char_width() / 2, rect.y() + 2, SCALE, &format!("{:X}", key), white())?;
+                self.hits.push((rect, Target::Key(key)));
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_disassembly(&mut self, canvas: &mut Canvas<Window>, x: i32, y: i32, chip8_vm: &Chip8, session: &Session) -> Result<(), String> {
+        let pc = chip8_vm.cpu().pc();
+        let heading_text = if self.disassembly_top.is_some() { "DISASSEMBLY - CLICK TO FOLLOW" } else { "DISASSEMBLY" };
+        heading(canvas, x, y, heading_text)?;
+        self.hits.push((Rect::new(x, y, text::text_width(heading_text, SCALE), text::line_height(SCALE)), Target::FollowPc));
+        self.disassembly_area = Rect::new(x, y, column_width(), row_y(DISASSEMBLY_LINES as i32 + 1) as u32);
+
+        let symbols = chip8_vm.symbols();
+        let mut addr = self.disassembly_top.unwrap_or_else(|| pc.saturating_sub(8));
+        let mut line = 1;
+        while line <= DISASSEMBLY_LINES as i32 {
+            if let Some(name) = symbols.and_then(|symbols| symbols.name(addr as u32)) {
+                label(canvas, x, y + row_y(line), &clip(&format!("{}:", name)))?;
+                line += 1;
+                if line > DISASSEMBLY_LINES as i32 {
+                    break;
+                }
+            }
+            let line_y = y + row_y(line);
+            let rect = Rect::new(x, line_y - 1, column_width(), text::line_height(SCALE));
+            if addr == pc {
+                canvas.set_draw_color(Color::RGB(0x30, 0x30, 0x80));
+                canvas.fill_rect(rect)?;
+            }
+            if session.breakpoints.contains(&(addr as u32)) {
+                text::draw_text(canvas, x, line_y, SCALE, "*", Color::RGB(0xFF, 0x40, 0x40))?;
+            }
+            let instruction = cpu::disassemble_with_symbols(chip8_vm.platform(), chip8_vm.memory(), addr, symbols);
+            text::draw_text(canvas, x + 2 * char_width(), line_y, SCALE, &clip(&format!("{:03X} {}", addr, instruction)), white())?;
+            self.hits.push((rect, Target::Instruction(addr)));
+            addr = addr.wrapping_add(2);
+            line += 1;
+        }
+        Ok(())
+    }
+
+    fn draw_memory(&mut self, canvas: &mut Canvas<Window>, x: i32, y: i32, chip8_vm: &Chip8) -> Result<(), String> {
+        let i = chip8_vm.cpu().i();
+        let heading_text = if self.memory_top.is_some() { "MEMORY - CLICK TO FOLLOW I" } else { "MEMORY" };
+        heading(canvas, x, y, heading_text)?;
+        self.hits.push((Rect::new(x, y, text::text_width(heading_text, SCALE), text::line_height(SCALE)), Target::FollowI));
+        self.memory_area = Rect::new(x, y, column_width(), row_y(MEMORY_ROWS as i32 + 1) as u32);
+
+        let addressed = i..i.wrapping_add(bytes_addressed_by_i(chip8_vm));
+        let top = self.memory_top.unwrap_or_else(|| (i & !(MEMORY_COLUMNS - 1)).saturating_sub(2 * MEMORY_COLUMNS));
+        for row in 0..MEMORY_ROWS as u32 {
+            let row_addr = top.wrapping_add(row * MEMORY_COLUMNS);
+            let row_y = y + row_y(1 + row as i32);
+            label(canvas, x, row_y, &format!("{:04X}", row_addr))?;
+            for column in 0..MEMORY_COLUMNS {
+                let addr = row_addr.wrapping_add(column);
+                let byte_x = x + (5 + column as i32 * 3) * char_width();
+                if addressed.contains(&addr) {
+                    canvas.set_draw_color(Color::RGB(0x60, 0x50, 0x00));
+                    canvas.fill_rect(Rect::new(byte_x - 2, row_y - 1, (2 * char_width()) as u32, text::line_height(SCALE)))?;
+                }
+                let value = format!("{:02X}", chip8_vm.memory().read_byte_at(addr));
+                self.field(canvas, byte_x, row_y, Target::Memory(addr), &value, chip8_vm)?;
+            }
+        }
+        Ok(())
+    }
+
+    // a value that can be clicked to edit, showing what is being typed
+    // while it is edited
+    fn field(&mut self, canvas: &mut Canvas<Window>, x: i32, y: i32, target: Target, value: &str, chip8_vm: &Chip8) -> Result<(), String> {
+        let rect = Rect::new(x - 1, y - 1, (target.digits(chip8_vm) as i32 * char_width()) as u32, text::line_height(SCALE));
+        self.hits.push((rect, target));
+        match self.editing {
+            Some((editing, ref typed)) if editing == target => {
+                canvas.set_draw_color(Color::RGB(0x60, 0x60, 0x00));
+                canvas.fill_rect(rect)?;
+                text::draw_text(canvas, x, y, SCALE, &format!("{}_", typed), Color::RGB(0xFF, 0xFF, 0x60))
+            },
+            _ => text::draw_text(canvas, x, y, SCALE, value, white()),
+        }
+    }
+
+    fn target_at(&self, x: i32, y: i32) -> Option<Target> {
+        self.hits.iter().find(|&&(rect, _)| rect.contains_point((x, y))).map(|&(_, target)| target)
+    }
+
+    // returns whether the click was on a panel
+    pub fn mouse_down(&mut self, x: i32, y: i32, chip8_vm: &mut Chip8, session: &mut Session) -> bool {
+        self.editing = None;
+        let target = match self.target_at(x, y) {
+            Some(target) => target,
+            None => return false,
+        };
+        match target {
+            Target::Instruction(addr) => {
+                let addr = addr as u32;
+                match session.breakpoints.iter().position(|&breakpoint| breakpoint == addr) {
+                    Some(index) => {
+                        session.breakpoints.remove(index);
+                    },
+                    None => session.breakpoints.push(addr),
+                }
+            },
+            Target::Key(key) => {
+                chip8_vm.set_key(key, true);
+                self.pressed_key = Some(key);
+            },
+            Target::FollowPc => self.disassembly_top = None,
+            Target::FollowI => self.memory_top = None,
+            _ => self.editing = Some((target, String::new())),
+        }
+        true
+    }
+
+    pub fn mouse_up(&mut self, chip8_vm: &mut Chip8) {
+        if let Some(key) = self.pressed_key.take() {
+            chip8_vm.set_key(key, false);
+        }
+    }
+
+    // scrolls the view under the mouse, up is positive
+    pub fn scroll(&mut self, x: i32, y: i32, amount: i32, chip8_vm: &Chip8) {
+        if self.disassembly_area.contains_point((x, y)) {
+            let top = self.disassembly_top.unwrap_or_else(|| chip8_vm.cpu().pc().saturating_sub(8));
+            self.disassembly_top = Some((top as i32 - amount * 2).max(0) as u16);
+        } else if self.memory_area.contains_point((x, y)) {
+            let i = chip8_vm.cpu().i();
+            let top = self.memory_top.unwrap_or_else(|| (i & !(MEMORY_COLUMNS - 1)).saturating_sub(2 * MEMORY_COLUMNS));
+            self.memory_top = Some((top as i64 - amount as i64 * MEMORY_COLUMNS as i64).max(0) as u32);
+        }
+    }
+
+    // Takes the keys while a value is being edited, returns whether the
+    // key was used
+    pub fn key_down(&mut self, keycode: Keycode, chip8_vm: &mut Chip8) -> bool {
+        let (target, mut typed) = match self.editing.take() {
+            Some(editing) => editing,
+            None => return false,
+        };
+        match keycode {
+            Keycode::Return | Keycode::KpEnter => {
+                if let Ok(value) = u32::from_str_radix(&typed, 16) {
+                    set_value(chip8_vm, target, value);
+                }
+                return true;
+            },
+            Keycode::Escape => return true,
+            Keycode::Backspace => {
+                typed.pop();
+            },
+            _ => {
+                let name = keycode.name();
+                let mut chars = name.chars();
+                if let (Some(c), None) = (chars.next(), chars.next()) {
+                    if c.is_ascii_hexdigit() && typed.len() < target.digits(chip8_vm) {
+                        typed.push(c.to_ascii_uppercase());
+                    }
+                }
+            },
+        }
+        self.editing = Some((target, typed));
+        true
+    }
+}
+
+fn set_value(chip8_vm: &mut Chip8, target: Target, value: u32) {
+    let delay = chip8_vm.cpu().delay_timer();
+    let sound = chip8_vm.cpu().sound_timer();
+    match target {
+        Target::Register(index) => chip8_vm.cpu_mut().reg_gp[index] = value as u8,
+        Target::I => chip8_vm.cpu_mut().set_i(value),
+        Target::Pc => chip8_vm.cpu_mut().set_pc(value as u16),
+        Target::DelayTimer => chip8_vm.cpu_mut().set_timers(value as u8, sound),
+        Target::SoundTimer => chip8_vm.cpu_mut().set_timers(delay, value as u8),
+        Target::Memory(addr) => chip8_vm.memory_mut().write_byte_at(addr, value as u8),
+        _ => {},
+    }
+}
+
+// the bytes the instruction at PC reads or writes through I
+fn bytes_addressed_by_i(chip8_vm: &Chip8) -> u32 {
+    let pc = chip8_vm.cpu().pc();
+    let memory = chip8_vm.memory();
+    let opcode = ((memory.read_byte(pc) as u16) << 8) | memory.read_byte(pc.wrapping_add(1)) as u16;
+    match cpu::decode_for(chip8_vm.platform(), opcode) {
+        Some(IntermediateAsm::DRW {nibble, ..}) => (nibble as u32).max(1),
+        Some(IntermediateAsm::STORE_REG_ARR {reg_x_index}) | Some(IntermediateAsm::LOAD_REG_ARR {reg_x_index}) =>
+            reg_x_index as u32 + 1,
+        Some(IntermediateAsm::STORE_BCD {..}) => 3,
+        _ => 1,
+    }
+}
+
+fn char_width() -> i32 {
+    ((text::GLYPH_WIDTH + 1) * SCALE) as i32
+}
+
+fn column_width() -> u32 {
+    COLUMN_CHARS as u32 * char_width() as u32
+}
+
+fn row_y(row: i32) -> i32 {
+    row * text::line_height(SCALE) as i32
+}
+
+fn clip(text: &str) -> String {
+    text.chars().take(COLUMN_CHARS).collect()
+}
+
+fn white() -> Color {
+    Color::RGB(0xFF, 0xFF, 0xFF)
+}
+
+fn heading(canvas: &mut Canvas<Window>, x: i32, y: i32, text: &str) -> Result<(), String> {
+    text::draw_text(canvas, x, y, SCALE, text, Color::RGB(0x60, 0xA0, 0xFF))
+}
+
+fn label(canvas: &mut Canvas<Window>, x: i32, y: i32, text: &str) -> Result<(), String> {
+    text::draw_text(canvas, x, y, SCALE, text, Color::RGB(0x80, 0x80, 0x80))
+}
@@ -0,0 +1,286 @@
+use std::time::{Duration, Instant};
+
+use chip8_emulator::chip8::{Chip8, ResetKind};
+use chip8_emulator::chip8::CHIP8_FRAMES_PER_SECOND;
+use chip8_emulator::chip8::keymap::Keymap;
+use chip8_emulator::chip8::video::VideoFilter;
+
+use sdl2;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::{MouseButton, MouseWheelDirection};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::{FullscreenType, Window};
+
+use frontend::{Hotkey, Session};
+use frontend::panels::{self, Panels};
+use frontend::text;
+use options::Options;
+
+const WINDOW_TITLE: &str = "Rusty Chip-8 Emulator";
+
+// SDL frontend
+//
+// The chip-8 framebuffer is streamed into a texture the size of the
+// chip-8 display, which SDL then scales up with nearest neighbour
+// filtering to the largest whole multiple that fits in the window.
+// Whatever is left over is letterboxed in black.
+//
+// Tab shows the debug panels to the right of the display, widening the
+// window to make room for them. Holding ` fast forwards.
+pub fn run(chip8_vm: &mut Chip8, options: &Options, session: &mut Session) -> Result<(), String> {
+    // nearest neighbour scaling, otherwise the pixels get blurred
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "0");
+
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+
+    let mut panels = Panels::new(options.debug_panel);
+    let mut window_width = (chip8_vm.graphics().width() as u32) * options.scale;
+    let mut window_height = (chip8_vm.graphics().height() as u32) * options.scale;
+    if panels.visible {
+        window_width += panels::PANEL_WIDTH;
+        window_height = window_height.max(panels::PANEL_HEIGHT);
+    }
+
+    let title = match chip8_vm.rom_info() {
+        Some(info) => info.title.clone(),
+        None => String::from(WINDOW_TITLE),
+    };
+    let keymap = chip8_vm.keymap().clone();
+    let keymap2 = options.keymap2();
+
+    let mut window_builder = video_subsystem.window(&title, window_width, window_height);
+    window_builder.position_centered().resizable();
+    if options.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().map_err(|e| e.to_string())?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+
+    let mut texture_size = (chip8_vm.graphics().width(), chip8_vm.graphics().height());
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, texture_size.0 as u32, texture_size.1 as u32)
+        .map_err(|e| e.to_string())?;
+    let mut pixels = Vec::new();
+    let mut video_filter = VideoFilter::new(chip8_vm.palette(), options.persistence);
+
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut mouse_position = (0, 0);
+    let frame_time = Duration::new(0, 1_000_000_000u32 / CHIP8_FRAMES_PER_SECOND);
+    'running: loop {
+        let frame_start = Instant::now();
+
+        // step emulation
+        session.tick(chip8_vm, frame_start + frame_time)?;
+
+        // keyboard and mouse events
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} => {
+                    break 'running
+                },
+                // a value being edited in the panels takes the keys,
+                // escape included
+                Event::KeyDown { keycode: Some(keycode), .. } if panels.key_down(keycode, chip8_vm) => {},
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    break 'running
+                },
+                Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+                    panels.visible = !panels.visible;
+                    toggle_panel_space(&mut canvas, panels.visible)?;
+                },
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    if let Some(n) = function_key(keycode) {
+                        session.function_key(n, chip8_vm)?;
+                    } else if let Some(key) = chip8_key(&keymap, keycode) {
+                        chip8_vm.set_key(key, true);
+                    } else if let Some(key) = chip8_key(&keymap2, keycode) {
+                        chip8_vm.set_keypad2_key(key, true);
+                    } else if let Some(hotkey) = hotkey(keycode) {
+                        session.hotkey(hotkey, chip8_vm);
+                    } else if keycode == Keycode::Backquote {
+                        session.control.fast_forwarding = true;
+                    }
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(key) = chip8_key(&keymap, keycode) {
+                        chip8_vm.set_key(key, false);
+                    } else if let Some(key) = chip8_key(&keymap2, keycode) {
+                        chip8_vm.set_keypad2_key(key, false);
+                    } else if keycode == Keycode::Backquote {
+                        session.control.fast_forwarding = false;
+                    }
+                },
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } if panels.visible => {
+                    panels.mouse_down(x, y, chip8_vm, session);
+                },
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                    panels.mouse_up(chip8_vm);
+                },
+                // wheel events don't say where the mouse is
+                Event::MouseMotion { x, y, .. } => {
+                    mouse_position = (x, y);
+                },
+                Event::MouseWheel { y, direction, .. } if panels.visible => {
+                    let amount = if direction == MouseWheelDirection::Flipped { -y } else { y };
+                    panels.scroll(mouse_position.0, mouse_position.1, amount, chip8_vm);
+                },
+                _ => {}
+            }
+        }
+
+        // the display can change size when a rom switches to a hi-res
+        // mode, so the texture has to follow it
+        let graphics_size = (chip8_vm.graphics().width(), chip8_vm.graphics().height());
+        if graphics_size != texture_size {
+            texture_size = graphics_size;
+            texture = texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, texture_size.0 as u32, texture_size.1 as u32)
+                .map_err(|e| e.to_string())?;
+        }
+
+        // uploaded every frame rather than on the draw flag so that
+        // faded pixels keep fading while nothing is being drawn
+        chip8_vm.take_draw_flag();
+        video_filter.render(chip8_vm.graphics(), chip8_vm.colours(), &mut pixels);
+        texture.update(None, &pixels, texture_size.0 * 3).map_err(|e| e.to_string())?;
+
+        // redrawn every frame so resizing the window doesn't leave junk behind
+        let (output_width, output_height) = canvas.output_size()?;
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        let display_width = if panels.visible { output_width.saturating_sub(panels::PANEL_WIDTH).max(1) } else { output_width };
+        let display = letterbox(display_width, output_height, texture_size.0 as u32, texture_size.1 as u32);
+        canvas.copy(&texture, None, Some(display))?;
+        draw_overlay(&mut canvas, display, &session.overlay(chip8_vm))?;
+        if panels.visible {
+            let area = Rect::new(display_width as i32, 0, panels::PANEL_WIDTH, output_height);
+            panels.draw(&mut canvas, area, chip8_vm, session)?;
+        }
+        canvas.present();
+
+        let elapsed = frame_start.elapsed();
+        if elapsed < frame_time {
+            ::std::thread::sleep(frame_time - elapsed);
+        }
+    }
+
+    Ok(())
+}
+
+// largest whole multiple of the display that fits in the output,
+// centred so the rest of the window is letterboxed
+fn letterbox(output_width: u32, output_height: u32, width: u32, height: u32) -> Rect {
+    let scale = ::std::cmp::max(1, ::std::cmp::min(output_width / width, output_height / height));
+    let scaled_width = width * scale;
+    let scaled_height = height * scale;
+    Rect::new((output_width as i32 - scaled_width as i32) / 2,
+              (output_height as i32 - scaled_height as i32) / 2,
+              scaled_width,
+              scaled_height)
+}
+
+// Widens the window by the width of the panels when they are shown and
+// narrows it again when they are hidden. A fullscreen window is left
+// alone and the display shrinks to fit beside them.
+fn toggle_panel_space(canvas: &mut Canvas<Window>, visible: bool) -> Result<(), String> {
+    let window = canvas.window_mut();
+    if window.fullscreen_state() != FullscreenType::Off {
+        return Ok(());
+    }
+    let (width, height) = window.size();
+    let (width, height) = if visible {
+        (width + panels::PANEL_WIDTH, height.max(panels::PANEL_HEIGHT))
+    } else {
+        (width.saturating_sub(panels::PANEL_WIDTH).max(64), height)
+    };
+    window.set_size(width, height).map_err(|e| e.to_string())
+}
+
+// script and cheat text in the top left corner of the display, on black so it
+// can be read over anything
+fn draw_overlay(canvas: &mut Canvas<Window>, display: Rect, lines: &[String]) -> Result<(), String> {
+    const SCALE: u32 = 2;
+    let mut y = display.y() + SCALE as i32;
+    for line in lines {
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.fill_rect(Rect::new(display.x(), y - SCALE as i32,
+                                   text::text_width(line, SCALE) + SCALE * 2, text::line_height(SCALE) + SCALE))?;
+        text::draw_text(canvas, display.x() + SCALE as i32, y, SCALE, line, Color::RGB(0xFF, 0xFF, 0xFF))?;
+        y += text::line_height(SCALE) as i32;
+    }
+    Ok(())
+}
+
+fn function_key(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        Keycode::F10 => Some(10),
+        _ => None,
+    }
+}
+
+fn hotkey(keycode: Keycode) -> Option<Hotkey> {
+    match keycode {
+        Keycode::F11 => Some(Hotkey::Pause),
+        Keycode::F12 => Some(Hotkey::AdvanceFrame),
+        Keycode::PageDown => Some(Hotkey::StepInstruction),
+        Keycode::Equals | Keycode::KpPlus => Some(Hotkey::Faster),
+        Keycode::Minus | Keycode::KpMinus => Some(Hotkey::Slower),
+        Keycode::Home => Some(Hotkey::Reset(ResetKind::Soft)),
+        Keycode::End => Some(Hotkey::Reset(ResetKind::Hard)),
+        _ => None,
+    }
+}
+
+// keymaps are written with the characters on the keys, which is what
+// sdl uses as the keycode of the printable keys
+fn chip8_key(keymap: &Keymap, keycode: Keycode) -> Option<u8> {
+    match ::std::char::from_u32(keycode as i32 as u32) {
+        Some(c) if c.is_ascii_graphic() => keymap.chip8_key(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_display_is_scaled_by_whole_multiples_and_centred() {
+        assert_eq!(letterbox(640, 320, 64, 32), Rect::new(0, 0, 640, 320));
+        // 11x fits across but only 10x down
+        assert_eq!(letterbox(710, 330, 64, 32), Rect::new(35, 5, 640, 320));
+        // a hi-res display in the same window
+        assert_eq!(letterbox(640, 320, 128, 64), Rect::new(0, 0, 640, 320));
+        assert_eq!(letterbox(1920, 1080, 64, 48), Rect::new(256, 12, 1408, 1056));
+        // never smaller than 1x, however small the window
+        assert_eq!(letterbox(32, 16, 64, 32), Rect::new(-16, -8, 64, 32));
+    }
+
+    #[test]
+    fn keys_are_mapped_by_the_character_on_them() {
+        let keymap = Keymap::default();
+        assert_eq!(chip8_key(&keymap, Keycode::Num1), Some(0x0));
+        assert_eq!(chip8_key(&keymap, Keycode::Q), Some(0x4));
+        assert_eq!(chip8_key(&keymap, Keycode::V), Some(0xF));
+        assert_eq!(chip8_key(&keymap, Keycode::P), None);
+        assert_eq!(chip8_key(&keymap, Keycode::F1), None);
+        assert_eq!(chip8_key(&keymap, Keycode::Space), None);
+        assert_eq!(chip8_key(&Keymap::keypad2(), Keycode::Semicolon), Some(0xB));
+        assert_eq!(chip8_key(&Keymap::new("x123qweasdzc4rfv").unwrap(), Keycode::X), Some(0x0));
+    }
+}
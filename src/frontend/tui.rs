@@ -0,0 +1,265 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use chip8_emulator::chip8::{Chip8, ResetKind};
+use chip8_emulator::chip8::CHIP8_FRAMES_PER_SECOND;
+use chip8_emulator::chip8::cpu;
+use chip8_emulator::chip8::symbols;
+use chip8_emulator::chip8::video::{self, Rgb, VideoFilter};
+
+use termion;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+use frontend::{Hotkey, Session};
+use options::Options;
+
+// Terminal frontend
+//
+// Draws the display with unicode half blocks (two pixels per character
+// cell) or braille (eight pixels per cell) in 24-bit ANSI colour, so it
+// works over ssh on machines without a display.
+//
+// Terminals only report key presses, never releases, so a key is let go
+// once no repeat for it has arrived within the key timeout, and fast
+// forward with ` the same way.
+pub fn run(chip8_vm: &mut Chip8, options: &Options, session: &mut Session) -> Result<(), String> {
+    let stdout = io::stdout().into_raw_mode().map_err(|e| e.to_string())?;
+    let mut screen = AlternateScreen::from(stdout);
+    let mut keys = termion::async_stdin().keys();
+
+    write!(screen, "{}{}", termion::cursor::Hide, termion::clear::All).map_err(|e| e.to_string())?;
+
+    let keymap = chip8_vm.keymap().clone();
+    let keymap2 = options.keymap2();
+    let key_timeout = Duration::from_millis(options.key_timeout_ms);
+    // keys 16 to 31 are the CHIP-8X second keypad
+    let mut last_pressed: [Option<Instant>; 32] = [None; 32];
+    let mut fast_forward_pressed: Option<Instant> = None;
+    let frame_time = Duration::new(0, 1_000_000_000u32 / CHIP8_FRAMES_PER_SECOND);
+
+    let mut video_filter = VideoFilter::new(chip8_vm.palette(), options.persistence);
+    let mut pixels = Vec::new();
+
+    let result = loop {
+        let frame_start = Instant::now();
+
+        // keyboard events
+        let mut quit = false;
+        let mut failed = None;
+        while let Some(Ok(key)) = keys.next() {
+            match key {
+                Key::Esc | Key::Ctrl('c') => {
+                    quit = true;
+                },
+                Key::F(11) => session.hotkey(Hotkey::Pause, chip8_vm),
+                Key::F(12) => session.hotkey(Hotkey::AdvanceFrame, chip8_vm),
+                Key::F(n) => {
+                    if let Err(e) = session.function_key(n, chip8_vm) {
+                        failed = Some(e);
+                    }
+                },
+                Key::PageDown => session.hotkey(Hotkey::StepInstruction, chip8_vm),
+                Key::Home => session.hotkey(Hotkey::Reset(ResetKind::Soft), chip8_vm),
+                Key::End => session.hotkey(Hotkey::Reset(ResetKind::Hard), chip8_vm),
+                Key::Char(c) => {
+                    if let Some(key) = keymap.chip8_key(c) {
+                        chip8_vm.set_key(key, true);
+                        last_pressed[key as usize] = Some(frame_start);
+                    } else if let Some(key) = keymap2.chip8_key(c) {
+                        chip8_vm.set_keypad2_key(key, true);
+                        last_pressed[16 + key as usize] = Some(frame_start);
+                    } else if c == '=' || c == '+' {
+                        session.hotkey(Hotkey::Faster, chip8_vm);
+                    } else if c == '-' {
+                        session.hotkey(Hotkey::Slower, chip8_vm);
+                    } else if c == '`' {
+                        session.control.fast_forwarding = true;
+                        fast_forward_pressed = Some(frame_start);
+                    }
+                },
+                _ => {}
+            }
+        }
+        if quit {
+            break Ok(());
+        }
+        if let Some(e) = failed {
+            break Err(e);
+        }
+
+        for (key, last) in last_pressed.iter_mut().enumerate() {
+            let expired = match *last {
+                Some(pressed_at) => frame_start.duration_since(pressed_at) >= key_timeout,
+                None => false,
+            };
+            if expired {
+                if key < 16 {
+                    chip8_vm.set_key(key as u8, false);
+                } else {
+                    chip8_vm.set_keypad2_key(key as u8 - 16, false);
+                }
+                *last = None;
+            }
+        }
+
+        if let Some(pressed_at) = fast_forward_pressed {
+            if frame_start.duration_since(pressed_at) >= key_timeout {
+                session.control.fast_forwarding = false;
+                fast_forward_pressed = None;
+            }
+        }
+
+        // step emulation
+        if let Err(e) = session.tick(chip8_vm, frame_start + frame_time) {
+            break Err(e);
+        }
+
+        chip8_vm.take_draw_flag();
+        video_filter.render(chip8_vm.graphics(), chip8_vm.colours(), &mut pixels);
+
+        let mut lines = if options.braille {
+            let background = match chip8_vm.colours() {
+                Some(colours) => video::vp590_background(colours.background),
+                None => video_filter.palette.background,
+            };
+            braille_lines(chip8_vm, &pixels, background, video_filter.palette.foreground)
+        } else {
+            half_block_lines(chip8_vm, &pixels)
+        };
+        if options.debug_panel {
+            let panel = debug_panel_lines(chip8_vm);
+            for (i, panel_line) in panel.into_iter().enumerate() {
+                if i < lines.len() {
+                    lines[i].push_str("  ");
+                    lines[i].push_str(&panel_line);
+                } else {
+                    lines.push(panel_line);
+                }
+            }
+        }
+        lines.extend(session.overlay(chip8_vm));
+
+        let mut frame = String::new();
+        frame.push_str(&format!("{}", termion::cursor::Goto(1, 1)));
+        for line in lines {
+            frame.push_str(&line);
+            frame.push_str("\r\n");
+        }
+        if let Err(e) = screen.write_all(frame.as_bytes()).and_then(|_| screen.flush()) {
+            break Err(e.to_string());
+        }
+
+        let elapsed = frame_start.elapsed();
+        if elapsed < frame_time {
+            ::std::thread::sleep(frame_time - elapsed);
+        }
+    };
+
+    write!(screen, "{}{}", termion::style::Reset, termion::cursor::Show).map_err(|e| e.to_string())?;
+    result
+}
+
+fn pixel_at(chip8_vm: &Chip8, pixels: &[u8], x: usize, y: usize) -> Rgb {
+    let index = (y * chip8_vm.graphics().width() + x) * 3;
+    Rgb::new(pixels[index], pixels[index + 1], pixels[index + 2])
+}
+
+// every character is an upper half block, the top pixel is the
+// foreground colour and the bottom pixel the background colour
+fn half_block_lines(chip8_vm: &Chip8, pixels: &[u8]) -> Vec<String> {
+    let width = chip8_vm.graphics().width();
+    let height = chip8_vm.graphics().height();
+
+    let mut lines = Vec::new();
+    for row in 0..(height / 2) {
+        let mut line = String::new();
+        for x in 0..width {
+            let top = pixel_at(chip8_vm, pixels, x, row * 2);
+            let bottom = pixel_at(chip8_vm, pixels, x, row * 2 + 1);
+            line.push_str(&format!("{}{}\u{2580}",
+                                   termion::color::Fg(termion::color::Rgb(top.r, top.g, top.b)),
+                                   termion::color::Bg(termion::color::Rgb(bottom.r, bottom.g, bottom.b))));
+        }
+        line.push_str(&format!("{}", termion::style::Reset));
+        lines.push(line);
+    }
+    lines
+}
+
+// braille cells are 2 pixels wide and 4 tall, a dot is raised for any
+// pixel that isn't the background colour
+fn braille_lines(chip8_vm: &Chip8, pixels: &[u8], background: Rgb, foreground: Rgb) -> Vec<String> {
+    // bit for each dot of the cell, indexed by [y][x]
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+    let width = chip8_vm.graphics().width();
+    let height = chip8_vm.graphics().height();
+
+    let mut lines = Vec::new();
+    for row in 0..height.div_ceil(4) {
+        let mut line = format!("{}{}",
+                               termion::color::Fg(termion::color::Rgb(foreground.r, foreground.g, foreground.b)),
+                               termion::color::Bg(termion::color::Rgb(background.r, background.g, background.b)));
+        for column in 0..width.div_ceil(2) {
+            let mut cell = 0x2800;
+            for (dy, dots) in DOTS.iter().enumerate() {
+                for (dx, &dot) in dots.iter().enumerate() {
+                    let x = column * 2 + dx;
+                    let y = row * 4 + dy;
+                    if x < width && y < height && pixel_at(chip8_vm, pixels, x, y) != background {
+                        cell |= dot;
+                    }
+                }
+            }
+            line.push(::std::char::from_u32(cell).unwrap());
+        }
+        line.push_str(&format!("{}", termion::style::Reset));
+        lines.push(line);
+    }
+    lines
+}
+
+fn debug_panel_lines(chip8_vm: &Chip8) -> Vec<String> {
+    let cpu_ref = chip8_vm.cpu();
+    let mut lines = Vec::new();
+
+    for row in 0..4 {
+        let mut line = String::new();
+        for column in 0..4 {
+            let index = row * 4 + column;
+            line.push_str(&format!("V{:X}={:02X} ", index, cpu_ref.v(index)));
+        }
+        lines.push(line);
+    }
+    lines.push(format!("I={:03X}  PC={:03X}  SP={:X}", cpu_ref.i(), cpu_ref.pc(), cpu_ref.sp()));
+    let symbols = chip8_vm.symbols();
+    lines.push(format!("DT={:02X}  ST={:02X}", cpu_ref.delay_timer(), cpu_ref.sound_timer()));
+    lines.push(String::new());
+
+    // a few instructions either side of the program counter
+    let pc = cpu_ref.pc();
+    let first = if pc >= 0x206 { pc - 6 } else { pc };
+    for i in 0..8 {
+        let addr = first + i * 2;
+        if addr as usize + 1 >= chip8_vm.memory().size() {
+            break;
+        }
+        let marker = if addr == pc { ">" } else { " " };
+        if let Some(name) = symbols.and_then(|symbols| symbols.name(addr as u32)) {
+            lines.push(format!("      {}:", name));
+        }
+        lines.push(format!("{}{:03X}  {}", marker, addr, cpu::disassemble_with_symbols(chip8_vm.platform(), chip8_vm.memory(), addr, symbols)));
+    }
+
+    if symbols.is_some() {
+        lines.push(String::new());
+        for frame in symbols::call_stack(cpu_ref, symbols) {
+            lines.push(format!("<- {}", frame));
+        }
+    }
+
+    lines
+}
@@ -1,4 +1,2 @@
-mod chip8;
-
-
-
+pub mod chip8;
+pub mod ffi;
@@ -1,187 +1,273 @@
 extern crate chip8_emulator;
 extern crate sdl2;
+extern crate termion;
 
 // std lib
-use std::env;
-use std::io::Read;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
-// sdl imports
-use sdl2::pixels::Color;
-use sdl2::event::Event; use sdl2::keyboard::Keycode;
-use std::time::Duration;
+use chip8_emulator::chip8::Chip8;
+use chip8_emulator::chip8::cheats::{CheatFile, CheatList};
+use chip8_emulator::chip8::coverage::{Coverage, LineMap};
+use chip8_emulator::chip8::database::Database;
+use chip8_emulator::chip8::font::Font;
+use chip8_emulator::chip8::platform::Platform;
+use chip8_emulator::chip8::profiler::Profiler;
+use chip8_emulator::chip8::rom::Rom;
+use chip8_emulator::chip8::script::Script;
+use chip8_emulator::chip8::symbols::Symbols;
+use chip8_emulator::chip8::video::{self, GifRecorder, VideoFilter};
 
-mod chip8;
-use chip8::Chip8;
+mod options;
+mod frontend;
 
-const SDL_SCREEN_WIDTH: u32 = 640;
-const SDL_SCREEN_HEIGHT: u32 = 320;
+use frontend::Session;
+use options::Options;
 
 fn main() {
-    let args: Vec<_> = env::args().collect();
-    if args.len() == 1 {
-        println!("Error, no rom file specified.");
-        return
-    }
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
- 
-    let window = video_subsystem.window("Rusty Chip-8 Emulator", SDL_SCREEN_WIDTH, SDL_SCREEN_HEIGHT)
-        .position_centered()
-        .build()
-        .unwrap();
- 
-    let mut canvas = window.into_canvas().build().unwrap();
- 
-    let mut file = File::open(&args[1]).unwrap();
-    let mut file_buffer = Vec::new();
-    file.read_to_end(&mut file_buffer).unwrap();
-
-    //println!("{:?}", file_buffer);
-
-    let mut chip8_vm = chip8::Chip8::new();
-    chip8_vm.boot(&file_buffer);
-
-    //println!("{:?}", chip8_vm);
-    
-    //println!("{:?}", rom);
-    
-    // see rust-sdl2 docs
-
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    'running: loop {
-        // step emulation 
-        chip8_vm.step();
-
-        // replace the below with a transfer from graphics memory
-        // to the sdl screen
-
-        // keyboard events
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    break 'running
-                },
-                Event::KeyDown { keycode: Some(keycode), .. } => {
-                    match keycode {
-                        Keycode::Num1 => {
-                            chip8_vm.do_key_event(0);
-                        },
-                        Keycode::Num2 => {
-                            chip8_vm.do_key_event(1);
-                        },
-                        Keycode::Num3 => {
-                            chip8_vm.do_key_event(2);
-                        },
-                        Keycode::Num4 => {
-                            chip8_vm.do_key_event(3);
-                        },
-                        Keycode::Q => {
-                            chip8_vm.do_key_event(4);
-                        },
-                        Keycode::W => {
-                            chip8_vm.do_key_event(5);
-                        },
-                        Keycode::E => {
-                            chip8_vm.do_key_event(6);
-                        },
-                        Keycode::R => {
-                            chip8_vm.do_key_event(7);
-                        },
-                        Keycode::A => {
-                            chip8_vm.do_key_event(8);
-                        },
-                        Keycode::S => {
-                            chip8_vm.do_key_event(9);
-                        },
-                        Keycode::D => {
-                            chip8_vm.do_key_event(10);
-                        },
-                        Keycode::F => {
-                            chip8_vm.do_key_event(11);
-                        },
-                        Keycode::Z => {
-                            chip8_vm.do_key_event(12);
-                        },
-                        Keycode::X => {
-                            chip8_vm.do_key_event(13);
-                        },
-                        Keycode::C => {
-                            chip8_vm.do_key_event(14);
-                        },
-                        Keycode::V => {
-                            chip8_vm.do_key_event(15);
-                        },
-                        _ => {}
-                    }
-                },
-                Event::KeyUp { keycode: Some(keycode), .. } => {
-                    match keycode {
-                        Keycode::Num1 => {
-                            chip8_vm.do_key_event(0);
-                        },
-                        Keycode::Num2 => {
-                            chip8_vm.do_key_event(1);
-                        },
-                        Keycode::Num3 => {
-                            chip8_vm.do_key_event(2);
-                        },
-                        Keycode::Num4 => {
-                            chip8_vm.do_key_event(3);
-                        },
-                        Keycode::Q => {
-                            chip8_vm.do_key_event(4);
-                        },
-                        Keycode::W => {
-                            chip8_vm.do_key_event(5);
-                        },
-                        Keycode::E => {
-                            chip8_vm.do_key_event(6);
-                        },
-                        Keycode::R => {
-                            chip8_vm.do_key_event(7);
-                        },
-                        Keycode::A => {
-                            chip8_vm.do_key_event(8);
-                        },
-                        Keycode::S => {
-                            chip8_vm.do_key_event(9);
-                        },
-                        Keycode::D => {
-                            chip8_vm.do_key_event(10);
-                        },
-                        Keycode::F => {
-                            chip8_vm.do_key_event(11);
-                        },
-                        Keycode::Z => {
-                            chip8_vm.do_key_event(12);
-                        },
-                        Keycode::X => {
-                            chip8_vm.do_key_event(13);
-                        },
-                        Keycode::C => {
-                            chip8_vm.do_key_event(14);
-                        },
-                        Keycode::V => {
-                            chip8_vm.do_key_event(15);
-                        },
-                        _ => {}
-                    }
-                }
-                _ => {}
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(message) => {
+            println!("{}", message);
+            println!("{}", options::USAGE);
+            return
+        }
+    };
+
+    // Read with room for the largest platform, whether the rom fits is
+    // checked once the database and command line have picked the platform
+    let platform = options.platform.unwrap_or_default();
+    let rom = load_rom(&options.rom_path, options.load_address.unwrap_or(platform.load_address()), Platform::MegaChip.memory_size());
+
+    let mut database = Database::builtin();
+    if let Some(ref path) = options.database_path {
+        match Database::load_file(Path::new(path)) {
+            Ok(extra) => database.merge(extra),
+            Err(e) => {
+                println!("Error, could not load database {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut chip8_vm = Chip8::new();
+    // nothing is loaded yet for the platform to refuse
+    chip8_vm.set_platform(platform).unwrap();
+    chip8_vm.set_ram_init(options.ram_init);
+    let mut font = match options.font {
+        Some(ref path) if Path::new(path).is_file() => Font::load(Path::new(path)).unwrap_or_else(|e| {
+            println!("Error, could not load font {}: {}", path, e);
+            process::exit(1);
+        }),
+        Some(ref name) => Font::from_name(name).unwrap_or_else(|e| {
+            println!("Error, {}.", e);
+            process::exit(1);
+        }),
+        None => Font::default(),
+    };
+    if let Some(address) = options.font_address {
+        font.address = address;
+    }
+    chip8_vm.set_font(font);
+    if let Err(e) = chip8_vm.boot_with_database(&rom, &database) {
+        println!("Error, could not load {}: {}", options.rom_path, e);
+        process::exit(1);
+    }
+
+    // the command line wins over the database
+    if let Some(platform) = options.platform {
+        if let Err(e) = chip8_vm.set_platform(platform) {
+            println!("Error, could not load {}: {}", options.rom_path, e);
+            process::exit(1);
+        }
+    }
+    if let Some(palette) = options.palette {
+        chip8_vm.set_palette(palette);
+    }
+    if let Some(ref keymap) = options.keymap {
+        chip8_vm.set_keymap(keymap.clone());
+    }
+    if let Some(ipf) = options.instructions_per_frame {
+        chip8_vm.set_instructions_per_frame(ipf);
+    }
+    if let Some(timing) = options.timing {
+        chip8_vm.set_timing(timing);
+    }
+    if let Some(quirks) = options.quirks {
+        chip8_vm.set_quirks(quirks);
+    }
+    chip8_vm.set_rca1802(options.rca1802);
+    if let Some(ref path) = options.symbols_path {
+        match Symbols::load(Path::new(path)) {
+            Ok(symbols) => chip8_vm.set_symbols(symbols),
+            Err(e) => {
+                println!("Error, could not load symbols {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let script = options.script_path.as_ref().map(|path| {
+        match Script::from_file(Path::new(path)) {
+            Ok(mut script) => {
+                script.set_palette(chip8_vm.palette());
+                script
+            },
+            Err(e) => {
+                println!("Error, {}", e);
+                process::exit(1);
+            }
+        }
+    });
+
+    let cheats = match options.cheat_path {
+        Some(ref path) => match CheatFile::load(Path::new(path)) {
+            Ok(file) => file.cheats(&rom.sha1_hex()),
+            Err(e) => {
+                println!("Error, could not load cheats {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => CheatList::default(),
+    };
+    cheats.apply_patches(&mut chip8_vm);
+    let cheat_file = options.cheat_path.as_ref().map(|path| (PathBuf::from(path), rom.sha1_hex()));
+    let mut session = Session::new(script, cheats, cheat_file);
+    session.control.speed = options.speed;
+    session.control.fast_forward_speed = options.fast_forward_speed;
+    if options.profile_path.is_some() {
+        session.profiler = Some(Profiler::new());
+    }
+    // loaded now so a bad line map doesn't wait for the rom to finish
+    let line_map = options.line_map_path.as_ref().map(|path| {
+        match LineMap::load(Path::new(path)) {
+            Ok(map) => map,
+            Err(e) => {
+                println!("Error, could not load line map {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    });
+    if options.coverage_path.is_some() {
+        session.coverage = Some(Coverage::new());
+    }
+    session.trace = options.trace_path.as_ref().map(|path| -> Box<dyn Write> {
+        if path == "-" {
+            return Box::new(io::stdout());
+        }
+        match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                println!("Error, could not create trace {}: {}", path, e);
+                process::exit(1);
             }
         }
-        // The rest of the game loop goes here...
-        chip8_vm.render(&mut canvas);
+    });
+    let no_symbols = Symbols::new();
+    let symbols = chip8_vm.symbols().unwrap_or(&no_symbols);
+    session.breakpoints = symbols.breakpoints.clone();
+    for location in options.breakpoints.iter() {
+        match symbols.resolve(location) {
+            Ok(addr) => session.breakpoints.push(addr),
+            Err(e) => {
+                println!("Error, invalid breakpoint: {}", e);
+                process::exit(1);
+            }
+        }
+    }
 
-        canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 540 ));
-    } 
+    let result = match options.headless_frames {
+        Some(frames) => run_headless(&mut chip8_vm, &options, &mut session, frames),
+        None if options.tui => frontend::tui::run(&mut chip8_vm, &options, &mut session),
+        None => frontend::sdl::run(&mut chip8_vm, &options, &mut session),
+    };
+    // written even when the rom stopped with an error, the profile is
+    // often what explains it
+    if let (Some(path), Some(profiler)) = (options.profile_path.as_ref(), session.profiler.as_ref()) {
+        if let Err(e) = write_profile(path, profiler, &chip8_vm) {
+            println!("Error, could not write profile {}: {}", path, e);
+        }
+    }
+    if let (Some(path), Some(coverage)) = (options.coverage_path.as_ref(), session.coverage.as_ref()) {
+        if let Err(e) = write_coverage(path, coverage, &options.rom_path, &rom, line_map.as_ref()) {
+            println!("Error, could not write coverage {}: {}", path, e);
+        }
+    }
+    if let Some(ref mut trace) = session.trace {
+        if let Err(e) = trace.flush() {
+            println!("Error, could not write the trace: {}", e);
+        }
+    }
+    if let Err(message) = result {
+        println!("Error: {}", message);
+        process::exit(1);
+    }
+}
 
+fn write_profile(path: &str, profiler: &Profiler, chip8_vm: &Chip8) -> ::std::io::Result<()> {
+    fs::write(path, profiler.report(chip8_vm))?;
+    fs::write(format!("{}.folded", path), profiler.folded(chip8_vm.symbols()))?;
+    fs::write(format!("{}.histogram", path), profiler.histogram())
 }
 
+fn write_coverage(path: &str, coverage: &Coverage, rom_path: &str, rom: &Rom, line_map: Option<&LineMap>) -> ::std::io::Result<()> {
+    fs::write(path, coverage.lcov(rom_path, rom, line_map))?;
+    let (width, height, pixels) = coverage.heatmap();
+    video::write_png(Path::new(&format!("{}.png", path)), width, height, &pixels)
+}
+
+fn load_rom(path: &str, load_address: u16, memory_size: usize) -> Rom {
+    match Rom::from_file_with_memory_size(Path::new(path), load_address, memory_size) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("Error, could not load {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+// runs without any display, optionally recording a gif and saving the
+// last frame
+fn run_headless(chip8_vm: &mut Chip8, options: &Options, session: &mut Session, frames: u32) -> Result<(), String> {
+    let mut video_filter = VideoFilter::new(chip8_vm.palette(), options.persistence);
+    let mut pixels = Vec::new();
+    let mut gif = match options.gif_path {
+        Some(ref path) => {
+            let graphics = chip8_vm.graphics();
+            Some(GifRecorder::create(Path::new(path), graphics.width(), graphics.height())
+                .map_err(|e| format!("could not write {}: {}", path, e))?)
+        },
+        None => None,
+    };
+    let mut record = |chip8_vm: &Chip8, pixels: &mut Vec<u8>| -> Result<(), String> {
+        let graphics = chip8_vm.graphics();
+        video_filter.render(graphics, chip8_vm.colours(), pixels);
+        match gif {
+            Some(ref mut gif) => gif.add_frame(graphics.width(), graphics.height(), pixels)
+                .map_err(|e| format!("could not write the gif: {}", e)),
+            None => Ok(()),
+        }
+    };
 
+    // the starting display, so that even 0 frames leave something to save
+    record(chip8_vm, &mut pixels)?;
+    for _ in 0..frames {
+        session.run_frame(chip8_vm)?;
+        record(chip8_vm, &mut pixels)?;
+        if session.stopped_at.is_some() {
+            for line in session.break_report(chip8_vm) {
+                println!("{}", line);
+            }
+            break;
+        }
+    }
+
+    if let Some(ref path) = options.screenshot_path {
+        let graphics = chip8_vm.graphics();
+        video::write_png(Path::new(path), graphics.width(), graphics.height(), &pixels)
+            .map_err(|e| format!("could not write {}: {}", path, e))?;
+    }
+    Ok(())
+}
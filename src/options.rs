@@ -0,0 +1,374 @@
+use std::env;
+
+use chip8_emulator::chip8::control::Speed;
+use chip8_emulator::chip8::keymap::Keymap;
+use chip8_emulator::chip8::memory::RamInit;
+use chip8_emulator::chip8::platform::Platform;
+use chip8_emulator::chip8::quirks::Quirks;
+use chip8_emulator::chip8::timing::Timing;
+use chip8_emulator::chip8::video::Palette;
+
+pub const USAGE: &str = "Usage: chip8_emulator [options] <rom>
+
+Options:
+    --platform NAME        chip8, hires, eti660, chip8x or megachip (default chip8)
+    --load-address ADDR    where the rom is loaded and starts (default 0x200, 0x600 on eti660, 0x300 on chip8x)
+    --scale N              initial window size as a multiple of the display (default 10)
+    --fullscreen           start in desktop fullscreen
+    --palette NAME         bw, green, amber, octo or #background,#foreground
+    --ipf N                instructions to run per 60Hz frame (default 9)
+    --timing NAME          ipf for a fixed number of instructions per frame, vip for COSMAC VIP instruction timing
+    --rca1802              run the RCA 1802 machine code that 0NNN calls, as on the COSMAC VIP
+    --quirks LIST          vip, schip or a list from vf_reset,memory,shifting,jumping,clipping,display_wait
+    --font NAME            vip, dream6800, eti660, schip, octo or a font file (default schip)
+    --font-address ADDR    where the font is installed (default 0x000)
+    --ram-init NAME        what RAM holds at power on: zeros, random or vip (default zeros)
+    --database FILE        extra rom database entries, see src/chip8/database.txt
+    --persistence N        fade pixels out over N frames instead of instantly
+    --speed SPEED          run at SPEED times normal speed, up to 64, 0.5 for half or max for
+                           uncapped, - and = change it
+    --fast-forward SPEED   the speed while ` is held down (default max)
+    --headless FRAMES      run FRAMES frames without opening a window
+    --screenshot FILE      write the final frame to FILE as a png (headless only)
+    --gif FILE             record the run to FILE as an animated gif (headless only)
+    --tui                  draw in the terminal instead of opening a window
+    --braille              draw with braille characters in the terminal
+    --keymap KEYS          16 keys for chip-8 keys 0-F (default 1234qwerasdfzxcv)
+    --keymap2 KEYS         16 keys for the CHIP-8X second keypad (default 7890uiopjkl;m,./)
+    --key-timeout MS       release a terminal key after MS without a repeat (default 150)
+    --debug-panel          show registers and disassembly next to the display, Tab toggles them in the window
+    --script FILE          run a Rhai script alongside the rom, see src/chip8/script.rs
+    --cheats FILE          load and save the rom's cheats in FILE, F1-F9 search RAM and switch them
+    --profile FILE         write a profile to FILE, with folded stacks in FILE.folded and a
+                           histogram of instructions per frame in FILE.histogram
+    --coverage FILE        write an lcov coverage report of the rom to FILE and a heatmap of
+                           memory accesses to FILE.png
+    --line-map FILE        map rom addresses to source lines in the coverage report
+    --symbols FILE         label addresses with an Octo .sym or \"address name\" file
+    --break LOCATION       stop before the instruction at a label or hex address, F10 carries on,
+                           can be given more than once
+    --trace FILE           write every instruction run to FILE, - for the terminal";
+
+// Command line options for the emulator frontend
+#[derive(Debug)]
+pub struct Options {
+    pub rom_path: String,
+    // both default to what the rom database says
+    pub platform: Option<Platform>,
+    pub load_address: Option<u16>,
+
+    // initial window size is the chip-8 display times this
+    pub scale: u32,
+    pub fullscreen: bool,
+
+    // these are taken from the rom database when not given
+    pub palette: Option<Palette>,
+    pub instructions_per_frame: Option<u32>,
+    pub timing: Option<Timing>,
+    pub quirks: Option<Quirks>,
+    pub rca1802: bool,
+    pub ram_init: RamInit,
+    // a built in font's name or a font file
+    pub font: Option<String>,
+    pub font_address: Option<u16>,
+    pub database_path: Option<String>,
+    pub persistence: u8,
+    pub speed: Speed,
+    pub fast_forward_speed: Speed,
+
+    // run this many frames without a window instead of the sdl frontend
+    pub headless_frames: Option<u32>,
+    pub screenshot_path: Option<String>,
+    pub gif_path: Option<String>,
+
+    // terminal frontend
+    pub tui: bool,
+    pub braille: bool,
+    pub keymap: Option<Keymap>,
+    pub keymap2: Option<Keymap>,
+    pub key_timeout_ms: u64,
+    pub debug_panel: bool,
+
+    pub script_path: Option<String>,
+    pub cheat_path: Option<String>,
+    pub profile_path: Option<String>,
+    pub coverage_path: Option<String>,
+    pub line_map_path: Option<String>,
+    pub symbols_path: Option<String>,
+    // labels or addresses, resolved once the symbols are loaded
+    pub breakpoints: Vec<String>,
+    pub trace_path: Option<String>,
+}
+
+impl Options {
+    pub fn from_args() -> Result<Options, String> {
+        Options::parse(env::args().skip(1).collect())
+    }
+
+    pub fn keymap2(&self) -> Keymap {
+        self.keymap2.clone().unwrap_or_else(Keymap::keypad2)
+    }
+
+    pub fn parse(args: Vec<String>) -> Result<Options, String> {
+        let mut rom_path = None;
+        let mut platform = None;
+        let mut load_address = None;
+        let mut scale = 10;
+        let mut fullscreen = false;
+        let mut palette = None;
+        let mut instructions_per_frame = None;
+        let mut timing = None;
+        let mut quirks = None;
+        let mut rca1802 = false;
+        let mut ram_init = RamInit::default();
+        let mut font = None;
+        let mut font_address = None;
+        let mut database_path = None;
+        let mut persistence = 0;
+        let mut speed = Speed::default();
+        let mut fast_forward_speed = Speed::Uncapped;
+        let mut headless_frames = None;
+        let mut screenshot_path = None;
+        let mut gif_path = None;
+        let mut tui = false;
+        let mut braille = false;
+        let mut keymap = None;
+        let mut keymap2 = None;
+        let mut key_timeout_ms = 150;
+        let mut debug_panel = false;
+        let mut script_path = None;
+        let mut cheat_path = None;
+        let mut profile_path = None;
+        let mut coverage_path = None;
+        let mut line_map_path = None;
+        let mut symbols_path = None;
+        let mut breakpoints = Vec::new();
+        let mut trace_path = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--platform" => {
+                    let value = next_value(&mut args, "--platform")?;
+                    platform = Some(Platform::parse(&value).map_err(|e| format!("Error, {}.", e))?);
+                },
+                "--load-address" => {
+                    let value = next_value(&mut args, "--load-address")?;
+                    load_address = Some(parse_address(&value)
+                        .ok_or_else(|| format!("Error, invalid load address \"{}\".", value))?);
+                },
+                "--scale" => {
+                    let value = next_value(&mut args, "--scale")?;
+                    scale = match value.parse::<u32>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("Error, invalid scale \"{}\".", value)),
+                    };
+                },
+                "--fullscreen" => {
+                    fullscreen = true;
+                },
+                "--palette" => {
+                    let value = next_value(&mut args, "--palette")?;
+                    palette = Some(Palette::from_name(&value).map_err(|e| format!("Error, {}.", e))?);
+                },
+                "--ipf" => {
+                    let value = next_value(&mut args, "--ipf")?;
+                    instructions_per_frame = match value.parse::<u32>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("Error, invalid ipf \"{}\".", value)),
+                    };
+                },
+                "--timing" => {
+                    let value = next_value(&mut args, "--timing")?;
+                    timing = Some(Timing::parse(&value).map_err(|e| format!("Error, {}.", e))?);
+                },
+                "--rca1802" => {
+                    rca1802 = true;
+                },
+                "--quirks" => {
+                    let value = next_value(&mut args, "--quirks")?;
+                    quirks = Some(Quirks::parse(&value).map_err(|e| format!("Error, {}.", e))?);
+                },
+                "--font" => {
+                    font = Some(next_value(&mut args, "--font")?);
+                },
+                "--font-address" => {
+                    let value = next_value(&mut args, "--font-address")?;
+                    font_address = Some(parse_address(&value)
+                        .ok_or_else(|| format!("Error, invalid font address \"{}\".", value))?);
+                },
+                "--ram-init" => {
+                    let value = next_value(&mut args, "--ram-init")?;
+                    ram_init = RamInit::parse(&value).map_err(|e| format!("Error, {}.", e))?;
+                },
+                "--database" => {
+                    database_path = Some(next_value(&mut args, "--database")?);
+                },
+                "--persistence" => {
+                    let value = next_value(&mut args, "--persistence")?;
+                    persistence = value.parse::<u8>()
+                        .map_err(|_| format!("Error, invalid persistence \"{}\".", value))?;
+                },
+                "--speed" => {
+                    let value = next_value(&mut args, "--speed")?;
+                    speed = Speed::parse(&value).map_err(|e| format!("Error, {}.", e))?;
+                },
+                "--fast-forward" => {
+                    let value = next_value(&mut args, "--fast-forward")?;
+                    fast_forward_speed = Speed::parse(&value).map_err(|e| format!("Error, {}.", e))?;
+                },
+                "--headless" => {
+                    let value = next_value(&mut args, "--headless")?;
+                    headless_frames = Some(value.parse::<u32>()
+                        .map_err(|_| format!("Error, invalid frame count \"{}\".", value))?);
+                },
+                "--screenshot" => {
+                    screenshot_path = Some(next_value(&mut args, "--screenshot")?);
+                },
+                "--gif" => {
+                    gif_path = Some(next_value(&mut args, "--gif")?);
+                },
+                "--tui" => {
+                    tui = true;
+                },
+                "--braille" => {
+                    tui = true;
+                    braille = true;
+                },
+                "--keymap" => {
+                    let value = next_value(&mut args, "--keymap")?;
+                    keymap = Some(Keymap::new(&value).map_err(|e| format!("Error, {}.", e))?);
+                },
+                "--keymap2" => {
+                    let value = next_value(&mut args, "--keymap2")?;
+                    keymap2 = Some(Keymap::new(&value).map_err(|e| format!("Error, {}.", e))?);
+                },
+                "--key-timeout" => {
+                    let value = next_value(&mut args, "--key-timeout")?;
+                    key_timeout_ms = value.parse::<u64>()
+                        .map_err(|_| format!("Error, invalid key timeout \"{}\".", value))?;
+                },
+                "--debug-panel" => {
+                    debug_panel = true;
+                },
+                "--script" => {
+                    script_path = Some(next_value(&mut args, "--script")?);
+                },
+                "--cheats" => {
+                    cheat_path = Some(next_value(&mut args, "--cheats")?);
+                },
+                "--profile" => {
+                    profile_path = Some(next_value(&mut args, "--profile")?);
+                },
+                "--coverage" => {
+                    coverage_path = Some(next_value(&mut args, "--coverage")?);
+                },
+                "--line-map" => {
+                    line_map_path = Some(next_value(&mut args, "--line-map")?);
+                },
+                "--symbols" => {
+                    symbols_path = Some(next_value(&mut args, "--symbols")?);
+                },
+                "--break" => {
+                    breakpoints.push(next_value(&mut args, "--break")?);
+                },
+                "--trace" => {
+                    trace_path = Some(next_value(&mut args, "--trace")?);
+                },
+                _ if arg.starts_with("--") => {
+                    return Err(format!("Error, unknown option \"{}\".", arg));
+                },
+                _ => {
+                    rom_path = Some(arg);
+                }
+            }
+        }
+
+        match rom_path {
+            Some(rom_path) => Ok(Options {
+                rom_path,
+                platform,
+                load_address,
+                scale,
+                fullscreen,
+                palette,
+                instructions_per_frame,
+                timing,
+                quirks,
+                rca1802,
+                ram_init,
+                font,
+                font_address,
+                database_path,
+                persistence,
+                speed,
+                fast_forward_speed,
+                headless_frames,
+                screenshot_path,
+                gif_path,
+                tui,
+                braille,
+                keymap,
+                keymap2,
+                key_timeout_ms,
+                debug_panel,
+                script_path,
+                cheat_path,
+                profile_path,
+                coverage_path,
+                line_map_path,
+                symbols_path,
+                breakpoints,
+                trace_path,
+            }),
+            None => Err(String::from("Error, no rom file specified.")),
+        }
+    }
+}
+
+fn next_value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, String> {
+    match args.next() {
+        Some(value) => Ok(value),
+        None => Err(format!("Error, {} needs a value.", option)),
+    }
+}
+
+// accepts 0x prefixed hex or plain decimal
+pub fn parse_address(value: &str) -> Option<u16> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        u16::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse::<u16>().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| String::from(*arg)).collect())
+    }
+
+    #[test]
+    fn scale_and_fullscreen() {
+        let options = parse(&["game.ch8"]).unwrap();
+        assert_eq!((options.rom_path.as_str(), options.scale, options.fullscreen), ("game.ch8", 10, false));
+
+        let options = parse(&["--scale", "3", "--fullscreen", "game.ch8"]).unwrap();
+        assert_eq!((options.scale, options.fullscreen), (3, true));
+
+        assert_eq!(parse(&["--scale", "0", "game.ch8"]).err().unwrap(), "Error, invalid scale \"0\".");
+        assert_eq!(parse(&["--scale", "big", "game.ch8"]).err().unwrap(), "Error, invalid scale \"big\".");
+        assert_eq!(parse(&["game.ch8", "--scale"]).err().unwrap(), "Error, --scale needs a value.");
+        assert_eq!(parse(&["--fullscreen"]).err().unwrap(), "Error, no rom file specified.");
+    }
+
+    #[test]
+    fn keymaps() {
+        let options = parse(&["--keymap", "x123qweasdzc4rfv", "game.ch8"]).unwrap();
+        assert_eq!(options.keymap.as_ref().unwrap().chip8_key('x'), Some(0x0));
+        assert_eq!(options.keymap2(), Keymap::keypad2());
+        assert!(parse(&["--keymap", "1234", "game.ch8"]).err().unwrap().starts_with("Error, keymap \"1234\""));
+    }
+}
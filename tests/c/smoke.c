@@ -0,0 +1,97 @@
+/*
+ * Smoke test for the C interface, built and run by tests/c_api.rs.
+ * Exits with 0 when everything checks out.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "chip8.h"
+
+#define CHECK(condition) \
+    do { \
+        if (!(condition)) { \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #condition); \
+            return 1; \
+        } \
+    } while (0)
+
+/* draws the font's 0, sounds the buzzer and waits */
+static const uint8_t draw_zero[] = {
+    0x60, 0x00, /* LD V0, 0 */
+    0xF0, 0x29, /* LD F, V0 */
+    0xD0, 0x05, /* DRW V0, V0, 5 */
+    0x61, 0x10, /* LD V1, 16 */
+    0xF1, 0x18, /* LD ST, V1 */
+    0x12, 0x0A, /* JP 0x20A */
+};
+
+/* RET with nothing on the stack */
+static const uint8_t crashes[] = {0x00, 0xEE};
+
+int main(void)
+{
+    Chip8Handle *chip8 = chip8_new();
+    size_t width = 0, height = 0, size = 0;
+    uint32_t background = 1, foreground = 0;
+    const uint8_t *pixels;
+    uint8_t *state;
+
+    CHECK(chip8 != NULL);
+    CHECK(chip8_load_rom(NULL, draw_zero, sizeof draw_zero) == CHIP8_ERROR_NULL);
+    CHECK(chip8_load_rom(chip8, draw_zero, 0) == CHIP8_ERROR_INVALID_ROM);
+    CHECK(chip8_load_rom(chip8, draw_zero, sizeof draw_zero) == CHIP8_OK);
+    CHECK(chip8_set_key(chip8, 0x10, 1) == CHIP8_ERROR_INVALID_KEY);
+    CHECK(chip8_set_key(chip8, 0xF, 1) == CHIP8_OK);
+    CHECK(chip8_palette(chip8, &background, &foreground) == CHIP8_OK);
+    CHECK(background == 0x000000 && foreground == 0xFFFFFF);
+    CHECK(chip8_key_for_char(chip8, 'v') == 0xF);
+    CHECK(chip8_key_for_char(chip8, 'X') == 0xD);
+    CHECK(chip8_key_for_char(chip8, 'p') == CHIP8_ERROR_INVALID_KEY);
+
+    CHECK(chip8_run_frame(chip8) == CHIP8_OK);
+    CHECK(chip8_sound_active(chip8) == 1);
+    pixels = chip8_framebuffer(chip8, &width, &height);
+    CHECK(pixels != NULL);
+    CHECK(width == 64 && height == 32);
+    /* the top row of a 0 is 0xF0 */
+    CHECK(pixels[0] == 1 && pixels[3] == 1 && pixels[4] == 0);
+    CHECK(pixels[width] == 1 && pixels[width + 1] == 0);
+
+    CHECK(chip8_save_state(chip8, NULL, 0, &size) == CHIP8_ERROR_BUFFER_TOO_SMALL);
+    CHECK(size > 0);
+    state = malloc(size);
+    CHECK(chip8_save_state(chip8, state, size - 1, &size) == CHIP8_ERROR_BUFFER_TOO_SMALL);
+    CHECK(chip8_save_state(chip8, state, size, &size) == CHIP8_OK);
+
+    for (int frame = 0; frame < 30; frame++) {
+        CHECK(chip8_run_frame(chip8) == CHIP8_OK);
+    }
+    CHECK(chip8_sound_active(chip8) == 0);
+    CHECK(chip8_load_state(chip8, state, size) == CHIP8_OK);
+    CHECK(chip8_sound_active(chip8) == 1);
+    CHECK(chip8_load_state(chip8, state, size - 1) == CHIP8_ERROR_INVALID_STATE);
+    CHECK(chip8_load_state(chip8, (const uint8_t *) "nope", 4) == CHIP8_ERROR_INVALID_STATE);
+
+    CHECK(chip8_load_rom(chip8, crashes, sizeof crashes) == CHIP8_OK);
+    CHECK(chip8_run_frame(chip8) == CHIP8_ERROR_CRASHED);
+    CHECK(chip8_run_frame(chip8) == CHIP8_ERROR_CRASHED);
+    CHECK(chip8_load_state(chip8, state, size) == CHIP8_OK);
+    CHECK(chip8_run_frame(chip8) == CHIP8_OK);
+
+    CHECK(chip8_load_rom_for_platform(chip8, draw_zero, sizeof draw_zero, 99) == CHIP8_ERROR_INVALID_PLATFORM);
+    CHECK(chip8_load_rom_for_platform(chip8, draw_zero, sizeof draw_zero, CHIP8_PLATFORM_HIRES) == CHIP8_OK);
+    CHECK(chip8_run_frame(chip8) == CHIP8_OK);
+    chip8_framebuffer(chip8, &width, &height);
+    CHECK(width == 64 && height == 64);
+
+    CHECK(chip8_framebuffer(NULL, &width, &height) == NULL);
+    CHECK(chip8_sound_active(NULL) == CHIP8_ERROR_NULL);
+    CHECK(chip8_palette(NULL, NULL, NULL) == CHIP8_ERROR_NULL);
+    free(state);
+    chip8_free(chip8);
+    chip8_free(NULL);
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,70 @@
+// C interface smoke test
+//
+// Builds tests/c/smoke.c with the system cc against the static library
+// cargo builds next to this test and runs it. Skipped when there is no cc.
+// Also checks include/chip8.h declares every function src/ffi.rs exports.
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn read(path: &PathBuf) -> String {
+    let mut contents = String::new();
+    File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+    contents
+}
+
+#[test]
+fn header_declares_every_export() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let ffi = read(&root.join("src/ffi.rs"));
+    let header = read(&root.join("include/chip8.h"));
+
+    let exports: Vec<&str> = ffi.lines()
+        .filter_map(|line| line.split("extern \"C\" fn ").nth(1))
+        .map(|rest| rest.split('(').next().unwrap())
+        .collect();
+    assert!(exports.len() >= 8);
+    for name in exports {
+        assert!(header.contains(&format!(" {}(", name)) || header.contains(&format!("*{}(", name)),
+                "{} is missing from include/chip8.h", name);
+    }
+    for line in ffi.lines().filter(|line| line.starts_with("pub const CHIP8_")) {
+        let name = line.split(':').next().unwrap().trim_start_matches("pub const ");
+        let value = line.split("= ").nth(1).unwrap().trim_end_matches(';');
+        assert!(header.contains(&format!("#define {} {}", name, value)),
+                "{} = {} is missing from include/chip8.h", name, value);
+    }
+}
+
+#[test]
+fn c_smoke_test() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        println!("no cc, skipping");
+        return;
+    }
+
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // target/debug/deps/c_api-... -> target/debug
+    let target = env::current_exe().unwrap().parent().unwrap().parent().unwrap().to_path_buf();
+    let library = target.join("libchip8_emulator.a");
+    assert!(library.exists(), "{} was not built", library.display());
+    let binary = target.join("c_smoke");
+
+    let status = Command::new("cc")
+        .arg(root.join("tests/c/smoke.c"))
+        .arg("-std=c99")
+        .arg("-I").arg(root.join("include"))
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm"])
+        .arg("-o").arg(&binary)
+        .status()
+        .unwrap();
+    assert!(status.success(), "smoke.c did not compile");
+
+    let output = Command::new(&binary).output().unwrap();
+    assert!(output.status.success(), "smoke test failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
@@ -0,0 +1,269 @@
+// Conformance harness
+//
+// Boots the test roms in tests/roms, runs them for a fixed number of
+// frames with scripted key presses and compares the display with the
+// golden snapshot in tests/golden. A snapshot is the display as ASCII
+// art, '#' for a set pixel and '.' for an unset one.
+//
+// The goldens are not generated from the emulator. The same program runs
+// on the reference interpreter in tests/reference, written from the spec,
+// and both displays have to match the golden. A golden that is missing
+// or out of date after a deliberate change is written by hand from the
+// reference display the failure prints and the .lst file next to the
+// rom, which is its disassembly and says what each test draws.
+//
+// The roms were written for these tests and are under the same licence
+// as the rest of the emulator.
+//
+// The community_ tests run Timendus' CHIP-8 test suite the same way, with
+// goldens transcribed from the result screens the suite documents. The
+// suite is vendored in tests/community, see the README there; the tests
+// say so and pass when it isn't.
+
+extern crate chip8_emulator;
+
+mod reference;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use chip8_emulator::chip8::{Chip8, CHIP8_INSTRUCTIONS_PER_FRAME};
+use chip8_emulator::chip8::quirks::Quirks;
+use chip8_emulator::chip8::rom::Rom;
+
+use reference::Machine;
+
+struct KeyEvent {
+    frame: u32,
+    key: u8,
+    pressed: bool,
+}
+
+struct Case {
+    // under tests, roms for our own and community for the suite
+    directory: &'static str,
+    rom: &'static str,
+    golden: &'static str,
+    frames: u32,
+    quirks: Quirks,
+    input: Vec<KeyEvent>,
+    // bytes written after booting
+    pokes: Vec<(u16, u8)>,
+}
+
+impl Case {
+    fn new(rom: &'static str, golden: &'static str, frames: u32) -> Case {
+        Case {
+            directory: "roms",
+            rom,
+            golden,
+            frames,
+            quirks: Quirks::default(),
+            input: Vec::new(),
+            pokes: Vec::new(),
+        }
+    }
+
+    // a rom of the community test suite, its goldens are in golden/community
+    fn community(rom: &'static str, golden: &'static str, frames: u32) -> Case {
+        Case {directory: "community", ..Case::new(rom, golden, frames)}
+    }
+
+    fn poke(mut self, addr: u16, value: u8) -> Case {
+        self.pokes.push((addr, value));
+        self
+    }
+
+    fn quirks(mut self, quirks: Quirks) -> Case {
+        self.quirks = quirks;
+        self
+    }
+
+    fn press(mut self, frame: u32, key: u8, frames_held: u32) -> Case {
+        self.input.push(KeyEvent {frame, key, pressed: true});
+        self.input.push(KeyEvent {frame: frame + frames_held, key, pressed: false});
+        self
+    }
+}
+
+fn data_path(directory: &str, name: &str) -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests");
+    path.push(directory);
+    path.push(name);
+    path
+}
+
+fn snapshot<F: Fn(usize, usize) -> bool>(width: usize, height: usize, pixel: F) -> String {
+    let mut text = String::new();
+    for y in 0..height {
+        for x in 0..width {
+            text.push(if pixel(x, y) { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// A 60Hz frame on the reference, as Chip8::run_frame runs them: a few
+// instructions unless the program waits for a key or for the display,
+// then the timers tick. The test roms don't use CXNN.
+fn run_reference_frame(machine: &mut Machine) {
+    for _ in 0..CHIP8_INSTRUCTIONS_PER_FRAME {
+        if machine.waiting_for_key.is_none() {
+            machine.step(0).unwrap();
+        }
+        if machine.waiting_for_vblank {
+            break;
+        }
+    }
+    machine.waiting_for_vblank = false;
+    machine.delay = machine.delay.saturating_sub(1);
+    machine.sound = machine.sound.saturating_sub(1);
+}
+
+// FX0A is satisfied by a key going down
+fn set_reference_key(machine: &mut Machine, key: u8, pressed: bool) {
+    if pressed && !machine.keys[key as usize] {
+        if let Some(register) = machine.waiting_for_key.take() {
+            machine.v[register as usize] = key;
+        }
+    }
+    machine.keys[key as usize] = pressed;
+}
+
+// overlays both snapshots, '+' is a pixel that is only set in the
+// actual display and '-' one that is only set in the golden one
+fn visual_diff(expected: &str, actual: &str) -> String {
+    let mut diff = String::new();
+    for (expected_line, actual_line) in expected.lines().zip(actual.lines()) {
+        for (e, a) in expected_line.chars().zip(actual_line.chars()) {
+            diff.push(match (e, a) {
+                ('#', '#') => '#',
+                ('.', '#') => '+',
+                ('#', '.') => '-',
+                _ => '.',
+            });
+        }
+        diff.push('\n');
+    }
+    diff
+}
+
+fn run(case: Case) {
+    let rom_path = data_path(case.directory, case.rom);
+    if case.directory == "community" && !rom_path.exists() {
+        println!("{} is not vendored, see tests/community/README.md", rom_path.display());
+        return;
+    }
+    let rom = Rom::from_file(&rom_path, 0x200).unwrap();
+    let mut chip8 = Chip8::new();
+    chip8.boot(&rom).unwrap();
+    chip8.set_quirks(case.quirks);
+    for &(addr, value) in case.pokes.iter() {
+        chip8.memory_mut().write_byte(addr, value);
+    }
+    // font, rom and whatever else the emulator put in RAM
+    let mut reference = Machine::new(chip8.memory().to_vec(), case.quirks);
+
+    for frame in 0..case.frames {
+        for event in case.input.iter().filter(|event| event.frame == frame) {
+            chip8.set_key(event.key, event.pressed);
+            set_reference_key(&mut reference, event.key, event.pressed);
+        }
+        chip8.run_frame().unwrap();
+        run_reference_frame(&mut reference);
+    }
+    let graphics = chip8.graphics();
+    let actual = snapshot(graphics.width(), graphics.height(), |x, y| graphics.get_pixel_value(x, y));
+    let expected_by_reference = snapshot(reference::WIDTH, reference::HEIGHT, |x, y| reference.display[y * reference::WIDTH + x]);
+
+    let golden_directory = if case.directory == "community" { "golden/community" } else { "golden" };
+    let golden_path = data_path(golden_directory, case.golden);
+    let mut expected = String::new();
+    if File::open(&golden_path).and_then(|mut file| file.read_to_string(&mut expected)).is_err() {
+        panic!("missing golden snapshot {}, check what the reference drew against {}:\n\n{}",
+               golden_path.display(), case.rom.replace(".ch8", ".lst"), expected_by_reference);
+    }
+
+    if expected != expected_by_reference {
+        panic!("the reference interpreter does not draw {} for {} after {} frames\n\
+                ('+' is only set on the reference, '-' only in the golden snapshot)\n\n{}",
+               case.golden, case.rom, case.frames, visual_diff(&expected, &expected_by_reference));
+    }
+    if expected != actual {
+        panic!("{} does not match {} after {} frames\n\
+                ('+' is only set in the actual display, '-' only in the golden one)\n\n{}",
+               case.rom, case.golden, case.frames, visual_diff(&expected, &actual));
+    }
+}
+
+#[test]
+fn digits() {
+    run(Case::new("digits.ch8", "digits.txt", 20));
+}
+
+#[test]
+fn flags() {
+    run(Case::new("flags.ch8", "flags.txt", 40));
+}
+
+#[test]
+fn flags_vip() {
+    // the flags must not depend on the quirks
+    run(Case::new("flags.ch8", "flags.txt", 40).quirks(Quirks::cosmac_vip()));
+}
+
+#[test]
+fn keypad() {
+    run(Case::new("keypad.ch8", "keypad.txt", 30)
+        .press(2, 0x5, 3)
+        .press(10, 0xA, 3)
+        .press(20, 0x3, 3));
+}
+
+#[test]
+fn quirks_default() {
+    run(Case::new("quirks.ch8", "quirks_default.txt", 10));
+}
+
+#[test]
+fn quirks_vip() {
+    run(Case::new("quirks.ch8", "quirks_vip.txt", 10).quirks(Quirks::cosmac_vip()));
+}
+
+#[test]
+fn quirks_schip() {
+    run(Case::new("quirks.ch8", "quirks_schip.txt", 10).quirks(Quirks::super_chip()));
+}
+
+#[test]
+fn quirks_wrapping() {
+    run(Case::new("quirks.ch8", "quirks_wrapping.txt", 10).quirks(Quirks::none()));
+}
+
+#[test]
+fn community_corax_plus() {
+    run(Case::community("3-corax+.ch8", "corax+.txt", 60));
+}
+
+#[test]
+fn community_flags() {
+    run(Case::community("4-flags.ch8", "flags.txt", 120));
+}
+
+// 0x1FF picks the test and skips the suite's menu
+#[test]
+fn community_quirks_chip8() {
+    run(Case::community("5-quirks.ch8", "quirks_chip8.txt", 600)
+        .quirks(Quirks::cosmac_vip())
+        .poke(0x1FF, 1));
+}
+
+#[test]
+fn community_keypad_fx0a() {
+    run(Case::community("6-keypad.ch8", "keypad_fx0a.txt", 60)
+        .poke(0x1FF, 3)
+        .press(20, 0x5, 3));
+}
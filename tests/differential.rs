@@ -0,0 +1,346 @@
+// Differential fuzzer
+//
+// Generates random programs, memory images and register states, runs them
+// instruction by instruction on Chip8CPU::step and on the reference
+// interpreter in tests/reference and compares the whole machine after
+// every step. The cpu has to fault exactly where the reference does, e.g.
+// on an illegal instruction, and never panic.
+//
+// A failing case is shrunk to the shortest program that still diverges
+// before it is reported. The run is deterministic, by default it tries
+// 2000 cases from a fixed seed; for a longer run use
+//
+//     FUZZ_ITERATIONS=1000000 FUZZ_SEED=1234 cargo test --release --test differential
+//
+// A reported case can be replayed with the FUZZ_SEED it prints and
+// FUZZ_ITERATIONS=1.
+
+extern crate chip8_emulator;
+extern crate rand;
+
+mod reference;
+
+use std::any::Any;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use chip8_emulator::chip8::Chip8Bus;
+use chip8_emulator::chip8::cpu::{self, Chip8CPU};
+use chip8_emulator::chip8::graphics::{CHIP8_SCREEN_HEIGHT, CHIP8_SCREEN_WIDTH};
+use chip8_emulator::chip8::quirks::Quirks;
+
+use reference::{Fault, Machine, MEMORY_SIZE};
+
+const DEFAULT_SEED: u64 = 0xC8C8;
+const DEFAULT_ITERATIONS: u64 = 2000;
+const MAX_STEPS: usize = 64;
+
+#[derive(Clone, Debug)]
+struct Case {
+    seed: u64,
+    quirks: Quirks,
+    v: [u8; 16],
+    i: u16,
+    delay: u8,
+    sound: u8,
+    keys: [bool; 16],
+    // memory before the program is written over it at 0x200
+    memory: Vec<u8>,
+    program: Vec<u16>,
+}
+
+impl Case {
+    fn generate(seed: u64) -> Case {
+        let mut rng: XorShiftRng = SeedableRng::from_seed(
+            [seed as u32 | 1, (seed >> 32) as u32, 0x9E3779B9, 0x7F4A7C15]);
+
+        let quirks = Quirks {
+            vf_reset: rng.gen(),
+            memory_increments_i: rng.gen(),
+            shift_ignores_vy: rng.gen(),
+            jump_uses_vx: rng.gen(),
+            clipping: rng.gen(),
+            display_wait: rng.gen(),
+        };
+
+        let mut v = [0u8; 16];
+        rng.fill_bytes(&mut v);
+        let mut keys = [false; 16];
+        for key in keys.iter_mut() {
+            *key = rng.gen();
+        }
+
+        let mut memory = vec![0u8; MEMORY_SIZE];
+        rng.fill_bytes(&mut memory);
+
+        let length = rng.gen_range(1, 48);
+        let program = (0..length).map(|_| random_instruction(&mut rng)).collect();
+
+        Case {
+            seed,
+            quirks,
+            v,
+            // now and then right at the end of memory
+            i: if rng.gen_weighted_bool(4) { rng.gen_range(0xFF0, 0x1000) } else { rng.gen_range(0, 0x1000) },
+            delay: rng.gen(),
+            sound: rng.gen(),
+            keys,
+            memory,
+            program,
+        }
+    }
+
+    fn memory_image(&self) -> Vec<u8> {
+        let mut memory = self.memory.clone();
+        for (n, word) in self.program.iter().enumerate() {
+            memory[0x200 + n * 2] = (word >> 8) as u8;
+            memory[0x200 + n * 2 + 1] = *word as u8;
+        }
+        memory
+    }
+}
+
+// Mostly well formed instructions with random operands, jumps stay
+// inside the program area so runs don't wander off into random data
+fn random_instruction<R: Rng>(rng: &mut R) -> u16 {
+    let x = rng.gen_range(0, 16) as u16;
+    let y = rng.gen_range(0, 16) as u16;
+    let nn = rng.gen::<u8>() as u16;
+    let target = 0x200 + rng.gen_range(0, 48) as u16 * 2;
+    let templates: [u16; 36] = [
+        0x00E0, 0x00EE, 0x1000 | target, 0x2000 | target,
+        0x3000 | x << 8 | nn, 0x4000 | x << 8 | nn, 0x5000 | x << 8 | y << 4, 0x6000 | x << 8 | nn,
+        0x7000 | x << 8 | nn, 0x8000 | x << 8 | y << 4, 0x8001 | x << 8 | y << 4, 0x8002 | x << 8 | y << 4,
+        0x8003 | x << 8 | y << 4, 0x8004 | x << 8 | y << 4, 0x8005 | x << 8 | y << 4, 0x8006 | x << 8 | y << 4,
+        0x8007 | x << 8 | y << 4, 0x800E | x << 8 | y << 4, 0x9000 | x << 8 | y << 4, 0xA000 | rng.gen_range(0, 0x1000),
+        0xB000 | target, 0xC000 | x << 8 | nn, 0xD000 | x << 8 | y << 4 | rng.gen_range(0, 16), 0xE09E | x << 8,
+        0xE0A1 | x << 8, 0xF007 | x << 8, 0xF00A | x << 8, 0xF015 | x << 8,
+        0xF018 | x << 8, 0xF01E | x << 8, 0xF029 | x << 8, 0xF033 | x << 8,
+        0xF055 | x << 8, 0xF065 | x << 8,
+        // anything at all, mostly illegal instructions
+        rng.gen(), rng.gen(),
+    ];
+    // FX0A stops the run, keep it rare
+    loop {
+        let instruction = *rng.choose(&templates).unwrap();
+        if instruction & 0xF0FF != 0xF00A || rng.gen_weighted_bool(8) {
+            return instruction;
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        String::from(*message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("panic without a message")
+    }
+}
+
+fn same_fault(fault: cpu::Fault, expected: Fault) -> bool {
+    match (fault, expected) {
+        (cpu::Fault::IllegalInstruction(opcode), Fault::IllegalInstruction(expected)) => opcode == expected,
+        (cpu::Fault::MachineCode(addr), Fault::MachineCode(expected)) => addr == expected,
+        (cpu::Fault::StackOverflow, Fault::StackOverflow) => true,
+        (cpu::Fault::StackUnderflow, Fault::StackUnderflow) => true,
+        _ => false,
+    }
+}
+
+// the differences between the cpu and the reference, empty if none
+fn compare(cpu: &Chip8CPU, bus: &Chip8Bus, reference: &Machine) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut check = |name: &str, actual: String, expected: String| {
+        if actual != expected {
+            differences.push(format!("{}: cpu {} reference {}", name, actual, expected));
+        }
+    };
+
+    for r in 0..16 {
+        check(&format!("V{:X}", r), format!("{:02X}", cpu.v(r)), format!("{:02X}", reference.v[r]));
+    }
+    check("I", format!("{:04X}", cpu.i()), format!("{:04X}", reference.i));
+    check("PC", format!("{:04X}", cpu.pc()), format!("{:04X}", reference.pc));
+    check("stack", format!("{:X?}", cpu.stack()), format!("{:X?}", reference.stack));
+    check("DT", format!("{:02X}", cpu.delay_timer()), format!("{:02X}", reference.delay));
+    check("ST", format!("{:02X}", cpu.sound_timer()), format!("{:02X}", reference.sound));
+    check("waiting for key",
+          format!("{:?}", if cpu.is_halted_flag { Some(cpu.halted_register) } else { None }),
+          format!("{:?}", reference.waiting_for_key));
+    check("waiting for vblank", format!("{}", cpu.waiting_for_vblank), format!("{}", reference.waiting_for_vblank));
+    check("draw flag", format!("{}", cpu.draw_to_screen_flag), format!("{}", reference.drew));
+
+    // formatting every byte and pixel would make the run crawl
+    if bus.memory.ram()[..] != reference.memory[..] {
+        for addr in 0..MEMORY_SIZE {
+            check(&format!("memory {:03X}", addr),
+                  format!("{:02X}", bus.memory.ram()[addr]), format!("{:02X}", reference.memory[addr]));
+        }
+    }
+    for y in 0..CHIP8_SCREEN_HEIGHT {
+        for x in 0..CHIP8_SCREEN_WIDTH {
+            let (actual, expected) = (bus.graphics.get_pixel_value(x, y), reference.display[y * CHIP8_SCREEN_WIDTH + x]);
+            if actual != expected {
+                check(&format!("pixel {},{}", x, y), format!("{}", actual), format!("{}", expected));
+            }
+        }
+    }
+    differences
+}
+
+// Runs a case on both, the description of the first divergence if any
+fn diverges(case: &Case) -> Option<String> {
+    let memory = case.memory_image();
+
+    let mut cpu = Chip8CPU::new();
+    let mut bus = Chip8Bus::new();
+    bus.memory.load(0, &memory);
+    bus.keypad.keys.copy_from_slice(&case.keys);
+    cpu.reg_gp.copy_from_slice(&case.v);
+    cpu.set_i(case.i as u32);
+    cpu.set_timers(case.delay, case.sound);
+    cpu.quirks = case.quirks;
+    cpu.draw_to_screen_flag = false;
+
+    let mut reference = Machine::new(memory, case.quirks);
+    reference.v = case.v;
+    reference.i = case.i;
+    reference.delay = case.delay;
+    reference.sound = case.sound;
+    reference.keys = case.keys;
+
+    for step in 0..MAX_STEPS {
+        let pc = reference.pc;
+        let opcode = reference.opcode();
+        let instruction = match cpu::decode(opcode) {
+            Some(instruction) => format!("{}", instruction),
+            None => format!("DW #{:04X}", opcode),
+        };
+        let at = format!("step {}, {:04X}: {:04X} {}", step, pc, opcode, instruction);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| cpu.step(&mut bus).map(|_| ())));
+
+        // CXNN has to be given the byte the cpu drew
+        let random_byte = if opcode >> 12 == 0xC { cpu.v(((opcode >> 8) & 0xF) as usize) } else { 0 };
+        let expected = reference.step(random_byte);
+
+        match (result, expected) {
+            (Ok(Ok(())), Ok(())) => {},
+            (Ok(Err(error)), Err(fault)) if same_fault(error.fault, fault) => return None,
+            (Ok(Err(error)), Err(fault)) =>
+                return Some(format!("{}\ncpu faulted with {:?} but the reference with {:?}", at, error.fault, fault)),
+            (Ok(Err(error)), Ok(())) =>
+                return Some(format!("{}\ncpu faulted with {:?} but the reference carried on", at, error.fault)),
+            (Ok(Ok(())), Err(fault)) =>
+                return Some(format!("{}\nreference faulted with {:?} but the cpu carried on", at, fault)),
+            (Err(payload), _) =>
+                return Some(format!("{}\ncpu panicked: {}", at, panic_message(payload))),
+        }
+
+        let differences = compare(&cpu, &bus, &reference);
+        if !differences.is_empty() {
+            return Some(format!("{}\n{}", at, differences.join("\n")));
+        }
+        if reference.waiting_for_key.is_some() {
+            return None;
+        }
+    }
+    None
+}
+
+// Shrinks a diverging case, first the program and then the rest of the
+// state, keeping every simplification that still diverges
+fn minimise(case: &Case) -> Case {
+    let mut best = case.clone();
+    loop {
+        let mut candidates = Vec::new();
+        for n in (0..best.program.len()).rev() {
+            let mut candidate = best.clone();
+            candidate.program.remove(n);
+            candidates.push(candidate);
+        }
+        if best.memory.iter().any(|&byte| byte != 0) {
+            let mut candidate = best.clone();
+            candidate.memory = vec![0; MEMORY_SIZE];
+            candidates.push(candidate);
+        }
+        for r in 0..16 {
+            if best.v[r] != 0 {
+                let mut candidate = best.clone();
+                candidate.v[r] = 0;
+                candidates.push(candidate);
+            }
+        }
+        if best.i != 0 {
+            let mut candidate = best.clone();
+            candidate.i = 0;
+            candidates.push(candidate);
+        }
+        if best.keys.iter().any(|&key| key) {
+            let mut candidate = best.clone();
+            candidate.keys = [false; 16];
+            candidates.push(candidate);
+        }
+        if best.quirks != Quirks::none() {
+            let mut candidate = best.clone();
+            candidate.quirks = Quirks::none();
+            candidates.push(candidate);
+        }
+
+        match candidates.into_iter().find(|candidate| !candidate.program.is_empty() && diverges(candidate).is_some()) {
+            Some(smaller) => best = smaller,
+            None => return best,
+        }
+    }
+}
+
+fn report(case: &Case, divergence: &str) -> String {
+    let mut text = format!("cpu and reference diverge, replay with FUZZ_SEED={} FUZZ_ITERATIONS=1\n\n", case.seed);
+    text.push_str(&format!("quirks: {:?}\n", case.quirks));
+    text.push_str(&format!("V: {:02X?}\nI: {:04X}\nkeys: {:?}\n", case.v, case.i, case.keys));
+    text.push_str(&format!("memory: {}\n\n",
+                           if case.memory.iter().all(|&byte| byte == 0) { "zeroed" } else { "random" }));
+    for (n, word) in case.program.iter().enumerate() {
+        let instruction = match cpu::decode(*word) {
+            Some(instruction) => format!("{}", instruction),
+            None => format!("DW #{:04X}", word),
+        };
+        text.push_str(&format!("{:03X}: {:04X}  {}\n", 0x200 + n * 2, word, instruction));
+    }
+    text.push_str(&format!("\n{}\n", divergence));
+    text
+}
+
+fn env_number(name: &str, default: u64) -> u64 {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+#[test]
+fn cpu_matches_the_reference() {
+    let seed = env_number("FUZZ_SEED", DEFAULT_SEED);
+    let iterations = env_number("FUZZ_ITERATIONS", DEFAULT_ITERATIONS);
+
+    // a panic is reported with the smallest case, not as it happens
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failure = None;
+    for n in 0..iterations {
+        let case = Case::generate(seed.wrapping_add(n));
+        if diverges(&case).is_some() {
+            let smallest = minimise(&case);
+            let divergence = diverges(&smallest).unwrap();
+            failure = Some(report(&smallest, &divergence));
+            break;
+        }
+    }
+
+    panic::set_hook(default_hook);
+    if let Some(failure) = failure {
+        panic!("{}", failure);
+    }
+}
@@ -0,0 +1,242 @@
+// Reference chip-8 interpreter for the differential fuzzer
+//
+// Written straight from the spec with no regard for speed, so that it is
+// easy to check by eye. Where the spec is silent this is the behaviour the
+// emulator promises:
+//
+// - memory is 4 KiB and every access wraps around at the end of it
+// - PC and I are 16 bits wide and wrap, instructions are fetched from PC
+//   modulo the memory size
+// - the stack holds 16 return addresses
+// - EX9E, EXA1 and FX29 only look at the low nibble of VX
+//
+// Anything a program can't do on a real machine is a Fault, the only
+// case in which the emulator is allowed to stop, with a CpuError.
+
+use chip8_emulator::chip8::quirks::Quirks;
+
+pub const MEMORY_SIZE: usize = 4096;
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const STACK_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    IllegalInstruction(u16),
+    // 0NNN runs machine code, which the reference doesn't model
+    MachineCode(u16),
+    StackOverflow,
+    StackUnderflow,
+}
+
+#[derive(Clone)]
+pub struct Machine {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub delay: u8,
+    pub sound: u8,
+    pub memory: Vec<u8>,
+    pub display: Vec<bool>,
+    pub keys: [bool; 16],
+    pub quirks: Quirks,
+    // register FX0A is waiting to fill
+    pub waiting_for_key: Option<u8>,
+    // DXYN with the display wait quirk ends the frame
+    pub waiting_for_vblank: bool,
+    pub drew: bool,
+}
+
+impl Machine {
+    pub fn new(memory: Vec<u8>, quirks: Quirks) -> Machine {
+        assert_eq!(memory.len(), MEMORY_SIZE);
+        Machine {
+            v: [0; 16],
+            i: 0,
+            pc: 0x200,
+            stack: Vec::new(),
+            delay: 0,
+            sound: 0,
+            memory,
+            display: vec![false; WIDTH * HEIGHT],
+            keys: [false; 16],
+            quirks,
+            waiting_for_key: None,
+            waiting_for_vblank: false,
+            drew: false,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        self.memory[addr as usize % MEMORY_SIZE]
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize % MEMORY_SIZE] = value;
+    }
+
+    pub fn opcode(&self) -> u16 {
+        ((self.read(self.pc) as u16) << 8) | self.read(self.pc.wrapping_add(1)) as u16
+    }
+
+    // Executes one instruction. CXNN has no random number generator of its
+    // own and ANDs random_byte with NN instead.
+    pub fn step(&mut self, random_byte: u8) -> Result<(), Fault> {
+        let opcode = self.opcode();
+        let x = ((opcode >> 8) & 0xF) as usize;
+        let y = ((opcode >> 4) & 0xF) as usize;
+        let n = (opcode & 0xF) as u8;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        let next = self.pc.wrapping_add(2);
+        let skip = self.pc.wrapping_add(4);
+
+        match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => {
+                    for pixel in self.display.iter_mut() {
+                        *pixel = false;
+                    }
+                    self.drew = true;
+                    self.pc = next;
+                },
+                0x00EE => {
+                    self.pc = self.stack.pop().ok_or(Fault::StackUnderflow)?;
+                },
+                _ => return Err(Fault::MachineCode(nnn)),
+            },
+            0x1 => self.pc = nnn,
+            0x2 => {
+                if self.stack.len() == STACK_SIZE {
+                    return Err(Fault::StackOverflow);
+                }
+                self.stack.push(next);
+                self.pc = nnn;
+            },
+            0x3 => self.pc = if self.v[x] == nn { skip } else { next },
+            0x4 => self.pc = if self.v[x] != nn { skip } else { next },
+            0x5 if n == 0 => self.pc = if self.v[x] == self.v[y] { skip } else { next },
+            0x6 => {
+                self.v[x] = nn;
+                self.pc = next;
+            },
+            0x7 => {
+                self.v[x] = self.v[x].wrapping_add(nn);
+                self.pc = next;
+            },
+            0x8 => {
+                let vx = self.v[x];
+                let vy = self.v[y];
+                let shifted = if self.quirks.shift_ignores_vy { vx } else { vy };
+                // the result goes to VX before the flag goes to VF
+                let (result, flag) = match n {
+                    0x0 => (vy, None),
+                    0x1 => (vx | vy, if self.quirks.vf_reset { Some(0) } else { None }),
+                    0x2 => (vx & vy, if self.quirks.vf_reset { Some(0) } else { None }),
+                    0x3 => (vx ^ vy, if self.quirks.vf_reset { Some(0) } else { None }),
+                    0x4 => (vx.wrapping_add(vy), Some(if vx as u16 + vy as u16 > 0xFF { 1 } else { 0 })),
+                    0x5 => (vx.wrapping_sub(vy), Some(if vx >= vy { 1 } else { 0 })),
+                    0x6 => (shifted >> 1, Some(shifted & 1)),
+                    0x7 => (vy.wrapping_sub(vx), Some(if vy >= vx { 1 } else { 0 })),
+                    0xE => (shifted << 1, Some(shifted >> 7)),
+                    _ => return Err(Fault::IllegalInstruction(opcode)),
+                };
+                self.v[x] = result;
+                if let Some(flag) = flag {
+                    self.v[0xF] = flag;
+                }
+                self.pc = next;
+            },
+            0x9 if n == 0 => self.pc = if self.v[x] != self.v[y] { skip } else { next },
+            0xA => {
+                self.i = nnn;
+                self.pc = next;
+            },
+            0xB => {
+                let offset = if self.quirks.jump_uses_vx { self.v[x] } else { self.v[0] };
+                self.pc = nnn + offset as u16;
+            },
+            0xC => {
+                self.v[x] = random_byte & nn;
+                self.pc = next;
+            },
+            0xD => {
+                self.draw(self.v[x] as usize, self.v[y] as usize, n);
+                self.waiting_for_vblank = self.quirks.display_wait;
+                self.pc = next;
+            },
+            0xE if nn == 0x9E => self.pc = if self.keys[(self.v[x] & 0xF) as usize] { skip } else { next },
+            0xE if nn == 0xA1 => self.pc = if !self.keys[(self.v[x] & 0xF) as usize] { skip } else { next },
+            0xF => {
+                match nn {
+                    0x07 => self.v[x] = self.delay,
+                    0x0A => self.waiting_for_key = Some(x as u8),
+                    0x15 => self.delay = self.v[x],
+                    0x18 => self.sound = self.v[x],
+                    0x1E => self.i = self.i.wrapping_add(self.v[x] as u16),
+                    0x29 => self.i = (self.v[x] & 0xF) as u16 * 5,
+                    0x33 => {
+                        let value = self.v[x];
+                        let i = self.i;
+                        self.write(i, value / 100);
+                        self.write(i.wrapping_add(1), value / 10 % 10);
+                        self.write(i.wrapping_add(2), value % 10);
+                    },
+                    0x55 => {
+                        for r in 0..(x + 1) {
+                            let (addr, value) = (self.i.wrapping_add(r as u16), self.v[r]);
+                            self.write(addr, value);
+                        }
+                        if self.quirks.memory_increments_i {
+                            self.i = self.i.wrapping_add(x as u16 + 1);
+                        }
+                    },
+                    0x65 => {
+                        for r in 0..(x + 1) {
+                            self.v[r] = self.read(self.i.wrapping_add(r as u16));
+                        }
+                        if self.quirks.memory_increments_i {
+                            self.i = self.i.wrapping_add(x as u16 + 1);
+                        }
+                    },
+                    _ => return Err(Fault::IllegalInstruction(opcode)),
+                }
+                self.pc = next;
+            },
+            _ => return Err(Fault::IllegalInstruction(opcode)),
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, x: usize, y: usize, rows: u8) {
+        let x = x % WIDTH;
+        let y = y % HEIGHT;
+        let mut collision = false;
+
+        for row in 0..rows as usize {
+            let byte = self.read(self.i.wrapping_add(row as u16));
+            for column in 0..8 {
+                if byte & (0x80 >> column) == 0 {
+                    continue;
+                }
+                let mut px = x + column;
+                let mut py = y + row;
+                if px >= WIDTH || py >= HEIGHT {
+                    if self.quirks.clipping {
+                        continue;
+                    }
+                    px %= WIDTH;
+                    py %= HEIGHT;
+                }
+                let pixel = &mut self.display[py * WIDTH + px];
+                collision |= *pixel;
+                *pixel = !*pixel;
+            }
+        }
+
+        self.v[0xF] = if collision { 1 } else { 0 };
+        self.drew = true;
+    }
+}